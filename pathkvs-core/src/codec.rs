@@ -0,0 +1,480 @@
+use std::{
+    borrow::Cow,
+    collections::HashMap,
+    io::{Error, ErrorKind, Read},
+    time::Duration,
+};
+
+/// a single entry of the history, the changes have none for the keys deleted by the commit
+#[non_exhaustive]
+pub enum Record<'a> {
+    Commit {
+        time: Duration,
+        changes: Cow<'a, HashMap<Vec<u8>, Option<Vec<u8>>>>,
+    },
+    /// a commit that appended to some keys, `appends` has just the bytes appended to each of them,
+    /// the new value is the value the key had before the commit followed by them, see [`RecordCodec::encodes_appends`]
+    Append {
+        time: Duration,
+        changes: Cow<'a, HashMap<Vec<u8>, Option<Vec<u8>>>>,
+        appends: Cow<'a, HashMap<Vec<u8>, Vec<u8>>>,
+    },
+}
+
+/// the length written for an empty value, deleted keys are written with the length 0, as every history
+/// from before empty values could be stored has them, so their deletions keep meaning the same
+const EMPTY_VALUE: u32 = u32::MAX;
+
+/// the start of every history written since the header exists, followed by the version of its format
+/// and the id of its codec, as little endian u16s, which can't be mistaken for the start of a record of any codec
+pub const MAGIC: [u8; 8] = *b"pathkvs\0";
+
+/// the version of the format in the header of new histories, raised by changes to the format
+/// the versions before would misread, histories are only rewritten in a new format by [`crate::Database::migrate`]
+///
+/// * 0 - the histories from before the header, which have none
+/// * 1 - the header followed by the records of the codec
+/// * 2 - the header has the id of the codec, which was 0 before, read as a u32 by version 1 it is a newer version
+/// * 3 - the high bit of the id of the codec is set while the history is closed cleanly, see [`Header::Version`],
+///   version 2 would take it for an unknown codec
+pub const FORMAT_VERSION: u32 = 3;
+
+/// the bit of the id of the codec in the header of a history closed cleanly, from version 3
+const CLEAN: u16 = 0x8000;
+
+/// the length of the header, the magic number, the version and the codec
+pub const HEADER_LEN: u64 = 12;
+
+/// the start of a history, see [`read_header`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Header {
+    /// the history ends before a whole header, as a new one does
+    Missing,
+    /// a history of format version 0, from before the header, with the bytes read looking for it,
+    /// which are the start of its first record
+    Legacy(Vec<u8>),
+    /// a history with a header, `codec` is the [`RecordCodec::id`] it was written with, 0 if it does not say
+    ///
+    /// `clean` if it was closed by [`crate::Database::close`] and nothing was appended since, so it ends
+    /// with a complete record, it is cleared before the next append, see [`crate::storage::StorageBackend::rewrite_header`]
+    Version { version: u32, codec: u16, clean: bool },
+}
+
+impl Header {
+    /// the header of a new history, in the current format, written with the codec of this id
+    pub fn current(codec: u16) -> [u8; HEADER_LEN as usize] {
+        let mut header = [0; HEADER_LEN as usize];
+        header[..8].copy_from_slice(&MAGIC);
+        header[8..10].copy_from_slice(&(FORMAT_VERSION as u16).to_le_bytes());
+        header[10..].copy_from_slice(&codec.to_le_bytes());
+        header
+    }
+    /// like [`Header::current`], for a history closed cleanly
+    pub fn closed(codec: u16) -> [u8; HEADER_LEN as usize] {
+        let mut header = Self::current(codec);
+        header[10..].copy_from_slice(&(codec | CLEAN).to_le_bytes());
+        header
+    }
+    /// the version of the format, a missing header is taken as the current one, as it is about to be written
+    pub fn version(&self) -> u32 {
+        match self {
+            Header::Missing => FORMAT_VERSION,
+            Header::Legacy(_) => 0,
+            Header::Version { version, .. } => *version,
+        }
+    }
+    /// the id of the codec the history was written with, none if it does not say
+    pub fn codec(&self) -> Option<u16> {
+        match self {
+            Header::Version { codec, .. } if *codec != 0 => Some(*codec),
+            _ => None,
+        }
+    }
+    /// whether the history was closed cleanly
+    pub fn clean(&self) -> bool {
+        matches!(self, Header::Version { clean: true, .. })
+    }
+}
+
+/// reads the header at the start of a history, an error of kind [`ErrorKind::InvalidData`] means
+/// the history is of a format newer than [`FORMAT_VERSION`], which this version can't read
+pub fn read_header(input: &mut dyn Read) -> Result<Header, Error> {
+    let mut header = Vec::with_capacity(HEADER_LEN as usize);
+    (&mut *input).take(HEADER_LEN).read_to_end(&mut header)?;
+    if !header.starts_with(&MAGIC[..header.len().min(MAGIC.len())]) {
+        return Ok(Header::Legacy(header));
+    }
+    let Some(version) = header.get(8..12) else {
+        return Ok(Header::Missing);
+    };
+    let codec = u16::from_le_bytes([version[2], version[3]]);
+    let version = u16::from_le_bytes([version[0], version[1]]) as u32;
+    if version > FORMAT_VERSION {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            format!("pathkvs history is of format version {version}, newer than {FORMAT_VERSION}"),
+        ));
+    }
+    let clean = version >= 3 && codec & CLEAN != 0;
+    Ok(Header::Version {
+        version,
+        codec: if version >= 3 { codec & !CLEAN } else { codec },
+        clean,
+    })
+}
+
+/// how records are laid out in the history of a persistent database
+///
+/// the id of the codec is in the header, see [`Header::codec`], and opening a history with another codec fails
+pub trait RecordCodec: Send + Sync {
+    /// the id written in the header, 0 for codecs that don't have one, see [`by_id`] for the ones of this crate
+    fn id(&self) -> u16 {
+        0
+    }
+    fn encode(&self, record: &Record, out: &mut Vec<u8>);
+    /// reads the next record
+    ///
+    /// an error of kind [`ErrorKind::UnexpectedEof`] means the history ended, possibly in the middle of a record,
+    /// in which case everything after the last complete record is discarded
+    fn decode(&self, input: &mut RecordReader<dyn Read + '_>) -> Result<Record<'static>, Error>;
+    /// whether [`Record::Append`] can be encoded, otherwise appends are persisted as commits with the whole value
+    fn encodes_appends(&self) -> bool {
+        false
+    }
+}
+
+impl<C: RecordCodec + ?Sized> RecordCodec for Box<C> {
+    fn id(&self) -> u16 {
+        (**self).id()
+    }
+    fn encode(&self, record: &Record, out: &mut Vec<u8>) {
+        (**self).encode(record, out)
+    }
+    fn decode(&self, input: &mut RecordReader<dyn Read + '_>) -> Result<Record<'static>, Error> {
+        (**self).decode(input)
+    }
+    fn encodes_appends(&self) -> bool {
+        (**self).encodes_appends()
+    }
+}
+
+/// the codec of this crate with the id, see [`RecordCodec::id`]
+pub fn by_id(id: u16) -> Option<Box<dyn RecordCodec>> {
+    match id {
+        RawCodec::ID => Some(Box::new(RawCodec)),
+        TaggedCodec::ID => Some(Box::new(TaggedCodec)),
+        _ => None,
+    }
+}
+
+/// the original layout, commits written back to back with no framing
+///
+/// this codec can only ever store commits, and readers from before empty values could be stored
+/// take the length of an empty value for a commit cut short, and discard the history from it on
+#[derive(Debug, Default, Clone, Copy)]
+pub struct RawCodec;
+
+impl RawCodec {
+    pub const ID: u16 = 1;
+}
+
+impl RecordCodec for RawCodec {
+    fn id(&self) -> u16 {
+        Self::ID
+    }
+    fn encode(&self, record: &Record, out: &mut Vec<u8>) {
+        match record {
+            Record::Commit { time, changes } => encode_commit(*time, changes, out),
+            Record::Append { .. } => panic!("pathkvs: RawCodec does not encode appends"),
+        }
+    }
+    fn decode(&self, input: &mut RecordReader<dyn Read + '_>) -> Result<Record<'static>, Error> {
+        decode_commit(input)
+    }
+}
+
+/// every record is wrapped in an envelope with a type tag and the length of its body
+///
+/// tags with the high bit set are optional, readers that don't know them skip them,
+/// any other unknown tag is an error, since skipping it could change the contents of the database
+///
+/// records with empty values have [`TaggedCodec::EMPTY_VALUES`] added to their tag,
+/// so readers from before they could be stored fail on them instead of misreading them
+#[derive(Debug, Default, Clone, Copy)]
+pub struct TaggedCodec;
+
+impl TaggedCodec {
+    pub const ID: u16 = 2;
+    pub const COMMIT: u8 = 1;
+    pub const APPEND: u8 = 2;
+    pub const EMPTY_VALUES: u8 = 0x40;
+    pub const OPTIONAL: u8 = 0x80;
+}
+
+impl RecordCodec for TaggedCodec {
+    fn id(&self) -> u16 {
+        Self::ID
+    }
+    fn encode(&self, record: &Record, out: &mut Vec<u8>) {
+        let (tag, changes) = match record {
+            Record::Commit { changes, .. } => (Self::COMMIT, changes),
+            Record::Append { changes, .. } => (Self::APPEND, changes),
+        };
+        if changes
+            .values()
+            .any(|value| value.as_ref().is_some_and(Vec::is_empty))
+        {
+            out.push(tag | Self::EMPTY_VALUES);
+        } else {
+            out.push(tag);
+        }
+        let len_at = out.len();
+        out.extend_from_slice(&[0; 4]);
+        match record {
+            Record::Commit { time, changes } => encode_commit(*time, changes, out),
+            Record::Append {
+                time,
+                changes,
+                appends,
+            } => {
+                encode_commit(*time, changes, out);
+                encode_appends(appends, out);
+            }
+        }
+        let len = (out.len() - len_at - 4) as u32;
+        out[len_at..len_at + 4].copy_from_slice(&len.to_le_bytes());
+    }
+    fn encodes_appends(&self) -> bool {
+        true
+    }
+    fn decode(&self, input: &mut RecordReader<dyn Read + '_>) -> Result<Record<'static>, Error> {
+        loop {
+            let mut tag = [0; 1];
+            input.read_exact(&mut tag)?;
+            let len = input.read_u32()?;
+            let (record, rest) = input.limited(len as u64, |body| match tag[0] {
+                tag if tag & Self::OPTIONAL != 0 => {
+                    let skipped = std::io::copy(body, &mut std::io::sink())?;
+                    if skipped != len as u64 {
+                        return Err(ErrorKind::UnexpectedEof.into());
+                    }
+                    Ok(None)
+                }
+                tag if matches!(tag & !Self::EMPTY_VALUES, Self::COMMIT | Self::APPEND) => {
+                    let record = match tag & !Self::EMPTY_VALUES {
+                        Self::COMMIT => decode_commit(body),
+                        _ => (|| {
+                            Ok(Record::Append {
+                                time: decode_time(body)?,
+                                changes: Cow::Owned(decode_changes(body)?),
+                                appends: Cow::Owned(decode_appends(body)?),
+                            })
+                        })(),
+                    };
+                    match record {
+                        // the whole body was there, so it is its contents that are wrong, not a torn tail
+                        Err(error)
+                            if error.kind() == ErrorKind::UnexpectedEof && body.limit == Some(0) =>
+                        {
+                            let error = "record is longer than its body";
+                            Err(crate::Error::Corruption(error.into()).into())
+                        }
+                        record => record.map(Some),
+                    }
+                }
+                tag => Err(crate::Error::Corruption(format!("unknown record tag {tag}")).into()),
+            })?;
+            let Some(record) = record else {
+                continue;
+            };
+            if rest != 0 {
+                return Err(crate::Error::Corruption("record has trailing bytes".into()).into());
+            }
+            return Ok(record);
+        }
+    }
+}
+
+/// how many bytes are read at a time into the buffer of a [`RecordReader`]
+const CHUNK_LEN: usize = 64 * 1024;
+
+/// the input of [`RecordCodec::decode`], reads the byte strings of the records through a buffer
+/// that is reused for every read, instead of allocating and zeroing each of them
+///
+/// the lengths come from the history itself, which may be cut short or corrupted,
+/// so a byte string only takes as much memory as the bytes of it that were actually read
+pub struct RecordReader<R: ?Sized> {
+    /// zeroed once, then only ever overwritten by reads
+    buffer: Vec<u8>,
+    /// how many bytes can still be read, see [`RecordReader::limited`]
+    limit: Option<u64>,
+    /// see [`RecordReader::trusting`]
+    trusted: bool,
+    input: R,
+}
+
+impl<R: Read> RecordReader<R> {
+    pub fn new(input: R) -> Self {
+        Self {
+            buffer: Vec::new(),
+            limit: None,
+            trusted: false,
+            input,
+        }
+    }
+    /// takes the lengths as they are, so each byte string is allocated whole and read straight into it,
+    /// for a history closed cleanly, see [`Header::clean`]
+    pub fn trusting(mut self) -> Self {
+        self.trusted = true;
+        self
+    }
+    pub fn into_inner(self) -> R {
+        self.input
+    }
+}
+
+impl<R: Read + ?Sized> RecordReader<R> {
+    pub fn get_ref(&self) -> &R {
+        &self.input
+    }
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.input
+    }
+    pub fn read_u32(&mut self) -> Result<u32, Error> {
+        let mut bytes = [0; 4];
+        self.read_exact(&mut bytes)?;
+        Ok(u32::from_le_bytes(bytes))
+    }
+    /// reads exactly `len` bytes, an error of kind [`ErrorKind::UnexpectedEof`] if the input ends before
+    pub fn read_vec(&mut self, len: usize) -> Result<Vec<u8>, Error> {
+        if self.trusted {
+            let mut bytes = vec![0; len];
+            self.read_exact(&mut bytes)?;
+            return Ok(bytes);
+        }
+        let mut bytes = Vec::with_capacity(len.min(CHUNK_LEN));
+        while bytes.len() < len {
+            let chunk = (len - bytes.len()).min(CHUNK_LEN);
+            if self.buffer.len() < chunk {
+                self.buffer.resize(chunk, 0);
+            }
+            let mut buffer = std::mem::take(&mut self.buffer);
+            let read = self.read_exact(&mut buffer[..chunk]);
+            bytes.extend_from_slice(&buffer[..chunk]);
+            self.buffer = buffer;
+            read?;
+        }
+        Ok(bytes)
+    }
+    /// reads through `f` with at most `len` more bytes available, and returns its result and how many of them
+    /// it left unread, for records that are framed with their length
+    pub fn limited<T>(
+        &mut self,
+        len: u64,
+        f: impl FnOnce(&mut Self) -> Result<T, Error>,
+    ) -> Result<(T, u64), Error> {
+        let outer = self.limit;
+        let len = outer.map_or(len, |outer| outer.min(len));
+        self.limit = Some(len);
+        let result = f(self);
+        let rest = self.limit.unwrap_or(0);
+        self.limit = outer.map(|outer| outer - (len - rest));
+        Ok((result?, rest))
+    }
+}
+
+impl<R: Read + ?Sized> Read for RecordReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+        let max = match self.limit {
+            Some(limit) => buf.len().min(usize::try_from(limit).unwrap_or(usize::MAX)),
+            None => buf.len(),
+        };
+        let read = self.input.read(&mut buf[..max])?;
+        if let Some(limit) = &mut self.limit {
+            *limit -= read as u64;
+        }
+        Ok(read)
+    }
+}
+
+fn encode_commit(time: Duration, changes: &HashMap<Vec<u8>, Option<Vec<u8>>>, out: &mut Vec<u8>) {
+    out.extend_from_slice(&time.as_secs().to_le_bytes());
+    out.extend_from_slice(&time.subsec_nanos().to_le_bytes());
+    out.extend_from_slice(&(changes.len() as u32).to_le_bytes());
+    for (k, v) in changes {
+        out.extend_from_slice(&(k.len() as u32).to_le_bytes());
+        out.extend_from_slice(k);
+        match v.as_deref() {
+            None => out.extend_from_slice(&0u32.to_le_bytes()),
+            Some([]) => out.extend_from_slice(&EMPTY_VALUE.to_le_bytes()),
+            Some(v) => {
+                out.extend_from_slice(&(v.len() as u32).to_le_bytes());
+                out.extend_from_slice(v);
+            }
+        }
+    }
+}
+
+fn encode_appends(appends: &HashMap<Vec<u8>, Vec<u8>>, out: &mut Vec<u8>) {
+    out.extend_from_slice(&(appends.len() as u32).to_le_bytes());
+    for (k, v) in appends {
+        out.extend_from_slice(&(k.len() as u32).to_le_bytes());
+        out.extend_from_slice(k);
+        out.extend_from_slice(&(v.len() as u32).to_le_bytes());
+        out.extend_from_slice(v);
+    }
+}
+
+fn decode_commit(input: &mut RecordReader<dyn Read + '_>) -> Result<Record<'static>, Error> {
+    Ok(Record::Commit {
+        time: decode_time(input)?,
+        changes: Cow::Owned(decode_changes(input)?),
+    })
+}
+
+fn decode_time(input: &mut RecordReader<dyn Read + '_>) -> Result<Duration, Error> {
+    let mut seconds = [0; 8];
+    input.read_exact(&mut seconds)?;
+    let seconds = u64::from_le_bytes(seconds);
+    let nanoseconds = input.read_u32()?;
+
+    if nanoseconds >= 1_000_000_000 {
+        return Err(Error::new(ErrorKind::UnexpectedEof, "bad nanosecond field"));
+    }
+    Ok(Duration::new(seconds, nanoseconds))
+}
+
+fn decode_changes(
+    input: &mut RecordReader<dyn Read + '_>,
+) -> Result<HashMap<Vec<u8>, Option<Vec<u8>>>, Error> {
+    let kv_len = input.read_u32()?;
+    let mut changes = HashMap::new();
+    for _ in 0..kv_len {
+        let k = read_bytes(input)?;
+        let v = match input.read_u32()? {
+            0 => None,
+            EMPTY_VALUE => Some(Vec::new()),
+            len => Some(input.read_vec(len as usize)?),
+        };
+        changes.insert(k, v);
+    }
+    Ok(changes)
+}
+
+fn decode_appends(
+    input: &mut RecordReader<dyn Read + '_>,
+) -> Result<HashMap<Vec<u8>, Vec<u8>>, Error> {
+    let kv_len = input.read_u32()?;
+    let mut appends = HashMap::new();
+    for _ in 0..kv_len {
+        let k = read_bytes(input)?;
+        let v = read_bytes(input)?;
+        appends.insert(k, v);
+    }
+    Ok(appends)
+}
+
+fn read_bytes(input: &mut RecordReader<dyn Read + '_>) -> Result<Vec<u8>, Error> {
+    let len = input.read_u32()? as usize;
+    input.read_vec(len)
+}
@@ -0,0 +1,158 @@
+//! the content type of values, so readers can show them as what they are instead of guessing from their bytes
+//!
+//! the content type of a key is kept under [`system::TYPES`] followed by the key, as a single byte,
+//! so it is persisted in the history, backed up and replicated along with the value,
+//! it belongs to the key until it is replaced or the key is deleted, writing the key again without one keeps it
+//!
+//! the bytes of the value are never checked against it, it only tells readers how they were meant
+
+use std::{fmt, str::FromStr};
+
+use crate::{error::SnapshotExpired, system, Commit, Database, Error, Snapshot, Transaction};
+
+/// what the bytes of a value are
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ContentType {
+    /// utf-8 text
+    Text,
+    /// a json document, in utf-8
+    Json,
+    /// bytes that are not meant to be read as text
+    Binary,
+    /// a signed integer as decimal text, like the numbers of [`Transaction::incr`]
+    Integer,
+}
+
+impl ContentType {
+    pub const ALL: [ContentType; 4] = [Self::Text, Self::Json, Self::Binary, Self::Integer];
+
+    pub const fn to_u8(self) -> u8 {
+        match self {
+            Self::Text => 1,
+            Self::Json => 2,
+            Self::Binary => 3,
+            Self::Integer => 4,
+        }
+    }
+    pub const fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            1 => Some(Self::Text),
+            2 => Some(Self::Json),
+            3 => Some(Self::Binary),
+            4 => Some(Self::Integer),
+            _ => None,
+        }
+    }
+    /// `text`, `json`, `binary` or `integer`
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::Text => "text",
+            Self::Json => "json",
+            Self::Binary => "binary",
+            Self::Integer => "integer",
+        }
+    }
+    /// the content type stored in `value`, none if it is not one
+    pub fn decode(value: &[u8]) -> Option<Self> {
+        match value {
+            [byte] => Self::from_u8(*byte),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for ContentType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// parses the [`ContentType::name`]
+impl FromStr for ContentType {
+    type Err = UnknownContentType;
+    fn from_str(name: &str) -> Result<Self, UnknownContentType> {
+        Self::ALL
+            .into_iter()
+            .find(|content_type| content_type.name() == name)
+            .ok_or(UnknownContentType)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UnknownContentType;
+
+impl fmt::Display for UnknownContentType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("pathkvs unknown content type, expected text, json, binary or integer")
+    }
+}
+
+impl std::error::Error for UnknownContentType {}
+
+impl Database {
+    /// [`Transaction::write_typed`] in a transaction of its own
+    pub fn write_typed(
+        &self,
+        key: &[u8],
+        value: &[u8],
+        content_type: ContentType,
+    ) -> Result<(), Error> {
+        if key.is_empty() {
+            return Ok(());
+        }
+        self.commit_blind(|ts| ts.write_typed(key, value, content_type))
+    }
+    /// the content type of the key, none if it has none
+    pub fn content_type(&self, key: &[u8]) -> Option<ContentType> {
+        ContentType::decode(&self.read(&system::key(system::TYPES, key)))
+    }
+}
+
+impl Snapshot {
+    /// the content type of the key, none if it has none
+    pub fn content_type(&self, key: &[u8]) -> Result<Option<ContentType>, SnapshotExpired> {
+        Ok(ContentType::decode(
+            self.read(&system::key(system::TYPES, key))?,
+        ))
+    }
+}
+
+impl<'a> Transaction<'a> {
+    /// writes the value along with its content type, the validator is only given the value
+    pub fn write_typed(&mut self, key: &[u8], value: &[u8], content_type: ContentType) {
+        if key.is_empty() {
+            return;
+        }
+        self.write(key, value);
+        self.set_content_type(key, Some(content_type));
+    }
+    /// replaces the content type of the key, none removes it, the value is left as it is
+    pub fn set_content_type(&mut self, key: &[u8], content_type: Option<ContentType>) {
+        if key.is_empty() {
+            return;
+        }
+        let types_key = system::key(system::TYPES, key);
+        self.merges.remove(&types_key);
+        match content_type {
+            Some(content_type) => self.stage(&types_key, Some(&[content_type.to_u8()])),
+            None => self.stage(&types_key, None),
+        }
+    }
+    /// the content type of the key, none if it has none, it counts as read
+    pub fn content_type(&mut self, key: &[u8]) -> Option<ContentType> {
+        ContentType::decode(self.read(&system::key(system::TYPES, key)))
+    }
+    /// removes the content type of a key being deleted, without reading it, so it doesn't cause conflicts
+    pub(crate) fn forget_content_type(&mut self, key: &[u8]) {
+        if system::is_system_key(key) {
+            return;
+        }
+        let types_key = system::key(system::TYPES, key);
+        if self.commit.changes.contains_key(&types_key)
+            || Commit::get(self.commit.prev.head(), &types_key).is_some()
+        {
+            self.merges.remove(&types_key);
+            self.stage(&types_key, None);
+        }
+    }
+}
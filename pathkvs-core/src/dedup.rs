@@ -0,0 +1,62 @@
+//! skips persisting writes of the value a key already had, see [`Database::dedup_writes`](crate::Database::dedup_writes)
+
+use std::{
+    borrow::Cow,
+    collections::HashMap,
+    hash::{BuildHasher, RandomState},
+};
+
+use crate::{system, Changes};
+
+/// a hash of the last value persisted for each key, two independent 64 bit hashes,
+/// so telling two different values apart does not rely on a single 64 bit hash
+#[derive(Default)]
+pub(crate) struct Dedup {
+    hashers: [RandomState; 2],
+    persisted: HashMap<Vec<u8>, u128>,
+}
+
+impl Dedup {
+    fn hash(&self, value: Option<&[u8]>) -> u128 {
+        let [first, second] = &self.hashers;
+        (first.hash_one(value) as u128) << 64 | second.hash_one(value) as u128
+    }
+    /// the changes whose value differs from the last one persisted for the key, `None` if there is none,
+    /// the changes are then expected to be persisted, or [`Dedup::clear`] to be called if that fails
+    pub(crate) fn filter<'a>(&mut self, changes: Cow<'a, Changes>) -> Option<Cow<'a, Changes>> {
+        let mut unchanged = Vec::new();
+        for (key, value) in changes.iter() {
+            // the metadata of a commit is about the commit, not the last value of the key
+            if key.as_slice() == system::META {
+                continue;
+            }
+            let hash = self.hash(value.as_deref());
+            match self.persisted.get_mut(key) {
+                Some(persisted) if *persisted == hash => unchanged.push(key.clone()),
+                Some(persisted) => *persisted = hash,
+                None => {
+                    self.persisted.insert(key.clone(), hash);
+                }
+            }
+        }
+        if unchanged.len() == changes.len() && !changes.is_empty() {
+            return None;
+        }
+        let mut changes = changes;
+        if !unchanged.is_empty() {
+            let changes = changes.to_mut();
+            for key in unchanged {
+                changes.remove(&key);
+            }
+        }
+        Some(changes)
+    }
+    /// the value of the key is not known anymore, like after appending to it
+    pub(crate) fn forget(&mut self, key: &[u8]) {
+        self.persisted.remove(key);
+    }
+    /// forgets every value, after persisting fails the values given to [`Dedup::filter`] may not be in the storage
+    pub(crate) fn clear(&mut self) {
+        self.persisted.clear();
+    }
+}
@@ -1,4 +1,4 @@
-use std::io::Error;
+use std::io::{self, ErrorKind};
 
 #[derive(Clone, Copy)]
 pub struct ProtocolError;
@@ -13,7 +13,7 @@ impl std::fmt::Display for ProtocolError {
     }
 }
 impl std::error::Error for ProtocolError {}
-impl From<ProtocolError> for Error {
+impl From<ProtocolError> for io::Error {
     fn from(value: ProtocolError) -> Self {
         Self::other(value)
     }
@@ -32,7 +32,7 @@ impl std::fmt::Display for TransactionConflict {
     }
 }
 impl std::error::Error for TransactionConflict {}
-impl From<TransactionConflict> for Error {
+impl From<TransactionConflict> for io::Error {
     fn from(value: TransactionConflict) -> Self {
         Self::other(value)
     }
@@ -51,28 +51,307 @@ impl std::fmt::Display for LimitExceeded {
     }
 }
 impl std::error::Error for LimitExceeded {}
-impl From<LimitExceeded> for Error {
+impl From<LimitExceeded> for io::Error {
     fn from(value: LimitExceeded) -> Self {
         Self::other(value)
     }
 }
 
+#[derive(Clone, Copy)]
+pub struct Unauthorized;
+impl std::fmt::Debug for Unauthorized {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        std::fmt::Display::fmt(self, f)
+    }
+}
+impl std::fmt::Display for Unauthorized {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("pahtkvs unauthorized")
+    }
+}
+impl std::error::Error for Unauthorized {}
+impl From<Unauthorized> for io::Error {
+    fn from(value: Unauthorized) -> Self {
+        Self::other(value)
+    }
+}
+
+/// a read from a snapshot older than [`Database::max_snapshot_age`](crate::Database::max_snapshot_age)
+#[derive(Clone, Copy)]
+pub struct SnapshotExpired;
+impl std::fmt::Debug for SnapshotExpired {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        std::fmt::Display::fmt(self, f)
+    }
+}
+impl std::fmt::Display for SnapshotExpired {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("pathkvs snapshot expired")
+    }
+}
+impl std::error::Error for SnapshotExpired {}
+impl From<SnapshotExpired> for io::Error {
+    fn from(value: SnapshotExpired) -> Self {
+        Self::other(value)
+    }
+}
+
+/// a commit that was done but not acknowledged by enough replicas in time,
+/// see [`Database::wait_for_replicas`](crate::Database::wait_for_replicas)
+///
+/// converts to an io error of kind [`ErrorKind::TimedOut`]
+#[derive(Clone, Copy)]
+pub struct NotReplicated;
+impl std::fmt::Debug for NotReplicated {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        std::fmt::Display::fmt(self, f)
+    }
+}
+impl std::fmt::Display for NotReplicated {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("pathkvs commit not acknowledged by enough replicas")
+    }
+}
+impl std::error::Error for NotReplicated {}
+impl From<NotReplicated> for io::Error {
+    fn from(value: NotReplicated) -> Self {
+        Self::new(ErrorKind::TimedOut, value)
+    }
+}
+
+/// an increment of a value that is not a number, or that overflows, see [`Database::incr`](crate::Database::incr)
+///
+/// converts to an io error of kind [`ErrorKind::InvalidData`]
+#[derive(Clone, Copy)]
+pub struct NotANumber;
+impl std::fmt::Debug for NotANumber {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        std::fmt::Display::fmt(self, f)
+    }
+}
+impl std::fmt::Display for NotANumber {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("pathkvs value is not a number, or the sum overflows")
+    }
+}
+impl std::error::Error for NotANumber {}
+impl From<NotANumber> for io::Error {
+    fn from(value: NotANumber) -> Self {
+        Self::new(ErrorKind::InvalidData, value)
+    }
+}
+
+/// a write refused by the validator of the database, see [`Database::validator`](crate::Database::validator)
+///
+/// commits that staged a refused write fail with an io error of kind [`ErrorKind::InvalidInput`] carrying it
+#[derive(Clone)]
+pub struct ValidationError {
+    reason: String,
+}
+impl ValidationError {
+    pub fn new(reason: impl Into<String>) -> Self {
+        Self {
+            reason: reason.into(),
+        }
+    }
+    pub fn reason(&self) -> &str {
+        &self.reason
+    }
+}
+impl std::fmt::Debug for ValidationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        std::fmt::Display::fmt(self, f)
+    }
+}
+impl std::fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "pathkvs invalid write: {}", self.reason)
+    }
+}
+impl std::error::Error for ValidationError {}
+impl From<ValidationError> for io::Error {
+    fn from(value: ValidationError) -> Self {
+        Self::new(ErrorKind::InvalidInput, value)
+    }
+}
+
+/// the keys that make a transaction conflict, see [`Transaction::validate`](crate::Transaction::validate)
+///
+/// these are the keys it read, or that are in a range it counted, listed or scanned,
+/// that were changed by commits since it started, in order
+#[derive(Clone)]
+pub struct ConflictReport {
+    keys: Vec<Vec<u8>>,
+}
+impl ConflictReport {
+    pub(crate) fn new(keys: Vec<Vec<u8>>) -> Self {
+        Self { keys }
+    }
+    pub fn keys(&self) -> &[Vec<u8>] {
+        &self.keys
+    }
+}
+impl std::fmt::Debug for ConflictReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        std::fmt::Display::fmt(self, f)
+    }
+}
+impl std::fmt::Display for ConflictReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "pathkvs transaction conflict on {} key(s)",
+            self.keys.len()
+        )
+    }
+}
+impl std::error::Error for ConflictReport {}
+impl From<ConflictReport> for Error {
+    fn from(_: ConflictReport) -> Self {
+        Error::Conflict
+    }
+}
+
+/// the cause of a failed database operation
+///
+/// converts to and from [`std::io::Error`], an io error carrying one of the marker errors of this module
+/// is converted back to the matching variant, so nothing is lost by passing through io code
 #[derive(Debug)]
-pub enum TransactionError {
+pub enum Error {
+    /// the stored history could not be decoded
+    Corruption(String),
     Conflict,
-    Io(Error),
+    LimitExceeded,
+    Unauthorized,
+    Protocol,
+    SnapshotExpired,
+    NotReplicated,
+    NotANumber,
+    Io(io::Error),
 }
 
-impl From<Error> for TransactionError {
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Corruption(description) => write!(f, "pathkvs database corrupted: {description}"),
+            Error::Conflict => std::fmt::Display::fmt(&TransactionConflict, f),
+            Error::LimitExceeded => std::fmt::Display::fmt(&LimitExceeded, f),
+            Error::Unauthorized => std::fmt::Display::fmt(&Unauthorized, f),
+            Error::Protocol => std::fmt::Display::fmt(&ProtocolError, f),
+            Error::SnapshotExpired => std::fmt::Display::fmt(&SnapshotExpired, f),
+            Error::NotReplicated => std::fmt::Display::fmt(&NotReplicated, f),
+            Error::NotANumber => std::fmt::Display::fmt(&NotANumber, f),
+            Error::Io(error) => std::fmt::Display::fmt(error, f),
+        }
+    }
+}
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+impl From<Error> for io::Error {
     fn from(value: Error) -> Self {
+        match value {
+            Error::Io(error) => error,
+            Error::Corruption(_) => io::Error::new(ErrorKind::InvalidData, value),
+            Error::NotReplicated => io::Error::new(ErrorKind::TimedOut, value),
+            Error::NotANumber => io::Error::new(ErrorKind::InvalidData, value),
+            value => io::Error::other(value),
+        }
+    }
+}
+impl From<io::Error> for Error {
+    fn from(value: io::Error) -> Self {
+        let Some(inner) = value.get_ref() else {
+            return Error::Io(value);
+        };
+        if inner.is::<Error>() {
+            let inner = value.into_inner().expect("checked by get_ref");
+            return *inner.downcast::<Error>().expect("checked by is");
+        }
+        if inner.is::<TransactionConflict>() {
+            Error::Conflict
+        } else if inner.is::<LimitExceeded>() {
+            Error::LimitExceeded
+        } else if inner.is::<Unauthorized>() {
+            Error::Unauthorized
+        } else if inner.is::<ProtocolError>() {
+            Error::Protocol
+        } else if inner.is::<SnapshotExpired>() {
+            Error::SnapshotExpired
+        } else if inner.is::<NotReplicated>() {
+            Error::NotReplicated
+        } else if inner.is::<NotANumber>() {
+            Error::NotANumber
+        } else {
+            Error::Io(value)
+        }
+    }
+}
+impl From<ProtocolError> for Error {
+    fn from(_: ProtocolError) -> Self {
+        Self::Protocol
+    }
+}
+impl From<TransactionConflict> for Error {
+    fn from(_: TransactionConflict) -> Self {
+        Self::Conflict
+    }
+}
+impl From<LimitExceeded> for Error {
+    fn from(_: LimitExceeded) -> Self {
+        Self::LimitExceeded
+    }
+}
+impl From<Unauthorized> for Error {
+    fn from(_: Unauthorized) -> Self {
+        Self::Unauthorized
+    }
+}
+impl From<SnapshotExpired> for Error {
+    fn from(_: SnapshotExpired) -> Self {
+        Self::SnapshotExpired
+    }
+}
+impl From<NotReplicated> for Error {
+    fn from(_: NotReplicated) -> Self {
+        Self::NotReplicated
+    }
+}
+impl From<NotANumber> for Error {
+    fn from(_: NotANumber) -> Self {
+        Self::NotANumber
+    }
+}
+impl From<TransactionError> for Error {
+    fn from(value: TransactionError) -> Self {
+        match value {
+            TransactionError::Conflict => Self::Conflict,
+            TransactionError::Io(error) => error.into(),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum TransactionError {
+    Conflict,
+    Io(io::Error),
+}
+
+impl From<io::Error> for TransactionError {
+    fn from(value: io::Error) -> Self {
         Self::Io(value)
     }
 }
 
-impl From<TransactionError> for Error {
+impl From<TransactionError> for io::Error {
     fn from(value: TransactionError) -> Self {
         match value {
-            TransactionError::Conflict => Error::other(TransactionConflict),
+            TransactionError::Conflict => io::Error::other(TransactionConflict),
             TransactionError::Io(error) => error,
         }
     }
@@ -80,11 +359,11 @@ impl From<TransactionError> for Error {
 
 pub trait TransposeConflict {
     type Output;
-    fn transpose_conflict(self) -> Result<Result<Self::Output, TransactionConflict>, Error>;
+    fn transpose_conflict(self) -> Result<Result<Self::Output, TransactionConflict>, io::Error>;
 }
 impl<T> TransposeConflict for Result<T, TransactionError> {
     type Output = T;
-    fn transpose_conflict(self) -> Result<Result<Self::Output, TransactionConflict>, Error> {
+    fn transpose_conflict(self) -> Result<Result<Self::Output, TransactionConflict>, io::Error> {
         match self {
             Ok(x) => Ok(Ok(x)),
             Err(TransactionError::Conflict) => Ok(Err(TransactionConflict)),
@@ -0,0 +1,180 @@
+//! keys that expire at a given time, see [`Transaction::expire_at`] and [`Database::spawn_expiry_sweeper`]
+//!
+//! the expiry of a key is kept under [`system::TTL`] followed by the key, as the seconds (8 bytes)
+//! and nanoseconds (4 bytes) since the unix epoch, little endian, so it is persisted, backed up
+//! and replicated along with the key
+//!
+//! an expired key is still read like any other until it is swept, by [`Database::sweep_expired`]
+//! or by a sweeper running in the background, which remove it with [`Transaction::expire`],
+//! so subscribers of the changes are told it expired, the sweep also expires the keys left unwritten
+//! for longer than the ttl of their [`crate::retention`]
+
+use std::time::Duration;
+#[cfg(not(target_arch = "wasm32"))]
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        mpsc::{self, RecvTimeoutError, Sender},
+        Arc,
+    },
+    thread::JoinHandle,
+};
+
+use crate::{error::TransactionError, system, Database, Error, Transaction};
+
+/// the most keys a single commit of a sweep expires, so a sweep of many keys is made of many small commits
+const SWEEP_BATCH: usize = 1024;
+
+/// a sweeper started by [`Database::spawn_expiry_sweeper`], it stops when dropped
+#[cfg(not(target_arch = "wasm32"))]
+pub struct ExpirySweeper {
+    stop: Option<Sender<()>>,
+    thread: Option<JoinHandle<()>>,
+    swept: Arc<AtomicU64>,
+}
+
+impl Database {
+    /// [`Transaction::expire_at`] in a transaction of its own
+    pub fn expire_at(&self, key: &[u8], at: Duration) -> Result<(), Error> {
+        if key.is_empty() {
+            return Ok(());
+        }
+        self.commit_blind(|ts| ts.expire_at(key, at))
+    }
+    /// the unix time the key expires at, none if it doesn't expire
+    pub fn expiry(&self, key: &[u8]) -> Option<Duration> {
+        decode(&self.read(&system::key(system::TTL, key)))
+    }
+    /// expires every key whose expiry has passed, and forgets their expiries, then the keys not written
+    /// for longer than the ttl of their [`Retention`](crate::retention::Retention), returns how many keys were expired
+    ///
+    /// keys whose expiry is extended or cleared while it runs are left alone,
+    /// if the key was already deleted only its expiry is removed
+    pub fn sweep_expired(&self) -> Result<u64, Error> {
+        let now = self.now();
+        let due = self
+            .scan(system::TTL, b"")
+            .into_iter()
+            .filter(|(_, value)| decode(value).is_some_and(|at| at <= now))
+            .map(|(ttl_key, _)| ttl_key)
+            .collect::<Vec<_>>();
+        let mut swept = 0;
+        for batch in due.chunks(SWEEP_BATCH) {
+            loop {
+                let mut ts = self.start_writes();
+                let mut expired = 0;
+                for ttl_key in batch {
+                    if decode(ts.read(ttl_key)).is_none_or(|at| at > now) {
+                        continue;
+                    }
+                    let key = &ttl_key[system::TTL.len()..];
+                    if ts.exists(key) {
+                        ts.expire(key);
+                        expired += 1;
+                    }
+                    ts.stage(ttl_key, None);
+                }
+                match ts.commit() {
+                    Ok(_) => {
+                        swept += expired;
+                        break;
+                    }
+                    Err(TransactionError::Conflict) => continue,
+                    Err(TransactionError::Io(error)) => return Err(error.into()),
+                }
+            }
+        }
+        Ok(swept + self.sweep_retained()?)
+    }
+    /// sweeps the expired keys every `interval`, in a thread of its own, see [`Database::sweep_expired`]
+    ///
+    /// the sweeper only holds a weak reference, so it stops once the database is dropped,
+    /// a sweep that fails is tried again at the next interval
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn spawn_expiry_sweeper(self: &Arc<Self>, interval: Duration) -> ExpirySweeper {
+        let database = Arc::downgrade(self);
+        let (stop, stopped) = mpsc::channel::<()>();
+        let swept = Arc::new(AtomicU64::new(0));
+        let counter = swept.clone();
+        let thread = std::thread::spawn(move || {
+            while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(interval) {
+                let Some(database) = database.upgrade() else {
+                    return;
+                };
+                if let Ok(count) = database.sweep_expired() {
+                    counter.fetch_add(count, Ordering::Relaxed);
+                }
+            }
+        });
+        ExpirySweeper {
+            stop: Some(stop),
+            thread: Some(thread),
+            swept,
+        }
+    }
+}
+
+impl<'a> Transaction<'a> {
+    /// makes the key expire at the unix time `at`, replacing its expiry, the key is then removed
+    /// by the next sweep after that, see [`Database::sweep_expired`]
+    ///
+    /// the expiry belongs to the key and not to its value, writing the key again keeps it,
+    /// the expiry is not given to the validator
+    pub fn expire_at(&mut self, key: &[u8], at: Duration) {
+        if key.is_empty() {
+            return;
+        }
+        let ttl_key = system::key(system::TTL, key);
+        self.merges.remove(&ttl_key);
+        self.stage(&ttl_key, Some(&encode(at)));
+    }
+    /// the key no longer expires
+    pub fn clear_expiry(&mut self, key: &[u8]) {
+        if key.is_empty() {
+            return;
+        }
+        let ttl_key = system::key(system::TTL, key);
+        self.merges.remove(&ttl_key);
+        self.stage(&ttl_key, None);
+    }
+    /// the unix time the key expires at, none if it doesn't expire, the expiry counts as read
+    pub fn expiry(&mut self, key: &[u8]) -> Option<Duration> {
+        decode(self.read(&system::key(system::TTL, key)))
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl ExpirySweeper {
+    /// how many keys it expired so far
+    pub fn swept(&self) -> u64 {
+        self.swept.load(Ordering::Relaxed)
+    }
+    /// same as dropping it, waits for a sweep in progress to finish
+    pub fn stop(self) {}
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl Drop for ExpirySweeper {
+    fn drop(&mut self) {
+        self.stop.take();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// the seconds and nanoseconds of the duration, little endian
+pub(crate) fn encode(duration: Duration) -> [u8; 12] {
+    let mut value = [0; 12];
+    value[..8].copy_from_slice(&duration.as_secs().to_le_bytes());
+    value[8..].copy_from_slice(&duration.subsec_nanos().to_le_bytes());
+    value
+}
+
+/// the unix time of an expiry, none if it is missing or invalid
+pub(crate) fn decode(value: &[u8]) -> Option<Duration> {
+    let value: &[u8; 12] = value.try_into().ok()?;
+    let seconds = u64::from_le_bytes(value[..8].try_into().unwrap());
+    let nanoseconds = u32::from_le_bytes(value[8..].try_into().unwrap());
+    (nanoseconds < 1_000_000_000).then(|| Duration::new(seconds, nanoseconds))
+}
@@ -0,0 +1,318 @@
+//! fault injection in the persistence layer, for testing crash recovery and what happens when persisting fails
+//!
+//! wrap the storage of a database in a [`FaultyStorage`] and plan faults on its [`Faults`],
+//! which can be shared with the test and changed while the database is open:
+//!
+//! - [`Fault::Error`] fails an operation of the storage without doing it
+//! - [`Fault::ShortWrite`] appends only part of the bytes and then fails, as if the process stopped in the middle
+//! - [`Fault::Delay`] does the operation after a delay, like a slow fsync
+//!
+//! each fault happens at a given call to an operation, counting from the moment it was planned,
+//! so the same plan always fails the same commits
+
+use std::{
+    collections::HashMap,
+    io::{Error, ErrorKind, Read},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use crate::{storage::StorageBackend, DatabaseWriteSyncMode};
+
+/// an operation of [`StorageBackend`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Point {
+    Reader,
+    Truncate,
+    Append,
+    Sync,
+    Replace,
+    RewriteHeader,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fault {
+    /// the operation fails with an error of this kind, without doing anything
+    Error(ErrorKind),
+    /// only this many of the bytes are appended, then it fails with [`ErrorKind::WriteZero`],
+    /// at points other than [`Point::Append`] it fails the same way without doing anything
+    ShortWrite(usize),
+    /// the operation is done after sleeping for this long
+    Delay(Duration),
+}
+
+/// the faults planned for a [`FaultyStorage`] and how many times each operation was called
+#[derive(Debug, Default)]
+pub struct Faults {
+    state: Mutex<State>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    calls: HashMap<Point, u64>,
+    /// the call each fault happens at and the fault
+    planned: HashMap<Point, Vec<(u64, Fault)>>,
+}
+
+impl Faults {
+    pub fn new() -> Arc<Self> {
+        Arc::default()
+    }
+    /// the next call to the operation at `point` has the fault
+    pub fn inject(&self, point: Point, fault: Fault) {
+        self.inject_after(point, 0, fault);
+    }
+    /// the call to the operation at `point` after the next `calls` calls has the fault,
+    /// more than one fault can be planned for the same call, they happen in the order they were planned
+    pub fn inject_after(&self, point: Point, calls: u64, fault: Fault) {
+        let mut state = self.state.lock().unwrap();
+        let at = state.calls.get(&point).copied().unwrap_or(0) + calls;
+        state.planned.entry(point).or_default().push((at, fault));
+    }
+    /// forgets every fault that hasn't happened yet
+    pub fn clear(&self) {
+        self.state.lock().unwrap().planned.clear();
+    }
+    /// how many times the operation at `point` was called, including calls that failed
+    pub fn calls(&self, point: Point) -> u64 {
+        self.state
+            .lock()
+            .unwrap()
+            .calls
+            .get(&point)
+            .copied()
+            .unwrap_or(0)
+    }
+    /// how many faults haven't happened yet
+    pub fn pending(&self) -> usize {
+        self.state
+            .lock()
+            .unwrap()
+            .planned
+            .values()
+            .map(Vec::len)
+            .sum()
+    }
+    /// counts the call and takes the faults planned for it
+    fn call(&self, point: Point) -> Vec<Fault> {
+        let mut state = self.state.lock().unwrap();
+        let calls = state.calls.entry(point).or_insert(0);
+        let call = *calls;
+        *calls += 1;
+        let Some(planned) = state.planned.get_mut(&point) else {
+            return Vec::new();
+        };
+        let mut faults = Vec::new();
+        planned.retain(|&(at, fault)| {
+            if at == call {
+                faults.push(fault);
+            }
+            at != call
+        });
+        faults
+    }
+}
+
+/// a [`StorageBackend`] that does what its [`Faults`] plan before passing each operation to the inner storage
+pub struct FaultyStorage<S> {
+    inner: S,
+    faults: Arc<Faults>,
+}
+
+impl<S: StorageBackend> FaultyStorage<S> {
+    pub fn new(inner: S, faults: Arc<Faults>) -> Self {
+        Self { inner, faults }
+    }
+    pub fn faults(&self) -> &Arc<Faults> {
+        &self.faults
+    }
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+    /// sleeps for the delays, returns the error of the first fault that fails the operation,
+    /// along with how many bytes a short write keeps
+    fn before(&self, point: Point) -> Option<(Error, Option<usize>)> {
+        for fault in self.faults.call(point) {
+            match fault {
+                Fault::Error(kind) => return Some((Error::new(kind, "injected fault"), None)),
+                Fault::ShortWrite(len) => {
+                    return Some((
+                        Error::new(ErrorKind::WriteZero, "injected short write"),
+                        Some(len),
+                    ))
+                }
+                Fault::Delay(delay) => std::thread::sleep(delay),
+            }
+        }
+        None
+    }
+}
+
+impl<S: StorageBackend> StorageBackend for FaultyStorage<S> {
+    fn reader(&mut self) -> Result<Box<dyn Read + '_>, Error> {
+        match self.before(Point::Reader) {
+            Some((error, _)) => Err(error),
+            None => self.inner.reader(),
+        }
+    }
+    fn truncate(&mut self, len: u64) -> Result<(), Error> {
+        match self.before(Point::Truncate) {
+            Some((error, _)) => Err(error),
+            None => self.inner.truncate(len),
+        }
+    }
+    fn append(&mut self, bytes: &[u8]) -> Result<(), Error> {
+        match self.before(Point::Append) {
+            Some((error, Some(len))) => {
+                self.inner.append(&bytes[..len.min(bytes.len())])?;
+                Err(error)
+            }
+            Some((error, None)) => Err(error),
+            None => self.inner.append(bytes),
+        }
+    }
+    fn sync(&mut self, mode: DatabaseWriteSyncMode) -> Result<(), Error> {
+        match self.before(Point::Sync) {
+            Some((error, _)) => Err(error),
+            None => self.inner.sync(mode),
+        }
+    }
+    fn replace(&mut self, history: &[u8]) -> Result<(), Error> {
+        match self.before(Point::Replace) {
+            Some((error, _)) => Err(error),
+            None => self.inner.replace(history),
+        }
+    }
+    fn rewrite_header(&mut self, header: &[u8]) -> Result<(), Error> {
+        match self.before(Point::RewriteHeader) {
+            Some((error, _)) => Err(error),
+            None => self.inner.rewrite_header(header),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Database;
+
+    /// a storage kept in memory and shared, so the history can be opened again after the database is dropped
+    #[derive(Clone, Default)]
+    struct MemoryStorage(Arc<Mutex<Vec<u8>>>);
+
+    impl StorageBackend for MemoryStorage {
+        fn reader(&mut self) -> Result<Box<dyn Read + '_>, Error> {
+            Ok(Box::new(std::io::Cursor::new(self.0.lock().unwrap().clone())))
+        }
+        fn truncate(&mut self, len: u64) -> Result<(), Error> {
+            self.0.lock().unwrap().truncate(len as usize);
+            Ok(())
+        }
+        fn append(&mut self, bytes: &[u8]) -> Result<(), Error> {
+            self.0.lock().unwrap().extend_from_slice(bytes);
+            Ok(())
+        }
+        fn sync(&mut self, _mode: DatabaseWriteSyncMode) -> Result<(), Error> {
+            Ok(())
+        }
+        fn replace(&mut self, history: &[u8]) -> Result<(), Error> {
+            *self.0.lock().unwrap() = history.to_vec();
+            Ok(())
+        }
+        fn rewrite_header(&mut self, header: &[u8]) -> Result<(), Error> {
+            self.0.lock().unwrap()[..header.len()].copy_from_slice(header);
+            Ok(())
+        }
+    }
+
+    fn open(storage: &MemoryStorage) -> (Database, Arc<Faults>) {
+        let faults = Faults::new();
+        let storage = FaultyStorage::new(storage.clone(), faults.clone());
+        (Database::open_storage(storage).unwrap(), faults)
+    }
+
+    #[test]
+    fn short_write_recovers_persisted_prefix() {
+        let storage = MemoryStorage::default();
+        let (db, faults) = open(&storage);
+        db.write(b"a", b"1").unwrap();
+        db.write(b"b", b"2").unwrap();
+        let len = storage.0.lock().unwrap().len();
+        faults.inject(Point::Append, Fault::ShortWrite(3));
+        assert!(db.write(b"c", b"3").is_err());
+        assert_eq!(storage.0.lock().unwrap().len(), len + 3);
+        drop(db);
+
+        let (db, _) = open(&storage);
+        assert_eq!(db.read(b"a"), b"1");
+        assert_eq!(db.read(b"b"), b"2");
+        assert!(!db.exists(b"c"));
+        assert_eq!(storage.0.lock().unwrap().len(), len);
+    }
+
+    #[test]
+    fn failed_append_is_retried_by_the_next_commit() {
+        let storage = MemoryStorage::default();
+        let (db, faults) = open(&storage);
+        db.write(b"a", b"1").unwrap();
+        faults.inject(Point::Append, Fault::ShortWrite(2));
+        assert!(db.write(b"b", b"2").is_err());
+        db.write(b"c", b"3").unwrap();
+        assert_eq!(faults.pending(), 0);
+        drop(db);
+
+        let (db, _) = open(&storage);
+        assert_eq!(db.read(b"a"), b"1");
+        assert_eq!(db.read(b"b"), b"2");
+        assert_eq!(db.read(b"c"), b"3");
+    }
+
+    #[test]
+    fn io_errors_leave_the_history_readable() {
+        let storage = MemoryStorage::default();
+        let (db, faults) = open(&storage);
+        db.write(b"a", b"1").unwrap();
+        faults.inject(Point::Append, Fault::Error(ErrorKind::Other));
+        assert!(db.write(b"b", b"2").is_err());
+        faults.inject(Point::Truncate, Fault::Error(ErrorKind::Other));
+        assert!(db.write(b"c", b"3").is_err());
+        drop(db);
+
+        let (db, _) = open(&storage);
+        assert_eq!(db.read(b"a"), b"1");
+        assert!(!db.exists(b"b"));
+        assert!(!db.exists(b"c"));
+
+        let faults = Faults::new();
+        faults.inject(Point::Reader, Fault::Error(ErrorKind::Other));
+        assert!(Database::open_storage(FaultyStorage::new(storage.clone(), faults)).is_err());
+    }
+
+    #[test]
+    fn close_marks_the_history_until_the_next_append() {
+        let clean = |storage: &MemoryStorage| {
+            crate::codec::read_header(&mut storage.0.lock().unwrap().as_slice())
+                .unwrap()
+                .clean()
+        };
+        let storage = MemoryStorage::default();
+        let (db, faults) = open(&storage);
+        db.write(b"a", b"1").unwrap();
+        db.close().unwrap();
+        assert!(clean(&storage));
+        assert_eq!(faults.calls(Point::RewriteHeader), 1);
+
+        let (db, faults) = open(&storage);
+        assert_eq!(db.read(b"a"), b"1");
+        assert_eq!(faults.calls(Point::Truncate), 0);
+        faults.inject(Point::Append, Fault::ShortWrite(3));
+        assert!(db.write(b"b", b"2").is_err());
+        assert!(!clean(&storage));
+        drop(db);
+
+        // not closed cleanly, so the torn commit is discarded as usual
+        let (db, _) = open(&storage);
+        assert_eq!(db.read(b"a"), b"1");
+        assert!(!db.exists(b"b"));
+    }
+}
@@ -0,0 +1,117 @@
+//! read only replicas of a database file written by another process, see [`Database::open_follower`]
+
+use std::{
+    fs::File,
+    io::{BufReader, ErrorKind, Seek, SeekFrom},
+    path::Path,
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime},
+};
+
+use crate::{
+    codec::{self, Header, RawCodec, RecordCodec, RecordReader},
+    Chain, Commit, CountingReader, Database, Error, Snapshot,
+};
+
+/// a database that only ever gets the commits another process writes to its file
+///
+/// the file is never written, new commits are read by [`Follower::poll`], which can run in
+/// a thread of its own while other threads read from snapshots
+pub struct Follower {
+    database: Database,
+    tail: Mutex<Tail>,
+}
+
+struct Tail {
+    file: File,
+    /// the end of the last complete record
+    cursor: u64,
+}
+
+impl Database {
+    /// replays the history of the file at `path`, without ever writing to it, so it can be open by
+    /// the process that writes it at the same time, see [`Follower::poll`] to read its new commits
+    ///
+    /// the file must be written with [`RawCodec`], the codec of [`Database::open`], in any format
+    /// up to [`codec::FORMAT_VERSION`]
+    pub fn open_follower(path: impl AsRef<Path>) -> Result<Follower, Error> {
+        let follower = Follower {
+            database: Database::memory(),
+            tail: Mutex::new(Tail {
+                file: File::open(path)?,
+                cursor: 0,
+            }),
+        };
+        follower.poll()?;
+        Ok(follower)
+    }
+}
+
+impl Follower {
+    /// reads the commits appended to the file since the last call, and returns how many there were
+    ///
+    /// a commit still being written is left for the next call, it is up to the caller to call this
+    /// periodically, checking the length of the file is cheap when nothing changed
+    pub fn poll(&self) -> Result<u64, Error> {
+        let mut tail = self.tail.lock().unwrap();
+        let len = tail.file.metadata()?.len();
+        if len < tail.cursor {
+            return Err(Error::Corruption(
+                "the followed file is shorter than the history already read".to_string(),
+            ));
+        }
+        if len == tail.cursor {
+            return Ok(0);
+        }
+        let mut cursor = tail.cursor;
+        if cursor == 0 {
+            tail.file.seek(SeekFrom::Start(0))?;
+            match codec::read_header(&mut tail.file)? {
+                // the writer is still creating the file
+                Header::Missing => return Ok(0),
+                Header::Legacy(_) => {}
+                Header::Version { codec, .. } if codec != 0 && codec != RawCodec::ID => {
+                    return Err(Error::Corruption(format!(
+                        "the followed file is written with codec {codec}, not the raw codec"
+                    )));
+                }
+                Header::Version { .. } => cursor = codec::HEADER_LEN,
+            }
+            tail.cursor = cursor;
+        }
+        tail.file.seek(SeekFrom::Start(cursor))?;
+        let mut reader = RecordReader::new(CountingReader {
+            inner: BufReader::new(&tail.file),
+            count: cursor,
+        });
+        let mut master = self.database.resolved_master.load();
+        let mut read = 0;
+        let mut new_cursor = cursor;
+        let error = loop {
+            match RawCodec.decode(&mut reader) {
+                Ok(record) => {
+                    master = Chain(Some(Arc::new(Commit::from_record(master, record))));
+                    // only polling changes the master, under the lock, so it can simply be replaced
+                    self.database.resolved_master.store(master.clone());
+                    new_cursor = reader.get_ref().count;
+                    read += 1;
+                }
+                Err(error) => break error,
+            }
+        };
+        tail.cursor = new_cursor;
+        if error.kind() != ErrorKind::UnexpectedEof {
+            return Err(error.into());
+        }
+        Ok(read)
+    }
+    pub fn snapshot(&self) -> Snapshot {
+        self.database.snapshot()
+    }
+    pub fn past_unix_time_snapshot_with(&self, time: Duration) -> Snapshot {
+        self.database.past_unix_time_snapshot_with(time)
+    }
+    pub fn past_sys_time_snapshot(&self, time: SystemTime) -> Snapshot {
+        self.database.past_sys_time_snapshot(time)
+    }
+}
@@ -0,0 +1,137 @@
+//! callbacks run by every commit that changes keys under a prefix, see [`Database::on_commit`](crate::Database::on_commit)
+//! and [`Database::on_change`](crate::Database::on_change)
+
+use std::{
+    collections::HashSet,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use crate::{system, Changes};
+
+/// when a commit hook is called
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CommitStage {
+    /// as soon as the commit is the new master, other transactions can already see it,
+    /// but it may not be durable yet, and if persisting it fails the commit returns an error anyway
+    Resolved,
+    /// after the commit was persisted as the sync mode requires, right before the commit returns,
+    /// it is never called if persisting fails, same as `Resolved` for memory databases
+    #[default]
+    Persisted,
+}
+
+/// identifies a hook, so it can be removed with [`Database::remove_commit_hook`](crate::Database::remove_commit_hook)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct CommitHookId(u64);
+
+/// how a key was changed by a commit, see [`Database::on_change`](crate::Database::on_change)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Change<'a> {
+    /// the new value of the key, which may be empty
+    Written(&'a [u8]),
+    Deleted,
+    /// deleted by [`Transaction::expire`](crate::Transaction::expire), because its time to live ran out
+    Expired,
+}
+
+type ValuesCallback = dyn Fn(Duration, &[(&[u8], &[u8])]) + Send + Sync;
+
+type ChangesCallback = dyn Fn(Duration, &[(&[u8], Change)]) + Send + Sync;
+
+#[derive(Clone)]
+pub(crate) enum Callback {
+    /// deleted and expired keys have empty values
+    Values(Arc<ValuesCallback>),
+    Changes(Arc<ChangesCallback>),
+}
+
+struct Hook {
+    id: CommitHookId,
+    prefix: Vec<u8>,
+    stage: CommitStage,
+    callback: Callback,
+}
+
+#[derive(Default)]
+pub(crate) struct Hooks {
+    next_id: AtomicU64,
+    hooks: Mutex<Vec<Hook>>,
+}
+
+impl Hooks {
+    pub(crate) fn add(
+        &self,
+        prefix: &[u8],
+        stage: CommitStage,
+        callback: Callback,
+    ) -> CommitHookId {
+        let id = CommitHookId(self.next_id.fetch_add(1, Ordering::Relaxed));
+        self.hooks.lock().unwrap().push(Hook {
+            id,
+            prefix: prefix.to_vec(),
+            stage,
+            callback,
+        });
+        id
+    }
+    pub(crate) fn remove(&self, id: CommitHookId) -> bool {
+        let mut hooks = self.hooks.lock().unwrap();
+        let len = hooks.len();
+        hooks.retain(|hook| hook.id != id);
+        hooks.len() != len
+    }
+    /// calls the hooks of the stage whose prefix matches any of the changes, with the matching changes sorted by key,
+    /// `expired` are the deletions that were expiries
+    pub(crate) fn run(
+        &self,
+        stage: CommitStage,
+        time: Duration,
+        changes: &Changes,
+        expired: &HashSet<Vec<u8>>,
+    ) {
+        // collected first so the lock is not held by the callbacks, which may commit or register hooks themselves
+        let hooks = self
+            .hooks
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|hook| hook.stage == stage)
+            .map(|hook| (hook.prefix.clone(), hook.callback.clone()))
+            .collect::<Vec<_>>();
+        for (prefix, callback) in hooks {
+            let mut matching = changes
+                .iter()
+                .filter(|(key, _)| key.starts_with(&prefix) && system::visible(key, &prefix))
+                .map(|(key, value)| (key.as_slice(), value.as_deref()))
+                .collect::<Vec<_>>();
+            if matching.is_empty() {
+                continue;
+            }
+            matching.sort_unstable();
+            match callback {
+                Callback::Values(callback) => {
+                    let values = matching
+                        .into_iter()
+                        .map(|(key, value)| (key, value.unwrap_or(&[])))
+                        .collect::<Vec<_>>();
+                    callback(time, &values);
+                }
+                Callback::Changes(callback) => {
+                    let changes = matching
+                        .into_iter()
+                        .map(|(key, value)| match value {
+                            Some(value) => (key, Change::Written(value)),
+                            None if expired.contains(key) => (key, Change::Expired),
+                            None => (key, Change::Deleted),
+                        })
+                        .collect::<Vec<_>>();
+                    callback(time, &changes);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,183 @@
+//! encodings of numbers, times and tuples as keys that sort in the same order as the values,
+//! so a [`scan_range`](crate::Database::scan_range) over the keys is a range of the values
+//!
+//! numbers written as text don't sort as numbers, `"10"` comes before `"9"`, and numbers in little endian
+//! don't either, the encodings here are big endian, with the sign bit of signed numbers flipped,
+//! so that negative numbers come before positive ones:
+//!
+//! - `u64` and `i64` are 8 bytes
+//! - a [`Duration`], the unix time of a timestamp, is 12 bytes, the seconds and then the nanoseconds
+//! - bytes and strings are their bytes with every zero byte followed by `0xff`, ended by two zero bytes,
+//!   so a shorter one still comes before every longer one that starts with it
+//! - a tuple is its values one after the other, ordered by the first value, then by the second and so on
+//!
+//! every encoding knows where it ends, so the encoding of the first values of a tuple is a prefix
+//! of the keys of every tuple that starts with them, and `scan(&encode(&(user,)), b"")` finds the keys
+//! of `(user, time)` for every time of the user
+//!
+//! the keys are binary, a key of a tuple can contain [`keys::SEPARATOR`](crate::keys::SEPARATOR),
+//! so the tuple is best kept at the end of a path, after [`prefixed`]
+
+use std::time::Duration;
+
+/// a value that can be encoded as a key, or as part of one, see the [module](self) for the order of each
+pub trait KeyEncode {
+    /// appends the encoding of the value
+    fn encode_key(&self, out: &mut Vec<u8>);
+}
+
+/// a value that can be decoded from the key [`KeyEncode`] encoded it as
+pub trait KeyDecode: Sized {
+    /// the value at the start of `input`, which is advanced past it, none if it isn't a valid encoding
+    fn decode_key(input: &mut &[u8]) -> Option<Self>;
+}
+
+/// the key of the value
+pub fn encode(value: &(impl KeyEncode + ?Sized)) -> Vec<u8> {
+    let mut out = Vec::new();
+    value.encode_key(&mut out);
+    out
+}
+
+/// the key of the value after `prefix`, such as `prefixed(b"orders/", &(user, time))`
+pub fn prefixed(prefix: &[u8], value: &(impl KeyEncode + ?Sized)) -> Vec<u8> {
+    let mut out = prefix.to_vec();
+    value.encode_key(&mut out);
+    out
+}
+
+/// the value of the key, none if the key isn't exactly the encoding of a value of that type
+pub fn decode<T: KeyDecode>(mut key: &[u8]) -> Option<T> {
+    let value = T::decode_key(&mut key)?;
+    key.is_empty().then_some(value)
+}
+
+/// takes the first `N` bytes of the input
+fn take<const N: usize>(input: &mut &[u8]) -> Option<[u8; N]> {
+    let (bytes, rest) = input.split_first_chunk::<N>()?;
+    *input = rest;
+    Some(*bytes)
+}
+
+impl<T: KeyEncode + ?Sized> KeyEncode for &T {
+    fn encode_key(&self, out: &mut Vec<u8>) {
+        (**self).encode_key(out);
+    }
+}
+
+impl KeyEncode for u64 {
+    fn encode_key(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.to_be_bytes());
+    }
+}
+
+impl KeyDecode for u64 {
+    fn decode_key(input: &mut &[u8]) -> Option<Self> {
+        take(input).map(u64::from_be_bytes)
+    }
+}
+
+impl KeyEncode for i64 {
+    fn encode_key(&self, out: &mut Vec<u8>) {
+        ((*self as u64) ^ (1 << 63)).encode_key(out);
+    }
+}
+
+impl KeyDecode for i64 {
+    fn decode_key(input: &mut &[u8]) -> Option<Self> {
+        u64::decode_key(input).map(|n| (n ^ (1 << 63)) as i64)
+    }
+}
+
+/// the unix time of a timestamp, as in [`CommitReceipt::time`](crate::CommitReceipt::time)
+impl KeyEncode for Duration {
+    fn encode_key(&self, out: &mut Vec<u8>) {
+        self.as_secs().encode_key(out);
+        out.extend_from_slice(&self.subsec_nanos().to_be_bytes());
+    }
+}
+
+impl KeyDecode for Duration {
+    fn decode_key(input: &mut &[u8]) -> Option<Self> {
+        let seconds = u64::decode_key(input)?;
+        let nanoseconds = u32::from_be_bytes(take(input)?);
+        (nanoseconds < 1_000_000_000).then(|| Duration::new(seconds, nanoseconds))
+    }
+}
+
+impl KeyEncode for [u8] {
+    fn encode_key(&self, out: &mut Vec<u8>) {
+        for &byte in self {
+            out.push(byte);
+            if byte == 0 {
+                out.push(0xff);
+            }
+        }
+        out.extend_from_slice(&[0, 0]);
+    }
+}
+
+impl KeyEncode for Vec<u8> {
+    fn encode_key(&self, out: &mut Vec<u8>) {
+        self.as_slice().encode_key(out);
+    }
+}
+
+impl KeyDecode for Vec<u8> {
+    fn decode_key(input: &mut &[u8]) -> Option<Self> {
+        let mut bytes = Vec::new();
+        loop {
+            match take(input)? {
+                [0] => match take(input)? {
+                    [0] => return Some(bytes),
+                    [0xff] => bytes.push(0),
+                    _ => return None,
+                },
+                [byte] => bytes.push(byte),
+            }
+        }
+    }
+}
+
+impl KeyEncode for str {
+    fn encode_key(&self, out: &mut Vec<u8>) {
+        self.as_bytes().encode_key(out);
+    }
+}
+
+impl KeyEncode for String {
+    fn encode_key(&self, out: &mut Vec<u8>) {
+        self.as_bytes().encode_key(out);
+    }
+}
+
+impl KeyDecode for String {
+    fn decode_key(input: &mut &[u8]) -> Option<Self> {
+        String::from_utf8(Vec::decode_key(input)?).ok()
+    }
+}
+
+macro_rules! tuple {
+    ($($name:ident)+) => {
+        impl<$($name: KeyEncode),+> KeyEncode for ($($name,)+) {
+            #[allow(non_snake_case)]
+            fn encode_key(&self, out: &mut Vec<u8>) {
+                let ($($name,)+) = self;
+                $($name.encode_key(out);)+
+            }
+        }
+
+        impl<$($name: KeyDecode),+> KeyDecode for ($($name,)+) {
+            fn decode_key(input: &mut &[u8]) -> Option<Self> {
+                Some(($($name::decode_key(input)?,)+))
+            }
+        }
+    };
+}
+
+tuple!(A);
+tuple!(A B);
+tuple!(A B C);
+tuple!(A B C D);
+tuple!(A B C D E);
+tuple!(A B C D E F);
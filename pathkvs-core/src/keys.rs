@@ -0,0 +1,57 @@
+//! the order of keys, for building ranges over sorted keys, such as the results of a scan in a `BTreeMap`
+//!
+//! keys are ordered byte by byte, and a key comes before every longer key that starts with it
+//!
+//! keys are often paths, with their parts separated by [`SEPARATOR`], such as `user/1/email`,
+//! so the keys under a part of a path are the keys that start with its [`path_prefix`]
+
+use std::ops::Bound;
+
+/// the separator of the parts of a key that is a path
+pub const SEPARATOR: u8 = b'/';
+
+/// the smallest key after `key`, which is `key` followed by a zero byte
+pub fn next_key(key: &[u8]) -> Vec<u8> {
+    let mut next = Vec::with_capacity(key.len() + 1);
+    next.extend_from_slice(key);
+    next.push(0);
+    next
+}
+
+/// the smallest key after every key that starts with `prefix`,
+/// none if there is no such key, when the prefix is empty or made only of `0xff` bytes
+///
+/// the trailing `0xff` bytes are dropped and the last byte left is incremented,
+/// so `prefix_end(b"a\xff")` is `b"b"`, not `b"b\x00"`
+pub fn prefix_end(prefix: &[u8]) -> Option<Vec<u8>> {
+    let len = prefix.iter().rposition(|&byte| byte != 0xff)? + 1;
+    let mut end = prefix[..len].to_vec();
+    end[len - 1] += 1;
+    Some(end)
+}
+
+/// the range of the keys that start with `prefix`, which can be given to `BTreeMap::range`,
+/// unbounded at the end if there is no [`prefix_end`]
+pub fn prefix_range(prefix: &[u8]) -> (Bound<Vec<u8>>, Bound<Vec<u8>>) {
+    let end = match prefix_end(prefix) {
+        Some(end) => Bound::Excluded(end),
+        None => Bound::Unbounded,
+    };
+    (Bound::Included(prefix.to_vec()), end)
+}
+
+/// the first `depth` parts of the path of `key`, with the separator after them,
+/// none if the key does not have more than that many parts
+///
+/// `path_prefix(b"user/1/email", 2)` is `b"user/1/"`, and `path_prefix(b"user/1", 2)` is none
+pub fn path_prefix(key: &[u8], depth: usize) -> Option<&[u8]> {
+    let Some(depth) = depth.checked_sub(1) else {
+        return Some(&[]);
+    };
+    let (end, _) = key
+        .iter()
+        .enumerate()
+        .filter(|&(_, &byte)| byte == SEPARATOR)
+        .nth(depth)?;
+    Some(&key[..=end])
+}
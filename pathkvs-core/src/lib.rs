@@ -1,30 +1,110 @@
+// the clock comes from javascript there, see `now_since_epoch`
+#[cfg(all(target_arch = "wasm32", target_os = "unknown", not(feature = "web")))]
+compile_error!("pathkvs-core needs the `web` feature on wasm32-unknown-unknown, for its clock");
+
+#[cfg(feature = "fs")]
+use std::path::Path;
 use std::{
-    collections::{BTreeMap, HashMap, HashSet},
-    fs::File,
-    io::{Error, ErrorKind, Read, Seek, SeekFrom, Write},
-    path::Path,
-    sync::{
-        atomic::{AtomicPtr, Ordering},
-        Mutex,
-    },
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+    sync::{Arc, RwLock},
+    ops::{Bound, RangeBounds},
     time::{Duration, SystemTime},
 };
+#[cfg(feature = "persistence")]
+use std::{
+    borrow::Cow,
+    io::{self, ErrorKind, Read},
+    sync::Mutex,
+};
 
-use error::TransactionError;
+#[cfg(feature = "persistence")]
+use codec::{Header, RawCodec, Record, RecordCodec, RecordReader};
+pub use error::Error;
+use error::{ConflictReport, SnapshotExpired, TransactionError, ValidationError};
+#[cfg(feature = "fs")]
+use storage::FileStorage;
+#[cfg(feature = "persistence")]
+use storage::StorageBackend;
 
+#[cfg(feature = "persistence")]
+pub mod codec;
+#[cfg(feature = "persistence")]
+mod dedup;
+pub mod content;
 pub mod error;
+pub mod expiry;
+#[cfg(all(feature = "testing", feature = "persistence"))]
+pub mod fault;
+#[cfg(feature = "fs")]
+pub mod follower;
+pub mod hook;
+pub mod keyenc;
+pub mod keys;
+mod merge;
+pub mod metrics;
+pub mod pin;
+pub mod procedure;
+#[cfg(feature = "persistence")]
+pub mod replication;
+pub mod retention;
+pub mod rewrite;
+#[cfg(feature = "fs")]
+mod spill;
+#[cfg(feature = "persistence")]
+pub mod storage;
+pub mod system;
+#[cfg(feature = "testing")]
+pub mod testing;
+#[cfg(all(feature = "io-uring", target_os = "linux"))]
+mod uring;
 
 pub struct Database {
-    resolved_master: AtomicPtr<Commit>,
+    resolved_master: Master,
+    metrics: metrics::Counters,
+    hooks: hook::Hooks,
+    pins: pin::Pins,
+    #[cfg(feature = "persistence")]
+    replicas: replication::Replicas,
+    validator: Option<Validator>,
+    merge_operator: Option<MergeOperator>,
+    max_snapshot_age: Option<Duration>,
+    #[cfg(feature = "persistence")]
     persistence: Option<Persistence>,
+    #[cfg(feature = "testing")]
+    harness: Option<std::sync::Arc<testing::Harness>>,
 }
 
+/// the keys and values found by a scan, in order
+type Entries<'a> = Vec<(&'a [u8], &'a [u8])>;
+
+/// the bounds of a lexicographic range of keys, as registered by [`Transaction::scan_range`]
+pub(crate) type KeyRange = (Bound<Vec<u8>>, Bound<Vec<u8>>);
+
+/// the new value of each key changed by a commit, none if the key was deleted
+pub(crate) type Changes = HashMap<Vec<u8>, Option<Vec<u8>>>;
+
+/// the value of every key that has one after a commit, shared with the indexes of the commits before it
+/// except for the parts that changed since, see [`INDEX_INTERVAL`]
+type Index = imbl::OrdMap<Arc<[u8]>, Arc<[u8]>>;
+
+/// every commit whose sequence number is a multiple of this keeps an [`Index`], so reads only
+/// go through the changes of the commits since the last one that does, instead of the whole history
+const INDEX_INTERVAL: u64 = 32;
+
+/// checks every key and value written, see [`Database::validator`]
+pub type Validator = fn(&[u8], &[u8]) -> Result<(), ValidationError>;
+
+/// the value of a key after a merge, given the value it has and the operand, see [`Database::with_merge_operator`]
+pub type MergeOperator = fn(&[u8], &[u8]) -> Vec<u8>;
+
+#[cfg(feature = "persistence")]
 pub struct Persistence {
-    serialized_master: AtomicPtr<Commit>,
+    serialized_master: Master,
+    codec: Box<dyn RecordCodec>,
     history_sink: Mutex<HistorySink>,
-    sync: DatabaseWriteSyncMode,
 }
 
+#[cfg(feature = "persistence")]
 #[derive(Default, Debug, Clone, Copy, PartialEq, Eq)]
 pub enum DatabaseWriteSyncMode {
     #[default]
@@ -33,6 +113,73 @@ pub enum DatabaseWriteSyncMode {
     Cached,
 }
 
+/// how [`Database::open_with`] and [`Database::create_with`] open the file
+///
+/// the platform specific options fall back to plain buffered writes where the platform
+/// or the filesystem does not support them, so the same options can be used everywhere
+#[cfg(feature = "fs")]
+#[derive(Default, Debug, Clone)]
+pub struct DatabaseOptions {
+    sync_mode: DatabaseWriteSyncMode,
+    write_through: bool,
+    direct_io: bool,
+    dedup_writes: bool,
+    validator: Option<Validator>,
+    merge_operator: Option<MergeOperator>,
+    max_snapshot_age: Option<Duration>,
+}
+
+#[cfg(feature = "fs")]
+impl DatabaseOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// same as [`Database::write_sync_mode`]
+    pub fn sync_mode(mut self, sync_mode: DatabaseWriteSyncMode) -> Self {
+        self.sync_mode = sync_mode;
+        self
+    }
+    /// same as [`Database::validator`]
+    pub fn validator(mut self, validator: Validator) -> Self {
+        self.validator = Some(validator);
+        self
+    }
+    /// same as [`Database::with_merge_operator`]
+    pub fn merge_operator(mut self, operator: MergeOperator) -> Self {
+        self.merge_operator = Some(operator);
+        self
+    }
+    /// same as [`Database::dedup_writes`]
+    pub fn dedup_writes(mut self, dedup_writes: bool) -> Self {
+        self.dedup_writes = dedup_writes;
+        self
+    }
+    /// same as [`Database::max_snapshot_age`]
+    pub fn max_snapshot_age(mut self, age: Duration) -> Self {
+        self.max_snapshot_age = Some(age);
+        self
+    }
+    /// writes only return once the data is on the disk, `O_DSYNC` on unix and `FILE_FLAG_WRITE_THROUGH` on windows
+    ///
+    /// commits are then durable in every sync mode, and [`DatabaseWriteSyncMode::Sync`] no longer needs
+    /// a separate fsync after each commit, which saves flushing the file metadata
+    pub fn write_through(mut self, write_through: bool) -> Self {
+        self.write_through = write_through;
+        self
+    }
+    /// appends bypass the page cache with `O_DIRECT`, linux only, ignored elsewhere
+    ///
+    /// only whole blocks can be written directly, so the partial block at the end of
+    /// the history is still written through the page cache, and rewritten once it fills up
+    ///
+    /// this keeps large databases from evicting the rest of the page cache, but it
+    /// makes small commits slower, best combined with [`DatabaseOptions::write_through`]
+    pub fn direct_io(mut self, direct_io: bool) -> Self {
+        self.direct_io = direct_io;
+        self
+    }
+}
+
 struct _AssertDatabaseSend
 where
     Database: Send;
@@ -41,475 +188,2080 @@ struct _AssertDatabaseSync
 where
     Database: Sync;
 
+#[cfg(feature = "persistence")]
 struct HistorySink {
-    output_stream: File,
+    storage: Box<dyn StorageBackend>,
     cursor: u64,
+    /// of the history in the storage, see [`codec::FORMAT_VERSION`]
+    format_version: u32,
+    /// the header says the history was closed cleanly, cleared before anything is appended to it
+    clean: bool,
+    sync: DatabaseWriteSyncMode,
+    dedup: Option<dedup::Dedup>,
+}
+
+/// counts the bytes read, so the end of the last complete record is known
+#[cfg(feature = "persistence")]
+struct CountingReader<R> {
+    inner: R,
+    count: u64,
 }
 
 #[derive(Clone)]
 struct Commit {
-    prev: *const Commit,
+    prev: Chain,
     time: Duration,
-    changes: HashMap<Vec<u8>, Vec<u8>>,
+    /// the number of commits in the history up to and including this one
+    seq: u64,
+    /// a deleted key is not the same as one with an empty value
+    changes: Changes,
+    /// the length of the bytes appended to the end of each value in `changes` that was appended to,
+    /// so only those bytes are persisted, when the codec supports it
+    #[cfg_attr(not(feature = "persistence"), allow(dead_code))]
+    appended: HashMap<Vec<u8>, usize>,
+    /// the deletions in `changes` that were expiries, see [`Transaction::expire`],
+    /// only known in memory, the history keeps them as deletions
+    expired: HashSet<Vec<u8>>,
+    /// set once the sequence number is final, see [`INDEX_INTERVAL`]
+    index: Option<Index>,
 }
 
+/// a commit and every commit before it, empty before the first commit
+///
+/// each commit is shared by the commits after it and by the snapshots and transactions
+/// that look at it, and freed once none is left
+#[derive(Clone, Default)]
+struct Chain(Option<Arc<Commit>>);
+
+/// the newest commit, replaced as a whole when a commit is published, see [`Master::publish`]
+struct Master(RwLock<Chain>);
+
 #[derive(Clone)]
 pub struct Transaction<'a> {
     database: &'a Database,
     commit: Commit,
     reads: HashSet<Vec<u8>>,
     scans: HashSet<(Vec<u8>, usize)>,
+    ranges: HashSet<KeyRange>,
+    #[cfg(feature = "fs")]
+    spill: Option<Box<spill::Spill>>,
+    /// the merges of the keys that were not read or written, applied to the value they have at commit
+    merges: HashMap<Vec<u8>, merge::Merge>,
+    /// the first write refused by the validator, returned by the commit
+    invalid: Option<Box<ValidationError>>,
+}
+
+/// what is known about a commit once it returns, see [`Transaction::commit_with_receipt`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CommitReceipt {
+    /// the unix time of the commit
+    pub time: Duration,
+    /// the number of commits in the history up to and including this one, see [`Database::past_seq_snapshot`]
+    pub seq: u64,
+    /// the length of the history in the storage once the commit was persisted, the commit ends at or before it,
+    /// none if the database is not persisted
+    pub offset: Option<u64>,
 }
 
+/// what [`Database::compact`] did to the history in the storage
+#[cfg(feature = "persistence")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Compaction {
+    /// the length of the history in the storage before it was compacted
+    pub before: u64,
+    /// the length of the history in the storage once compacted
+    pub after: u64,
+    /// how many commits are left in the storage, the one with the compacted values included
+    pub commits: u64,
+}
+
+/// a commit of the history, see [`Database::recent_commits`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CommitSummary {
+    /// the unix time of the commit
+    pub time: Duration,
+    /// the number of commits in the history up to and including this one, see [`Database::past_seq_snapshot`]
+    pub seq: u64,
+    /// how many keys the commit changed, not counting its metadata
+    pub keys: usize,
+    /// set by [`Transaction::set_meta`], none if the commit has none
+    pub meta: Option<Vec<u8>>,
+}
+
+/// the database as it was after a commit, which it keeps, along with the commits before it,
+/// for as long as it lives, so it can outlive the borrow of the database it was taken from
 #[derive(Clone)]
-pub struct Snapshot<'a> {
-    commit: Option<&'a Commit>,
+pub struct Snapshot {
+    commit: Chain,
+    /// the unix time after which reads fail, see [`Database::max_snapshot_age`]
+    expires: Option<Duration>,
+}
+
+/// where a scan split in pages stopped, see [`Snapshot::scan_page`]
+///
+/// it only holds the last key returned, so it can be kept between requests, or rebuilt with [`ScanCursor::after`],
+/// and the scan can go on in a newer snapshot, in which case it sees the keys after it as they are in that snapshot
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ScanCursor {
+    start: Vec<u8>,
+    end: Vec<u8>,
+    rev: bool,
+    last: Option<Vec<u8>>,
+    done: bool,
 }
 
 impl Database {
     pub fn memory() -> Self {
         Self {
-            resolved_master: AtomicPtr::new(std::ptr::null_mut()),
+            resolved_master: Master::new(Chain::default()),
+            metrics: metrics::Counters::default(),
+            hooks: hook::Hooks::default(),
+            pins: pin::Pins::default(),
+            #[cfg(feature = "persistence")]
+            replicas: replication::Replicas::default(),
+            validator: None,
+            merge_operator: None,
+            max_snapshot_age: None,
+            #[cfg(feature = "persistence")]
             persistence: None,
+            #[cfg(feature = "testing")]
+            harness: None,
         }
     }
+    #[cfg(feature = "fs")]
     pub fn create(path: impl AsRef<Path>) -> Result<Self, Error> {
-        let file = std::fs::File::options()
-            .read(true)
-            .write(true)
-            .truncate(true)
-            .create(true)
-            .open(path)?;
-        Ok(Self {
-            resolved_master: AtomicPtr::new(std::ptr::null_mut()),
-            persistence: Some(Persistence {
-                serialized_master: AtomicPtr::new(std::ptr::null_mut()),
-                history_sink: Mutex::new(HistorySink {
-                    output_stream: file,
-                    cursor: 0,
-                }),
-                sync: DatabaseWriteSyncMode::default(),
-            }),
-        })
+        Self::create_with(path, &DatabaseOptions::default())
     }
+    #[cfg(feature = "fs")]
     pub fn open(path: impl AsRef<Path>) -> Result<Self, Error> {
-        let mut file = std::fs::File::options()
-            .read(true)
-            .write(true)
-            .create(true)
-            .open(path)?;
-
-        let mut cursor = 0u64;
-        let mut commit_ptr = std::ptr::null_mut();
-
-        let result: Result<(), Error> = (|| loop {
-            let mut commit_cursor = 0u64;
-
-            let mut seconds = [0; 8];
-            let mut nanoseconds = [0; 4];
-            file.read_exact(&mut seconds)?;
-            file.read_exact(&mut nanoseconds)?;
-            commit_cursor += 12;
-            let seconds = u64::from_le_bytes(seconds);
-            let nanoseconds = u32::from_le_bytes(nanoseconds);
-
-            if nanoseconds >= 1_000_000_000 {
-                return Err(Error::new(ErrorKind::UnexpectedEof, "bad nanosecond field"));
+        Self::open_with(path, &DatabaseOptions::default())
+    }
+    /// like [`Database::create`], but with the file opened as `options` asks
+    #[cfg(feature = "fs")]
+    pub fn create_with(path: impl AsRef<Path>, options: &DatabaseOptions) -> Result<Self, Error> {
+        Self::open_file(path.as_ref(), options, true)
+    }
+    /// like [`Database::open`], but with the file opened as `options` asks
+    #[cfg(feature = "fs")]
+    pub fn open_with(path: impl AsRef<Path>, options: &DatabaseOptions) -> Result<Self, Error> {
+        Self::open_file(path.as_ref(), options, false)
+    }
+    #[cfg(feature = "fs")]
+    fn open_file(path: &Path, options: &DatabaseOptions, truncate: bool) -> Result<Self, Error> {
+        #[cfg(target_os = "linux")]
+        if options.direct_io {
+            if let Some(storage) =
+                storage::DirectFileStorage::open(path, options.write_through, truncate)?
+            {
+                return Ok(Self::open_storage(storage)?.with_options(options));
             }
+        }
+        let (file, durable_writes) = storage::open_file(path, options.write_through, truncate)?;
+        let storage = FileStorage::new(file)
+            .durable_writes(durable_writes)
+            .path(path);
+        Ok(Self::open_storage(storage)?.with_options(options))
+    }
+    #[cfg(feature = "fs")]
+    fn with_options(mut self, options: &DatabaseOptions) -> Self {
+        self.validator = options.validator;
+        self.merge_operator = options.merge_operator;
+        self.max_snapshot_age = options.max_snapshot_age;
+        self.write_sync_mode(options.sync_mode)
+            .dedup_writes(options.dedup_writes)
+    }
+    /// loads the history from the storage, any incomplete commit at the end of it is discarded
+    ///
+    /// it is read with the codec its header names, [`RawCodec`] if it names none
+    #[cfg(feature = "persistence")]
+    pub fn open_storage(mut storage: impl StorageBackend + 'static) -> Result<Self, Error> {
+        let header = codec::read_header(&mut storage.reader()?)?;
+        match header.codec() {
+            Some(id) => match codec::by_id(id) {
+                Some(codec) => Self::open_storage_with(storage, codec),
+                None => Err(Error::Corruption(format!("unknown codec {id}"))),
+            },
+            None => Self::open_storage_with(storage, RawCodec),
+        }
+    }
+    /// like [`Database::open_storage`], but with the records laid out by `codec`
+    #[cfg(feature = "persistence")]
+    pub fn open_storage_with(
+        mut storage: impl StorageBackend + 'static,
+        codec: impl RecordCodec + 'static,
+    ) -> Result<Self, Error> {
+        let mut chain = Chain::default();
 
-            let mut kv_len = [0; 4];
-            file.read_exact(&mut kv_len)?;
-            commit_cursor += 4;
-            let kv_len = u32::from_le_bytes(kv_len);
-
-            let time = Duration::new(seconds, nanoseconds);
-
-            let mut changes = HashMap::new();
-
-            for _ in 0..kv_len {
-                let mut k_len = [0; 4];
-                file.read_exact(&mut k_len)?;
-                commit_cursor += 4;
-                let k_len = u32::from_le_bytes(k_len);
-
-                let mut k = Vec::<u8>::new();
-                k.reserve_exact(k_len as usize);
-                unsafe {
-                    k.set_len(k_len as usize);
-                }
-                file.read_exact(&mut k)?;
-                commit_cursor += k_len as u64;
-
-                let mut v_len = [0; 4];
-                file.read_exact(&mut v_len)?;
-                commit_cursor += 4;
-                let v_len = u32::from_le_bytes(v_len);
-
-                let mut v = Vec::<u8>::new();
-                v.reserve_exact(v_len as usize);
-                unsafe {
-                    v.set_len(v_len as usize);
+        let mut reader = storage.reader()?;
+        let header = codec::read_header(&mut reader)?;
+        if let Some(id) = header.codec().filter(|&id| id != codec.id()) {
+            let error = format!("pathkvs history was written with codec {id}, not {}", codec.id());
+            return Err(io::Error::new(ErrorKind::InvalidInput, error).into());
+        }
+        let mut format_version = header.version();
+        let clean = header.clean();
+        let (start, count) = match header {
+            Header::Legacy(start) => (start, 0),
+            Header::Missing => (Vec::new(), 0),
+            Header::Version { .. } => (Vec::new(), codec::HEADER_LEN),
+        };
+        let mut cursor = count;
+        let mut file = RecordReader::new(CountingReader {
+            inner: start.as_slice().chain(reader),
+            count,
+        });
+        // a history closed cleanly ends with a complete record, so the lengths in it are taken as they are
+        if clean {
+            file = file.trusting();
+        }
+        let error = loop {
+            match codec.decode(&mut file) {
+                Ok(record) => {
+                    chain = Chain(Some(Arc::new(Commit::from_record(chain, record))));
+                    cursor = file.get_ref().count;
                 }
-                file.read_exact(&mut v)?;
-                commit_cursor += v_len as u64;
-
-                changes.insert(k, v);
+                Err(error) => break error,
             }
+        };
+        let end = file.get_ref().count;
+        drop(file);
 
-            commit_ptr = Box::into_raw(Box::new(Commit {
-                prev: commit_ptr,
-                time,
-                changes,
-            }));
-
-            cursor += commit_cursor;
-        })();
-
-        match result {
-            Ok(()) => {}
-            Err(error) if error.kind() == ErrorKind::UnexpectedEof => {}
-            Err(error) => return Err(error),
+        if error.kind() != ErrorKind::UnexpectedEof {
+            return Err(error.into());
         }
 
-        file.set_len(cursor)?;
+        // only a crash leaves a torn commit at the end, and there is nothing to discard otherwise
+        if clean && end != cursor {
+            return Err(Error::Corruption(
+                "history closed cleanly ends in the middle of a record".into(),
+            ));
+        } else if !clean {
+            storage.truncate(cursor)?;
+        }
+        // new histories, and those that ended before their first record, start with the header
+        if cursor == 0 {
+            storage.append(&Header::current(codec.id()))?;
+            storage.sync(DatabaseWriteSyncMode::Sync)?;
+            cursor = codec::HEADER_LEN;
+            format_version = codec::FORMAT_VERSION;
+        }
 
         Ok(Self {
-            resolved_master: AtomicPtr::new(commit_ptr),
+            resolved_master: Master::new(chain.clone()),
+            metrics: metrics::Counters::default(),
+            hooks: hook::Hooks::default(),
+            pins: pin::Pins::default(),
+            #[cfg(feature = "persistence")]
+            replicas: replication::Replicas::default(),
+            validator: None,
+            merge_operator: None,
+            max_snapshot_age: None,
             persistence: Some(Persistence {
-                serialized_master: AtomicPtr::new(commit_ptr),
+                serialized_master: Master::new(chain),
+                codec: Box::new(codec),
                 history_sink: Mutex::new(HistorySink {
-                    output_stream: file,
+                    storage: Box::new(storage),
                     cursor,
+                    format_version,
+                    clean,
+                    sync: DatabaseWriteSyncMode::default(),
+                    dedup: None,
                 }),
-                sync: DatabaseWriteSyncMode::default(),
             }),
+            #[cfg(feature = "testing")]
+            harness: None,
         })
     }
+    #[cfg(feature = "persistence")]
     pub fn write_sync_mode(mut self, sync_mode: DatabaseWriteSyncMode) -> Self {
         if let Some(persitence) = &mut self.persistence {
-            persitence.sync = sync_mode;
+            persitence.history_sink.get_mut().unwrap().sync = sync_mode;
+        }
+        self
+    }
+    /// every key and value written is checked by `validator`, by [`Transaction::write`] and everything built on it,
+    /// so conventions like utf-8 keys can be enforced for every writer, including imports and restores
+    ///
+    /// a refused write is not staged, and the transaction fails to commit with the [`ValidationError`],
+    /// see [`Transaction::try_write`] to handle it right away, deletions are checked too, with an empty value
+    pub fn validator(mut self, validator: Validator) -> Self {
+        self.validator = Some(validator);
+        self
+    }
+    /// registers the function that [`Transaction::merge`] merges operands into values with,
+    /// for values that many writers change at once without conflicting, such as counters or sets
+    ///
+    /// the operands are applied in order at commit, to the value the key has then, one without a value
+    /// is given as empty, so the history, the compaction and the replicas only ever see whole values,
+    /// and the operator is not needed to read them, it must not panic, it runs while the commit is resolved
+    pub fn with_merge_operator(mut self, operator: MergeOperator) -> Self {
+        self.merge_operator = Some(operator);
+        self
+    }
+    /// reads from snapshots taken more than `age` ago fail with [`SnapshotExpired`], so a reader that holds
+    /// on to a snapshot for too long finds out, instead of silently keeping an old view of the database
+    ///
+    /// the age is measured from when the snapshot is taken, not from the time of the commit it looks at,
+    /// snapshots from [`Database::pin_snapshot`] never expire, since keeping their history is deliberate
+    pub fn max_snapshot_age(mut self, age: Duration) -> Self {
+        self.max_snapshot_age = Some(age);
+        self
+    }
+    /// writes of the value the key already had are left out of the history, so writing the same data
+    /// again, like when a dataset is synced periodically, does not grow the file
+    ///
+    /// a hash of the last value persisted for each key is kept in memory, starting empty when the database
+    /// is opened, a commit whose writes are all left out is persisted with no changes, so the history has
+    /// the same commits once the database is opened again, and the sequence numbers given before still match
+    #[cfg(feature = "persistence")]
+    pub fn dedup_writes(mut self, dedup: bool) -> Self {
+        if let Some(persitence) = &mut self.persistence {
+            persitence.history_sink.get_mut().unwrap().dedup = dedup.then(dedup::Dedup::default);
+        }
+        self
+    }
+    /// like [`Database::write_sync_mode`], but on a database in use, commits persisted from now on use the new mode
+    #[cfg(feature = "persistence")]
+    pub fn set_write_sync_mode(&self, sync_mode: DatabaseWriteSyncMode) {
+        if let Some(persitence) = &self.persistence {
+            persitence.history_sink.lock().unwrap().sync = sync_mode;
         }
+    }
+    /// see [`testing`], the current contents of the database are the starting point of the recorded history
+    #[cfg(feature = "testing")]
+    pub fn with_harness(mut self, harness: std::sync::Arc<testing::Harness>) -> Self {
+        harness.set_baseline(
+            self.scan(b"", b"")
+                .into_iter()
+                .map(|(k, v)| (k.to_vec(), v.to_vec()))
+                .collect(),
+        );
+        self.harness = Some(harness);
         self
     }
-    fn load_master(&self) -> *const Commit {
+    fn now(&self) -> Duration {
+        #[cfg(feature = "testing")]
+        if let Some(harness) = &self.harness {
+            return harness.tick();
+        }
+        now_since_epoch()
+    }
+    fn snapshot_of(&self, commit: Chain) -> Snapshot {
+        Snapshot {
+            commit,
+            // the wall clock, not the clock of the testing harness, which advances every time it is read
+            expires: self.max_snapshot_age.map(|age| now_since_epoch() + age),
+        }
+    }
+    fn load_master(&self) -> Chain {
+        #[cfg(feature = "persistence")]
         if let Some(persistence) = &self.persistence {
-            persistence.serialized_master.load(Ordering::SeqCst)
-        } else {
-            self.resolved_master.load(Ordering::SeqCst)
+            return persistence.serialized_master.load();
         }
+        self.resolved_master.load()
     }
     pub fn start_writes<'a>(&'a self) -> Transaction<'a> {
+        #[cfg(feature = "testing")]
+        if let Some(harness) = &self.harness {
+            harness.pass_start();
+        }
         Transaction {
             database: self,
             commit: Commit {
                 prev: self.load_master(),
                 time: Duration::default(),
+                seq: 0,
                 changes: HashMap::new(),
+                appended: HashMap::new(),
+                expired: HashSet::new(),
+                index: None,
             },
             reads: HashSet::new(),
             scans: HashSet::new(),
+            ranges: HashSet::new(),
+            #[cfg(feature = "fs")]
+            spill: None,
+            merges: HashMap::new(),
+            invalid: None,
         }
     }
     pub fn write(&self, key: &[u8], value: &[u8]) -> Result<(), Error> {
         if key.is_empty() {
             return Ok(());
         }
+        self.commit_blind(|ts| ts.write(key, value))
+    }
+    /// [`Transaction::delete`] in a transaction of its own
+    pub fn delete(&self, key: &[u8]) -> Result<(), Error> {
+        if key.is_empty() {
+            return Ok(());
+        }
+        self.commit_blind(|ts| ts.delete(key))
+    }
+    /// [`Transaction::expire`] in a transaction of its own
+    pub fn expire(&self, key: &[u8]) -> Result<(), Error> {
+        if key.is_empty() {
+            return Ok(());
+        }
+        self.commit_blind(|ts| ts.expire(key))
+    }
+    /// [`Transaction::append`] in a transaction of its own
+    pub fn append(&self, key: &[u8], bytes: &[u8]) -> Result<(), Error> {
+        if key.is_empty() || bytes.is_empty() {
+            return Ok(());
+        }
+        self.commit_blind(|ts| ts.append(key, bytes))
+    }
+    /// [`Transaction::write_max`] in a transaction of its own
+    pub fn write_max(&self, key: &[u8], n: i64) -> Result<(), Error> {
+        if key.is_empty() {
+            return Ok(());
+        }
+        self.commit_blind(|ts| ts.write_max(key, n))
+    }
+    /// [`Transaction::write_min`] in a transaction of its own
+    pub fn write_min(&self, key: &[u8], n: i64) -> Result<(), Error> {
+        if key.is_empty() {
+            return Ok(());
+        }
+        self.commit_blind(|ts| ts.write_min(key, n))
+    }
+    /// [`Transaction::merge`] in a transaction of its own
+    pub fn merge(&self, key: &[u8], operand: &[u8]) -> Result<(), Error> {
+        if key.is_empty() {
+            return Ok(());
+        }
+        self.commit_blind(|ts| ts.merge(key, operand))
+    }
+    /// commits a transaction that only writes or merges, which cannot conflict
+    fn commit_blind(&self, f: impl FnOnce(&mut Transaction<'_>)) -> Result<(), Error> {
         let mut ts = self.start_writes();
-        ts.write(key, value);
+        f(&mut ts);
         match ts.commit() {
             Ok(_) => Ok(()),
             Err(TransactionError::Conflict) => {
-                unreachable!("a write only transaction cannot conflict")
+                unreachable!("a transaction that does not read cannot conflict")
             }
-            Err(TransactionError::Io(error)) => Err(error),
+            Err(TransactionError::Io(error)) => Err(error.into()),
         }
     }
-    pub fn snapshot<'a>(&'a self) -> Snapshot<'a> {
-        unsafe {
-            Snapshot {
-                commit: self.load_master().as_ref(),
+    /// [`Transaction::incr`] in a transaction of its own, retried until it commits, so concurrent
+    /// increments of the same key never conflict, returns the new value
+    ///
+    /// fails with [`Error::NotANumber`] if the value is not a number, the sum overflows or the key is empty,
+    /// and nothing is written
+    pub fn incr(&self, key: &[u8], delta: i64) -> Result<i64, Error> {
+        loop {
+            let mut ts = self.start_writes();
+            let Some(n) = ts.incr(key, delta) else {
+                ts.rollback();
+                return Err(Error::NotANumber);
+            };
+            match ts.commit() {
+                Ok(_) => return Ok(n),
+                Err(TransactionError::Conflict) => continue,
+                Err(TransactionError::Io(error)) => return Err(error.into()),
             }
         }
     }
-    pub fn past_unix_time_snapshot_with<'a>(&'a self, time: Duration) -> Snapshot<'a> {
-        let mut commit = self.load_master();
-        unsafe {
-            while let Some(reference) = commit.as_ref() {
-                if reference.time <= time {
-                    return Snapshot {
-                        commit: Some(reference),
-                    };
+    /// [`Transaction::incr_windowed`] in a transaction of its own, retried until it commits
+    pub fn incr_windowed(&self, key: &[u8], window: Duration) -> Result<u64, Error> {
+        loop {
+            let mut ts = self.start_writes();
+            let count = ts.incr_windowed(key, window);
+            match ts.commit() {
+                Ok(_) => return Ok(count),
+                Err(TransactionError::Conflict) => continue,
+                Err(TransactionError::Io(error)) => return Err(error.into()),
+            }
+        }
+    }
+    /// commits the writes if every key of `checks` has the expected value, none for a missing key, otherwise
+    /// writes nothing and returns the index of the first check that failed, a write of none deletes the key,
+    /// an empty value is a value like any other, in the checks and in the writes
+    ///
+    /// it runs in a transaction of its own, retried until it commits, so the checks always see the latest values
+    /// and a concurrent commit never makes it fail
+    pub fn check_write(
+        &self,
+        checks: &[(&[u8], Option<&[u8]>)],
+        writes: &[(&[u8], Option<&[u8]>)],
+    ) -> Result<Result<Duration, usize>, Error> {
+        loop {
+            let mut ts = self.start_writes();
+            let failed = checks.iter().position(|&(key, expected)| match expected {
+                Some(expected) => !ts.exists(key) || ts.read(key) != expected,
+                None => ts.exists(key),
+            });
+            if let Some(index) = failed {
+                ts.rollback();
+                return Ok(Err(index));
+            }
+            for &(key, value) in writes {
+                match value {
+                    Some(value) => ts.write(key, value),
+                    None => ts.delete(key),
                 }
-                commit = reference.prev;
+            }
+            match ts.commit() {
+                Ok(time) => return Ok(Ok(time)),
+                Err(TransactionError::Conflict) => continue,
+                Err(TransactionError::Io(error)) => return Err(error.into()),
             }
         }
-        Snapshot { commit: None }
     }
-    pub fn past_sys_time_snapshot<'a>(&'a self, time: SystemTime) -> Snapshot<'a> {
+    pub fn snapshot(&self) -> Snapshot {
+        self.snapshot_of(self.load_master())
+    }
+    pub fn past_unix_time_snapshot_with(&self, time: Duration) -> Snapshot {
+        self.snapshot_of(self.load_master().find(|commit| commit.time <= time))
+    }
+    /// the snapshot after the first `seq` commits of the history, the sequence number of a [`CommitReceipt`]
+    /// gives the snapshot right after that commit, an empty snapshot if `seq` is 0
+    pub fn past_seq_snapshot(&self, seq: u64) -> Snapshot {
+        self.snapshot_of(self.load_master().find(|commit| commit.seq <= seq))
+    }
+    /// the last `limit` commits of the history, newest first, to find a snapshot to look at
+    pub fn recent_commits(&self, limit: usize) -> Vec<CommitSummary> {
+        let master = self.load_master();
+        Commit::history(master.head())
+            .take(limit)
+            .map(|commit| {
+                let meta = commit.changes.get(system::META);
+                CommitSummary {
+                    time: commit.time,
+                    seq: commit.seq,
+                    keys: commit.changes.len() - meta.is_some() as usize,
+                    meta: meta.cloned().flatten(),
+                }
+            })
+            .collect()
+    }
+    pub fn past_sys_time_snapshot(&self, time: SystemTime) -> Snapshot {
         let Ok(time) = time.duration_since(SystemTime::UNIX_EPOCH) else {
-            return Snapshot { commit: None };
+            return self.snapshot_of(Chain::default());
         };
         self.past_unix_time_snapshot_with(time)
     }
-    pub fn len<'b>(&'b self, key: &[u8]) -> u32 {
+    /// every change to keys starting with `prefix` by commits after the unix time `after` and up to `until`,
+    /// as the time of the commit, the key and its new value, ordered by time and then by key,
+    /// deleted keys have empty values, and the [`system`] keyspace is left out unless `prefix` is in it
+    ///
+    /// passing the last `until` as the next `after` gets each change exactly once, for incremental syncs and audits
+    pub fn changes_between(
+        &self,
+        prefix: &[u8],
+        after: Duration,
+        until: Duration,
+    ) -> Vec<(Duration, Vec<u8>, Vec<u8>)> {
+        let master = self.load_master();
+        let commits = Commit::history(master.head())
+            .take_while(|commit| commit.time > after)
+            .filter(|commit| commit.time <= until)
+            .collect::<Vec<_>>();
+        let mut changes = Vec::new();
+        for commit in commits.into_iter().rev() {
+            let start = changes.len();
+            changes.extend(
+                commit
+                    .changes
+                    .iter()
+                    .filter(|(key, _)| key.starts_with(prefix) && system::visible(key, prefix))
+                    .map(|(key, value)| {
+                        (commit.time, key.clone(), value.clone().unwrap_or_default())
+                    }),
+            );
+            changes[start..].sort_unstable();
+        }
+        changes
+    }
+    /// like [`Database::past_unix_time_snapshot_with`], but the history it reads from is kept until it is released,
+    /// for long running reads, such as exports
+    pub fn pin_snapshot(&self, time: Duration) -> pin::PinnedSnapshot<'_> {
+        let mut snapshot = self.past_unix_time_snapshot_with(time);
+        snapshot.expires = None;
+        pin::PinnedSnapshot::new(self, snapshot)
+    }
+    /// the unix time of the oldest pinned snapshot, the commit it looks at and every commit after it must be kept
+    pub fn oldest_pin(&self) -> Option<Duration> {
+        self.pins.oldest()
+    }
+    pub fn len(&self, key: &[u8]) -> u32 {
         if key.is_empty() {
             return 0;
         }
-        self.snapshot().len(key)
+        Commit::len(self.load_master().head(), key)
     }
-    pub fn read<'b>(&'b self, key: &[u8]) -> &'b [u8] {
+    /// a copy of the value, as the commit it is in may be freed once newer commits replace it,
+    /// see [`Database::snapshot`] to read without copying, the same goes for the other reads of the database
+    pub fn read(&self, key: &[u8]) -> Vec<u8> {
         if key.is_empty() {
-            return &[];
+            return Vec::new();
         }
-        self.snapshot().read(key)
-    }
-    pub fn count<'b>(&'b self, start: &[u8], end: &[u8]) -> u32 {
-        self.snapshot().count(start, end)
-    }
-    pub fn list<'b>(&'b self, start: &[u8], end: &[u8]) -> Vec<&'b [u8]> {
-        self.snapshot().list(start, end)
+        Commit::read(self.load_master().head(), key).to_vec()
     }
-    pub fn scan<'b>(&'b self, start: &[u8], end: &[u8]) -> Vec<(&'b [u8], &'b [u8])> {
-        self.snapshot().scan(start, end)
-    }
-
-    fn persist(&self) -> Result<(), Error> {
-        let Some(persistence) = &self.persistence else {
-            return Ok(());
-        };
-        loop {
-            let mut resolved_master = self.resolved_master.load(Ordering::SeqCst) as *const Commit;
-            let mut workbench = persistence.history_sink.lock().unwrap();
-            let serialized_master = persistence.serialized_master.load(Ordering::SeqCst) as *const Commit;
-            let mut stack = Vec::new();
-            while resolved_master != serialized_master {
-                stack.push(resolved_master);
-                unsafe {
-                    resolved_master = resolved_master
-                        .as_ref()
-                        .expect("snapshot cannot be unwound without first hiting the last_commit")
-                        .prev;
-                }
-            }
-            if stack.is_empty() {
-                return Ok(());
-            }
-            for commit in stack.into_iter().rev() {
-                let commit_ref = unsafe { commit.as_ref().unwrap_unchecked() };
-                let mut new_cursor = workbench.cursor;
-                workbench.output_stream.seek(SeekFrom::Start(new_cursor))?;
-                workbench.output_stream.set_len(new_cursor)?;
-
-                let seconds = commit_ref.time.as_secs().to_le_bytes();
-                let nanoseconds = commit_ref.time.subsec_nanos().to_le_bytes();
-                workbench.output_stream.write_all(&seconds)?;
-                workbench.output_stream.write_all(&nanoseconds)?;
-                new_cursor += 12;
-
-                let kv_len_bytes = (commit_ref.changes.len() as u32).to_le_bytes();
-                workbench.output_stream.write_all(&kv_len_bytes)?;
-                new_cursor += 4;
-
-                for (k, v) in &commit_ref.changes {
-                    let k_len_bytes = (k.len() as u32).to_le_bytes();
-                    workbench.output_stream.write_all(&k_len_bytes)?;
-                    workbench.output_stream.write_all(&k)?;
-                    let v_len_bytes = (v.len() as u32).to_le_bytes();
-                    workbench.output_stream.write_all(&v_len_bytes)?;
-                    workbench.output_stream.write_all(&v)?;
-                    new_cursor += 8 + k.len() as u64 + v.len() as u64;
-                }
-                match persistence.sync {
-                    DatabaseWriteSyncMode::Sync => {
-                        workbench.output_stream.flush()?;
-                        workbench.output_stream.sync_all()?;
-                    }
-                    DatabaseWriteSyncMode::Flush => {
-                        workbench.output_stream.flush()?;
-                    }
-                    DatabaseWriteSyncMode::Cached => {}
-                }
-                persistence.serialized_master
-                    .store(commit as *mut _, Ordering::SeqCst);
-                workbench.cursor = new_cursor;
-            }
+    /// whether the key has a value, which may be empty, false if it was deleted or never written
+    pub fn exists(&self, key: &[u8]) -> bool {
+        if key.is_empty() {
+            return false;
         }
+        Commit::get(self.load_master().head(), key).is_some()
     }
-}
-
-impl Drop for Database {
-    fn drop(&mut self) {
-        let mut commit_ptr = *self.resolved_master.get_mut();
-        unsafe {
-            while let Some(commit) = commit_ptr.as_mut() {
-                std::ptr::drop_in_place(&mut commit.changes);
-                commit_ptr = commit.prev as *mut Commit;
-            }
-        }
+    /// see [`Snapshot::read_range`]
+    pub fn read_range(&self, key: &[u8], offset: u32, len: u32) -> Vec<u8> {
+        let master = self.load_master();
+        value_range(Commit::read(master.head(), key), offset, len).to_vec()
     }
-}
-
-impl Commit {
-    unsafe fn ptr_len(commit: *const Commit, key: &[u8]) -> u32 {
-        Commit::ptr_read(commit, key).len() as u32
+    pub fn count(&self, start: &[u8], end: &[u8]) -> u32 {
+        Commit::count(self.load_master().head(), start, end)
     }
-    unsafe fn ptr_read<'a>(mut commit: *const Commit, key: &[u8]) -> &'a [u8] {
-        if key.len() > u32::MAX as usize {
-            return &[];
-        }
-        while let Some(reference) = commit.as_ref() {
-            if let Some(value) = reference.changes.get(key) {
-                return value;
-            }
-            commit = reference.prev;
-        }
-        &[]
+    pub fn list(&self, start: &[u8], end: &[u8]) -> Vec<Vec<u8>> {
+        let master = self.load_master();
+        Commit::list(master.head(), start, end)
+            .into_iter()
+            .map(<[u8]>::to_vec)
+            .collect()
     }
-    unsafe fn ptr_count(commit: *const Commit, start: &[u8], end: &[u8]) -> u32 {
-        let mut count = 0;
-        let mut keys = HashMap::new();
-        Commit::ptr_historic_scan(commit, start, end, |key, value| {
-            keys.entry(key).or_insert_with(|| {
-                if !value.is_empty() {
-                    count += 1;
-                }
-            });
-        });
-        count
+    pub fn scan(&self, start: &[u8], end: &[u8]) -> Vec<(Vec<u8>, Vec<u8>)> {
+        let master = self.load_master();
+        owned(Commit::scan(master.head(), start, end))
     }
-    unsafe fn ptr_list<'a>(commit: *const Commit, start: &[u8], end: &[u8]) -> Vec<&'a [u8]> {
-        let mut count = 0;
-        let mut keys = BTreeMap::new();
-        Commit::ptr_historic_scan(commit, start, end, |key, value| {
-            keys.entry(key).or_insert_with(|| {
-                if !value.is_empty() {
-                    count += 1;
-                }
-                !value.is_empty()
-            });
-        });
-        let mut vec = Vec::new();
-        vec.reserve_exact(count);
-        vec.extend(keys.into_iter().filter_map(|(k, v)| v.then_some(k)));
-        vec
+    /// see [`Snapshot::scan_limited`]
+    pub fn scan_limited(
+        &self,
+        start: &[u8],
+        end: &[u8],
+        offset: u32,
+        limit: u32,
+    ) -> Vec<(Vec<u8>, Vec<u8>)> {
+        let master = self.load_master();
+        owned(Commit::scan_window(master.head(), start, end, false, offset, limit))
     }
-    unsafe fn ptr_scan<'a>(
-        commit: *const Commit,
+    /// see [`Snapshot::scan_rev`]
+    pub fn scan_rev(
+        &self,
         start: &[u8],
         end: &[u8],
-    ) -> Vec<(&'a [u8], &'a [u8])> {
-        let mut count = 0;
-        let mut keys = BTreeMap::new();
-        Commit::ptr_historic_scan(commit, start, end, |key, value| {
-            keys.entry(key).or_insert_with(|| {
-                if !value.is_empty() {
-                    count += 1;
-                }
-                value
-            });
-        });
-        let mut vec = Vec::new();
-        vec.reserve_exact(count);
-        vec.extend(keys.into_iter().filter(|(_, v)| !v.is_empty()));
-        vec
+        offset: u32,
+        limit: u32,
+    ) -> Vec<(Vec<u8>, Vec<u8>)> {
+        let master = self.load_master();
+        owned(Commit::scan_window(master.head(), start, end, true, offset, limit))
     }
-
-    pub fn len(&self, key: &[u8]) -> u32 {
-        unsafe { Commit::ptr_len(self, key) }
+    /// see [`Snapshot::list_limited`]
+    pub fn list_limited(&self, start: &[u8], end: &[u8], offset: u32, limit: u32) -> Vec<Vec<u8>> {
+        let master = self.load_master();
+        let entries = Commit::scan_window(master.head(), start, end, false, offset, limit);
+        entries.into_iter().map(|(key, _)| key.to_vec()).collect()
     }
-    pub fn read<'a>(&'a self, key: &[u8]) -> &'a [u8] {
-        unsafe { Commit::ptr_read(self, key) }
+    /// see [`Snapshot::list_rev`]
+    pub fn list_rev(&self, start: &[u8], end: &[u8], offset: u32, limit: u32) -> Vec<Vec<u8>> {
+        let master = self.load_master();
+        let entries = Commit::scan_window(master.head(), start, end, true, offset, limit);
+        entries.into_iter().map(|(key, _)| key.to_vec()).collect()
     }
-    pub fn count(&self, start: &[u8], end: &[u8]) -> u32 {
-        unsafe { Commit::ptr_count(self, start, end) }
+    /// see [`Snapshot::scan_page`], each page is read from the newest commit
+    pub fn scan_page(&self, cursor: &mut ScanCursor, limit: u32) -> Vec<(Vec<u8>, Vec<u8>)> {
+        let master = self.load_master();
+        owned(cursor.next_page(master.head(), limit))
     }
-    pub fn list<'a>(&'a self, start: &[u8], end: &[u8]) -> Vec<&'a [u8]> {
-        unsafe { Commit::ptr_list(self, start, end) }
+    /// see [`Snapshot::scan_range`]
+    pub fn scan_range<K: AsRef<[u8]> + ?Sized>(
+        &self,
+        range: impl RangeBounds<K>,
+    ) -> Vec<(Vec<u8>, Vec<u8>)> {
+        let master = self.load_master();
+        let (start, end) = key_bounds(&range);
+        owned(Commit::scan_between(master.head(), start, end))
+    }
+    /// see [`Snapshot::scan_modified_since`]
+    pub fn scan_modified_since(
+        &self,
+        start: &[u8],
+        end: &[u8],
+        since: Duration,
+    ) -> Vec<(Vec<u8>, Vec<u8>)> {
+        let master = self.load_master();
+        owned(Commit::scan_modified_since(master.head(), start, end, since))
     }
-    pub fn scan<'a>(&'a self, start: &[u8], end: &[u8]) -> Vec<(&'a [u8], &'a [u8])> {
-        unsafe { Commit::ptr_scan(self, start, end) }
+    pub fn commit_count(&self) -> u64 {
+        self.snapshot().commit_count()
     }
-
-    /// callback may be called with multiple values for a same key
+    /// writes every commit after the first `seq` of a consistent snapshot, and returns the sequence number
+    /// to pass to the next call, the number of commits in the snapshot, so a backup from 0 followed by
+    /// backups from the returned numbers holds the whole history
     ///
-    /// consider only the first one
+    /// the commits are written in the format of the database file, with [`RawCodec`], a backup from 0 starts with
+    /// the header of the current format, so it is itself a database file, and appending the later backups to it
+    /// while it is not open restores the history with its original times, see [`Database::apply_backup`]
+    /// to apply them to an open database
     ///
-    /// callback may also be called with empty value, which means the key is not present, beware
-    unsafe fn ptr_historic_scan<'a>(
-        mut commit: *const Commit,
-        start: &[u8],
-        end: &[u8],
-        mut callback: impl FnMut(&'a [u8], &'a [u8]),
-    ) {
-        if !start
-            .len()
-            .checked_add(end.len())
-            .is_some_and(|x| x < u32::MAX as usize)
-        {
-            return;
+    /// the sequence numbers count the commits in the history, they stay the same once the database is opened again,
+    /// until it is compacted, see [`Database::compact_keeping`]
+    #[cfg(feature = "persistence")]
+    pub fn backup_since(&self, seq: u64, mut writer: impl io::Write) -> Result<u64, Error> {
+        let snapshot = self.snapshot();
+        let commits = Commit::history(snapshot.commit.head()).collect::<Vec<_>>();
+        let count = commits.len() as u64;
+        if seq > count {
+            return Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                "pathkvs backup sequence number is past the end of the history",
+            )
+            .into());
+        }
+        if seq == 0 {
+            writer.write_all(&Header::current(RawCodec::ID))?;
+        }
+        let mut record = Vec::new();
+        for commit in commits.iter().rev().skip(seq as usize) {
+            record.clear();
+            RawCodec.encode(
+                &Record::Commit {
+                    time: commit.time,
+                    changes: Cow::Borrowed(&commit.changes),
+                },
+                &mut record,
+            );
+            writer.write_all(&record)?;
+        }
+        writer.flush()?;
+        Ok(count)
+    }
+    /// writes the state of a consistent snapshot as a database file with a single commit, that gives every key
+    /// the value it has in the snapshot, and returns the sequence number of the snapshot, to pass to
+    /// [`Database::backup_since`] for the commits after it
+    ///
+    /// so a new replica starts from the latest value of each key instead of replaying the whole history,
+    /// system keys included, and like with [`Database::compact`] without the metadata of the commits
+    #[cfg(feature = "persistence")]
+    pub fn backup_snapshot(&self, mut writer: impl io::Write) -> Result<u64, Error> {
+        let snapshot = self.snapshot();
+        let head = snapshot.commit.head();
+        writer.write_all(&Header::current(RawCodec::ID))?;
+        let changes: Changes = Commit::scan(head, b"", b"")
+            .into_iter()
+            .chain(Commit::scan(head, system::PREFIX, b""))
+            .filter(|&(key, _)| key != system::META)
+            .map(|(key, value)| (key.to_vec(), Some(value.to_vec())))
+            .collect();
+        if let Some(time) = snapshot.time().filter(|_| !changes.is_empty()) {
+            let mut record = Vec::new();
+            RawCodec.encode(
+                &Record::Commit {
+                    time,
+                    changes: Cow::Owned(changes),
+                },
+                &mut record,
+            );
+            writer.write_all(&record)?;
         }
-        while let Some(reference) = commit.as_ref() {
-            for (k, v) in &reference.changes {
-                if k.len() >= start.len() + end.len() && k.starts_with(start) && k.ends_with(end) {
-                    callback(k, v);
+        writer.flush()?;
+        Ok(snapshot.commit_count())
+    }
+    /// commits each commit of a backup written by [`Database::backup_since`], in order and in a transaction of its own,
+    /// and returns how many were applied
+    ///
+    /// the commits get the time they are applied at, a backup that ends in the middle of a commit is an error,
+    /// but the commits before it stay applied, backups from 0 without a header, from before it existed, are read too
+    ///
+    /// the records are read with the codec the header names, so a copy of a database file written with another codec
+    /// can be applied too, an unknown codec is an error of kind [`ErrorKind::InvalidData`], as in `verify-backup`
+    #[cfg(feature = "persistence")]
+    pub fn apply_backup(&self, mut reader: impl Read) -> Result<u64, Error> {
+        let header = codec::read_header(&mut reader)?;
+        let codec = match header.codec() {
+            Some(id) => codec::by_id(id).ok_or_else(|| {
+                let error = format!("pathkvs backup is written with unknown codec {id}");
+                io::Error::new(ErrorKind::InvalidData, error)
+            })?,
+            None => Box::new(RawCodec),
+        };
+        let start = match header {
+            Header::Legacy(start) => start,
+            Header::Missing | Header::Version { .. } => Vec::new(),
+        };
+        let mut reader = RecordReader::new(io::BufReader::new(start.as_slice().chain(reader)));
+        let mut applied = 0;
+        while !io::BufRead::fill_buf(reader.get_mut())?.is_empty() {
+            let (changes, appends) = match codec.decode(&mut reader)? {
+                Record::Commit { changes, .. } => (changes, Default::default()),
+                Record::Append {
+                    changes, appends, ..
+                } => (changes, appends),
+            };
+            let mut ts = self.start_writes();
+            for (key, value) in changes.iter() {
+                match value {
+                    Some(value) => ts.write(key, value),
+                    None => ts.delete(key),
                 }
             }
-            commit = reference.prev;
+            for (key, bytes) in appends.iter() {
+                ts.append(key, bytes);
+            }
+            ts.commit()?;
+            applied += 1;
         }
+        Ok(applied)
     }
-}
-
-impl<'a> Snapshot<'a> {
-    pub fn len(&self, key: &[u8]) -> u32 {
-        self.commit.map(|x| x.len(key)).unwrap_or(0)
-    }
-    pub fn read(&self, key: &[u8]) -> &'a [u8] {
-        self.commit.map(|x| x.read(key)).unwrap_or(&[])
+    /// records that the replica `name` has applied the first `seq` commits of the history, as returned by
+    /// [`Database::backup_since`], or the [`Snapshot::commit_count`] of a [`follower::Follower`], so
+    /// [`Database::replication_status`] can tell how far behind it is
+    ///
+    /// kept in memory and not in the history, as writing it would be a commit that every replica is behind by,
+    /// so the replicas are only known again once they acknowledge again after the database is opened
+    #[cfg(feature = "persistence")]
+    pub fn ack_replication(&self, name: &str, seq: u64) {
+        self.replicas.ack(name, seq, self.now());
     }
-    pub fn count(&self, start: &[u8], end: &[u8]) -> u32 {
-        self.commit.map(|x| x.count(start, end)).unwrap_or(0)
+    /// waits until at least `replicas` replicas acknowledged with [`Database::ack_replication`] that they applied
+    /// the first `seq` commits, like the [`CommitReceipt::seq`] of a commit, so it survives the loss of this storage
+    ///
+    /// fails with [`error::NotReplicated`] if they did not before the timeout, the commit is not undone by that,
+    /// it is still in this database and the replicas may apply it later
+    #[cfg(feature = "persistence")]
+    pub fn wait_for_replicas(&self, seq: u64, replicas: usize, timeout: Duration) -> Result<(), error::NotReplicated> {
+        if self.replicas.wait(seq, replicas, timeout) {
+            Ok(())
+        } else {
+            Err(error::NotReplicated)
+        }
     }
-    pub fn list(&self, start: &[u8], end: &[u8]) -> Vec<&'a [u8]> {
-        self.commit
-            .map(|x| x.list(start, end))
-            .unwrap_or_else(Vec::new)
+    /// every replica that acknowledged a position, by name, with how far behind the newest commit it is
+    #[cfg(feature = "persistence")]
+    pub fn replication_status(&self) -> Vec<replication::ReplicaStatus> {
+        let acked = self.replicas.acked();
+        let snapshot = self.snapshot();
+        let count = snapshot.commit_count();
+        let oldest = acked.iter().map(|&(_, seq, _)| seq).min().unwrap_or(count);
+        // the size of each commit the most behind replica has yet to apply, newest first
+        let mut record = Vec::new();
+        let sizes = Commit::history(snapshot.commit.head())
+            .take_while(|commit| commit.seq > oldest)
+            .map(|commit| {
+                record.clear();
+                RawCodec.encode(
+                    &Record::Commit {
+                        time: commit.time,
+                        changes: Cow::Borrowed(&commit.changes),
+                    },
+                    &mut record,
+                );
+                record.len() as u64
+            })
+            .collect::<Vec<_>>();
+        acked
+            .into_iter()
+            .map(|(name, seq, acked)| {
+                let lag_commits = count.saturating_sub(seq);
+                let mut lag_bytes = sizes[..lag_commits as usize].iter().sum();
+                if seq == 0 {
+                    lag_bytes += codec::HEADER_LEN;
+                }
+                replication::ReplicaStatus {
+                    name,
+                    seq,
+                    acked,
+                    lag_commits,
+                    lag_bytes,
+                }
+            })
+            .collect()
     }
-    pub fn scan(&self, start: &[u8], end: &[u8]) -> Vec<(&'a [u8], &'a [u8])> {
-        self.commit
-            .map(|x| x.scan(start, end))
-            .unwrap_or_else(Vec::new)
+    /// rewrites the history in the storage with only the latest value of each key, leaving out
+    /// the values overwritten since and the keys deleted, so keys written again and again don't grow it forever
+    ///
+    /// see [`Database::compact_keeping`], none if the database is not persisted
+    #[cfg(feature = "persistence")]
+    pub fn compact(&self) -> Result<Option<Compaction>, Error> {
+        self.compact_keeping(Duration::ZERO)
     }
-}
-
-impl<'a> Transaction<'a> {
-    pub fn len(&mut self, key: &[u8]) -> u32 {
-        if key.is_empty() {
-            return 0;
+    /// like [`Database::compact`], but the commits of the last `age` are kept as they are, after a single commit
+    /// with the value each key had before them, and no metadata, at the time of the last of the commits it replaces
+    ///
+    /// the changes a [`retention::Retention`] keeps for longer are kept too, each older commit that has some
+    /// is kept with only those, and the single commit goes before them, at the time of the first of them
+    ///
+    /// the new history is swapped in with [`StorageBackend::replace`], so it is either the old or the new one,
+    /// commits go on being persisted while it is built, and only wait while it is written
+    ///
+    /// only the storage is compacted, the history in memory is kept whole until the database is opened again,
+    /// from then on snapshots before the commits kept see the compacted values, and the sequence numbers
+    /// no longer match the ones given before
+    #[cfg(feature = "persistence")]
+    pub fn compact_keeping(&self, age: Duration) -> Result<Option<Compaction>, Error> {
+        let Some(persistence) = &self.persistence else {
+            return Ok(None);
+        };
+        let codec = &*persistence.codec;
+        let now = self.now();
+        let since = now.saturating_sub(age);
+        let retentions = self.retentions();
+        // the changes the retention of their key keeps as they are, even in the commits older than `age`
+        let retained = |commit: &Commit, key: &[u8]| {
+            key != system::META
+                && retention::applies(&retentions, key)
+                    .is_some_and(|(_, retention)| commit.time >= now.saturating_sub(retention.history))
+        };
+        // built from what is persisted without the lock, so commits go on being persisted meanwhile
+        let serialized_master = persistence.serialized_master.load();
+        let mut older = Commit::history(serialized_master.head()).peekable();
+        let mut kept = Vec::new();
+        while let Some(commit) = older.next_if(|commit| commit.time >= since) {
+            kept.push(commit);
+        }
+        // always in the current format, whatever the format of the history it replaces
+        let mut history = Header::current(codec.id()).to_vec();
+        let mut commits = 0;
+        let mut partial = Vec::new();
+        if let Some(last) = older.peek().copied() {
+            let mut latest = HashMap::new();
+            for commit in older {
+                let mut changes = Changes::new();
+                for (key, value) in &commit.changes {
+                    if retained(commit, key) {
+                        changes.insert(key.clone(), value.clone());
+                    } else {
+                        latest.entry(key).or_insert(value);
+                    }
+                }
+                if !changes.is_empty() {
+                    partial.push((commit.time, changes));
+                }
+            }
+            // the metadata belongs to the commits replaced, not to the one replacing them
+            let changes: Changes = latest
+                .into_iter()
+                .filter(|(key, _)| key.as_slice() != system::META)
+                .filter_map(|(key, value)| Some((key.clone(), Some(value.clone()?))))
+                .collect();
+            // before the changes retained, which are older than the last of the commits replaced
+            let time = partial.last().map_or(last.time, |&(time, _)| time);
+            if !changes.is_empty() {
+                codec.encode(
+                    &Record::Commit {
+                        time,
+                        changes: Cow::Owned(changes),
+                    },
+                    &mut history,
+                );
+                commits += 1;
+            }
+        }
+        for (time, changes) in partial.into_iter().rev() {
+            codec.encode(
+                &Record::Commit {
+                    time,
+                    changes: Cow::Owned(changes),
+                },
+                &mut history,
+            );
+            commits += 1;
+        }
+        let encode = |commit: &Commit, history: &mut Vec<u8>| {
+            let (changes, appends) = commit.split_appends(codec.encodes_appends());
+            let time = commit.time;
+            codec.encode(
+                &if appends.is_empty() {
+                    Record::Commit { time, changes }
+                } else {
+                    Record::Append {
+                        time,
+                        changes,
+                        appends: Cow::Owned(appends),
+                    }
+                },
+                history,
+            );
+        };
+        for commit in kept.into_iter().rev() {
+            encode(commit, &mut history);
+            commits += 1;
+        }
+        let mut workbench = persistence.history_sink.lock().unwrap();
+        // the commits persisted since go after the ones kept, the rest wait for the lock
+        let persisted = persistence.serialized_master.load();
+        let newer = Commit::history(persisted.head())
+            .take_while(|&commit| !same(Some(commit), serialized_master.head()))
+            .collect::<Vec<_>>();
+        for commit in newer.into_iter().rev() {
+            encode(commit, &mut history);
+            commits += 1;
+        }
+        workbench.storage.replace(&history)?;
+        workbench.format_version = codec::FORMAT_VERSION;
+        workbench.clean = false;
+        let before = std::mem::replace(&mut workbench.cursor, history.len() as u64);
+        Ok(Some(Compaction {
+            before,
+            after: history.len() as u64,
+            commits,
+        }))
+    }
+    /// rewrites the history in the storage with every commit as it is, in the current format, so a history
+    /// from an older version, which is still read and appended to in the format it has, is brought up to date
+    ///
+    /// like [`Database::compact_keeping`] with an age that keeps every commit, none if the database is not persisted
+    #[cfg(feature = "persistence")]
+    pub fn migrate(&self) -> Result<Option<Compaction>, Error> {
+        self.compact_keeping(Duration::MAX)
+    }
+    /// the version of the format of the history in the storage, see [`codec::FORMAT_VERSION`],
+    /// none if the database is not persisted
+    #[cfg(feature = "persistence")]
+    pub fn format_version(&self) -> Option<u32> {
+        let persistence = self.persistence.as_ref()?;
+        Some(persistence.history_sink.lock().unwrap().format_version)
+    }
+    /// commit and persistence counters since the database was opened
+    pub fn metrics(&self) -> metrics::Metrics {
+        self.metrics.get()
+    }
+    /// commits other transactions can already see, but that were not written to the storage yet
+    ///
+    /// commits are persisted by the threads that commit them, so this grows when commits resolve
+    /// faster than the storage takes them, always zero for memory databases
+    pub fn persist_backlog(&self) -> u64 {
+        #[cfg(feature = "persistence")]
+        if self.persistence.is_some() {
+            return self.metrics.persist_backlog();
+        }
+        0
+    }
+    /// persists every commit that was not persisted yet and syncs the storage, whatever the sync mode,
+    /// so once this returns everything committed before is durable, and then frees the database
+    ///
+    /// dropping the database only frees it, in [`DatabaseWriteSyncMode::Flush`] and [`DatabaseWriteSyncMode::Cached`]
+    /// the last commits may still be in the buffers of the system, as may commits whose persisting failed
+    ///
+    /// on error the database is still freed, the commits not persisted yet are lost
+    ///
+    /// then the header is marked as closed cleanly, so the next open trusts the history to end with a complete
+    /// record, reads its byte strings without checking their lengths first and skips truncating the tail,
+    /// histories of a format older than [`codec::FORMAT_VERSION`] and storages that can't rewrite their header
+    /// are left unmarked, see [`StorageBackend::rewrite_header`]
+    pub fn close(self) -> Result<(), Error> {
+        #[cfg(feature = "persistence")]
+        if let Some(persistence) = &self.persistence {
+            self.persist()?;
+            let mut workbench = persistence.history_sink.lock().unwrap();
+            workbench.storage.sync(DatabaseWriteSyncMode::Sync)?;
+            if workbench.format_version == codec::FORMAT_VERSION && !workbench.clean {
+                let header = Header::closed(persistence.codec.id());
+                match workbench.storage.rewrite_header(&header) {
+                    Ok(()) => workbench.storage.sync(DatabaseWriteSyncMode::Sync)?,
+                    Err(error) if error.kind() == ErrorKind::Unsupported => {}
+                    Err(error) => return Err(error.into()),
+                }
+            }
+        }
+        Ok(())
+    }
+    /// calls `callback` for every commit that changes keys starting with `prefix`, at the `stage` of the commit,
+    /// with the time of the commit and its changes under the prefix sorted by key, deleted keys have empty values,
+    /// changes to the [`system`] keyspace are left out unless `prefix` is in it
+    ///
+    /// it is called on the thread that committed, before the commit returns, so commits from different threads
+    /// can call it concurrently, and with [`CommitStage::Persisted`](hook::CommitStage::Persisted) out of order
+    ///
+    /// the callback may commit to the database itself, if that changes keys under its prefix it is called again
+    pub fn on_commit(
+        &self,
+        prefix: &[u8],
+        stage: hook::CommitStage,
+        callback: impl Fn(Duration, &[(&[u8], &[u8])]) + Send + Sync + 'static,
+    ) -> hook::CommitHookId {
+        self.hooks.add(
+            prefix,
+            stage,
+            hook::Callback::Values(std::sync::Arc::new(callback)),
+        )
+    }
+    /// like [`Database::on_commit`], but each change says whether the key was written, deleted or expired,
+    /// so caches can react to keys whose time to live ran out differently from keys deleted on purpose
+    pub fn on_change(
+        &self,
+        prefix: &[u8],
+        stage: hook::CommitStage,
+        callback: impl Fn(Duration, &[(&[u8], hook::Change)]) + Send + Sync + 'static,
+    ) -> hook::CommitHookId {
+        self.hooks.add(
+            prefix,
+            stage,
+            hook::Callback::Changes(std::sync::Arc::new(callback)),
+        )
+    }
+    /// returns false if there was no such hook, a call already in progress is not interrupted
+    pub fn remove_commit_hook(&self, id: hook::CommitHookId) -> bool {
+        self.hooks.remove(id)
+    }
+
+    /// returns the length of the history in the storage once every resolved commit is persisted,
+    /// none if the database is not persisted
+    #[cfg(feature = "persistence")]
+    fn persist(&self) -> Result<Option<u64>, io::Error> {
+        let Some(persistence) = &self.persistence else {
+            return Ok(None);
+        };
+        loop {
+            let mut workbench = persistence.history_sink.lock().unwrap();
+            // loaded under the lock, otherwise another thread could persist past it in the meantime
+            let resolved_master = self.resolved_master.load();
+            let serialized_master = persistence.serialized_master.load();
+            let stack = Commit::history(resolved_master.head())
+                .take_while(|&commit| !same(Some(commit), serialized_master.head()))
+                .collect::<Vec<_>>();
+            if stack.is_empty() {
+                return Ok(Some(workbench.cursor));
+            }
+            // every commit waiting to be persisted is appended before a single sync, so when many
+            // threads commit at the same time, they share the cost of syncing
+            let stopwatch = metrics::Stopwatch::start();
+            let cursor = workbench.cursor;
+            workbench.storage.truncate(cursor)?;
+            // durably unmarked first, so a crash while appending is never taken for a clean close
+            if workbench.clean {
+                let header = Header::current(persistence.codec.id());
+                workbench.storage.rewrite_header(&header)?;
+                workbench.storage.sync(DatabaseWriteSyncMode::Sync)?;
+                workbench.clean = false;
+            }
+            let mut new_cursor = cursor;
+            let mut record = Vec::new();
+            let workbench = &mut *workbench;
+            let written = (|| {
+                for &commit_ref in stack.iter().rev() {
+                    let (changes, appends) =
+                        commit_ref.split_appends(persistence.codec.encodes_appends());
+                    let changes = match &mut workbench.dedup {
+                        Some(dedup) => {
+                            for key in appends.keys() {
+                                dedup.forget(key);
+                            }
+                            match dedup.filter(changes) {
+                                Some(changes) => changes,
+                                // still persisted, empty, so the commits keep their sequence numbers
+                                None => Cow::Owned(HashMap::new()),
+                            }
+                        }
+                        None => changes,
+                    };
+                    let time = commit_ref.time;
+                    record.clear();
+                    persistence.codec.encode(
+                        &if appends.is_empty() {
+                            Record::Commit { time, changes }
+                        } else {
+                            Record::Append {
+                                time,
+                                changes,
+                                appends: Cow::Owned(appends),
+                            }
+                        },
+                        &mut record,
+                    );
+                    workbench.storage.append(&record)?;
+                    new_cursor += record.len() as u64;
+                }
+                let sync = workbench.sync;
+                workbench.storage.sync(sync)
+            })();
+            if let Err(error) = written {
+                // the values given to the dedup may not have made it to the storage
+                if let Some(dedup) = &mut workbench.dedup {
+                    dedup.clear();
+                }
+                return Err(error);
+            }
+            self.metrics.persist(stack.len(), stopwatch.elapsed());
+            persistence.serialized_master.store(resolved_master);
+            workbench.cursor = new_cursor;
+        }
+    }
+}
+
+#[cfg(feature = "persistence")]
+impl<R: Read> Read for CountingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, io::Error> {
+        let len = self.inner.read(buf)?;
+        self.count += len as u64;
+        Ok(len)
+    }
+}
+
+impl Commit {
+    /// the commit of a record of the history, appends are resolved against `prev`, the commit before it
+    #[cfg(feature = "persistence")]
+    fn from_record(prev: Chain, record: Record) -> Commit {
+        let (time, changes) = match record {
+            Record::Commit { time, changes } => (time, changes.into_owned()),
+            Record::Append {
+                time,
+                changes,
+                appends,
+            } => {
+                let mut changes = changes.into_owned();
+                for (key, suffix) in appends.into_owned() {
+                    let mut value = Commit::read(prev.head(), &key).to_vec();
+                    value.extend_from_slice(&suffix);
+                    changes.insert(key, Some(value));
+                }
+                (time, changes)
+            }
+        };
+        let mut commit = Commit {
+            seq: Commit::commit_count(prev.head()) + 1,
+            prev,
+            time,
+            changes,
+            appended: HashMap::new(),
+            expired: HashSet::new(),
+            index: None,
+        };
+        commit.build_index();
+        commit
+    }
+    /// the changes to persist, and the bytes appended to each value, split out of the changes
+    /// so only those are persisted, if `encodes_appends`
+    #[cfg(feature = "persistence")]
+    fn split_appends(
+        &self,
+        encodes_appends: bool,
+    ) -> (Cow<'_, Changes>, HashMap<Vec<u8>, Vec<u8>>) {
+        let mut changes = Cow::Borrowed(&self.changes);
+        let mut appends = HashMap::new();
+        if !self.appended.is_empty() && encodes_appends {
+            let changes = changes.to_mut();
+            for (key, &len) in &self.appended {
+                let Some((key, Some(mut value))) = changes.remove_entry(key) else {
+                    panic!("pathkvs: appended key without a value");
+                };
+                let suffix = value.split_off(value.len() - len);
+                appends.insert(key, suffix);
+            }
+        }
+        (changes, appends)
+    }
+    /// the commit and every commit before it, newest first
+    fn history(commit: Option<&Commit>) -> impl Iterator<Item = &Commit> {
+        std::iter::successors(commit, |commit| commit.prev.head())
+    }
+    /// sets the index, if the sequence number of the commit calls for one, from the index of the last commit
+    /// before it that has one and the changes since, so `prev` and `seq` must not change after it
+    fn build_index(&mut self) {
+        if !self.seq.is_multiple_of(INDEX_INTERVAL) {
+            self.index = None;
+            return;
+        }
+        let mut pending = vec![&self.changes];
+        let mut index = Index::new();
+        for commit in Commit::history(self.prev.head()) {
+            if let Some(older) = &commit.index {
+                index = older.clone();
+                break;
+            }
+            pending.push(&commit.changes);
+        }
+        for changes in pending.into_iter().rev() {
+            for (key, value) in changes {
+                match value {
+                    Some(value) => {
+                        index.insert(Arc::from(key.as_slice()), Arc::from(value.as_slice()));
+                    }
+                    None => {
+                        index.remove(key.as_slice());
+                    }
+                }
+            }
+        }
+        self.index = Some(index);
+    }
+    fn len(commit: Option<&Commit>, key: &[u8]) -> u32 {
+        Commit::read(commit, key).len() as u32
+    }
+    fn read<'a>(commit: Option<&'a Commit>, key: &[u8]) -> &'a [u8] {
+        Commit::get(commit, key).unwrap_or(&[])
+    }
+    /// the value of the key, none if it was deleted or never written
+    fn get<'a>(commit: Option<&'a Commit>, key: &[u8]) -> Option<&'a [u8]> {
+        if key.len() > u32::MAX as usize {
+            return None;
+        }
+        for commit in Commit::history(commit) {
+            if let Some(value) = commit.changes.get(key) {
+                return value.as_deref();
+            }
+            if let Some(index) = &commit.index {
+                return index.get(key).map(|value| &**value);
+            }
+        }
+        None
+    }
+    /// the newest value of the key, from before it was deleted if it is
+    fn get_undeleted<'a>(commit: Option<&'a Commit>, key: &[u8]) -> Option<&'a [u8]> {
+        Commit::history(commit).find_map(|commit| match commit.changes.get(key) {
+            Some(Some(value)) => Some(value.as_slice()),
+            _ => None,
+        })
+    }
+    fn count(commit: Option<&Commit>, start: &[u8], end: &[u8]) -> u32 {
+        let keys = Commit::range(commit, start, end);
+        keys.values().filter(|value| value.is_some()).count() as u32
+    }
+    fn list<'a>(commit: Option<&'a Commit>, start: &[u8], end: &[u8]) -> Vec<&'a [u8]> {
+        let keys = Commit::range(commit, start, end);
+        keys.into_iter()
+            .filter_map(|(k, v)| v.is_some().then_some(k))
+            .collect()
+    }
+    fn list_deleted<'a>(commit: Option<&'a Commit>, start: &[u8], end: &[u8]) -> Vec<&'a [u8]> {
+        // whether the key was deleted by its newest change, and whether any older one gave it a value
+        let mut keys = BTreeMap::new();
+        Commit::historic_scan(commit, start, end, |key, value| {
+            let (deleted, had_value) = keys.entry(key).or_insert((value.is_none(), false));
+            *had_value |= *deleted && value.is_some();
+        });
+        keys.into_iter()
+            .filter_map(|(k, (_, had_value))| had_value.then_some(k))
+            .collect()
+    }
+    fn scan<'a>(commit: Option<&'a Commit>, start: &[u8], end: &[u8]) -> Entries<'a> {
+        let keys = Commit::range(commit, start, end);
+        keys.into_iter()
+            .filter_map(|(k, v)| Some((k, v?)))
+            .collect()
+    }
+    /// the entries of [`Commit::scan`], in reverse order if `rev`, without the first `offset` and at most `limit`
+    fn scan_window<'a>(
+        commit: Option<&'a Commit>,
+        start: &[u8],
+        end: &[u8],
+        rev: bool,
+        offset: u32,
+        limit: u32,
+    ) -> Entries<'a> {
+        Commit::scan_past(commit, start, end, rev, None, offset, limit)
+    }
+    /// like [`Commit::scan_window`], but only the keys that come after `resume` in the order of the scan
+    ///
+    /// only the changes since the last index are collected, the index is walked in order and left as soon as
+    /// there are enough entries, so the cost depends on the entries returned and skipped, not on the whole range
+    fn scan_past<'a>(
+        commit: Option<&'a Commit>,
+        start: &[u8],
+        end: &[u8],
+        rev: bool,
+        resume: Option<&[u8]>,
+        offset: u32,
+        limit: u32,
+    ) -> Entries<'a> {
+        if start
+            .len()
+            .checked_add(end.len())
+            .is_none_or(|x| x >= u32::MAX as usize)
+        {
+            return Vec::new();
+        }
+        let past = |key: &[u8]| match resume {
+            Some(resume) if rev => key < resume,
+            Some(resume) => key > resume,
+            None => true,
+        };
+        let mut changes = BTreeMap::new();
+        let mut index = None;
+        for commit in Commit::history(commit) {
+            for (k, v) in &commit.changes {
+                if system::in_range(k, start, end) && past(k) {
+                    changes.entry(k.as_slice()).or_insert(v.as_deref());
+                }
+            }
+            if let Some(commit_index) = &commit.index {
+                index = Some(commit_index);
+                break;
+            }
+        }
+        let upper = keys::prefix_end(start);
+        let mut upper = match &upper {
+            Some(upper) => Bound::Excluded(upper.as_slice()),
+            None => Bound::Unbounded,
+        };
+        let mut lower = Bound::Included(start);
+        match resume {
+            Some(resume) if rev => {
+                upper = match upper {
+                    Bound::Excluded(prefix_end) if prefix_end <= resume => upper,
+                    _ => Bound::Excluded(resume),
+                };
+            }
+            Some(resume) if resume >= start => lower = Bound::Excluded(resume),
+            _ => {}
+        }
+        // the range of the index panics if the start comes after the end
+        let empty = match (lower, upper) {
+            (Bound::Included(lower) | Bound::Excluded(lower), Bound::Excluded(upper)) => lower >= upper,
+            _ => false,
+        };
+        let indexed = index
+            .filter(|_| !empty)
+            .into_iter()
+            .flat_map(|index| index.range::<_, [u8]>((lower, upper)));
+        let indexed: Box<dyn Iterator<Item = _>> = match rev {
+            false => Box::new(indexed),
+            true => Box::new(indexed.rev()),
+        };
+        // keys changed since the index was built are taken from the changes
+        let mut indexed = indexed
+            .map(|(k, v)| (&**k, Some(&**v)))
+            .filter(|&(k, _)| system::in_range(k, start, end) && !changes.contains_key(k))
+            .peekable();
+        let changed: Box<dyn Iterator<Item = _>> = match rev {
+            false => Box::new(changes.iter()),
+            true => Box::new(changes.iter().rev()),
+        };
+        let mut changed = changed.map(|(&k, &v)| (k, v)).peekable();
+        // both are in the same order and share no keys, so merging them keeps it
+        let merged = std::iter::from_fn(|| {
+            let from_changed = match (changed.peek(), indexed.peek()) {
+                (Some(&(a, _)), Some(&(b, _))) => (a < b) != rev,
+                (changed, _) => changed.is_some(),
+            };
+            match from_changed {
+                true => changed.next(),
+                false => indexed.next(),
+            }
+        });
+        merged
+            .filter_map(|(k, v)| Some((k, v?)))
+            .skip(offset as usize)
+            .take(limit as usize)
+            .collect()
+    }
+    fn scan_between<'a>(
+        commit: Option<&'a Commit>,
+        start: Bound<&[u8]>,
+        end: Bound<&[u8]>,
+    ) -> Entries<'a> {
+        let keys = Commit::between(commit, start, end);
+        keys.into_iter()
+            .filter_map(|(k, v)| Some((k, v?)))
+            .collect()
+    }
+    /// like [`Commit::range`], but every key between the bounds, in lexicographic order
+    fn between<'a>(
+        commit: Option<&'a Commit>,
+        start: Bound<&[u8]>,
+        end: Bound<&[u8]>,
+    ) -> BTreeMap<&'a [u8], Option<&'a [u8]>> {
+        let mut keys = BTreeMap::new();
+        // the range of the index panics if the start comes after the end
+        let empty = match (start, end) {
+            (Bound::Included(start), Bound::Included(end)) => start > end,
+            (Bound::Included(start) | Bound::Excluded(start), Bound::Excluded(end))
+            | (Bound::Excluded(start), Bound::Included(end)) => start >= end,
+            _ => false,
+        };
+        if empty {
+            return keys;
+        }
+        for commit in Commit::history(commit) {
+            for (k, v) in &commit.changes {
+                if system::in_bounds(k, start, end) {
+                    keys.entry(k.as_slice()).or_insert(v.as_deref());
+                }
+            }
+            let Some(index) = &commit.index else {
+                continue;
+            };
+            for (k, v) in index.range::<_, [u8]>((start, end)) {
+                if system::in_bounds(k, start, end) {
+                    keys.entry(&**k).or_insert(Some(&**v));
+                }
+            }
+            break;
+        }
+        keys
+    }
+    /// every key in the range with its value, from the changes of the commits since the last index
+    /// over the keys of the index, deleted keys included with none
+    fn range<'a>(
+        commit: Option<&'a Commit>,
+        start: &[u8],
+        end: &[u8],
+    ) -> BTreeMap<&'a [u8], Option<&'a [u8]>> {
+        let mut keys = BTreeMap::new();
+        if start
+            .len()
+            .checked_add(end.len())
+            .is_none_or(|x| x >= u32::MAX as usize)
+        {
+            return keys;
+        }
+        for commit in Commit::history(commit) {
+            for (k, v) in &commit.changes {
+                if system::in_range(k, start, end) {
+                    keys.entry(k.as_slice()).or_insert(v.as_deref());
+                }
+            }
+            let Some(index) = &commit.index else {
+                continue;
+            };
+            let upper = keys::prefix_end(start);
+            let upper = match &upper {
+                Some(upper) => Bound::Excluded(upper.as_slice()),
+                None => Bound::Unbounded,
+            };
+            for (k, v) in index.range::<_, [u8]>((Bound::Included(start), upper)) {
+                if system::in_range(k, start, end) {
+                    keys.entry(&**k).or_insert(Some(&**v));
+                }
+            }
+            break;
+        }
+        keys
+    }
+
+    /// the keys changed by the commits after the unix time `since`, with the value they have at `commit`,
+    /// deleted keys have empty values
+    fn scan_modified_since<'a>(
+        commit: Option<&'a Commit>,
+        start: &[u8],
+        end: &[u8],
+        since: Duration,
+    ) -> BTreeMap<&'a [u8], &'a [u8]> {
+        let mut keys = BTreeMap::new();
+        if start
+            .len()
+            .checked_add(end.len())
+            .is_none_or(|x| x >= u32::MAX as usize)
+        {
+            return keys;
+        }
+        // the commits are ordered by time, so the ones after `since` are the newest
+        for commit in Commit::history(commit).take_while(|commit| commit.time > since) {
+            for (k, v) in &commit.changes {
+                if system::in_range(k, start, end) {
+                    keys.entry(k.as_slice())
+                        .or_insert(v.as_deref().unwrap_or(&[]));
+                }
+            }
+        }
+        keys
+    }
+
+    fn commit_count(commit: Option<&Commit>) -> u64 {
+        commit.map_or(0, |commit| commit.seq)
+    }
+
+    /// callback may be called with multiple values for a same key
+    ///
+    /// consider only the first one
+    ///
+    /// callback may also be called with none, which means the key was deleted, beware
+    fn historic_scan<'a>(
+        commit: Option<&'a Commit>,
+        start: &[u8],
+        end: &[u8],
+        mut callback: impl FnMut(&'a [u8], Option<&'a [u8]>),
+    ) {
+        if !start
+            .len()
+            .checked_add(end.len())
+            .is_some_and(|x| x < u32::MAX as usize)
+        {
+            return;
+        }
+        for commit in Commit::history(commit) {
+            for (k, v) in &commit.changes {
+                if system::in_range(k, start, end) {
+                    callback(k, v.as_deref());
+                }
+            }
+        }
+    }
+}
+
+impl Chain {
+    fn head(&self) -> Option<&Commit> {
+        self.0.as_deref()
+    }
+    /// the newest commit of the chain `f` is true for, with the commits before it, empty if there is none
+    fn find(&self, f: impl Fn(&Commit) -> bool) -> Chain {
+        let mut chain = self.clone();
+        while let Some(commit) = &chain.0 {
+            if f(commit) {
+                break;
+            }
+            chain = commit.prev.clone();
+        }
+        chain
+    }
+}
+
+impl Drop for Chain {
+    fn drop(&mut self) {
+        // one commit at a time, dropping each commit along with the one before it would recurse
+        // as deep as the history, and overflow the stack of long histories
+        let mut next = self.0.take();
+        while let Some(commit) = next {
+            next = Arc::into_inner(commit).and_then(|mut commit| commit.prev.0.take());
+        }
+    }
+}
+
+impl Master {
+    fn new(chain: Chain) -> Self {
+        Self(RwLock::new(chain))
+    }
+    fn load(&self) -> Chain {
+        self.0.read().unwrap().clone()
+    }
+    #[cfg(feature = "persistence")]
+    fn store(&self, chain: Chain) {
+        *self.0.write().unwrap() = chain;
+    }
+    /// makes the commit the newest one, if the newest one is still the one it was made on, its `prev`,
+    /// otherwise gives it back along with the newest one
+    fn publish(&self, commit: Box<Commit>) -> Result<Arc<Commit>, (Box<Commit>, Chain)> {
+        let mut master = self.0.write().unwrap();
+        if !same(master.head(), commit.prev.head()) {
+            return Err((commit, master.clone()));
+        }
+        let commit = Arc::<Commit>::from(commit);
+        *master = Chain(Some(commit.clone()));
+        Ok(commit)
+    }
+}
+
+/// whether both are the same commit, or both are empty
+fn same(a: Option<&Commit>, b: Option<&Commit>) -> bool {
+    a.map(std::ptr::from_ref) == b.map(std::ptr::from_ref)
+}
+
+impl ScanCursor {
+    /// the keys that start with `start` and end with `end`, like [`Snapshot::scan`]
+    pub fn new(start: &[u8], end: &[u8]) -> Self {
+        Self {
+            start: start.to_vec(),
+            end: end.to_vec(),
+            rev: false,
+            last: None,
+            done: false,
+        }
+    }
+    /// from the last key to the first, like [`Snapshot::scan_rev`]
+    pub fn rev(mut self) -> Self {
+        self.rev = true;
+        self
+    }
+    /// goes on from the key after `key`, as returned by [`ScanCursor::last`]
+    pub fn after(mut self, key: &[u8]) -> Self {
+        self.last = Some(key.to_vec());
+        self
+    }
+    /// the last key returned, none before the first page
+    pub fn last(&self) -> Option<&[u8]> {
+        self.last.as_deref()
+    }
+    /// whether the last page reached the end of the range, after that the pages are empty
+    pub fn is_done(&self) -> bool {
+        self.done
+    }
+    /// the next page, at most `limit` entries, from one more so the end can be told apart from a full page
+    fn next_page<'a>(&mut self, commit: Option<&'a Commit>, limit: u32) -> Entries<'a> {
+        if self.done {
+            return Vec::new();
+        }
+        let (start, end, last) = (&self.start, &self.end, self.last.as_deref());
+        let mut page =
+            Commit::scan_past(commit, start, end, self.rev, last, 0, limit.saturating_add(1));
+        self.done = page.len() <= limit as usize;
+        page.truncate(limit as usize);
+        if let Some(&(key, _)) = page.last() {
+            self.last = Some(key.to_vec());
+        }
+        page
+    }
+}
+
+impl Snapshot {
+    pub fn len(&self, key: &[u8]) -> Result<u32, SnapshotExpired> {
+        self.check()?;
+        Ok(Commit::len(self.commit.head(), key))
+    }
+    pub fn read(&self, key: &[u8]) -> Result<&[u8], SnapshotExpired> {
+        self.check()?;
+        Ok(Commit::read(self.commit.head(), key))
+    }
+    /// whether the key has a value, which may be empty, false if it was deleted or never written
+    pub fn exists(&self, key: &[u8]) -> Result<bool, SnapshotExpired> {
+        self.check()?;
+        Ok(Commit::get(self.commit.head(), key).is_some())
+    }
+    /// up to `len` bytes of the value of the key starting at `offset`, fewer if the value ends before that,
+    /// and none if it ends before `offset`, for reading pieces of large values
+    pub fn read_range(
+        &self,
+        key: &[u8],
+        offset: u32,
+        len: u32,
+    ) -> Result<&[u8], SnapshotExpired> {
+        Ok(value_range(self.read(key)?, offset, len))
+    }
+    pub fn count(&self, start: &[u8], end: &[u8]) -> Result<u32, SnapshotExpired> {
+        self.check()?;
+        Ok(Commit::count(self.commit.head(), start, end))
+    }
+    pub fn list(&self, start: &[u8], end: &[u8]) -> Result<Vec<&[u8]>, SnapshotExpired> {
+        self.check()?;
+        Ok(Commit::list(self.commit.head(), start, end))
+    }
+    /// the keys in the range that were deleted and had a value before, in order,
+    /// each one can be brought back with [`Transaction::undelete`]
+    ///
+    /// keys deleted at any time in the history are listed, once the database is opened again after
+    /// [`Database::compact`], only those deleted in the commits it kept
+    pub fn list_deleted(&self, start: &[u8], end: &[u8]) -> Result<Vec<&[u8]>, SnapshotExpired> {
+        self.check()?;
+        Ok(Commit::list_deleted(self.commit.head(), start, end))
+    }
+    pub fn scan(&self, start: &[u8], end: &[u8]) -> Result<Entries<'_>, SnapshotExpired> {
+        self.check()?;
+        Ok(Commit::scan(self.commit.head(), start, end))
+    }
+    /// like [`Snapshot::scan`], but without the first `offset` entries and at most `limit` entries,
+    /// the entries after those are never visited
+    pub fn scan_limited(
+        &self,
+        start: &[u8],
+        end: &[u8],
+        offset: u32,
+        limit: u32,
+    ) -> Result<Entries<'_>, SnapshotExpired> {
+        self.check()?;
+        Ok(Commit::scan_window(self.commit.head(), start, end, false, offset, limit))
+    }
+    /// like [`Snapshot::scan_limited`], but from the last key to the first,
+    /// so `scan_rev(prefix, b"", 0, 50)` are the last 50 entries under the prefix
+    pub fn scan_rev(
+        &self,
+        start: &[u8],
+        end: &[u8],
+        offset: u32,
+        limit: u32,
+    ) -> Result<Entries<'_>, SnapshotExpired> {
+        self.check()?;
+        Ok(Commit::scan_window(self.commit.head(), start, end, true, offset, limit))
+    }
+    /// the keys of [`Snapshot::scan_limited`]
+    pub fn list_limited(
+        &self,
+        start: &[u8],
+        end: &[u8],
+        offset: u32,
+        limit: u32,
+    ) -> Result<Vec<&[u8]>, SnapshotExpired> {
+        Ok(keys_of(self.scan_limited(start, end, offset, limit)?))
+    }
+    /// the keys of [`Snapshot::scan_rev`]
+    pub fn list_rev(
+        &self,
+        start: &[u8],
+        end: &[u8],
+        offset: u32,
+        limit: u32,
+    ) -> Result<Vec<&[u8]>, SnapshotExpired> {
+        Ok(keys_of(self.scan_rev(start, end, offset, limit)?))
+    }
+    /// the next page of the scan of the cursor, at most `limit` entries, with the keys after the last one
+    /// of the page before, and moves the cursor past them, so a large range is read a page at a time
+    pub fn scan_page(
+        &self,
+        cursor: &mut ScanCursor,
+        limit: u32,
+    ) -> Result<Entries<'_>, SnapshotExpired> {
+        self.check()?;
+        Ok(cursor.next_page(self.commit.head(), limit))
+    }
+    /// every key between the bounds of the range with its value, in lexicographic order, unlike [`Snapshot::scan`],
+    /// which matches a prefix and a suffix, such as `scan_range(&b"user:1000"[..]..&b"user:2000"[..])`
+    ///
+    /// the system keys are only seen if the start of the range is in [`system::PREFIX`], like with the other scans
+    pub fn scan_range<K: AsRef<[u8]> + ?Sized>(
+        &self,
+        range: impl RangeBounds<K>,
+    ) -> Result<Entries<'_>, SnapshotExpired> {
+        self.check()?;
+        let (start, end) = key_bounds(&range);
+        Ok(Commit::scan_between(self.commit.head(), start, end))
+    }
+    /// like [`Snapshot::scan`], but only the keys changed by commits after the unix time `since`,
+    /// deleted keys included, with an empty value, so a client that keeps the time of its last sync
+    /// only gets what changed since then
+    ///
+    /// only the commits after `since` are visited, so it costs as much as the changes, not the whole range
+    pub fn scan_modified_since(
+        &self,
+        start: &[u8],
+        end: &[u8],
+        since: Duration,
+    ) -> Result<Entries<'_>, SnapshotExpired> {
+        self.check()?;
+        let keys = Commit::scan_modified_since(self.commit.head(), start, end, since);
+        Ok(keys.into_iter().collect())
+    }
+    /// reads fail once the snapshot is older than [`Database::max_snapshot_age`]
+    pub fn check(&self) -> Result<(), SnapshotExpired> {
+        match self.expires {
+            Some(expires) if now_since_epoch() > expires => Err(SnapshotExpired),
+            _ => Ok(()),
+        }
+    }
+    pub fn commit_count(&self) -> u64 {
+        Commit::commit_count(self.commit.head())
+    }
+    /// the unix time of the commit this snapshot is looking at, none if the snapshot is empty
+    pub fn time(&self) -> Option<Duration> {
+        self.commit.head().map(|x| x.time)
+    }
+}
+
+#[cfg(feature = "rayon")]
+impl Snapshot {
+    /// same as [`Snapshot::scan`], but the range is read from the index of the last commit that has one
+    /// with its keys split by the byte after `start`, and the parts are read in parallel by the rayon thread pool,
+    /// then the changes of the commits after it, at most [`INDEX_INTERVAL`], are laid over the result
+    ///
+    /// only worth it for ranges with very many keys, for small scans splitting costs more than it saves
+    pub fn par_scan<'a>(&'a self, start: &[u8], end: &[u8]) -> Result<Entries<'a>, SnapshotExpired> {
+        use rayon::prelude::*;
+
+        self.check()?;
+        if start
+            .len()
+            .checked_add(end.len())
+            .is_none_or(|x| x >= u32::MAX as usize)
+        {
+            return Ok(Vec::new());
+        }
+        // the newest change of each key in the commits after the index, none for deleted keys
+        let mut newer = BTreeMap::<&'a [u8], Option<&'a [u8]>>::new();
+        let mut index = None;
+        for commit in Commit::history(self.commit.head()) {
+            for (k, v) in &commit.changes {
+                if system::in_range(k, start, end) {
+                    newer.entry(k.as_slice()).or_insert(v.as_deref());
+                }
+            }
+            if let Some(found) = &commit.index {
+                index = Some(found);
+                break;
+            }
+        }
+        let indexed = match index {
+            Some(index) => {
+                let in_range = |k: &[u8]| system::in_range(k, start, end) && !newer.contains_key(k);
+                let exact = index
+                    .get_key_value(start)
+                    .filter(|(k, _)| in_range(k))
+                    .map(|(k, v)| (&**k, &**v));
+                let parts = (0..=u8::MAX)
+                    .into_par_iter()
+                    .map(|byte| {
+                        let mut lower = start.to_vec();
+                        lower.push(byte);
+                        let upper = keys::prefix_end(&lower);
+                        let upper = match &upper {
+                            Some(upper) => Bound::Excluded(upper.as_slice()),
+                            None => Bound::Unbounded,
+                        };
+                        index
+                            .range::<_, [u8]>((Bound::Included(lower.as_slice()), upper))
+                            .filter(|(k, _)| in_range(k))
+                            .map(|(k, v)| (&**k, &**v))
+                            .collect::<Entries<'a>>()
+                    })
+                    .collect::<Vec<_>>();
+                exact.into_iter().chain(parts.into_iter().flatten()).collect()
+            }
+            None => Vec::new(),
+        };
+        // both are sorted by key and have no key in common
+        let mut entries = Vec::with_capacity(indexed.len() + newer.len());
+        let mut newer = newer
+            .into_iter()
+            .filter_map(|(k, v)| Some((k, v?)))
+            .peekable();
+        for entry in indexed {
+            while let Some(change) = newer.next_if(|&(k, _)| k < entry.0) {
+                entries.push(change);
+            }
+            entries.push(entry);
+        }
+        entries.extend(newer);
+        Ok(entries)
+    }
+}
+
+impl<'a> Transaction<'a> {
+    pub fn len(&mut self, key: &[u8]) -> u32 {
+        if key.is_empty() {
+            return 0;
         }
         self.read(key).len() as u32
     }
+    /// see [`Snapshot::read_range`], the whole key counts as read
+    pub fn read_range<'b>(&'b mut self, key: &[u8], offset: u32, len: u32) -> &'b [u8] {
+        value_range(self.read(key), offset, len)
+    }
     pub fn read<'b>(&'b mut self, key: &[u8]) -> &'b [u8] {
+        self.get(key).unwrap_or(&[])
+    }
+    /// whether the key has a value, which may be empty, false if it was deleted or never written,
+    /// the key counts as read
+    pub fn exists(&mut self, key: &[u8]) -> bool {
+        self.get(key).is_some()
+    }
+    fn get<'b>(&'b mut self, key: &[u8]) -> Option<&'b [u8]> {
         if key.is_empty() {
-            return &[];
+            return None;
+        }
+        if let Some(merge) = self.merges.remove(key) {
+            self.resolve_merge(key.to_vec(), merge);
         }
         if let Some(value) = self.commit.changes.get(key) {
-            return value;
+            return value.as_deref();
+        }
+        #[cfg(feature = "fs")]
+        if let Some(value) = self.spill.as_mut().and_then(|spill| spill.read(key)) {
+            return Some(value);
         }
         self.reads.insert(key.to_vec());
-        unsafe { Commit::ptr_read(self.commit.prev, key) }
+        Commit::get(self.commit.prev.head(), key)
     }
 
     pub fn count(&mut self, start: &[u8], end: &[u8]) -> u32 {
+        self.resolve_merges(|key| system::in_range(key, start, end));
+        self.unspill();
         self.register_scan(start, end);
-        unsafe { Commit::ptr_count(&self.commit, start, end) }
+        Commit::count(Some(&self.commit), start, end)
     }
     pub fn list<'b>(&'b mut self, start: &[u8], end: &[u8]) -> Vec<&'b [u8]> {
+        self.resolve_merges(|key| system::in_range(key, start, end));
+        self.unspill();
         self.register_scan(start, end);
-        unsafe { Commit::ptr_list(&self.commit, start, end) }
+        Commit::list(Some(&self.commit), start, end)
     }
     pub fn scan<'b>(&'b mut self, start: &[u8], end: &[u8]) -> Vec<(&'b [u8], &'b [u8])> {
+        self.resolve_merges(|key| system::in_range(key, start, end));
+        self.unspill();
+        self.register_scan(start, end);
+        Commit::scan(Some(&self.commit), start, end)
+    }
+    /// see [`Snapshot::scan_limited`], the whole range counts as read, not only the entries returned
+    pub fn scan_limited<'b>(
+        &'b mut self,
+        start: &[u8],
+        end: &[u8],
+        offset: u32,
+        limit: u32,
+    ) -> Vec<(&'b [u8], &'b [u8])> {
+        self.scan_window(start, end, false, offset, limit)
+    }
+    /// see [`Snapshot::scan_rev`], the whole range counts as read, not only the entries returned
+    pub fn scan_rev<'b>(
+        &'b mut self,
+        start: &[u8],
+        end: &[u8],
+        offset: u32,
+        limit: u32,
+    ) -> Vec<(&'b [u8], &'b [u8])> {
+        self.scan_window(start, end, true, offset, limit)
+    }
+    /// see [`Snapshot::list_limited`], the whole range counts as read
+    pub fn list_limited<'b>(
+        &'b mut self,
+        start: &[u8],
+        end: &[u8],
+        offset: u32,
+        limit: u32,
+    ) -> Vec<&'b [u8]> {
+        keys_of(self.scan_window(start, end, false, offset, limit))
+    }
+    /// see [`Snapshot::list_rev`], the whole range counts as read
+    pub fn list_rev<'b>(&'b mut self, start: &[u8], end: &[u8], offset: u32, limit: u32) -> Vec<&'b [u8]> {
+        keys_of(self.scan_window(start, end, true, offset, limit))
+    }
+    fn scan_window<'b>(
+        &'b mut self,
+        start: &[u8],
+        end: &[u8],
+        rev: bool,
+        offset: u32,
+        limit: u32,
+    ) -> Vec<(&'b [u8], &'b [u8])> {
+        self.resolve_merges(|key| system::in_range(key, start, end));
+        self.unspill();
         self.register_scan(start, end);
-        unsafe { Commit::ptr_scan(&self.commit, start, end) }
+        Commit::scan_window(Some(&self.commit), start, end, rev, offset, limit)
+    }
+    /// see [`Snapshot::scan_range`], the transaction conflicts with commits that change any key between the bounds
+    pub fn scan_range<K: AsRef<[u8]> + ?Sized>(
+        &mut self,
+        range: impl RangeBounds<K>,
+    ) -> Vec<(&[u8], &[u8])> {
+        let (start, end) = key_bounds(&range);
+        self.resolve_merges(|key| system::in_bounds(key, start, end));
+        self.unspill();
+        self.ranges
+            .insert((start.map(<[u8]>::to_vec), end.map(<[u8]>::to_vec)));
+        Commit::scan_between(Some(&self.commit), start, end)
+    }
+    /// see [`Snapshot::scan_modified_since`], the writes of the transaction count as changed,
+    /// and the whole range counts as read
+    pub fn scan_modified_since<'b>(
+        &'b mut self,
+        start: &[u8],
+        end: &[u8],
+        since: Duration,
+    ) -> Vec<(&'b [u8], &'b [u8])> {
+        self.resolve_merges(|key| system::in_range(key, start, end));
+        self.unspill();
+        self.register_scan(start, end);
+        let mut keys = Commit::scan_modified_since(self.commit.prev.head(), start, end, since);
+        for (k, v) in &self.commit.changes {
+            if system::in_range(k, start, end) {
+                keys.insert(k, v.as_deref().unwrap_or(&[]));
+            }
+        }
+        keys.into_iter().collect()
+    }
+    /// turns a pending merge into a write of the value the key has now, merged
+    fn resolve_merge(&mut self, key: Vec<u8>, merge: merge::Merge) {
+        let value = merge.apply(Commit::read(self.commit.prev.head(), &key));
+        self.stage(&key, Some(&value));
+        self.reads.insert(key);
+    }
+    /// ranges are read from the staged writes, so the pending merges of the keys in them have to be resolved first,
+    /// the others stay pending, so they still don't conflict
+    fn resolve_merges(&mut self, scanned: impl Fn(&[u8]) -> bool) {
+        let keys = self
+            .merges
+            .keys()
+            .filter(|key| scanned(key))
+            .cloned()
+            .collect::<Vec<_>>();
+        for key in keys {
+            let merge = self.merges.remove(&key).expect("listed from the merges");
+            self.resolve_merge(key, merge);
+        }
+    }
+    /// ranges are read from the staged writes in memory, so they have to be brought back first
+    fn unspill(&mut self) {
+        #[cfg(feature = "fs")]
+        if let Some(spill) = &mut self.spill {
+            spill.unspill(&mut self.commit.changes);
+        }
     }
     fn register_scan(&mut self, start: &[u8], end: &[u8]) {
         if start
@@ -526,48 +2278,383 @@ impl<'a> Transaction<'a> {
         }
     }
 
+    /// if the validator of the database refuses the write, it is not staged and the commit fails
     pub fn write(&mut self, key: &[u8], value: &[u8]) {
+        if let Err(error) = self.try_write(key, value) {
+            self.invalid.get_or_insert(Box::new(error));
+        }
+    }
+    /// like [`Transaction::write`], but if the validator refuses the write the error is returned
+    /// instead of failing the commit, the write is still not staged
+    pub fn try_write(&mut self, key: &[u8], value: &[u8]) -> Result<(), ValidationError> {
         if key.is_empty() {
-            return;
+            return Ok(());
+        }
+        if let Some(validator) = self.database.validator {
+            validator(key, value)?;
+        }
+        self.merges.remove(key);
+        self.stage(key, Some(value));
+        Ok(())
+    }
+    /// removes the key, unlike writing an empty value, which the key then has,
+    /// it no longer exists and is left out of counts, lists and scans
+    ///
+    /// the validator of the database is given an empty value, like [`Transaction::write`] otherwise
+    pub fn delete(&mut self, key: &[u8]) {
+        if let Err(error) = self.try_delete(key) {
+            self.invalid.get_or_insert(Box::new(error));
+        }
+    }
+    /// like [`Transaction::delete`], but if the validator refuses it the error is returned
+    /// instead of failing the commit, like [`Transaction::try_write`]
+    pub fn try_delete(&mut self, key: &[u8]) -> Result<(), ValidationError> {
+        if key.is_empty() {
+            return Ok(());
+        }
+        if let Some(validator) = self.database.validator {
+            validator(key, &[])?;
+        }
+        self.merges.remove(key);
+        self.stage(key, None);
+        self.forget_content_type(key);
+        Ok(())
+    }
+    /// deletes the key because its time to live ran out, the same as [`Transaction::delete`],
+    /// but the hooks of [`Database::on_change`] are told it expired instead of being deleted
+    ///
+    /// only the hooks tell them apart, the history keeps it as a deletion,
+    /// writing the key again in the same transaction makes it a write again
+    pub fn expire(&mut self, key: &[u8]) {
+        self.delete(key);
+        if let Some(None) = self.commit.changes.get(key) {
+            self.commit.expired.insert(key.to_vec());
+        }
+    }
+    /// attaches the metadata to the commit, like its author, the reason for it or the id of the request
+    /// that made it, replacing the metadata set before, see [`CommitSummary::meta`]
+    ///
+    /// it is written to [`system::META`] in the same commit, so it is persisted, backed up and replicated
+    /// along with it, it is not given to the validator, and it is kept in memory, so it should be small
+    pub fn set_meta(&mut self, meta: &[u8]) {
+        self.merges.remove(system::META);
+        self.stage(system::META, Some(meta));
+    }
+    /// writes back the last value the key had before it was deleted, see [`Snapshot::list_deleted`],
+    /// returns false if the key exists or never had a value, the key counts as read
+    pub fn undelete(&mut self, key: &[u8]) -> bool {
+        if self.exists(key) {
+            return false;
         }
+        let Some(value) = Commit::get_undeleted(self.commit.prev.head(), key).map(<[u8]>::to_vec) else {
+            return false;
+        };
+        self.write(key, &value);
+        true
+    }
+    /// appends the bytes to the value the key has when the transaction commits, so transactions
+    /// appending to the same key don't conflict, unless they also read it
+    ///
+    /// reading the key, or counting, listing or scanning, reads its value and turns the append into a write,
+    /// the validator of the database is given the appended bytes, like [`Transaction::write`] otherwise
+    pub fn append(&mut self, key: &[u8], bytes: &[u8]) {
+        if let Err(error) = self.try_append(key, bytes) {
+            self.invalid.get_or_insert(Box::new(error));
+        }
+    }
+    /// like [`Transaction::append`], but if the validator refuses the bytes the error is returned
+    /// instead of failing the commit, like [`Transaction::try_write`]
+    pub fn try_append(&mut self, key: &[u8], bytes: &[u8]) -> Result<(), ValidationError> {
+        if bytes.is_empty() {
+            return Ok(());
+        }
+        assert!(bytes.len() <= u32::MAX as usize);
+        self.stage_merge(key, merge::Merge::Append(bytes.to_vec()))
+    }
+    /// writes `n` if it is larger than the number the key has when the transaction commits, so transactions
+    /// raising the same key don't conflict, unless they also read it, like [`Transaction::append`]
+    ///
+    /// numbers are stored as decimal text, like the counters of [`Transaction::incr_windowed`],
+    /// a value that is not a number is replaced by `n`, the validator is given `n` as decimal text
+    pub fn write_max(&mut self, key: &[u8], n: i64) {
+        if let Err(error) = self.stage_merge(key, merge::Merge::Max(n)) {
+            self.invalid.get_or_insert(Box::new(error));
+        }
+    }
+    /// writes `n` if it is smaller than the number the key has when the transaction commits,
+    /// see [`Transaction::write_max`]
+    pub fn write_min(&mut self, key: &[u8], n: i64) {
+        if let Err(error) = self.stage_merge(key, merge::Merge::Min(n)) {
+            self.invalid.get_or_insert(Box::new(error));
+        }
+    }
+    /// merges the operand into the value the key has when the transaction commits, with the merge operator
+    /// of the database, so transactions merging into the same key don't conflict, unless they also read it
+    /// or scan a range with it, like [`Transaction::append`]
+    ///
+    /// the validator of the database is given each operand on its own, panics if the database has no merge operator,
+    /// see [`Database::with_merge_operator`]
+    pub fn merge(&mut self, key: &[u8], operand: &[u8]) {
+        if let Err(error) = self.try_merge(key, operand) {
+            self.invalid.get_or_insert(Box::new(error));
+        }
+    }
+    /// like [`Transaction::merge`], but if the validator refuses the operand the error is returned
+    /// instead of failing the commit, like [`Transaction::try_write`]
+    pub fn try_merge(&mut self, key: &[u8], operand: &[u8]) -> Result<(), ValidationError> {
+        let Some(operator) = self.database.merge_operator else {
+            panic!("pathkvs: Transaction::merge needs a merge operator, see Database::with_merge_operator");
+        };
+        assert!(operand.len() <= u32::MAX as usize);
+        self.stage_merge(key, merge::Merge::Operator(operator, vec![operand.to_vec()]))
+    }
+    fn stage_merge(&mut self, key: &[u8], merge: merge::Merge) -> Result<(), ValidationError> {
+        if key.is_empty() {
+            return Ok(());
+        }
+        if let Some(validator) = self.database.validator {
+            for operand in merge.operands() {
+                validator(key, &operand)?;
+            }
+        }
+        assert!(key.len() <= u32::MAX as usize);
+        let merge = match self.merges.get_mut(key) {
+            Some(pending) => match pending.combine(merge) {
+                Ok(()) => return Ok(()),
+                // merges of different kinds don't combine, so the pending one is resolved first
+                Err(merge) => {
+                    let pending = self.merges.remove(key).expect("checked by get_mut");
+                    self.resolve_merge(key.to_vec(), pending);
+                    merge
+                }
+            },
+            None => merge,
+        };
+        if let Some(value) = self.staged(key) {
+            let value = merge.apply(value);
+            self.stage(key, Some(&value));
+        } else {
+            self.merges.insert(key.to_vec(), merge);
+        }
+        Ok(())
+    }
+    /// the value staged for the key, empty if it is staged to be deleted
+    fn staged(&mut self, key: &[u8]) -> Option<&[u8]> {
+        if let Some(value) = self.commit.changes.get(key) {
+            return Some(value.as_deref().unwrap_or(&[]));
+        }
+        #[cfg(feature = "fs")]
+        if let Some(value) = self.spill.as_mut().and_then(|spill| spill.read(key)) {
+            return Some(value);
+        }
+        None
+    }
+    /// none deletes the key
+    fn stage(&mut self, key: &[u8], value: Option<&[u8]>) {
         assert!(key.len() <= u32::MAX as usize);
-        assert!(value.len() <= u32::MAX as usize);
-        self.commit.changes.insert(key.to_vec(), value.to_vec());
+        // the length u32::MAX is how empty values are told apart from deletions in the history
+        assert!(value.map_or(0, <[u8]>::len) < u32::MAX as usize);
+        self.commit.expired.remove(key);
+        self.commit
+            .changes
+            .insert(key.to_vec(), value.map(<[u8]>::to_vec));
+        #[cfg(feature = "fs")]
+        if let Some(spill) = &mut self.spill {
+            let written = key.len() + value.map_or(0, <[u8]>::len);
+            spill.wrote(key, written, &mut self.commit.changes);
+        }
+    }
+    /// once the writes staged in memory pass `bytes`, their values are moved to a temporary file
+    /// and only their keys are kept in memory, for transactions too large to build in memory
+    ///
+    /// reading a key that was moved reads it from the file, but counting, listing or scanning
+    /// brings every value back into memory, and the transaction stays there from then on
+    ///
+    /// committed data is always kept in memory, so the commit streams the values back from the file,
+    /// this bounds the memory used while the transaction is built and by transactions that are rolled back,
+    /// not the memory of the database
+    ///
+    /// errors writing or reading the file are returned by the commit
+    #[cfg(feature = "fs")]
+    pub fn spill_threshold(mut self, bytes: usize) -> Self {
+        match &mut self.spill {
+            Some(spill) => spill.set_threshold(bytes),
+            None => self.spill = Some(Box::new(spill::Spill::new(bytes))),
+        }
+        self
+    }
+    /// adds `delta` to the number the key has and returns the new value, a negative `delta` decrements it
+    ///
+    /// numbers are stored as decimal text, like [`Transaction::write_max`], a missing or empty key is 0,
+    /// none if the value is not a number or the sum overflows, in which case nothing is written,
+    /// the key counts as read, an empty key is never a number
+    pub fn incr(&mut self, key: &[u8], delta: i64) -> Option<i64> {
+        if key.is_empty() {
+            return None;
+        }
+        let value = self.read(key);
+        let n = if value.is_empty() {
+            0
+        } else {
+            std::str::from_utf8(value).ok()?.parse::<i64>().ok()?
+        };
+        let n = n.checked_add(delta)?;
+        self.write(key, n.to_string().as_bytes());
+        Some(n)
+    }
+    /// adds one to the counter of the current time window of the key and returns the new count
+    ///
+    /// the counter of each window is stored as decimal text in `{key}\0{n}`, where n is the number of whole
+    /// windows since the unix epoch, windows before the previous one are deleted, the previous one is kept
+    /// so a sliding window can be estimated from it
+    ///
+    /// panics if the window is zero, an empty key is ignored and counts 0
+    pub fn incr_windowed(&mut self, key: &[u8], window: Duration) -> u64 {
+        assert!(!window.is_zero(), "pathkvs: the window cannot be zero");
+        if key.is_empty() {
+            return 0;
+        }
+        let current = self.database.now().as_nanos() / window.as_nanos();
+        let mut prefix = key.to_vec();
+        prefix.push(0);
+        let expired = self
+            .scan(&prefix, b"")
+            .into_iter()
+            .filter(|(bucket, _)| {
+                std::str::from_utf8(&bucket[prefix.len()..])
+                    .ok()
+                    .and_then(|n| n.parse::<u128>().ok())
+                    .is_some_and(|n| n.saturating_add(1) < current)
+            })
+            .map(|(bucket, _)| bucket.to_vec())
+            .collect::<Vec<_>>();
+        for bucket in expired {
+            self.delete(&bucket);
+        }
+        let mut bucket = prefix;
+        bucket.extend_from_slice(current.to_string().as_bytes());
+        let count = std::str::from_utf8(self.read(&bucket))
+            .ok()
+            .and_then(|count| count.parse::<u64>().ok())
+            .unwrap_or(0)
+            .saturating_add(1);
+        self.write(&bucket, count.to_string().as_bytes());
+        count
+    }
+    /// runs the conflict checks of [`Transaction::commit`] against the latest commit, without committing,
+    /// so a transaction that is bound to conflict is found out before it is finished
+    ///
+    /// passing does not mean the commit will, other transactions may still commit before it
+    pub fn validate(&self) -> Result<(), ConflictReport> {
+        let mut keys = BTreeSet::new();
+        let master = self.database.load_master();
+        let newer = Commit::history(master.head())
+            .take_while(|&commit| !same(Some(commit), self.commit.prev.head()));
+        for commit in newer {
+            for key in commit.changes.keys() {
+                let read = self.reads.contains(key)
+                    || self.scans.iter().any(|(start_end, start_len)| {
+                        let (start, end) = start_end.split_at(*start_len);
+                        system::in_range(key, start, end)
+                    })
+                    || self.ranges.iter().any(|(start, end)| {
+                        system::in_bounds(key, bound_ref(start), bound_ref(end))
+                    });
+                if read {
+                    keys.insert(key.clone());
+                }
+            }
+        }
+        if keys.is_empty() {
+            Ok(())
+        } else {
+            Err(ConflictReport::new(keys.into_iter().collect()))
+        }
     }
     pub fn commit(self) -> Result<Duration, TransactionError> {
+        self.commit_with_receipt().map(|receipt| receipt.time)
+    }
+    /// like [`Transaction::commit`], but also returns the sequence number of the commit and where the history ends
+    /// once it is persisted
+    pub fn commit_with_receipt(self) -> Result<CommitReceipt, TransactionError> {
+        let database = self.database;
+        let stopwatch = metrics::Stopwatch::start();
+        let result = self.publish();
+        database.metrics.commit(&result, stopwatch.elapsed());
+        result
+    }
+    fn publish(self) -> Result<CommitReceipt, TransactionError> {
         // TODO! don't commit empty commits
         let Transaction {
             database,
             commit:
                 Commit {
-                    prev: mut known_master,
+                    prev: known_master,
                     time: _,
+                    seq: _,
                     changes,
+                    appended: _,
+                    mut expired,
+                    index: _,
                 },
             reads,
             scans,
+            ranges,
+            #[cfg(feature = "fs")]
+            spill,
+            merges,
+            invalid,
         } = self;
-        let mut time = now_since_epoch();
-        let commit_ptr = Box::into_raw(Box::new(Commit {
+        if let Some(error) = invalid {
+            return Err(TransactionError::Io((*error).into()));
+        }
+        #[cfg(feature = "fs")]
+        let changes = match spill {
+            Some(mut spill) => {
+                let mut changes = changes;
+                spill.unspill(&mut changes);
+                if let Some(error) = spill.error() {
+                    return Err(TransactionError::Io(error));
+                }
+                changes
+            }
+            None => changes,
+        };
+        // the merges only take the value of the master they are published on, so they are not reads
+        let mut changes = changes;
+        let mut appended = HashMap::new();
+        for (key, merge) in &merges {
+            if let merge::Merge::Append(suffix) = merge {
+                appended.insert(key.clone(), suffix.len());
+            }
+            let value = merge.apply(Commit::read(known_master.head(), key));
+            changes.insert(key.clone(), Some(value));
+        }
+        // a key merged after it expired is written by the merge
+        expired.retain(|key| matches!(changes.get(key), Some(None)));
+        #[cfg(feature = "testing")]
+        let recorder = database
+            .harness
+            .as_ref()
+            .map(|harness| harness.begin_commit(known_master.head(), &reads, &scans, &ranges));
+        let mut commit = Box::new(Commit {
+            seq: Commit::commit_count(known_master.head()) + 1,
             prev: known_master,
-            time,
+            time: database.now(),
             changes,
-        }));
-        loop {
-            match database.resolved_master.compare_exchange(
-                known_master as *mut _,
-                commit_ptr,
-                Ordering::SeqCst,
-                Ordering::SeqCst,
-            ) {
-                Ok(old_master) => {
-                    assert_eq!(old_master, known_master as *mut _);
-                    break;
-                }
-                Err(new_master) => {
-                    let commit = unsafe { commit_ptr.as_mut().unwrap_unchecked() };
-                    let mut new_changes = new_master as *const Commit;
-                    while let Some(reference) = unsafe { new_changes.as_ref() } {
+            appended,
+            expired,
+            index: None,
+        });
+        let commit = loop {
+            commit.build_index();
+            match database.resolved_master.publish(commit) {
+                Ok(commit) => break commit,
+                Err((mut rejected, new_master)) => {
+                    database.metrics.commit_retry();
+                    let newer = Commit::history(new_master.head())
+                        .take_while(|&commit| !same(Some(commit), rejected.prev.head()));
+                    for reference in newer {
                         for key in &reads {
                             if reference.changes.get(key).is_some() {
                                 return Err(TransactionError::Conflict);
@@ -575,34 +2662,100 @@ impl<'a> Transaction<'a> {
                         }
                         for (key, _) in &reference.changes {
                             for (start_end, start_len) in &scans {
-                                if key.len() >= start_end.len()
-                                    && key.starts_with(&start_end[..*start_len])
-                                    && key.ends_with(&start_end[*start_len..])
-                                {
+                                let (start, end) = start_end.split_at(*start_len);
+                                if system::in_range(key, start, end) {
+                                    return Err(TransactionError::Conflict);
+                                }
+                            }
+                            for (start, end) in &ranges {
+                                if system::in_bounds(key, bound_ref(start), bound_ref(end)) {
                                     return Err(TransactionError::Conflict);
                                 }
                             }
                         }
-                        new_changes = reference.prev;
-                        if new_changes == known_master {
-                            break;
-                        }
                     }
-                    time = now_since_epoch();
-                    known_master = new_master;
-                    commit.time = time;
-                    commit.prev = new_master;
+                    for (key, merge) in &merges {
+                        let value = merge.apply(Commit::read(new_master.head(), key));
+                        rejected.changes.insert(key.clone(), Some(value));
+                    }
+                    rejected.time = database.now();
+                    rejected.seq = Commit::commit_count(new_master.head()) + 1;
+                    rejected.prev = new_master;
+                    commit = rejected;
                 }
             }
+        };
+        let time = commit.time;
+        #[cfg(feature = "testing")]
+        if let Some(recorder) = recorder {
+            recorder.finish(time, &commit.changes);
         }
-        self.database.persist().map_err(TransactionError::Io)?;
-        Ok(time)
+        database.metrics.resolved();
+        let Commit {
+            changes, expired, ..
+        } = &*commit;
+        database
+            .hooks
+            .run(hook::CommitStage::Resolved, time, changes, expired);
+        #[cfg(feature = "persistence")]
+        let offset = self.database.persist().map_err(TransactionError::Io)?;
+        #[cfg(not(feature = "persistence"))]
+        let offset = None;
+        database
+            .hooks
+            .run(hook::CommitStage::Persisted, time, changes, expired);
+        Ok(CommitReceipt {
+            time,
+            seq: commit.seq,
+            offset,
+        })
     }
     pub fn rollback(self) {
         drop(self)
     }
 }
 
+/// a copy of the entries, for reads of the database, which can't borrow from it
+fn owned<'a>(entries: impl IntoIterator<Item = (&'a [u8], &'a [u8])>) -> Vec<(Vec<u8>, Vec<u8>)> {
+    entries
+        .into_iter()
+        .map(|(key, value)| (key.to_vec(), value.to_vec()))
+        .collect()
+}
+
+/// the keys of the entries, in the same order
+fn keys_of(entries: Entries<'_>) -> Vec<&[u8]> {
+    entries.into_iter().map(|(key, _)| key).collect()
+}
+
+/// the bounds of a range of keys, as bytes
+fn key_bounds<'a, K: AsRef<[u8]> + ?Sized + 'a>(
+    range: &'a impl RangeBounds<K>,
+) -> (Bound<&'a [u8]>, Bound<&'a [u8]>) {
+    (
+        range.start_bound().map(AsRef::as_ref),
+        range.end_bound().map(AsRef::as_ref),
+    )
+}
+
+pub(crate) fn bound_ref(bound: &Bound<Vec<u8>>) -> Bound<&[u8]> {
+    bound.as_ref().map(Vec::as_slice)
+}
+
+/// the bytes of the value from `offset` to `offset + len`, clamped to its end
+fn value_range(value: &[u8], offset: u32, len: u32) -> &[u8] {
+    let start = value.len().min(offset as usize);
+    let end = value.len().min(start.saturating_add(len as usize));
+    &value[start..end]
+}
+
+#[cfg(all(feature = "web", target_arch = "wasm32"))]
+fn now_since_epoch() -> Duration {
+    // SystemTime::now panics on wasm32-unknown-unknown, building there without `web` is refused at the top
+    Duration::from_secs_f64(js_sys::Date::now() / 1000.0)
+}
+
+#[cfg(not(all(feature = "web", target_arch = "wasm32")))]
 fn now_since_epoch() -> Duration {
     std::time::SystemTime::now()
         .duration_since(std::time::SystemTime::UNIX_EPOCH)
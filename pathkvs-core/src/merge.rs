@@ -0,0 +1,68 @@
+//! writes that take the value the key has when the transaction commits, so they don't conflict,
+//! see [`Transaction::append`](crate::Transaction::append), [`Transaction::write_max`](crate::Transaction::write_max)
+//! and [`Transaction::merge`](crate::Transaction::merge)
+
+use std::borrow::Cow;
+
+use crate::MergeOperator;
+
+/// a write whose value is computed from the value the key has when it is applied
+#[derive(Clone)]
+pub(crate) enum Merge {
+    Append(Vec<u8>),
+    Max(i64),
+    Min(i64),
+    /// the operands of the merge operator of the database, applied in order
+    Operator(MergeOperator, Vec<Vec<u8>>),
+}
+
+impl Merge {
+    /// what the validator of the database is given, one operand at a time
+    pub(crate) fn operands(&self) -> Vec<Cow<'_, [u8]>> {
+        match self {
+            Merge::Append(bytes) => vec![Cow::Borrowed(bytes)],
+            Merge::Max(n) | Merge::Min(n) => vec![Cow::Owned(n.to_string().into_bytes())],
+            Merge::Operator(_, operands) => operands
+                .iter()
+                .map(|operand| Cow::Borrowed(&operand[..]))
+                .collect(),
+        }
+    }
+    /// makes `self` also do `next`, if both are of the same kind, otherwise `next` is given back
+    pub(crate) fn combine(&mut self, next: Merge) -> Result<(), Merge> {
+        match (self, next) {
+            (Merge::Append(suffix), Merge::Append(bytes)) => {
+                suffix.extend_from_slice(&bytes);
+                assert!(suffix.len() <= u32::MAX as usize);
+            }
+            (Merge::Max(n), Merge::Max(m)) => *n = (*n).max(m),
+            (Merge::Min(n), Merge::Min(m)) => *n = (*n).min(m),
+            (Merge::Operator(_, operands), Merge::Operator(_, next)) => operands.extend(next),
+            (_, next) => return Err(next),
+        }
+        Ok(())
+    }
+    /// the value of the key after the merge, given the value it has
+    ///
+    /// numbers are stored as decimal text, a value that is not a number is replaced
+    pub(crate) fn apply(&self, value: &[u8]) -> Vec<u8> {
+        match self {
+            Merge::Append(suffix) => [value, suffix].concat(),
+            Merge::Max(n) => parse(value)
+                .map_or(*n, |value| value.max(*n))
+                .to_string()
+                .into_bytes(),
+            Merge::Min(n) => parse(value)
+                .map_or(*n, |value| value.min(*n))
+                .to_string()
+                .into_bytes(),
+            Merge::Operator(operator, operands) => operands
+                .iter()
+                .fold(value.to_vec(), |value, operand| operator(&value, operand)),
+        }
+    }
+}
+
+fn parse(value: &[u8]) -> Option<i64> {
+    std::str::from_utf8(value).ok()?.parse().ok()
+}
@@ -0,0 +1,138 @@
+//! counters kept by every database, see [`Database::metrics`](crate::Database::metrics)
+
+use std::{
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+
+use crate::error::TransactionError;
+
+/// the counters at the time [`Database::metrics`](crate::Database::metrics) was called,
+/// they start at zero when the database is opened and only ever grow
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Metrics {
+    /// calls to [`Transaction::commit`](crate::Transaction::commit)
+    pub commits_attempted: u64,
+    /// commits that succeeded
+    pub commits: u64,
+    /// commits that failed because of a conflict
+    pub conflicts: u64,
+    /// times a commit found that another one was published first, and had to check its changes
+    /// before trying again, a single commit can retry many times under contention
+    pub commit_retries: u64,
+    /// the time spent in successful commits, including persisting them
+    pub commit_time: Duration,
+    /// the slowest successful commit
+    pub commit_time_max: Duration,
+    /// batches of commits written to the storage, each followed by a single sync
+    pub persists: u64,
+    /// commits written to the storage, divided by `persists` this is the average batch size
+    pub persisted_commits: u64,
+    /// the time spent writing and syncing batches
+    pub persist_time: Duration,
+    /// the slowest batch
+    pub persist_time_max: Duration,
+}
+
+#[derive(Default)]
+pub(crate) struct Counters {
+    commits_attempted: AtomicU64,
+    commits: AtomicU64,
+    conflicts: AtomicU64,
+    commit_retries: AtomicU64,
+    /// commits that became the master, persisted or not, unlike `commits` this is counted before persisting
+    resolved: AtomicU64,
+    commit_nanos: AtomicU64,
+    commit_nanos_max: AtomicU64,
+    persists: AtomicU64,
+    persisted_commits: AtomicU64,
+    persist_nanos: AtomicU64,
+    persist_nanos_max: AtomicU64,
+}
+
+impl Counters {
+    pub(crate) fn get(&self) -> Metrics {
+        let load = |counter: &AtomicU64| counter.load(Ordering::Relaxed);
+        Metrics {
+            commits_attempted: load(&self.commits_attempted),
+            commits: load(&self.commits),
+            conflicts: load(&self.conflicts),
+            commit_retries: load(&self.commit_retries),
+            commit_time: Duration::from_nanos(load(&self.commit_nanos)),
+            commit_time_max: Duration::from_nanos(load(&self.commit_nanos_max)),
+            persists: load(&self.persists),
+            persisted_commits: load(&self.persisted_commits),
+            persist_time: Duration::from_nanos(load(&self.persist_nanos)),
+            persist_time_max: Duration::from_nanos(load(&self.persist_nanos_max)),
+        }
+    }
+    pub(crate) fn commit_retry(&self) {
+        self.commit_retries.fetch_add(1, Ordering::Relaxed);
+    }
+    pub(crate) fn resolved(&self) {
+        self.resolved.fetch_add(1, Ordering::Relaxed);
+    }
+    /// the commits resolved but not persisted yet, persisting may count a commit
+    /// just before it is counted as resolved, hence the saturation
+    #[cfg(feature = "persistence")]
+    pub(crate) fn persist_backlog(&self) -> u64 {
+        let persisted = self.persisted_commits.load(Ordering::Relaxed);
+        let resolved = self.resolved.load(Ordering::Relaxed);
+        resolved.saturating_sub(persisted)
+    }
+    pub(crate) fn commit<T>(&self, result: &Result<T, TransactionError>, elapsed: Duration) {
+        self.commits_attempted.fetch_add(1, Ordering::Relaxed);
+        match result {
+            Ok(_) => {
+                let nanos = elapsed.as_nanos() as u64;
+                self.commits.fetch_add(1, Ordering::Relaxed);
+                self.commit_nanos.fetch_add(nanos, Ordering::Relaxed);
+                self.commit_nanos_max.fetch_max(nanos, Ordering::Relaxed);
+            }
+            Err(TransactionError::Conflict) => {
+                self.conflicts.fetch_add(1, Ordering::Relaxed);
+            }
+            Err(TransactionError::Io(_)) => {}
+        }
+    }
+    #[cfg(feature = "persistence")]
+    pub(crate) fn persist(&self, commits: usize, elapsed: Duration) {
+        let nanos = elapsed.as_nanos() as u64;
+        self.persists.fetch_add(1, Ordering::Relaxed);
+        self.persisted_commits
+            .fetch_add(commits as u64, Ordering::Relaxed);
+        self.persist_nanos.fetch_add(nanos, Ordering::Relaxed);
+        self.persist_nanos_max.fetch_max(nanos, Ordering::Relaxed);
+    }
+}
+
+/// measures durations, `Instant` panics on wasm32-unknown-unknown, so there the system clock is used
+pub(crate) struct Stopwatch {
+    #[cfg(not(all(feature = "web", target_arch = "wasm32")))]
+    start: std::time::Instant,
+    #[cfg(all(feature = "web", target_arch = "wasm32"))]
+    start: Duration,
+}
+
+impl Stopwatch {
+    #[cfg(not(all(feature = "web", target_arch = "wasm32")))]
+    pub(crate) fn start() -> Self {
+        Self {
+            start: std::time::Instant::now(),
+        }
+    }
+    #[cfg(not(all(feature = "web", target_arch = "wasm32")))]
+    pub(crate) fn elapsed(&self) -> Duration {
+        self.start.elapsed()
+    }
+    #[cfg(all(feature = "web", target_arch = "wasm32"))]
+    pub(crate) fn start() -> Self {
+        Self {
+            start: crate::now_since_epoch(),
+        }
+    }
+    #[cfg(all(feature = "web", target_arch = "wasm32"))]
+    pub(crate) fn elapsed(&self) -> Duration {
+        crate::now_since_epoch().saturating_sub(self.start)
+    }
+}
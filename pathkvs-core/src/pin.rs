@@ -0,0 +1,67 @@
+//! snapshots that keep the history they read from, see [`Database::pin_snapshot`](crate::Database::pin_snapshot)
+
+use std::{
+    collections::HashMap,
+    ops::Deref,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::Duration,
+};
+
+use crate::{Database, Snapshot};
+
+/// a snapshot whose history must be kept until it is released or dropped
+///
+/// the history is never trimmed yet, so this only matters to code that trims it,
+/// which must keep every commit needed by [`Database::oldest_pin`](crate::Database::oldest_pin)
+pub struct PinnedSnapshot<'a> {
+    snapshot: Snapshot,
+    database: &'a Database,
+    id: u64,
+}
+
+#[derive(Default)]
+pub(crate) struct Pins {
+    next_id: AtomicU64,
+    pins: Mutex<HashMap<u64, Duration>>,
+}
+
+impl Pins {
+    pub(crate) fn oldest(&self) -> Option<Duration> {
+        self.pins.lock().unwrap().values().min().copied()
+    }
+}
+
+impl<'a> PinnedSnapshot<'a> {
+    pub(crate) fn new(database: &'a Database, snapshot: Snapshot) -> Self {
+        let id = database.pins.next_id.fetch_add(1, Ordering::Relaxed);
+        database
+            .pins
+            .pins
+            .lock()
+            .unwrap()
+            .insert(id, snapshot.time().unwrap_or_default());
+        Self {
+            snapshot,
+            database,
+            id,
+        }
+    }
+    /// same as dropping it, lets the history it pinned be trimmed
+    pub fn release(self) {}
+}
+
+impl<'a> Deref for PinnedSnapshot<'a> {
+    type Target = Snapshot;
+    fn deref(&self) -> &Snapshot {
+        &self.snapshot
+    }
+}
+
+impl Drop for PinnedSnapshot<'_> {
+    fn drop(&mut self) {
+        self.database.pins.pins.lock().unwrap().remove(&self.id);
+    }
+}
@@ -0,0 +1,562 @@
+//! a small language for procedures that run inside a single transaction
+//!
+//! a procedure is one expression written as an s-expression, every value is a byte string,
+//! `(op arg...)` calls an operation, `"text"` is a string literal, `123` is a number literal,
+//! any other word is a variable and `;` starts a comment that goes until the end of the line
+//!
+//! numbers are stored as decimal text, the empty value counts as `0`, so counters need no initialization,
+//! booleans are `1` for true and the empty value for false, any other value is true
+//!
+//! there are no loops or function calls, so every procedure finishes in a number of steps bounded by its size
+//!
+//! [`Procedure::eval`] runs one without a transaction, as a function of its arguments, where `read`, `len`,
+//! `count`, `write` and `delete` fail
+//!
+//! | operation | result |
+//! |-|-|
+//! | `(arg N)` | the argument N, starting at 0, empty if it was not given |
+//! | `(argc)` | how many arguments were given |
+//! | `(read KEY)` | the value of the key |
+//! | `(len KEY)` | the length of the value of the key |
+//! | `(count START END)` | how many keys start with START and end with END |
+//! | `(write KEY VALUE)` | writes the value and returns it |
+//! | `(delete KEY)` | deletes the key and returns its old value |
+//! | `(let NAME VALUE)` | sets the variable and returns the value |
+//! | `(do EXPR...)` | evaluates every expression and returns the last one |
+//! | `(if COND THEN [ELSE])` | evaluates THEN if the condition is true, else ELSE, which defaults to empty |
+//! | `(and EXPR...)`, `(or EXPR...)` | short circuiting, returns the value that decided the result |
+//! | `(not VALUE)` | `1` if the value is false, empty otherwise |
+//! | `(eq A B)` | `1` if the values are equal |
+//! | `(cat VALUE...)` | the values concatenated |
+//! | `(add A B)`, `(sub A B)`, `(mul A B)`, `(div A B)`, `(mod A B)` | 64 bit signed arithmetic, overflow and division by zero fail |
+//! | `(lt A B)`, `(le A B)`, `(gt A B)`, `(ge A B)` | numeric comparison |
+//! | `(fail MESSAGE)` | aborts the procedure, none of its writes happen |
+//!
+//! for example, a transfer between two accounts that can't leave the source negative:
+//!
+//! ```text
+//! (do
+//!   (let from (cat "conta/" (arg 0)))
+//!   (let to (cat "conta/" (arg 1)))
+//!   (if (lt (read from) (arg 2)) (fail "saldo insuficiente"))
+//!   (write from (sub (read from) (arg 2)))
+//!   (write to (add (read to) (arg 2))))
+//! ```
+
+use std::collections::HashMap;
+
+use crate::Transaction;
+
+/// asked by [`Procedure::run_guarded`] whether a key may be used
+type Guard<'r> = &'r mut dyn FnMut(&[u8], KeyAccess) -> bool;
+
+/// nesting deeper than this is rejected when parsing, so evaluation can't overflow the stack
+const MAX_DEPTH: usize = 64;
+
+/// a parsed procedure, see the [module documentation](self) for the language
+#[derive(Debug, Clone)]
+pub struct Procedure {
+    body: Expr,
+}
+
+/// the procedure could not be parsed, or it failed while running
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProcedureError(pub String);
+impl std::fmt::Display for ProcedureError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "pathkvs procedure error: {}", self.0)
+    }
+}
+impl std::error::Error for ProcedureError {}
+
+/// how a procedure is about to use a key, given to the guard of [`Procedure::run_guarded`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyAccess {
+    Read,
+    Write,
+    /// the keys starting with the key, for `count`
+    Prefix,
+}
+
+#[derive(Debug, Clone)]
+enum Expr {
+    Literal(Vec<u8>),
+    Var(String),
+    Let(String, Box<Expr>),
+    Op(Op, Vec<Expr>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Op {
+    Arg,
+    Argc,
+    Read,
+    Len,
+    Count,
+    Write,
+    Delete,
+    Do,
+    If,
+    And,
+    Or,
+    Not,
+    Eq,
+    Cat,
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Mod,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Fail,
+}
+
+impl Op {
+    fn from_name(name: &str) -> Option<Self> {
+        Some(match name {
+            "arg" => Op::Arg,
+            "argc" => Op::Argc,
+            "read" => Op::Read,
+            "len" => Op::Len,
+            "count" => Op::Count,
+            "write" => Op::Write,
+            "delete" => Op::Delete,
+            "do" => Op::Do,
+            "if" => Op::If,
+            "and" => Op::And,
+            "or" => Op::Or,
+            "not" => Op::Not,
+            "eq" => Op::Eq,
+            "cat" => Op::Cat,
+            "add" => Op::Add,
+            "sub" => Op::Sub,
+            "mul" => Op::Mul,
+            "div" => Op::Div,
+            "mod" => Op::Mod,
+            "lt" => Op::Lt,
+            "le" => Op::Le,
+            "gt" => Op::Gt,
+            "ge" => Op::Ge,
+            "fail" => Op::Fail,
+            _ => return None,
+        })
+    }
+    /// minimum and maximum number of arguments
+    fn arity(self) -> (usize, usize) {
+        match self {
+            Op::Argc => (0, 0),
+            Op::Arg | Op::Read | Op::Len | Op::Delete | Op::Not | Op::Fail => (1, 1),
+            Op::Count | Op::Write | Op::Eq => (2, 2),
+            Op::Add | Op::Sub | Op::Mul | Op::Div | Op::Mod => (2, 2),
+            Op::Lt | Op::Le | Op::Gt | Op::Ge => (2, 2),
+            Op::If => (2, 3),
+            Op::Do => (1, usize::MAX),
+            Op::And | Op::Or | Op::Cat => (0, usize::MAX),
+        }
+    }
+}
+
+impl Procedure {
+    pub fn parse(source: &str) -> Result<Self, ProcedureError> {
+        let mut parser = Parser {
+            source,
+            cursor: 0,
+            depth: 0,
+        };
+        let body = parser.expr()?;
+        parser.skip_blank();
+        if parser.cursor != source.len() {
+            return Err(parser.error("unexpected text after the end of the procedure"));
+        }
+        Ok(Self { body })
+    }
+    /// runs the procedure in the transaction, the transaction is not committed
+    ///
+    /// if the procedure fails, its writes are undone, but the transaction keeps any writes made before
+    pub fn run(&self, ts: &mut Transaction, args: &[Vec<u8>]) -> Result<Vec<u8>, ProcedureError> {
+        self.run_inner(ts, args, None)
+    }
+    /// like [`Procedure::run`], but asks the guard before each key is used, and fails the procedure,
+    /// undoing its writes, on the first key the guard refuses
+    pub fn run_guarded(
+        &self,
+        ts: &mut Transaction,
+        args: &[Vec<u8>],
+        guard: &mut dyn FnMut(&[u8], KeyAccess) -> bool,
+    ) -> Result<Vec<u8>, ProcedureError> {
+        self.run_inner(ts, args, Some(guard))
+    }
+    fn run_inner<'r>(
+        &self,
+        ts: &'r mut Transaction,
+        args: &'r [Vec<u8>],
+        guard: Option<Guard<'r>>,
+    ) -> Result<Vec<u8>, ProcedureError> {
+        let saved = ts.commit.changes.clone();
+        #[cfg(feature = "fs")]
+        let saved_spill = ts.spill.clone();
+        let mut run = Run {
+            ts: Some(ts),
+            guard,
+            args,
+            vars: HashMap::new(),
+        };
+        match run.eval(&self.body) {
+            Ok(value) => Ok(value),
+            Err(error) => {
+                let ts = run.ts.unwrap();
+                ts.commit.changes = saved;
+                #[cfg(feature = "fs")]
+                {
+                    ts.spill = saved_spill;
+                }
+                Err(error)
+            }
+        }
+    }
+    /// evaluates the procedure without a database, as a function of its arguments,
+    /// such as the transformation of a value, the operations on keys fail
+    pub fn eval(&self, args: &[Vec<u8>]) -> Result<Vec<u8>, ProcedureError> {
+        Run {
+            ts: None,
+            guard: None,
+            args,
+            vars: HashMap::new(),
+        }
+        .eval(&self.body)
+    }
+}
+
+struct Parser<'a> {
+    source: &'a str,
+    cursor: usize,
+    depth: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn error(&self, message: &str) -> ProcedureError {
+        let line = self.source[..self.cursor].matches('\n').count() + 1;
+        ProcedureError(format!("{message}, at line {line}"))
+    }
+    fn rest(&self) -> &'a str {
+        &self.source[self.cursor..]
+    }
+    fn skip_blank(&mut self) {
+        loop {
+            let rest = self.rest();
+            let trimmed = rest.trim_start();
+            self.cursor += rest.len() - trimmed.len();
+            if !trimmed.starts_with(';') {
+                break;
+            }
+            self.cursor += trimmed.find('\n').unwrap_or(trimmed.len());
+        }
+    }
+    fn word(&mut self) -> &'a str {
+        let rest = &self.source[self.cursor..];
+        let len = rest
+            .find(|c: char| c.is_whitespace() || matches!(c, '(' | ')' | '"' | ';'))
+            .unwrap_or(rest.len());
+        self.cursor += len;
+        &rest[..len]
+    }
+    fn expr(&mut self) -> Result<Expr, ProcedureError> {
+        self.skip_blank();
+        match self.rest().chars().next() {
+            None => Err(self.error("unexpected end of the procedure")),
+            Some('(') => {
+                if self.depth == MAX_DEPTH {
+                    return Err(self.error("expressions are nested too deep"));
+                }
+                self.depth += 1;
+                self.cursor += 1;
+                let expr = self.call()?;
+                self.depth -= 1;
+                Ok(expr)
+            }
+            Some(')') => Err(self.error("unexpected `)`")),
+            Some('"') => self.string(),
+            Some(_) => {
+                let word = self.word();
+                if word.bytes().all(|x| x.is_ascii_digit())
+                    || word
+                        .strip_prefix('-')
+                        .is_some_and(|x| !x.is_empty() && x.bytes().all(|x| x.is_ascii_digit()))
+                {
+                    Ok(Expr::Literal(word.as_bytes().to_vec()))
+                } else {
+                    Ok(Expr::Var(word.to_string()))
+                }
+            }
+        }
+    }
+    /// the rest of a call, after the `(`
+    fn call(&mut self) -> Result<Expr, ProcedureError> {
+        self.skip_blank();
+        let name = self.word().to_string();
+        if name.is_empty() {
+            return Err(self.error("expected the name of an operation after `(`"));
+        }
+        let expr = if name == "let" {
+            self.skip_blank();
+            let var = self.word().to_string();
+            if var.is_empty() || var.bytes().all(|x| x.is_ascii_digit() || x == b'-') {
+                return Err(self.error("expected a variable name after `let`"));
+            }
+            Expr::Let(var, Box::new(self.expr()?))
+        } else {
+            let op = Op::from_name(&name)
+                .ok_or_else(|| self.error(&format!("unknown operation `{name}`")))?;
+            let mut args = Vec::new();
+            loop {
+                self.skip_blank();
+                if self.rest().starts_with(')') {
+                    break;
+                }
+                args.push(self.expr()?);
+            }
+            let (min, max) = op.arity();
+            if args.len() < min || args.len() > max {
+                return Err(self.error(&format!(
+                    "`{name}` does not take {} argument(s)",
+                    args.len()
+                )));
+            }
+            Expr::Op(op, args)
+        };
+        self.skip_blank();
+        if !self.rest().starts_with(')') {
+            return Err(self.error(&format!("expected `)` to close `{name}`")));
+        }
+        self.cursor += 1;
+        Ok(expr)
+    }
+    fn string(&mut self) -> Result<Expr, ProcedureError> {
+        self.cursor += 1;
+        let mut value = Vec::new();
+        let mut chars = self.rest().char_indices();
+        loop {
+            match chars.next() {
+                None => return Err(self.error("unterminated string")),
+                Some((index, '"')) => {
+                    self.cursor += index + 1;
+                    return Ok(Expr::Literal(value));
+                }
+                Some((_, '\\')) => match chars.next() {
+                    Some((_, 'n')) => value.push(b'\n'),
+                    Some((_, 't')) => value.push(b'\t'),
+                    Some((_, c @ ('"' | '\\'))) => value.push(c as u8),
+                    _ => return Err(self.error("invalid escape in string")),
+                },
+                Some((_, c)) => {
+                    value.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes());
+                }
+            }
+        }
+    }
+}
+
+struct Run<'r, 't> {
+    /// none for [`Procedure::eval`]
+    ts: Option<&'r mut Transaction<'t>>,
+    guard: Option<Guard<'r>>,
+    args: &'r [Vec<u8>],
+    vars: HashMap<String, Vec<u8>>,
+}
+
+impl<'t> Run<'_, 't> {
+    fn ts(&mut self) -> Result<&mut Transaction<'t>, ProcedureError> {
+        self.ts.as_deref_mut().ok_or_else(|| {
+            ProcedureError("keys can't be read or written outside of a transaction".to_string())
+        })
+    }
+    /// the transaction, once the guard permitted the access to the key
+    fn ts_for(
+        &mut self,
+        key: &[u8],
+        access: KeyAccess,
+    ) -> Result<&mut Transaction<'t>, ProcedureError> {
+        if let Some(guard) = &mut self.guard {
+            if !guard(key, access) {
+                return Err(ProcedureError(format!(
+                    "access to {:?} denied",
+                    String::from_utf8_lossy(key)
+                )));
+            }
+        }
+        self.ts()
+    }
+    fn eval(&mut self, expr: &Expr) -> Result<Vec<u8>, ProcedureError> {
+        let (op, args) = match expr {
+            Expr::Literal(value) => return Ok(value.clone()),
+            Expr::Var(name) => {
+                return self
+                    .vars
+                    .get(name)
+                    .cloned()
+                    .ok_or_else(|| ProcedureError(format!("undefined variable `{name}`")))
+            }
+            Expr::Let(name, value) => {
+                let value = self.eval(value)?;
+                self.vars.insert(name.clone(), value.clone());
+                return Ok(value);
+            }
+            Expr::Op(op, args) => (*op, args.as_slice()),
+        };
+        match op {
+            Op::If => {
+                if truthy(&self.eval(&args[0])?) {
+                    self.eval(&args[1])
+                } else if let Some(otherwise) = args.get(2) {
+                    self.eval(otherwise)
+                } else {
+                    Ok(Vec::new())
+                }
+            }
+            Op::And | Op::Or => {
+                let mut value = Vec::new();
+                if op == Op::And {
+                    value.push(b'1');
+                }
+                for arg in args {
+                    value = self.eval(arg)?;
+                    if truthy(&value) != (op == Op::And) {
+                        break;
+                    }
+                }
+                Ok(value)
+            }
+            Op::Do => {
+                let mut value = Vec::new();
+                for arg in args {
+                    value = self.eval(arg)?;
+                }
+                Ok(value)
+            }
+            _ => {
+                let values = args
+                    .iter()
+                    .map(|arg| self.eval(arg))
+                    .collect::<Result<Vec<_>, _>>()?;
+                self.apply(op, values)
+            }
+        }
+    }
+    fn apply(&mut self, op: Op, mut values: Vec<Vec<u8>>) -> Result<Vec<u8>, ProcedureError> {
+        Ok(match op {
+            Op::Arg => {
+                let index = number(&values[0])?;
+                usize::try_from(index)
+                    .ok()
+                    .and_then(|index| self.args.get(index))
+                    .cloned()
+                    .unwrap_or_default()
+            }
+            Op::Argc => self.args.len().to_string().into_bytes(),
+            Op::Read => self
+                .ts_for(&values[0], KeyAccess::Read)?
+                .read(&values[0])
+                .to_vec(),
+            Op::Len => self
+                .ts_for(&values[0], KeyAccess::Read)?
+                .len(&values[0])
+                .to_string()
+                .into_bytes(),
+            Op::Count => self
+                .ts_for(&values[0], KeyAccess::Prefix)?
+                .count(&values[0], &values[1])
+                .to_string()
+                .into_bytes(),
+            Op::Write => {
+                check_len(&values[0])?;
+                check_len(&values[1])?;
+                self.ts_for(&values[0], KeyAccess::Write)?
+                    .try_write(&values[0], &values[1])
+                    .map_err(|error| ProcedureError(error.reason().to_string()))?;
+                values.swap_remove(1)
+            }
+            Op::Delete => {
+                // the old value is returned, so deleting reads the key too
+                self.ts_for(&values[0], KeyAccess::Read)?;
+                let ts = self.ts_for(&values[0], KeyAccess::Write)?;
+                let old = ts.read(&values[0]).to_vec();
+                ts.try_delete(&values[0])
+                    .map_err(|error| ProcedureError(error.reason().to_string()))?;
+                old
+            }
+            Op::Not => boolean(!truthy(&values[0])),
+            Op::Eq => boolean(values[0] == values[1]),
+            Op::Cat => values.concat(),
+            Op::Add | Op::Sub | Op::Mul | Op::Div | Op::Mod => {
+                let a = number(&values[0])?;
+                let b = number(&values[1])?;
+                let result = match op {
+                    Op::Add => a.checked_add(b),
+                    Op::Sub => a.checked_sub(b),
+                    Op::Mul => a.checked_mul(b),
+                    Op::Div => a.checked_div(b),
+                    _ => a.checked_rem(b),
+                };
+                result
+                    .ok_or_else(|| {
+                        ProcedureError("arithmetic overflow or division by zero".to_string())
+                    })?
+                    .to_string()
+                    .into_bytes()
+            }
+            Op::Lt | Op::Le | Op::Gt | Op::Ge => {
+                let a = number(&values[0])?;
+                let b = number(&values[1])?;
+                boolean(match op {
+                    Op::Lt => a < b,
+                    Op::Le => a <= b,
+                    Op::Gt => a > b,
+                    _ => a >= b,
+                })
+            }
+            Op::Fail => {
+                return Err(ProcedureError(
+                    String::from_utf8_lossy(&values[0]).into_owned(),
+                ))
+            }
+            Op::If | Op::And | Op::Or | Op::Do => unreachable!("evaluated lazily"),
+        })
+    }
+}
+
+fn truthy(value: &[u8]) -> bool {
+    !value.is_empty()
+}
+
+fn boolean(value: bool) -> Vec<u8> {
+    if value {
+        b"1".to_vec()
+    } else {
+        Vec::new()
+    }
+}
+
+fn number(value: &[u8]) -> Result<i64, ProcedureError> {
+    if value.is_empty() {
+        return Ok(0);
+    }
+    std::str::from_utf8(value)
+        .ok()
+        .and_then(|text| text.parse().ok())
+        .ok_or_else(|| {
+            ProcedureError(format!(
+                "{:?} is not a number",
+                String::from_utf8_lossy(value)
+            ))
+        })
+}
+
+fn check_len(value: &[u8]) -> Result<(), ProcedureError> {
+    if value.len() > u32::MAX as usize {
+        return Err(ProcedureError("value is too long".to_string()));
+    }
+    Ok(())
+}
@@ -0,0 +1,70 @@
+//! how far behind each replica is, from the positions the replicas acknowledge,
+//! see [`Database::ack_replication`](crate::Database::ack_replication)
+
+use std::{
+    collections::BTreeMap,
+    sync::{Condvar, Mutex},
+    time::{Duration, Instant},
+};
+
+/// a replica that acknowledged a position, see [`Database::replication_status`](crate::Database::replication_status)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReplicaStatus {
+    pub name: String,
+    /// the number of commits of the history it has applied, as returned by
+    /// [`Database::backup_since`](crate::Database::backup_since)
+    pub seq: u64,
+    /// the unix time it acknowledged it
+    pub acked: Duration,
+    /// how many commits it has yet to apply
+    pub lag_commits: u64,
+    /// how many bytes the commits it has yet to apply take in a backup since its position
+    pub lag_bytes: u64,
+}
+
+#[derive(Default)]
+pub(crate) struct Replicas {
+    /// the sequence number and the time of the last acknowledgement of each replica
+    acked: Mutex<BTreeMap<String, (u64, Duration)>>,
+    /// notified on every acknowledgement, for the commits waiting on them
+    acknowledged: Condvar,
+}
+
+impl Replicas {
+    pub(crate) fn ack(&self, name: &str, seq: u64, time: Duration) {
+        self.acked
+            .lock()
+            .unwrap()
+            .insert(name.to_string(), (seq, time));
+        self.acknowledged.notify_all();
+    }
+    /// waits until at least `replicas` replicas acknowledged at least `seq` commits,
+    /// returns false if that did not happen before the timeout
+    pub(crate) fn wait(&self, seq: u64, replicas: usize, timeout: Duration) -> bool {
+        let deadline = Instant::now() + timeout;
+        let mut acked = self.acked.lock().unwrap();
+        loop {
+            if acked.values().filter(|&&(acked, _)| acked >= seq).count() >= replicas {
+                return true;
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                return false;
+            }
+            acked = self
+                .acknowledged
+                .wait_timeout(acked, deadline - now)
+                .unwrap()
+                .0;
+        }
+    }
+    /// by name
+    pub(crate) fn acked(&self) -> Vec<(String, u64, Duration)> {
+        self.acked
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(name, &(seq, time))| (name.clone(), seq, time))
+            .collect()
+    }
+}
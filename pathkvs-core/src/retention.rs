@@ -0,0 +1,119 @@
+//! how long the keys under each prefix are kept, see [`Database::set_retention`]
+//!
+//! the retention of a prefix is kept under [`system::RETENTION`] followed by the prefix, so it is persisted,
+//! backed up and replicated along with the keys, a key follows the retention of the longest prefix it has
+
+use std::{collections::HashSet, time::Duration};
+
+use crate::{error::TransactionError, expiry, system, Database, Error};
+
+/// the most keys a single commit of a sweep expires, like the sweep of [`crate::expiry`]
+const SWEEP_BATCH: usize = 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Retention {
+    /// the changes to the keys are kept as they are by [`Database::compact_keeping`] for at least this long,
+    /// [`Duration::MAX`] keeps them forever
+    pub history: Duration,
+    /// keys not written for this long, that don't expire on their own, are expired by [`Database::sweep_expired`]
+    pub ttl: Option<Duration>,
+}
+
+impl Database {
+    /// sets the retention of the keys under `prefix`, an empty prefix is every key without a longer one
+    pub fn set_retention(&self, prefix: &[u8], retention: Retention) -> Result<(), Error> {
+        let mut value = expiry::encode(retention.history).to_vec();
+        if let Some(ttl) = retention.ttl {
+            value.extend_from_slice(&expiry::encode(ttl));
+        }
+        self.commit_blind(|ts| ts.stage(&system::key(system::RETENTION, prefix), Some(&value)))
+    }
+    /// the keys under `prefix` follow the retention of a shorter prefix, if any
+    pub fn clear_retention(&self, prefix: &[u8]) -> Result<(), Error> {
+        self.commit_blind(|ts| ts.stage(&system::key(system::RETENTION, prefix), None))
+    }
+    /// every prefix that has a retention and its retention, sorted by prefix
+    pub fn retentions(&self) -> Vec<(Vec<u8>, Retention)> {
+        self.scan(system::RETENTION, b"")
+            .into_iter()
+            .filter_map(|(key, value)| {
+                let prefix = key[system::RETENTION.len()..].to_vec();
+                Some((prefix, decode(&value)?))
+            })
+            .collect()
+    }
+    /// expires the keys not written for longer than the ttl of their retention, returns how many keys were expired
+    ///
+    /// keys with an expiry of their own are left to it
+    pub(crate) fn sweep_retained(&self) -> Result<u64, Error> {
+        let retentions = self.retentions();
+        let mut swept = 0;
+        for (prefix, retention) in &retentions {
+            let Some(ttl) = retention.ttl else {
+                continue;
+            };
+            loop {
+                // started before the keys are picked, so a key written after they are picked makes it conflict
+                let mut ts = self.start_writes();
+                let since = self.now().saturating_sub(ttl);
+                let written = self
+                    .scan_modified_since(prefix, b"", since)
+                    .into_iter()
+                    .map(|(key, _)| key)
+                    .collect::<HashSet<_>>();
+                let due = self
+                    .list(prefix, b"")
+                    .into_iter()
+                    .filter(|key| {
+                        applies(&retentions, key).is_some_and(|(longest, _)| longest == prefix)
+                    })
+                    .filter(|key| !written.contains(key))
+                    .filter(|key| !self.exists(&system::key(system::TTL, key)))
+                    .take(SWEEP_BATCH)
+                    .collect::<Vec<_>>();
+                if due.is_empty() {
+                    ts.rollback();
+                    break;
+                }
+                let mut expired = 0;
+                for key in &due {
+                    if ts.exists(key) && ts.expiry(key).is_none() {
+                        ts.expire(key);
+                        expired += 1;
+                    }
+                }
+                match ts.commit() {
+                    // what is left is due, but was not expired, and would be picked again
+                    Ok(_) if expired == 0 => break,
+                    Ok(_) => swept += expired,
+                    Err(TransactionError::Conflict) => continue,
+                    Err(TransactionError::Io(error)) => return Err(error.into()),
+                }
+            }
+        }
+        Ok(swept)
+    }
+}
+
+/// the longest prefix of `retentions` the key has, and its retention
+pub(crate) fn applies<'r>(
+    retentions: &'r [(Vec<u8>, Retention)],
+    key: &[u8],
+) -> Option<(&'r [u8], Retention)> {
+    retentions
+        .iter()
+        .filter(|(prefix, _)| key.starts_with(prefix))
+        .max_by_key(|(prefix, _)| prefix.len())
+        .map(|(prefix, retention)| (prefix.as_slice(), *retention))
+}
+
+fn decode(value: &[u8]) -> Option<Retention> {
+    let (history, ttl) = value.split_at_checked(12)?;
+    Some(Retention {
+        history: expiry::decode(history)?,
+        ttl: match ttl {
+            [] => None,
+            ttl => Some(expiry::decode(ttl)?),
+        },
+    })
+}
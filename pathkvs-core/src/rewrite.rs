@@ -0,0 +1,97 @@
+//! rewrites of every key under a prefix, for migrations of the format of the values, see [`Database::rewrite`]
+//!
+//! a rewrite goes through the keys in order, in commits of up to 1024 keys, and each commit also writes
+//! the last key of its batch under [`system::REWRITE`] followed by the name of the rewrite,
+//! so a rewrite that is interrupted and run again with the same name goes on after the last batch that committed,
+//! instead of giving the keys already rewritten to the transformation again, the commit of the last batch removes it
+//!
+//! the keys are listed once, when the rewrite starts, keys created after that are left alone,
+//! as whoever writes them is expected to write them in the new format already
+
+use crate::{error::TransactionError, system, Database, Error};
+
+/// the most keys a single commit of a rewrite changes
+const REWRITE_BATCH: usize = 1024;
+
+/// how far a rewrite got, see [`Database::rewrite`]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct RewriteProgress {
+    /// the keys gone through so far
+    pub done: u64,
+    /// the keys to go through, without the ones gone through by earlier runs of the same rewrite
+    pub total: u64,
+    /// of the keys gone through, the ones whose value was replaced
+    pub rewritten: u64,
+}
+
+impl Database {
+    /// gives every key under `prefix` and its value to `map`, and writes the value it returns in its place,
+    /// none leaves the key as it is, `progress` is called after each batch commits,
+    /// see the [module](self) for how a rewrite goes on after an interruption
+    ///
+    /// each batch is a transaction that reads its keys, if another commit changes them first the batch is done again
+    /// with the new values, so `map` may be given a key more than once, and keys deleted since the start are skipped,
+    /// an error of `map` stops the rewrite before the batch it happened in is committed
+    pub fn rewrite<E: From<Error>>(
+        &self,
+        name: &[u8],
+        prefix: &[u8],
+        mut map: impl FnMut(&[u8], &[u8]) -> Result<Option<Vec<u8>>, E>,
+        mut progress: impl FnMut(RewriteProgress),
+    ) -> Result<RewriteProgress, E> {
+        let position = system::key(system::REWRITE, name);
+        let last = self.read(&position);
+        let keys = self
+            .list(prefix, b"")
+            .into_iter()
+            .filter(|key| last.is_empty() || *key > last)
+            .collect::<Vec<_>>();
+        let mut status = RewriteProgress {
+            total: keys.len() as u64,
+            ..Default::default()
+        };
+        if keys.is_empty() {
+            if !last.is_empty() {
+                self.commit_blind(|ts| ts.stage(&position, None))?;
+            }
+            return Ok(status);
+        }
+        let batches = keys.len().div_ceil(REWRITE_BATCH);
+        for (index, batch) in keys.chunks(REWRITE_BATCH).enumerate() {
+            loop {
+                let mut ts = self.start_writes();
+                // two runs of the same rewrite at once conflict, instead of both doing the batch
+                ts.read(&position);
+                let mut rewritten = 0;
+                for key in batch {
+                    if !ts.exists(key) {
+                        continue;
+                    }
+                    let value = ts.read(key).to_vec();
+                    if let Some(new) = map(key, &value)? {
+                        if new != value {
+                            ts.write(key, &new);
+                            rewritten += 1;
+                        }
+                    }
+                }
+                if index + 1 == batches {
+                    ts.stage(&position, None);
+                } else {
+                    ts.stage(&position, batch.last().map(Vec::as_slice));
+                }
+                match ts.commit() {
+                    Ok(_) => {
+                        status.done += batch.len() as u64;
+                        status.rewritten += rewritten;
+                        break;
+                    }
+                    Err(TransactionError::Conflict) => continue,
+                    Err(TransactionError::Io(error)) => return Err(Error::from(error).into()),
+                }
+            }
+            progress(status);
+        }
+        Ok(status)
+    }
+}
@@ -0,0 +1,173 @@
+//! staged values of large transactions kept in a temporary file, see [`Transaction::spill_threshold`](crate::Transaction::spill_threshold)
+
+use std::{
+    cell::Cell,
+    collections::HashMap,
+    fs::File,
+    io::{BufReader, Error, Read, Seek, SeekFrom, Write},
+    path::PathBuf,
+    rc::Rc,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use crate::Changes;
+
+#[derive(Clone)]
+pub(crate) struct Spill {
+    threshold: usize,
+    /// bytes written to the transaction since it last spilled, overwritten keys are counted again
+    staged: usize,
+    /// created on the first spill, shared by clones of the transaction, it is only ever appended to,
+    /// so the offsets of every clone stay valid
+    file: Option<Rc<SpillFile>>,
+    /// the keys whose value is in the file, with its offset and length
+    index: HashMap<Vec<u8>, (u64, u32)>,
+    /// the last value read from the file, so it can be borrowed
+    loaded: Vec<u8>,
+    /// reads and writes can't fail, so the first error is kept and returned by the commit
+    error: Option<Rc<Error>>,
+}
+
+struct SpillFile {
+    file: File,
+    path: PathBuf,
+    len: Cell<u64>,
+}
+
+impl Spill {
+    pub(crate) fn new(threshold: usize) -> Self {
+        Self {
+            threshold,
+            staged: 0,
+            file: None,
+            index: HashMap::new(),
+            loaded: Vec::new(),
+            error: None,
+        }
+    }
+    pub(crate) fn set_threshold(&mut self, threshold: usize) {
+        self.threshold = threshold;
+    }
+    /// called after each write of `written` bytes, moves the values of `changes` to the file once they pass the threshold
+    ///
+    /// if the file can't be written the transaction stays in memory from then on
+    pub(crate) fn wrote(&mut self, key: &[u8], written: usize, changes: &mut Changes) {
+        self.index.remove(key);
+        self.staged = self.staged.saturating_add(written);
+        if self.staged > self.threshold {
+            if let Err(error) = self.spill(changes) {
+                self.threshold = usize::MAX;
+                self.error.get_or_insert(Rc::new(error));
+            }
+        }
+    }
+    /// deletions have no value, so they stay in `changes`
+    fn spill(&mut self, changes: &mut Changes) -> Result<(), Error> {
+        let file = match &self.file {
+            Some(file) => file.clone(),
+            None => self.file.insert(Rc::new(SpillFile::create()?)).clone(),
+        };
+        let start = file.len.get();
+        let mut buffer = Vec::new();
+        let mut entries = Vec::with_capacity(changes.len());
+        for value in changes.values().flatten() {
+            entries.push((start + buffer.len() as u64, value.len() as u32));
+            buffer.extend_from_slice(value);
+        }
+        // reads move the cursor, and other clones may have appended since
+        (&file.file).seek(SeekFrom::Start(start))?;
+        (&file.file).write_all(&buffer)?;
+        file.len.set(start + buffer.len() as u64);
+        // draining visits the entries in the same order as `values` did
+        let mut entries = entries.into_iter();
+        for (key, value) in std::mem::take(changes) {
+            match value {
+                Some(_) => {
+                    let entry = entries
+                        .next()
+                        .expect("pathkvs: spilled value without an entry");
+                    self.index.insert(key, entry);
+                }
+                None => {
+                    changes.insert(key, None);
+                }
+            }
+        }
+        self.staged = 0;
+        Ok(())
+    }
+    /// the value of a spilled key, empty if it can't be read
+    pub(crate) fn read(&mut self, key: &[u8]) -> Option<&[u8]> {
+        let &(offset, len) = self.index.get(key)?;
+        let file = self.file.as_ref().expect("pathkvs: spilled without a file");
+        self.loaded.resize(len as usize, 0);
+        let result = (&file.file)
+            .seek(SeekFrom::Start(offset))
+            .and_then(|_| (&file.file).read_exact(&mut self.loaded));
+        if let Err(error) = result {
+            self.loaded.clear();
+            self.error.get_or_insert(Rc::new(error));
+        }
+        Some(&self.loaded)
+    }
+    /// moves every spilled value back into `changes`, reading the file in order, the transaction stays in memory from then on
+    pub(crate) fn unspill(&mut self, changes: &mut Changes) {
+        self.threshold = usize::MAX;
+        let Some(file) = self.file.take() else {
+            return;
+        };
+        let mut entries = std::mem::take(&mut self.index)
+            .into_iter()
+            .collect::<Vec<_>>();
+        entries.sort_unstable_by_key(|&(_, (offset, _))| offset);
+        let mut reader = BufReader::new(&file.file);
+        let mut position = None;
+        changes.reserve(entries.len());
+        for (key, (offset, len)) in entries {
+            let mut value = vec![0; len as usize];
+            let result = match position {
+                Some(position) if position == offset => Ok(()),
+                _ => reader.seek(SeekFrom::Start(offset)).map(drop),
+            }
+            .and_then(|()| reader.read_exact(&mut value));
+            if let Err(error) = result {
+                self.error.get_or_insert(Rc::new(error));
+                return;
+            }
+            position = Some(offset + len as u64);
+            changes.insert(key, Some(value));
+        }
+    }
+    /// the first error spilling, reading or unspilling
+    pub(crate) fn error(&self) -> Option<Error> {
+        let error = self.error.as_ref()?;
+        Some(Error::new(error.kind(), error.to_string()))
+    }
+}
+
+impl SpillFile {
+    fn create() -> Result<Self, Error> {
+        static COUNTER: AtomicU64 = AtomicU64::new(0);
+        let path = std::env::temp_dir().join(format!(
+            "pathkvs-spill-{}-{}",
+            std::process::id(),
+            COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+        let file = File::options()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .open(&path)?;
+        Ok(Self {
+            file,
+            path,
+            len: Cell::new(0),
+        })
+    }
+}
+
+impl Drop for SpillFile {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
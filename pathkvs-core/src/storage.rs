@@ -0,0 +1,570 @@
+use std::io::{Error, ErrorKind, Read};
+
+#[cfg(feature = "fs")]
+use std::{
+    fs::{File, OpenOptions},
+    io::{BufReader, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+};
+
+use crate::DatabaseWriteSyncMode;
+
+/// where the commit history of a persistent database is kept
+///
+/// the history is a single append only byte stream, the database only ever reads it whole when opening,
+/// and after that only truncates the invalid tail and appends new commits, besides marking its header
+/// when it is closed cleanly
+pub trait StorageBackend: Send {
+    /// a reader positioned at the start of the history
+    fn reader(&mut self) -> Result<Box<dyn Read + '_>, Error>;
+    /// discard everything after the first `len` bytes, appends continue from there
+    fn truncate(&mut self, len: u64) -> Result<(), Error>;
+    /// write the bytes at the end of the history
+    fn append(&mut self, bytes: &[u8]) -> Result<(), Error>;
+    /// called after each commit is appended, must not return until the commit is as durable as `mode` requires
+    fn sync(&mut self, mode: DatabaseWriteSyncMode) -> Result<(), Error>;
+    /// replace the whole history with `history` and make it durable, appends continue after it,
+    /// see [`crate::Database::compact`]
+    ///
+    /// either the old or the new history must be found if it fails or the process stops in the middle,
+    /// return an error of kind [`ErrorKind::Unsupported`] if that can't be done
+    fn replace(&mut self, _history: &[u8]) -> Result<(), Error> {
+        Err(ErrorKind::Unsupported.into())
+    }
+    /// overwrite the start of the history with `header`, which is as long as the header already there,
+    /// appends still continue from the end, it is durable once synced, see [`crate::Database::close`]
+    ///
+    /// return an error of kind [`ErrorKind::Unsupported`] if that can't be done, the history is then
+    /// never marked as closed cleanly
+    fn rewrite_header(&mut self, _header: &[u8]) -> Result<(), Error> {
+        Err(ErrorKind::Unsupported.into())
+    }
+}
+
+#[cfg(feature = "fs")]
+pub struct FileStorage {
+    file: File,
+    durable_writes: bool,
+    path: Option<PathBuf>,
+}
+
+#[cfg(feature = "fs")]
+impl FileStorage {
+    /// the file must be opened with read and write access
+    pub fn new(file: File) -> Self {
+        Self {
+            file,
+            durable_writes: false,
+            path: None,
+        }
+    }
+    /// the file was opened so that writes only return once they are on the disk, like with `O_DSYNC`,
+    /// so [`DatabaseWriteSyncMode::Sync`] does not fsync it
+    pub fn durable_writes(mut self, durable_writes: bool) -> Self {
+        self.durable_writes = durable_writes;
+        self
+    }
+    /// the path the file was opened from, without it the history can't be replaced,
+    /// since the new history is written next to the file and then renamed over it
+    pub fn path(mut self, path: impl Into<PathBuf>) -> Self {
+        self.path = Some(path.into());
+        self
+    }
+}
+
+#[cfg(feature = "fs")]
+impl StorageBackend for FileStorage {
+    fn reader(&mut self) -> Result<Box<dyn Read + '_>, Error> {
+        self.file.seek(SeekFrom::Start(0))?;
+        Ok(Box::new(BufReader::new(&mut self.file)))
+    }
+    fn truncate(&mut self, len: u64) -> Result<(), Error> {
+        self.file.seek(SeekFrom::Start(len))?;
+        self.file.set_len(len)
+    }
+    fn append(&mut self, bytes: &[u8]) -> Result<(), Error> {
+        self.file.write_all(bytes)
+    }
+    fn sync(&mut self, mode: DatabaseWriteSyncMode) -> Result<(), Error> {
+        match mode {
+            DatabaseWriteSyncMode::Sync if !self.durable_writes => {
+                self.file.flush()?;
+                self.file.sync_all()
+            }
+            DatabaseWriteSyncMode::Sync | DatabaseWriteSyncMode::Flush => self.file.flush(),
+            DatabaseWriteSyncMode::Cached => Ok(()),
+        }
+    }
+    fn replace(&mut self, history: &[u8]) -> Result<(), Error> {
+        let Some(path) = &self.path else {
+            return Err(ErrorKind::Unsupported.into());
+        };
+        (self.file, self.durable_writes) = replace_file(path, self.durable_writes, history)?;
+        Ok(())
+    }
+    fn rewrite_header(&mut self, header: &[u8]) -> Result<(), Error> {
+        let end = self.file.stream_position()?;
+        self.file.seek(SeekFrom::Start(0))?;
+        let written = self.file.write_all(header);
+        self.file.seek(SeekFrom::Start(end))?;
+        written
+    }
+}
+
+/// writes `history` to a new file next to `path` and renames it over it, so the file is either
+/// the old or the new one, returns the new file, positioned at its end, opened like [`open_file`] does
+///
+/// the history is a single file, so this rename is the whole layout change, a crash before it only leaves
+/// the `.compact` file behind, which opening ignores and the next compaction truncates
+#[cfg(feature = "fs")]
+fn replace_file(path: &Path, write_through: bool, history: &[u8]) -> Result<(File, bool), Error> {
+    let mut replacement = path.as_os_str().to_owned();
+    replacement.push(".compact");
+    let replacement = PathBuf::from(replacement);
+    let (mut file, durable_writes) = open_file(&replacement, write_through, true)?;
+    let written = file.write_all(history).and_then(|()| file.sync_all());
+    if let Err(error) = written.and_then(|()| std::fs::rename(&replacement, path)) {
+        let _ = std::fs::remove_file(&replacement);
+        return Err(error);
+    }
+    // the rename itself is only durable once the directory is synced, but by now the new file
+    // is the history, so failing here would have the caller keep appending to the old one
+    #[cfg(unix)]
+    if let Some(parent) = path.parent() {
+        let parent = if parent.as_os_str().is_empty() {
+            Path::new(".")
+        } else {
+            parent
+        };
+        let _ = File::open(parent).and_then(|parent| parent.sync_all());
+    }
+    Ok((file, durable_writes))
+}
+
+#[cfg(feature = "fs")]
+fn open_options(truncate: bool) -> OpenOptions {
+    let mut options = File::options();
+    options
+        .read(true)
+        .write(true)
+        .truncate(truncate)
+        .create(true);
+    options
+}
+
+/// opens the history file, with writes that only return once they are on the disk if `write_through`
+/// is set and supported, returns whether they do
+#[cfg(feature = "fs")]
+pub(crate) fn open_file(
+    path: &Path,
+    write_through: bool,
+    truncate: bool,
+) -> Result<(File, bool), Error> {
+    if write_through {
+        if let Some(file) = open_write_through(path, truncate)? {
+            return Ok((file, true));
+        }
+    }
+    Ok((open_options(truncate).open(path)?, false))
+}
+
+/// `None` if the platform or the filesystem can't do it
+#[cfg(feature = "fs")]
+fn open_write_through(path: &Path, truncate: bool) -> Result<Option<File>, Error> {
+    #[cfg(any(unix, windows))]
+    {
+        let mut options = open_options(truncate);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::custom_flags(&mut options, libc::O_DSYNC);
+        #[cfg(windows)]
+        {
+            const FILE_FLAG_WRITE_THROUGH: u32 = 0x80000000;
+            std::os::windows::fs::OpenOptionsExt::custom_flags(
+                &mut options,
+                FILE_FLAG_WRITE_THROUGH,
+            );
+        }
+        match options.open(path) {
+            Ok(file) => return Ok(Some(file)),
+            Err(error) if error.kind() != std::io::ErrorKind::InvalidInput => return Err(error),
+            Err(_) => {}
+        }
+    }
+    let _ = (path, truncate);
+    Ok(None)
+}
+
+/// a file whose whole blocks are written with `O_DIRECT`, linux only, see [`crate::DatabaseOptions::direct_io`]
+///
+/// the partial block at the end is kept in memory and written through a second, buffered handle,
+/// so the file never has to be padded, once it fills up it is written again directly
+#[cfg(all(feature = "fs", target_os = "linux"))]
+pub(crate) struct DirectFileStorage {
+    direct: File,
+    buffered: File,
+    /// the length of the file, appends start here
+    len: u64,
+    /// the bytes of the file after its last whole block
+    tail: Vec<u8>,
+    pending: Vec<u8>,
+    durable_writes: bool,
+    /// to open the file again once its history is replaced
+    path: PathBuf,
+    write_through: bool,
+}
+
+/// the alignment `O_DIRECT` needs for offsets, lengths and buffers, the page size covers every common block size
+#[cfg(all(feature = "fs", target_os = "linux"))]
+const DIRECT_BLOCK: usize = 4096;
+
+#[cfg(all(feature = "fs", target_os = "linux"))]
+impl DirectFileStorage {
+    /// `None` if the filesystem does not support `O_DIRECT`, as with tmpfs
+    pub(crate) fn open(
+        path: &Path,
+        write_through: bool,
+        truncate: bool,
+    ) -> Result<Option<Self>, Error> {
+        use std::os::unix::fs::OpenOptionsExt;
+
+        let (buffered, durable_writes) = open_file(path, write_through, truncate)?;
+        let mut options = open_options(false);
+        options.custom_flags(libc::O_DIRECT | if durable_writes { libc::O_DSYNC } else { 0 });
+        let direct = match options.open(path) {
+            Ok(direct) => direct,
+            Err(error) if error.kind() == std::io::ErrorKind::InvalidInput => return Ok(None),
+            Err(error) => return Err(error),
+        };
+        let mut storage = Self {
+            direct,
+            buffered,
+            len: 0,
+            tail: Vec::new(),
+            pending: Vec::new(),
+            durable_writes,
+            path: path.to_path_buf(),
+            write_through,
+        };
+        storage.load_tail(storage.buffered.metadata()?.len())?;
+        Ok(Some(storage))
+    }
+    fn load_tail(&mut self, len: u64) -> Result<(), Error> {
+        use std::os::unix::fs::FileExt;
+
+        let tail_len = len % DIRECT_BLOCK as u64;
+        self.len = len;
+        self.tail = vec![0; tail_len as usize];
+        self.buffered.read_exact_at(&mut self.tail, len - tail_len)
+    }
+}
+
+#[cfg(all(feature = "fs", target_os = "linux"))]
+impl StorageBackend for DirectFileStorage {
+    fn reader(&mut self) -> Result<Box<dyn Read + '_>, Error> {
+        self.buffered.seek(SeekFrom::Start(0))?;
+        Ok(Box::new(BufReader::new(&mut self.buffered)))
+    }
+    fn truncate(&mut self, len: u64) -> Result<(), Error> {
+        if len >= self.len {
+            self.pending.truncate((len - self.len) as usize);
+            Ok(())
+        } else {
+            self.pending.clear();
+            self.buffered.set_len(len)?;
+            self.load_tail(len)
+        }
+    }
+    fn append(&mut self, bytes: &[u8]) -> Result<(), Error> {
+        self.pending.extend_from_slice(bytes);
+        Ok(())
+    }
+    fn sync(&mut self, mode: DatabaseWriteSyncMode) -> Result<(), Error> {
+        use std::os::unix::fs::FileExt;
+
+        if !self.pending.is_empty() {
+            // the tail and the pending bytes are only changed once both writes succeeded, so a failed sync
+            // leaves them as they were, and the next one writes the same blocks again from the same offset
+            let start = self.len - self.tail.len() as u64;
+            let mut data = Vec::with_capacity(self.tail.len() + self.pending.len());
+            data.extend_from_slice(&self.tail);
+            data.extend_from_slice(&self.pending);
+            let whole = data.len() / DIRECT_BLOCK * DIRECT_BLOCK;
+            let written = (|| {
+                if whole > 0 {
+                    let mut buffer = AlignedBuffer::new(whole);
+                    buffer.as_mut_slice().copy_from_slice(&data[..whole]);
+                    self.direct.write_all_at(buffer.as_mut_slice(), start)?;
+                }
+                self.buffered
+                    .write_all_at(&data[whole..], start + whole as u64)
+            })();
+            if let Err(error) = written {
+                // what was written past the end may differ from what the next sync writes there
+                let _ = self.buffered.set_len(self.len);
+                return Err(error);
+            }
+            self.len = start + data.len() as u64;
+            data.drain(..whole);
+            self.tail = data;
+            self.pending.clear();
+        }
+        if mode == DatabaseWriteSyncMode::Sync && !self.durable_writes {
+            self.buffered.sync_data()?;
+        }
+        Ok(())
+    }
+    fn replace(&mut self, history: &[u8]) -> Result<(), Error> {
+        replace_file(&self.path, self.write_through, history)?;
+        // it was supported by the filesystem when opened, and the file was renamed within the same directory
+        *self = Self::open(&self.path, self.write_through, false)?
+            .ok_or_else(|| Error::other("O_DIRECT is no longer supported"))?;
+        Ok(())
+    }
+    /// written through the buffered handle, since it is less than a block, and into the tail
+    /// if the file still has no whole block, as the tail is what is written there next
+    fn rewrite_header(&mut self, header: &[u8]) -> Result<(), Error> {
+        use std::os::unix::fs::FileExt;
+
+        self.buffered.write_all_at(header, 0)?;
+        if self.len < DIRECT_BLOCK as u64 {
+            let len = header.len().min(self.tail.len());
+            self.tail[..len].copy_from_slice(&header[..len]);
+        }
+        Ok(())
+    }
+}
+
+/// a zeroed buffer aligned for `O_DIRECT`
+#[cfg(all(feature = "fs", target_os = "linux"))]
+struct AlignedBuffer {
+    ptr: std::ptr::NonNull<u8>,
+    layout: std::alloc::Layout,
+}
+
+#[cfg(all(feature = "fs", target_os = "linux"))]
+impl AlignedBuffer {
+    fn new(len: usize) -> Self {
+        let layout = std::alloc::Layout::from_size_align(len, DIRECT_BLOCK)
+            .expect("pathkvs: direct write too large");
+        let ptr = std::ptr::NonNull::new(unsafe { std::alloc::alloc_zeroed(layout) })
+            .unwrap_or_else(|| std::alloc::handle_alloc_error(layout));
+        Self { ptr, layout }
+    }
+    fn as_mut_slice(&mut self) -> &mut [u8] {
+        unsafe { std::slice::from_raw_parts_mut(self.ptr.as_ptr(), self.layout.size()) }
+    }
+}
+
+#[cfg(all(feature = "fs", target_os = "linux"))]
+impl Drop for AlignedBuffer {
+    fn drop(&mut self) {
+        unsafe { std::alloc::dealloc(self.ptr.as_ptr(), self.layout) };
+    }
+}
+
+/// a file written through io_uring, linux only
+///
+/// appends are buffered in memory and written on sync, in [`DatabaseWriteSyncMode::Sync`] the write and the
+/// fsync are submitted together and the kernel runs them back to back, so each sync costs a single system call
+/// instead of two, and all commits persisted together are written with a single write
+#[cfg(all(feature = "io-uring", target_os = "linux"))]
+pub struct UringStorage {
+    file: File,
+    ring: crate::uring::Ring,
+    /// the length of the file, appends start here
+    len: u64,
+    pending: Vec<u8>,
+}
+
+#[cfg(all(feature = "io-uring", target_os = "linux"))]
+impl UringStorage {
+    /// the file must be opened with read and write access
+    ///
+    /// fails if io_uring is not available, as in kernels before 5.6 or where it is disabled,
+    /// in which case [`FileStorage`] can be used instead
+    pub fn new(file: File) -> Result<Self, Error> {
+        let len = file.metadata()?.len();
+        Ok(Self {
+            file,
+            ring: crate::uring::Ring::new()?,
+            len,
+            pending: Vec::new(),
+        })
+    }
+}
+
+#[cfg(all(feature = "io-uring", target_os = "linux"))]
+impl StorageBackend for UringStorage {
+    fn reader(&mut self) -> Result<Box<dyn Read + '_>, Error> {
+        self.file.seek(SeekFrom::Start(0))?;
+        Ok(Box::new(BufReader::new(&mut self.file)))
+    }
+    fn truncate(&mut self, len: u64) -> Result<(), Error> {
+        if len >= self.len {
+            self.pending.truncate((len - self.len) as usize);
+        } else {
+            self.pending.clear();
+            self.file.set_len(len)?;
+            self.len = len;
+        }
+        Ok(())
+    }
+    fn append(&mut self, bytes: &[u8]) -> Result<(), Error> {
+        self.pending.extend_from_slice(bytes);
+        Ok(())
+    }
+    fn sync(&mut self, mode: DatabaseWriteSyncMode) -> Result<(), Error> {
+        use crate::uring::Sqe;
+        use std::os::fd::AsRawFd;
+
+        // the data always reaches the os, like with FileStorage, only Sync also waits for the disk
+        let durable = mode == DatabaseWriteSyncMode::Sync;
+        let fd = self.file.as_raw_fd();
+        loop {
+            let writing = !self.pending.is_empty();
+            let mut entries = Vec::with_capacity(2);
+            if writing {
+                let len = self.pending.len().min(i32::MAX as usize) as u32;
+                let write = Sqe::write(fd, self.pending.as_ptr(), len, self.len);
+                entries.push(if durable { write.linked() } else { write });
+            }
+            if durable {
+                entries.push(Sqe::fsync(fd));
+            }
+            if entries.is_empty() {
+                return Ok(());
+            }
+            let mut results = self.ring.submit_and_wait(&entries)?.into_iter();
+            if writing {
+                let written = results.next().expect("one result per entry");
+                if written < 0 {
+                    return Err(Error::from_raw_os_error(-written));
+                }
+                if written == 0 {
+                    return Err(std::io::ErrorKind::WriteZero.into());
+                }
+                self.pending.drain(..written as usize);
+                self.len += written as u64;
+            }
+            if durable {
+                match results.next().expect("one result per entry") {
+                    0 if self.pending.is_empty() => return Ok(()),
+                    0 => continue,
+                    // a short write cancels the linked fsync, both are submitted again for the rest
+                    error if error == -libc::ECANCELED => continue,
+                    error => return Err(Error::from_raw_os_error(-error)),
+                }
+            } else if self.pending.is_empty() {
+                return Ok(());
+            }
+        }
+    }    fn rewrite_header(&mut self, header: &[u8]) -> Result<(), Error> {
+        std::os::unix::fs::FileExt::write_all_at(&self.file, header, 0)
+    }
+}
+
+/// keeps the history in the browser's `localStorage` under a single item
+///
+/// `localStorage` can only store whole strings, so the history is kept in memory
+/// and the entire item is rewritten on every sync, this is only suitable for small databases
+///
+/// IndexedDB is not offered because it is asynchronous, and commits must be durable before they return
+#[cfg(feature = "web")]
+pub struct LocalStorage {
+    item: String,
+    history: Vec<u8>,
+    dirty: bool,
+}
+
+#[cfg(feature = "web")]
+impl LocalStorage {
+    /// loads the history stored under the item name, if any
+    pub fn new(item: impl Into<String>) -> Result<Self, crate::Error> {
+        let item = item.into();
+        let history = match Self::storage()?.get_item(&item).map_err(js_error)? {
+            // each char holds one byte, see `sync`
+            Some(text) => text
+                .chars()
+                .map(|char| {
+                    u8::try_from(char).map_err(|_| {
+                        crate::Error::Corruption(format!(
+                            "invalid char in localStorage item {item:?}"
+                        ))
+                    })
+                })
+                .collect::<Result<Vec<u8>, crate::Error>>()?,
+            None => Vec::new(),
+        };
+        Ok(Self {
+            item,
+            history,
+            dirty: false,
+        })
+    }
+    fn storage() -> Result<web_sys::Storage, Error> {
+        web_sys::window()
+            .ok_or_else(|| Error::other("no window object"))?
+            .local_storage()
+            .map_err(js_error)?
+            .ok_or_else(|| Error::other("localStorage is not available"))
+    }
+}
+
+#[cfg(feature = "web")]
+fn js_error(value: wasm_bindgen::JsValue) -> Error {
+    Error::other(format!("{value:?}"))
+}
+
+#[cfg(feature = "web")]
+impl StorageBackend for LocalStorage {
+    fn reader(&mut self) -> Result<Box<dyn Read + '_>, Error> {
+        Ok(Box::new(self.history.as_slice()))
+    }
+    fn truncate(&mut self, len: u64) -> Result<(), Error> {
+        if (len as usize) < self.history.len() {
+            self.history.truncate(len as usize);
+            self.dirty = true;
+        }
+        Ok(())
+    }
+    fn append(&mut self, bytes: &[u8]) -> Result<(), Error> {
+        self.history.extend_from_slice(bytes);
+        self.dirty = true;
+        Ok(())
+    }
+    fn sync(&mut self, mode: DatabaseWriteSyncMode) -> Result<(), Error> {
+        if mode == DatabaseWriteSyncMode::Cached || !self.dirty {
+            return Ok(());
+        }
+        // javascript strings are utf-16, mapping each byte to the char of the same value
+        // stores one byte per code unit, which is as compact as localStorage allows
+        let text = self
+            .history
+            .iter()
+            .map(|&byte| byte as char)
+            .collect::<String>();
+        Self::storage()?
+            .set_item(&self.item, &text)
+            .map_err(js_error)?;
+        self.dirty = false;
+        Ok(())
+    }
+    /// a single item is set, so this is atomic
+    fn replace(&mut self, history: &[u8]) -> Result<(), Error> {
+        self.history = history.to_vec();
+        self.dirty = true;
+        self.sync(DatabaseWriteSyncMode::Sync)
+    }
+    fn rewrite_header(&mut self, header: &[u8]) -> Result<(), Error> {
+        self.history[..header.len()].copy_from_slice(header);
+        self.dirty = true;
+        Ok(())
+    }
+}
+
+#[cfg(feature = "web")]
+impl Drop for LocalStorage {
+    fn drop(&mut self) {
+        let _ = self.sync(DatabaseWriteSyncMode::Sync);
+    }
+}
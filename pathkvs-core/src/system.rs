@@ -0,0 +1,65 @@
+//! the keyspace reserved for the metadata of the database itself
+//!
+//! every key starting with [`PREFIX`] belongs to it, and it is hidden from counts, lists, scans and changes,
+//! unless the start of the range is in it too, so `scan(b"", b"")` never sees it,
+//! but `scan(system::INDEXES, b"")` sees the index definitions
+//!
+//! the keys are still read and written like any other, and they are kept in backups and replicated by followers
+
+use std::ops::{Bound, RangeBounds};
+
+/// the start of every key of the keyspace
+pub const PREFIX: &[u8] = b"__pathkvs__/";
+/// the version of the format of the data, and of each subsystem that keeps metadata
+pub const SCHEMA: &[u8] = b"__pathkvs__/schema/";
+/// the definitions of the indexes, one key per index, with the entries of the index under it
+pub const INDEXES: &[u8] = b"__pathkvs__/indexes/";
+/// the expiry of keys, one key per key that expires
+pub const TTL: &[u8] = b"__pathkvs__/ttl/";
+/// the retention of the keys under each prefix, one key per prefix, see [`crate::retention`]
+pub const RETENTION: &[u8] = b"__pathkvs__/retention/";
+/// the content type of values, one key per key that has one, see [`crate::content`]
+pub const TYPES: &[u8] = b"__pathkvs__/types/";
+/// how far each follower or replica has applied the log
+pub const REPLICATION: &[u8] = b"__pathkvs__/replication/";
+/// the last key done by each rewrite that is under way, see [`crate::rewrite`]
+pub const REWRITE: &[u8] = b"__pathkvs__/rewrite/";
+/// the users of the server, one key per user, with its role and the hash of its token
+pub const USERS: &[u8] = b"__pathkvs__/users/";
+/// the rules of which role may read or write each prefix, one key per prefix and kind of access
+pub const ACL: &[u8] = b"__pathkvs__/acl/";
+/// the metadata of a commit, written in the commit it describes by [`crate::Transaction::set_meta`],
+/// so the key itself has the metadata of the last commit that had some
+pub const META: &[u8] = b"__pathkvs__/meta";
+
+pub fn is_system_key(key: &[u8]) -> bool {
+    key.starts_with(PREFIX)
+}
+
+/// the key `name` under the section `section` of the keyspace, such as `key(system::TTL, b"user/1")`
+pub fn key(section: &[u8], name: &[u8]) -> Vec<u8> {
+    debug_assert!(is_system_key(section), "pathkvs: not a system section");
+    [section, name].concat()
+}
+
+/// whether the key is seen by a range that starts with `start`
+pub(crate) fn visible(key: &[u8], start: &[u8]) -> bool {
+    !is_system_key(key) || is_system_key(start)
+}
+
+/// whether the key is between the bounds, system keys only if the start is a system key
+pub(crate) fn in_bounds(key: &[u8], start: Bound<&[u8]>, end: Bound<&[u8]>) -> bool {
+    let first = match start {
+        Bound::Included(first) | Bound::Excluded(first) => first,
+        Bound::Unbounded => &[],
+    };
+    (start, end).contains(key) && visible(key, first)
+}
+
+/// whether the key is in the range that starts with `start` and ends with `end`
+pub(crate) fn in_range(key: &[u8], start: &[u8], end: &[u8]) -> bool {
+    key.len() >= start.len() + end.len()
+        && key.starts_with(start)
+        && key.ends_with(end)
+        && visible(key, start)
+}
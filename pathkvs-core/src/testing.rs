@@ -0,0 +1,383 @@
+//! deterministic harness for testing code that uses transactions concurrently
+//!
+//! install a [`Harness`] with [`crate::Database::with_harness`], after that:
+//!
+//! - commit times come from a virtual clock instead of the system clock
+//! - threads that called [`set_actor`] start transactions and commit them in the order given by [`Harness::schedule`]
+//! - every successful commit is recorded, and [`Harness::check_serializable`] checks the recorded history
+//!
+//! a transaction reads from the snapshot taken when it started, so the order of the starts and commits
+//! completely determines what every transaction sees, no matter how the threads are scheduled
+
+use std::{
+    cell::Cell,
+    collections::{BTreeMap, HashSet, VecDeque},
+    ops::Bound,
+    sync::{Condvar, Mutex, MutexGuard},
+    time::{Duration, Instant},
+};
+
+use crate::{bound_ref, system, Changes, Commit, KeyRange};
+
+/// how long a scheduled thread waits for its turn before the harness gives up and panics
+const STUCK_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Step {
+    /// the actor starts a transaction
+    Start(usize),
+    /// the actor tries to commit, the step is consumed even if the commit conflicts
+    Commit(usize),
+}
+
+thread_local! {
+    static ACTOR: Cell<Option<usize>> = const { Cell::new(None) };
+}
+
+/// identifies the current thread in the steps of the schedule
+pub fn set_actor(actor: usize) {
+    ACTOR.with(|cell| cell.set(Some(actor)));
+}
+
+pub struct Harness {
+    clock: Mutex<Clock>,
+    schedule: Mutex<VecDeque<Step>>,
+    turn: Condvar,
+    baseline: Mutex<BTreeMap<Vec<u8>, Vec<u8>>>,
+    history: Mutex<Vec<CommitRecord>>,
+}
+
+struct Clock {
+    now: Duration,
+    step: Duration,
+}
+
+/// a successful commit, as seen by the harness
+#[derive(Debug, Clone)]
+pub struct CommitRecord {
+    pub actor: Option<usize>,
+    pub time: Duration,
+    /// keys read by the transaction and the values they had in its snapshot
+    pub reads: Vec<(Vec<u8>, Vec<u8>)>,
+    pub scans: Vec<ObservedScan>,
+    pub ranges: Vec<ObservedRange>,
+    /// sorted by key, none for the keys deleted
+    pub writes: Vec<(Vec<u8>, Option<Vec<u8>>)>,
+}
+
+/// a scan, count or list done by the transaction and the keys it matched in its snapshot
+#[derive(Debug, Clone)]
+pub struct ObservedScan {
+    pub start: Vec<u8>,
+    pub end: Vec<u8>,
+    pub result: Vec<(Vec<u8>, Vec<u8>)>,
+}
+
+/// a range scan done by the transaction and the keys it matched in its snapshot,
+/// see [`crate::Transaction::scan_range`]
+#[derive(Debug, Clone)]
+pub struct ObservedRange {
+    pub start: Bound<Vec<u8>>,
+    pub end: Bound<Vec<u8>>,
+    pub result: Vec<(Vec<u8>, Vec<u8>)>,
+}
+
+/// holds the history lock while the commit is in progress, so records are kept in commit order,
+/// and only moves the schedule forward once the commit is done
+pub(crate) struct Recorder<'a> {
+    harness: &'a Harness,
+    scheduled: bool,
+    history: MutexGuard<'a, Vec<CommitRecord>>,
+    actor: Option<usize>,
+    reads: Vec<(Vec<u8>, Vec<u8>)>,
+    scans: Vec<ObservedScan>,
+    ranges: Vec<ObservedRange>,
+}
+
+impl Harness {
+    /// the virtual clock starts at one second after the unix epoch and advances one second per commit
+    pub fn new() -> Self {
+        Self {
+            clock: Mutex::new(Clock {
+                now: Duration::from_secs(1),
+                step: Duration::from_secs(1),
+            }),
+            schedule: Mutex::new(VecDeque::new()),
+            turn: Condvar::new(),
+            baseline: Mutex::new(BTreeMap::new()),
+            history: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// the time the next commit will get
+    pub fn time(&self) -> Duration {
+        self.clock.lock().unwrap().now
+    }
+    pub fn set_time(&self, time: Duration) {
+        self.clock.lock().unwrap().now = time;
+    }
+    pub fn advance(&self, by: Duration) {
+        self.clock.lock().unwrap().now += by;
+    }
+    /// how much the clock advances every time a commit reads it
+    pub fn set_step(&self, step: Duration) {
+        self.clock.lock().unwrap().step = step;
+    }
+    pub(crate) fn tick(&self) -> Duration {
+        let clock = &mut *self.clock.lock().unwrap();
+        let now = clock.now;
+        clock.now += clock.step;
+        now
+    }
+
+    /// appends steps to the schedule
+    ///
+    /// an actor that reaches a step that is in the schedule waits until all the steps before it happened,
+    /// steps that are not in the schedule happen immediately
+    pub fn schedule(&self, steps: impl IntoIterator<Item = Step>) {
+        self.schedule.lock().unwrap().extend(steps);
+        self.turn.notify_all();
+    }
+    pub fn is_done(&self) -> bool {
+        self.schedule.lock().unwrap().is_empty()
+    }
+    /// waits until the step is the next one in the schedule, returns false if it is not scheduled at all
+    fn wait_turn(&self, step: Step) -> bool {
+        let mut schedule = self.schedule.lock().unwrap();
+        if !schedule.contains(&step) {
+            return false;
+        }
+        let deadline = Instant::now() + STUCK_TIMEOUT;
+        while schedule.front() != Some(&step) {
+            let timeout = deadline.saturating_duration_since(Instant::now());
+            if timeout.is_zero() {
+                panic!(
+                    "pathkvs harness: {step:?} is stuck waiting for {:?}",
+                    schedule.front()
+                );
+            }
+            schedule = self.turn.wait_timeout(schedule, timeout).unwrap().0;
+        }
+        true
+    }
+    fn next_turn(&self) {
+        self.schedule.lock().unwrap().pop_front();
+        self.turn.notify_all();
+    }
+    pub(crate) fn pass_start(&self) {
+        if let Some(actor) = ACTOR.with(Cell::get) {
+            if self.wait_turn(Step::Start(actor)) {
+                self.next_turn();
+            }
+        }
+    }
+
+    pub(crate) fn set_baseline(&self, baseline: BTreeMap<Vec<u8>, Vec<u8>>) {
+        *self.baseline.lock().unwrap() = baseline;
+    }
+    /// waits for the commit step of the current actor and captures what the transaction observed,
+    /// `snapshot` is the commit the transaction started from
+    pub(crate) fn begin_commit(
+        &self,
+        snapshot: Option<&Commit>,
+        reads: &HashSet<Vec<u8>>,
+        scans: &HashSet<(Vec<u8>, usize)>,
+        ranges: &HashSet<KeyRange>,
+    ) -> Recorder<'_> {
+        let actor = ACTOR.with(Cell::get);
+        let scheduled = actor.is_some_and(|actor| self.wait_turn(Step::Commit(actor)));
+        let mut reads = reads
+            .iter()
+            .map(|key| (key.clone(), Commit::read(snapshot, key).to_vec()))
+            .collect::<Vec<_>>();
+        reads.sort();
+        let mut scans = scans
+            .iter()
+            .map(|(start_end, start_len)| {
+                let (start, end) = start_end.split_at(*start_len);
+                ObservedScan {
+                    start: start.to_vec(),
+                    end: end.to_vec(),
+                    result: Commit::scan(snapshot, start, end)
+                        .into_iter()
+                        .map(|(k, v)| (k.to_vec(), v.to_vec()))
+                        .collect(),
+                }
+            })
+            .collect::<Vec<_>>();
+        scans.sort_by(|a, b| (&a.start, &a.end).cmp(&(&b.start, &b.end)));
+        let ranges = ranges
+            .iter()
+            .map(|(start, end)| ObservedRange {
+                start: start.clone(),
+                end: end.clone(),
+                result: Commit::scan_between(snapshot, bound_ref(start), bound_ref(end))
+                    .into_iter()
+                    .map(|(k, v)| (k.to_vec(), v.to_vec()))
+                    .collect(),
+            })
+            .collect();
+        Recorder {
+            harness: self,
+            scheduled,
+            history: self.history.lock().unwrap(),
+            actor,
+            reads,
+            scans,
+            ranges,
+        }
+    }
+
+    /// every successful commit since the harness was installed, in commit order
+    pub fn history(&self) -> Vec<CommitRecord> {
+        self.history.lock().unwrap().clone()
+    }
+    /// replays the history serially, in commit order, starting from the contents the database had
+    /// when the harness was installed, and checks that every transaction observed exactly what it would
+    /// have observed if it had run alone at the moment it committed
+    ///
+    /// the error describes the first commit that breaks serializability
+    pub fn check_serializable(&self) -> Result<(), String> {
+        let mut state = self.baseline.lock().unwrap().clone();
+        for (index, record) in self.history.lock().unwrap().iter().enumerate() {
+            for (key, value) in &record.reads {
+                let serial = state.get(key).map(Vec::as_slice).unwrap_or(&[]);
+                if serial != value.as_slice() {
+                    return Err(format!(
+                        "commit {index} (actor {:?}) read {:?} as {:?}, but serially it was {:?}",
+                        record.actor,
+                        String::from_utf8_lossy(key),
+                        String::from_utf8_lossy(value),
+                        String::from_utf8_lossy(serial),
+                    ));
+                }
+            }
+            for scan in &record.scans {
+                let serial = state
+                    .iter()
+                    .filter(|(key, _)| system::in_range(key, &scan.start, &scan.end))
+                    .map(|(k, v)| (k.clone(), v.clone()))
+                    .collect::<Vec<_>>();
+                if serial != scan.result {
+                    return Err(format!(
+                        "commit {index} (actor {:?}) scanned {:?}..{:?} and saw {} key(s), but serially there were {}",
+                        record.actor,
+                        String::from_utf8_lossy(&scan.start),
+                        String::from_utf8_lossy(&scan.end),
+                        scan.result.len(),
+                        serial.len(),
+                    ));
+                }
+            }
+            for range in &record.ranges {
+                let (start, end) = (bound_ref(&range.start), bound_ref(&range.end));
+                let serial = state
+                    .iter()
+                    .filter(|(key, _)| system::in_bounds(key, start, end))
+                    .map(|(k, v)| (k.clone(), v.clone()))
+                    .collect::<Vec<_>>();
+                if serial != range.result {
+                    return Err(format!(
+                        "commit {index} (actor {:?}) scanned the range {:?}..{:?} and saw {} key(s), but serially there were {}",
+                        record.actor,
+                        start.map(String::from_utf8_lossy),
+                        end.map(String::from_utf8_lossy),
+                        range.result.len(),
+                        serial.len(),
+                    ));
+                }
+            }
+            for (key, value) in &record.writes {
+                match value {
+                    Some(value) => state.insert(key.clone(), value.clone()),
+                    None => state.remove(key),
+                };
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Default for Harness {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for Recorder<'_> {
+    fn drop(&mut self) {
+        if self.scheduled {
+            self.harness.next_turn();
+        }
+    }
+}
+
+impl Recorder<'_> {
+    pub(crate) fn finish(mut self, time: Duration, changes: &Changes) {
+        let mut writes = changes
+            .iter()
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .collect::<Vec<_>>();
+        writes.sort();
+        self.history.push(CommitRecord {
+            actor: self.actor,
+            time,
+            reads: std::mem::take(&mut self.reads),
+            scans: std::mem::take(&mut self.scans),
+            ranges: std::mem::take(&mut self.ranges),
+            writes,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{error::TransactionError, Database};
+    use std::sync::Arc;
+
+    /// both actors read `x` and write it back incremented, starting from the same snapshot
+    fn increment(db: &Database, actor: usize) -> Result<Duration, TransactionError> {
+        set_actor(actor);
+        let mut ts = db.start_writes();
+        let n = std::str::from_utf8(ts.read(b"x")).unwrap().parse::<u32>().unwrap();
+        ts.write(b"x", (n + 1).to_string().as_bytes());
+        ts.commit()
+    }
+
+    #[test]
+    fn conflicting_interleaving_is_serializable() {
+        let harness = Arc::new(Harness::new());
+        let db = Database::memory();
+        db.write(b"x", b"0").unwrap();
+        let db = db.with_harness(harness.clone());
+        harness.schedule([Step::Start(0), Step::Start(1), Step::Commit(0), Step::Commit(1)]);
+        let (first, second) = std::thread::scope(|scope| {
+            let first = scope.spawn(|| increment(&db, 0));
+            let second = scope.spawn(|| increment(&db, 1));
+            (first.join().unwrap(), second.join().unwrap())
+        });
+        assert!(first.is_ok());
+        assert!(matches!(second, Err(TransactionError::Conflict)));
+        assert!(harness.is_done());
+        assert_eq!(db.read(b"x"), b"1");
+        assert_eq!(harness.history().len(), 1);
+        assert_eq!(harness.check_serializable(), Ok(()));
+    }
+
+    #[test]
+    fn lost_update_is_not_serializable() {
+        let harness = Arc::new(Harness::new());
+        let db = Database::memory();
+        db.write(b"x", b"0").unwrap();
+        let db = db.with_harness(harness.clone());
+        increment(&db, 0).unwrap();
+        assert_eq!(harness.check_serializable(), Ok(()));
+        // what the second actor would have recorded had its commit not conflicted
+        let mut lost = harness.history()[0].clone();
+        lost.actor = Some(1);
+        harness.history.lock().unwrap().push(lost);
+        let error = harness.check_serializable().unwrap_err();
+        assert!(error.starts_with("commit 1 (actor Some(1)) read \"x\" as \"0\""), "{error}");
+    }
+}
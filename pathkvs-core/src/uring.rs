@@ -0,0 +1,260 @@
+//! the minimum of io_uring needed to append to a file and fsync it, talking to the kernel directly
+//!
+//! the structures mirror `linux/io_uring.h`
+
+use std::{
+    io::{Error, ErrorKind},
+    os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd},
+    sync::atomic::{AtomicU32, Ordering},
+};
+
+const IORING_OFF_SQ_RING: libc::off_t = 0;
+const IORING_OFF_CQ_RING: libc::off_t = 0x8000000;
+const IORING_OFF_SQES: libc::off_t = 0x10000000;
+const IORING_ENTER_GETEVENTS: libc::c_uint = 1;
+const IORING_OP_FSYNC: u8 = 3;
+const IORING_OP_WRITE: u8 = 23;
+const IOSQE_IO_LINK: u8 = 1 << 2;
+
+/// a sync submits at most a write and an fsync
+const ENTRIES: u32 = 4;
+
+#[repr(C)]
+#[derive(Default)]
+struct SqringOffsets {
+    head: u32,
+    tail: u32,
+    ring_mask: u32,
+    ring_entries: u32,
+    flags: u32,
+    dropped: u32,
+    array: u32,
+    resv1: u32,
+    user_addr: u64,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct CqringOffsets {
+    head: u32,
+    tail: u32,
+    ring_mask: u32,
+    ring_entries: u32,
+    overflow: u32,
+    cqes: u32,
+    flags: u32,
+    resv1: u32,
+    user_addr: u64,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct Params {
+    sq_entries: u32,
+    cq_entries: u32,
+    flags: u32,
+    sq_thread_cpu: u32,
+    sq_thread_idle: u32,
+    features: u32,
+    wq_fd: u32,
+    resv: [u32; 3],
+    sq_off: SqringOffsets,
+    cq_off: CqringOffsets,
+}
+
+#[repr(C)]
+#[derive(Default, Clone, Copy)]
+pub(crate) struct Sqe {
+    opcode: u8,
+    flags: u8,
+    ioprio: u16,
+    fd: i32,
+    off: u64,
+    addr: u64,
+    len: u32,
+    op_flags: u32,
+    user_data: u64,
+    buf_index: u16,
+    personality: u16,
+    splice_fd_in: i32,
+    addr3: u64,
+    pad: u64,
+}
+
+#[repr(C)]
+struct Cqe {
+    user_data: u64,
+    res: i32,
+    flags: u32,
+}
+
+impl Sqe {
+    /// writes `len` bytes at `addr` to the file at `offset`
+    ///
+    /// the buffer must stay alive until [`Ring::submit_and_wait`] returns
+    pub(crate) fn write(fd: RawFd, addr: *const u8, len: u32, offset: u64) -> Self {
+        Self {
+            opcode: IORING_OP_WRITE,
+            fd,
+            off: offset,
+            addr: addr as u64,
+            len,
+            ..Self::default()
+        }
+    }
+    pub(crate) fn fsync(fd: RawFd) -> Self {
+        Self {
+            opcode: IORING_OP_FSYNC,
+            fd,
+            ..Self::default()
+        }
+    }
+    /// the next entry only starts after this one completes successfully, otherwise it is cancelled
+    pub(crate) fn linked(mut self) -> Self {
+        self.flags |= IOSQE_IO_LINK;
+        self
+    }
+}
+
+struct Mapping {
+    ptr: *mut u8,
+    len: usize,
+}
+
+impl Mapping {
+    fn new(fd: &OwnedFd, len: usize, offset: libc::off_t) -> Result<Self, Error> {
+        let ptr = unsafe {
+            libc::mmap(
+                std::ptr::null_mut(),
+                len,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_SHARED | libc::MAP_POPULATE,
+                fd.as_raw_fd(),
+                offset,
+            )
+        };
+        if ptr == libc::MAP_FAILED {
+            return Err(Error::last_os_error());
+        }
+        Ok(Self {
+            ptr: ptr.cast(),
+            len,
+        })
+    }
+    /// # Safety
+    ///
+    /// `offset` must be inside the mapping and aligned for `T`
+    unsafe fn at<T>(&self, offset: u32) -> *mut T {
+        self.ptr.add(offset as usize).cast()
+    }
+}
+
+impl Drop for Mapping {
+    fn drop(&mut self) {
+        unsafe { libc::munmap(self.ptr.cast(), self.len) };
+    }
+}
+
+pub(crate) struct Ring {
+    // the mappings are declared before the fd so they are unmapped before it is closed
+    sq: Mapping,
+    cq: Mapping,
+    sqes: Mapping,
+    sq_off: SqringOffsets,
+    cq_off: CqringOffsets,
+    fd: OwnedFd,
+}
+
+// the ring is only ever used through &mut self, the raw pointers are into mappings it owns
+unsafe impl Send for Ring {}
+
+impl Ring {
+    /// fails if the kernel does not support io_uring, or it is disabled
+    pub(crate) fn new() -> Result<Self, Error> {
+        let mut params = Params::default();
+        let fd = unsafe {
+            libc::syscall(
+                libc::SYS_io_uring_setup,
+                ENTRIES,
+                &mut params as *mut Params,
+            )
+        };
+        if fd < 0 {
+            return Err(Error::last_os_error());
+        }
+        let fd = unsafe { OwnedFd::from_raw_fd(fd as RawFd) };
+        let sq_len = params.sq_off.array as usize + params.sq_entries as usize * 4;
+        let cq_len = params.cq_off.cqes as usize
+            + params.cq_entries as usize * std::mem::size_of::<Cqe>();
+        let sqes_len = params.sq_entries as usize * std::mem::size_of::<Sqe>();
+        Ok(Self {
+            sq: Mapping::new(&fd, sq_len, IORING_OFF_SQ_RING)?,
+            cq: Mapping::new(&fd, cq_len, IORING_OFF_CQ_RING)?,
+            sqes: Mapping::new(&fd, sqes_len, IORING_OFF_SQES)?,
+            sq_off: params.sq_off,
+            cq_off: params.cq_off,
+            fd,
+        })
+    }
+
+    /// submits the entries and waits for all of them, returns their results in the same order,
+    /// negative results are errno values
+    pub(crate) fn submit_and_wait(&mut self, entries: &[Sqe]) -> Result<Vec<i32>, Error> {
+        assert!(entries.len() <= ENTRIES as usize);
+        unsafe {
+            let sq_head = &*self.sq.at::<AtomicU32>(self.sq_off.head);
+            let sq_tail = &*self.sq.at::<AtomicU32>(self.sq_off.tail);
+            let sq_mask = *self.sq.at::<u32>(self.sq_off.ring_mask);
+            let sq_array = self.sq.at::<u32>(self.sq_off.array);
+            let cq_head = &*self.cq.at::<AtomicU32>(self.cq_off.head);
+            let cq_tail = &*self.cq.at::<AtomicU32>(self.cq_off.tail);
+            let cq_mask = *self.cq.at::<u32>(self.cq_off.ring_mask);
+            let cqes = self.cq.at::<Cqe>(self.cq_off.cqes);
+
+            // this is the only producer, so the tail can't change under us
+            let mut tail = sq_tail.load(Ordering::Relaxed);
+            for (user_data, entry) in entries.iter().enumerate() {
+                let index = tail & sq_mask;
+                let mut entry = *entry;
+                entry.user_data = user_data as u64;
+                self.sqes.at::<Sqe>(0).add(index as usize).write(entry);
+                sq_array.add(index as usize).write(index);
+                tail = tail.wrapping_add(1);
+            }
+            sq_tail.store(tail, Ordering::Release);
+
+            let mut results = vec![None; entries.len()];
+            let mut completed = 0;
+            while completed < entries.len() {
+                let to_submit = tail.wrapping_sub(sq_head.load(Ordering::Acquire));
+                let entered = libc::syscall(
+                    libc::SYS_io_uring_enter,
+                    self.fd.as_raw_fd(),
+                    to_submit,
+                    1 as libc::c_uint,
+                    IORING_ENTER_GETEVENTS,
+                    std::ptr::null::<libc::sigset_t>(),
+                    0 as libc::size_t,
+                );
+                if entered < 0 {
+                    let error = Error::last_os_error();
+                    if error.kind() == ErrorKind::Interrupted {
+                        continue;
+                    }
+                    return Err(error);
+                }
+                let mut head = cq_head.load(Ordering::Relaxed);
+                while head != cq_tail.load(Ordering::Acquire) {
+                    let cqe = &*cqes.add((head & cq_mask) as usize);
+                    if let Some(result) = results.get_mut(cqe.user_data as usize) {
+                        *result = Some(cqe.res);
+                        completed += 1;
+                    }
+                    head = head.wrapping_add(1);
+                }
+                cq_head.store(head, Ordering::Release);
+            }
+            Ok(results.into_iter().map(Option::unwrap).collect())
+        }
+    }
+}
@@ -0,0 +1,263 @@
+//! named collections of serde documents over a pathkvs database
+//!
+//! a document with id `ID` in the collection `users` is stored as json under the key `doc/users/ID`
+//!
+//! each indexed field `F` is an index of [`system::INDEXES`], defined by its key `doc/users/F`, and every document
+//! with the field gets the entry `doc/users/F/<json of F>/ID` under it holding the id, so the entries are in the system
+//! keyspace, out of the scans of the documents, and are written in the same transaction as the document,
+//! so they can never disagree with it
+//!
+//! index lookups compare the json encoding of the values, so `1` and `1.0` are different values
+
+use std::io::{Error, ErrorKind};
+
+use pathkvs_core::{system, Transaction};
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::Value;
+
+#[derive(Debug, Clone)]
+pub struct Collection {
+    name: String,
+    indexes: Vec<String>,
+}
+
+impl Collection {
+    /// panics if the name is empty or contains a `/`
+    pub fn new(name: impl Into<String>) -> Self {
+        let name = name.into();
+        assert!(valid_name(&name), "invalid collection name {name:?}");
+        Self {
+            name,
+            indexes: Vec::new(),
+        }
+    }
+    /// maintains an index on the top level field, so it can be used with [`Collection::find`]
+    ///
+    /// panics if the field is empty or contains a `/`
+    ///
+    /// the documents written before the index was added are indexed by the first [`Collection::put`]
+    /// or [`Collection::find`] that sees it is not defined yet
+    pub fn with_index(mut self, field: impl Into<String>) -> Self {
+        let field = field.into();
+        assert!(valid_name(&field), "invalid index field {field:?}");
+        if !self.indexes.contains(&field) {
+            self.indexes.push(field);
+        }
+        self
+    }
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+    pub fn indexes(&self) -> &[String] {
+        &self.indexes
+    }
+
+    pub fn get<T: DeserializeOwned>(
+        &self,
+        ts: &mut Transaction,
+        id: &str,
+    ) -> Result<Option<T>, Error> {
+        let bytes = ts.read(&self.document_key(id));
+        if bytes.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(serde_json::from_slice(bytes)?))
+    }
+    /// inserts or replaces the document, fails with [`ErrorKind::InvalidInput`] if the id is empty
+    pub fn put<T: Serialize>(
+        &self,
+        ts: &mut Transaction,
+        id: &str,
+        document: &T,
+    ) -> Result<(), Error> {
+        if id.is_empty() {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "document id cannot be empty",
+            ));
+        }
+        let document = serde_json::to_value(document)?;
+        self.define_indexes(ts)?;
+        if let Some(old) = self.get::<Value>(ts, id)? {
+            self.update_indexes(ts, id, &old, None)?;
+        }
+        ts.write(&self.document_key(id), &serde_json::to_vec(&document)?);
+        self.update_indexes(ts, id, &document, Some(id.as_bytes()))
+    }
+    /// returns false if there was no such document
+    pub fn delete(&self, ts: &mut Transaction, id: &str) -> Result<bool, Error> {
+        let Some(old) = self.get::<Value>(ts, id)? else {
+            return Ok(false);
+        };
+        self.update_indexes(ts, id, &old, None)?;
+        ts.delete(&self.document_key(id));
+        Ok(true)
+    }
+    /// all documents whose `field` is equal to `value`, ordered by id
+    ///
+    /// the field must have been registered with [`Collection::with_index`]
+    pub fn find<T: DeserializeOwned>(
+        &self,
+        ts: &mut Transaction,
+        field: &str,
+        value: &impl Serialize,
+    ) -> Result<Vec<(String, T)>, Error> {
+        if !self.indexes.iter().any(|index| index == field) {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!(
+                    "field {field:?} of collection {:?} is not indexed",
+                    self.name
+                ),
+            ));
+        }
+        self.define_indexes(ts)?;
+        let value = serde_json::to_value(value)?;
+        let prefix = self.index_key(field, &value, "")?;
+        let ids = ts
+            .scan(&prefix, b"")
+            .into_iter()
+            .map(|(_, id)| String::from_utf8(id.to_vec()).map_err(corrupted))
+            .collect::<Result<Vec<_>, Error>>()?;
+        let mut documents = Vec::with_capacity(ids.len());
+        for id in ids {
+            let document = self
+                .get(ts, &id)?
+                .ok_or_else(|| corrupted("dangling index entry"))?;
+            documents.push((id, document));
+        }
+        Ok(documents)
+    }
+    /// every document of the collection, ordered by id
+    pub fn all<T: DeserializeOwned>(
+        &self,
+        ts: &mut Transaction,
+    ) -> Result<Vec<(String, T)>, Error> {
+        let prefix = self.document_key("");
+        ts.scan(&prefix, b"")
+            .into_iter()
+            .map(|(key, document)| {
+                let id = String::from_utf8(key[prefix.len()..].to_vec()).map_err(corrupted)?;
+                Ok((id, serde_json::from_slice(document)?))
+            })
+            .collect()
+    }
+
+    /// defines the indexes that are not defined yet, and indexes every document already there in them
+    fn define_indexes(&self, ts: &mut Transaction) -> Result<(), Error> {
+        for field in &self.indexes {
+            let definition = self.index_definition(field);
+            if ts.exists(&definition) {
+                continue;
+            }
+            for (id, document) in self.all::<Value>(ts)? {
+                if let Some(value) = document.get(field).filter(|value| !value.is_null()) {
+                    ts.write(&self.index_key(field, value, &id)?, id.as_bytes());
+                }
+            }
+            ts.write(&definition, field.as_bytes());
+        }
+        Ok(())
+    }
+    /// writes `entry` to the index keys of the document, none deletes them
+    fn update_indexes(
+        &self,
+        ts: &mut Transaction,
+        id: &str,
+        document: &Value,
+        entry: Option<&[u8]>,
+    ) -> Result<(), Error> {
+        for field in &self.indexes {
+            match document.get(field) {
+                None | Some(Value::Null) => {}
+                Some(value) => {
+                    let key = self.index_key(field, value, id)?;
+                    match entry {
+                        Some(entry) => ts.write(&key, entry),
+                        None => ts.delete(&key),
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+    fn document_key(&self, id: &str) -> Vec<u8> {
+        format!("doc/{}/{id}", self.name).into_bytes()
+    }
+    /// the key of the index in [`system::INDEXES`], holding the field, its entries are under it
+    fn index_definition(&self, field: &str) -> Vec<u8> {
+        system::key(
+            system::INDEXES,
+            format!("doc/{}/{field}", self.name).as_bytes(),
+        )
+    }
+    /// json encodings are self delimiting, except for numbers which never contain a `/`,
+    /// so the `/` after the value is enough to separate it from the id
+    fn index_key(&self, field: &str, value: &Value, id: &str) -> Result<Vec<u8>, Error> {
+        let mut key = self.index_definition(field);
+        key.push(b'/');
+        serde_json::to_writer(&mut key, value)?;
+        key.push(b'/');
+        key.extend_from_slice(id.as_bytes());
+        Ok(key)
+    }
+}
+
+fn valid_name(name: &str) -> bool {
+    !name.is_empty() && !name.contains('/')
+}
+
+fn corrupted(error: impl Into<Box<dyn std::error::Error + Send + Sync>>) -> Error {
+    Error::new(ErrorKind::InvalidData, error)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pathkvs_core::Database;
+    use serde_json::json;
+
+    fn users() -> Collection {
+        Collection::new("users").with_index("email")
+    }
+
+    #[test]
+    fn overwriting_a_document_replaces_its_index_entries() {
+        let db = Database::memory();
+        let users = users();
+        let mut ts = db.start_writes();
+        users.put(&mut ts, "1", &json!({"email": "old@x"})).unwrap();
+        users.put(&mut ts, "1", &json!({"email": "new@x"})).unwrap();
+        ts.commit().unwrap();
+
+        let mut ts = db.start_writes();
+        let old = users.find::<Value>(&mut ts, "email", &"old@x").unwrap();
+        assert!(old.is_empty());
+        let new = users.find::<Value>(&mut ts, "email", &"new@x").unwrap();
+        assert_eq!(new, vec![("1".to_string(), json!({"email": "new@x"}))]);
+        // the entries are in the system keyspace, only the document is seen by a scan
+        assert_eq!(ts.count(b"", b""), 1);
+        ts.rollback();
+    }
+
+    #[test]
+    fn index_added_later_covers_the_documents_already_there() {
+        let db = Database::memory();
+        let mut ts = db.start_writes();
+        Collection::new("users")
+            .put(&mut ts, "1", &json!({"email": "a@x"}))
+            .unwrap();
+        let found = users().find::<Value>(&mut ts, "email", &"a@x").unwrap();
+        assert_eq!(found.len(), 1);
+        ts.rollback();
+    }
+
+    #[test]
+    fn empty_id_is_invalid_input() {
+        let db = Database::memory();
+        let mut ts = db.start_writes();
+        let error = users().put(&mut ts, "", &json!({})).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidInput);
+        ts.rollback();
+    }
+}
@@ -1,15 +1,44 @@
 use std::{
-    io::{Error, ErrorKind, Read, Write},
-    time::SystemTime,
+    collections::HashMap,
+    io::{self, Read, Write},
+    net::{TcpStream, ToSocketAddrs},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc::{self, RecvTimeoutError, Sender},
+        Arc, Mutex,
+    },
+    thread::JoinHandle,
+    time::{Duration, Instant, SystemTime},
 };
 
-use pathkvs_core::error::{LimitExceeded, ProtocolError, TransactionError};
+use pathkvs_core::{
+    content::ContentType,
+    error::{ProtocolError, TransactionError},
+    replication::ReplicaStatus,
+    system, CommitReceipt, CommitSummary,
+};
 
 use crate::{
+    compression::Compression,
     message,
     utils::{ReadEx, WriteEx},
+    Error,
 };
 
+/// the keys and values of a scan
+type Entries = Vec<(Vec<u8>, Vec<u8>)>;
+
+/// attempts made by [`Connection::transact`]
+pub const TRANSACT_ATTEMPTS: u32 = 10;
+/// the wait before the first retry, it doubles with each retry up to [`TRANSACT_MAX_BACKOFF`]
+const TRANSACT_BACKOFF: Duration = Duration::from_millis(2);
+const TRANSACT_MAX_BACKOFF: Duration = Duration::from_millis(200);
+
+/// the points each server has on the ring of a [`ShardedClient`], more points spread the keys more evenly
+const SHARD_POINTS: u32 = 128;
+/// the most idle connections a [`ShardedClient`] keeps for each server
+const SHARD_IDLE_CONNECTIONS: usize = 8;
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum ConnectionMode {
     Normal,
@@ -27,11 +56,672 @@ impl ConnectionMode {
     pub const fn is_snapshot(self) -> bool {
         matches!(self, Self::Snapshot)
     }
+    pub(crate) const fn to_u8(self) -> u8 {
+        match self {
+            Self::Normal => 0,
+            Self::Transaction => 1,
+            Self::Snapshot => 2,
+        }
+    }
+    pub(crate) const fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0 => Some(Self::Normal),
+            1 => Some(Self::Transaction),
+            2 => Some(Self::Snapshot),
+            _ => None,
+        }
+    }
+}
+
+/// what reads outside of transactions and snapshots see, see [`Connection::set_read_consistency`]
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReadConsistency {
+    /// every operation reads the newest state of the database
+    ///
+    /// writes are only acknowledged once they are visible, so reads always see the writes of the connection
+    /// and never go back in time, but two operations may see different states if others commit in between
+    #[default]
+    Latest,
+    /// operations read from a snapshot kept by the connection, taken when the consistency is set,
+    /// and advanced to the newest state after each write or commit of the connection
+    ///
+    /// operations in between see the same state, including the connection's own writes,
+    /// setting it again advances the snapshot to the newest state
+    Session,
+}
+
+impl ReadConsistency {
+    pub(crate) const fn to_u8(self) -> u8 {
+        match self {
+            Self::Latest => 0,
+            Self::Session => 1,
+        }
+    }
+    pub(crate) const fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0 => Some(Self::Latest),
+            1 => Some(Self::Session),
+            _ => None,
+        }
+    }
+}
+
+/// the kind of access a rule of the access control list restricts, see [`Connection::set_acl`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Access {
+    Read,
+    Write,
+}
+
+impl Access {
+    pub(crate) const fn to_u8(self) -> u8 {
+        match self {
+            Self::Read => 0,
+            Self::Write => 1,
+        }
+    }
+    pub(crate) const fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0 => Some(Self::Read),
+            1 => Some(Self::Write),
+            _ => None,
+        }
+    }
+}
+
+/// only connections authenticated with `role`, or as admin, may access the keys starting with `prefix` this way,
+/// the rule with the longest prefix of a key is the one that applies, keys under no rule are open to every connection
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AclRule {
+    pub prefix: Vec<u8>,
+    pub access: Access,
+    pub role: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConnectionInfo {
+    pub id: u64,
+    pub peer: String,
+    /// set by the client with [`Connection::set_name`], empty if it did not set one
+    pub name: String,
+    pub mode: ConnectionMode,
+    /// the requests served on the connection so far
+    pub commands: u64,
+    /// the bytes read and written by those requests
+    pub bytes: u64,
+    /// since the last request ended, or since it connected
+    pub idle: Duration,
+    /// how long the transaction it is in has been open, none if it is not in one
+    pub transaction: Option<Duration>,
+}
+
+/// a request that took longer than the threshold of the server, see [`Connection::slow_log`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SlowCommand {
+    /// raised for every slow request, so the entries already seen can be told apart
+    pub id: u64,
+    /// the unix time it ended
+    pub time: Duration,
+    /// the connection that sent it, as listed by [`Connection::connections`]
+    pub connection: u64,
+    pub peer: String,
+    /// the name the connection had then, empty if it did not set one
+    pub name: String,
+    /// the name of the request, as in [`CommandEvent`](crate::stats::CommandEvent)
+    pub request: String,
+    /// the total length of the keys it named, the start and end of ranges included
+    pub key_bytes: u64,
+    /// the bytes it read and wrote on the connection
+    pub bytes: u64,
+    pub elapsed: Duration,
+    /// `ok`, or the name of the status the server responded with instead
+    pub outcome: String,
+}
+
+/// the version of the sections and counters of [`Info`] this client knows, raised when one of them
+/// changes meaning or is removed, adding new ones doesn't raise it
+pub const INFO_VERSION: u32 = 1;
+
+/// the state of a server, as named sections of named counters, see [`Connection::info`]
+///
+/// the typed sections hold the counters of [`INFO_VERSION`], a counter the server didn't send is 0
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Info {
+    /// the version the server follows, the typed sections may not match it if it differs from [`INFO_VERSION`]
+    pub version: u32,
+    pub server: ServerInfo,
+    pub clients: ClientsInfo,
+    pub keyspace: KeyspaceInfo,
+    pub persistence: PersistenceInfo,
+    /// every section as the server sent it, including the sections and counters this client doesn't know
+    pub sections: Vec<InfoSection>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct InfoSection {
+    pub name: String,
+    pub fields: Vec<(String, u64)>,
+}
+
+/// the `server` section of [`Info`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ServerInfo {
+    pub uptime_seconds: u64,
+    /// writes and commits refused because the server was behind persisting
+    pub busy_replies: u64,
+}
+
+/// the `clients` section of [`Info`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ClientsInfo {
+    pub connections: u64,
+    /// the connections accepted since the server started, including those that already ended
+    pub connections_accepted: u64,
+    /// the connections with the same name as another one, usually a client that connected again
+    /// without closing its previous connection, see [`Connection::set_name`]
+    pub duplicate_names: u64,
+}
+
+/// the `keyspace` section of [`Info`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct KeyspaceInfo {
+    pub keys: u64,
+    /// the commits in the history, see [`pathkvs_core::Database::commit_count`]
+    pub commits: u64,
+    pub commits_attempted: u64,
+    pub commits_succeeded: u64,
+    pub conflicts: u64,
+    pub commit_retries: u64,
+    pub commit_time_micros: u64,
+    pub commit_time_max_micros: u64,
+}
+
+/// the `persistence` section of [`Info`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PersistenceInfo {
+    pub persists: u64,
+    pub persisted_commits: u64,
+    pub persist_time_micros: u64,
+    pub persist_time_max_micros: u64,
+    /// the commits resolved but not persisted yet
+    pub persist_backlog: u64,
+}
+
+impl Info {
+    pub fn section(&self, name: &str) -> Option<&InfoSection> {
+        self.sections.iter().find(|section| section.name == name)
+    }
+    /// the counter of the section, none if the server didn't send it
+    pub fn get(&self, section: &str, field: &str) -> Option<u64> {
+        self.section(section)?.get(field)
+    }
+    fn from_sections(version: u32, sections: Vec<InfoSection>) -> Self {
+        let info = Info {
+            version,
+            sections,
+            ..Info::default()
+        };
+        let field = |section, field| info.get(section, field).unwrap_or(0);
+        let server = ServerInfo {
+            uptime_seconds: field("server", "uptime_seconds"),
+            busy_replies: field("server", "busy_replies"),
+        };
+        let clients = ClientsInfo {
+            connections: field("clients", "connections"),
+            connections_accepted: field("clients", "connections_accepted"),
+            duplicate_names: field("clients", "duplicate_names"),
+        };
+        let keyspace = KeyspaceInfo {
+            keys: field("keyspace", "keys"),
+            commits: field("keyspace", "commits"),
+            commits_attempted: field("keyspace", "commits_attempted"),
+            commits_succeeded: field("keyspace", "commits_succeeded"),
+            conflicts: field("keyspace", "conflicts"),
+            commit_retries: field("keyspace", "commit_retries"),
+            commit_time_micros: field("keyspace", "commit_time_micros"),
+            commit_time_max_micros: field("keyspace", "commit_time_max_micros"),
+        };
+        let persistence = PersistenceInfo {
+            persists: field("persistence", "persists"),
+            persisted_commits: field("persistence", "persisted_commits"),
+            persist_time_micros: field("persistence", "persist_time_micros"),
+            persist_time_max_micros: field("persistence", "persist_time_max_micros"),
+            persist_backlog: field("persistence", "persist_backlog"),
+        };
+        Info {
+            server,
+            clients,
+            keyspace,
+            persistence,
+            ..info
+        }
+    }
+}
+
+impl InfoSection {
+    pub fn get(&self, field: &str) -> Option<u64> {
+        self.fields
+            .iter()
+            .find(|(name, _)| name == field)
+            .map(|&(_, value)| value)
+    }
 }
 
 pub struct Connection<T> {
     conn: T,
     mode: ConnectionMode,
+    trace_id: Option<Vec<u8>>,
+    batching: bool,
+    /// writes of the transaction not sent yet, they go with the next request
+    batch: HashMap<Vec<u8>, Vec<u8>>,
+    /// the batch was sent before the current request, so its response comes first
+    batch_sent: bool,
+}
+
+/// a connection that receives the messages published to the channels it subscribed to,
+/// see [`Connection::subscribe`]
+pub struct Subscription<T> {
+    conn: T,
+}
+
+impl Connection<TcpStream> {
+    /// connects to the first server that accepts the connection, trying the addresses in order,
+    /// for several identical servers with no load balancer in front of them,
+    /// fails with the error of the last address if none of them accepts it
+    pub fn connect_any<A: ToSocketAddrs>(addrs: &[A]) -> Result<Self, io::Error> {
+        Self::connect_any_from(addrs, 0)
+    }
+    /// like [`Connection::connect_any`], but trying the address at `start` first,
+    /// and going around to the ones before it, see [`RoundRobin`]
+    pub fn connect_any_from<A: ToSocketAddrs>(
+        addrs: &[A],
+        start: usize,
+    ) -> Result<Self, io::Error> {
+        let mut error = io::Error::new(io::ErrorKind::InvalidInput, "no server address given");
+        for i in 0..addrs.len() {
+            match TcpStream::connect(&addrs[(start + i) % addrs.len()]) {
+                Ok(stream) => return Ok(Connection::new(stream)),
+                Err(last) => error = last,
+            }
+        }
+        Err(error)
+    }
+}
+
+/// spreads connections across several identical servers, such as read replicas,
+/// each connection tries first the server after the one the connection before it tried first,
+/// falling back to the others in order, see [`Connection::connect_any_from`]
+///
+/// the servers must be identical for reads to go to any of them, writes should go to the primary
+#[derive(Debug)]
+pub struct RoundRobin<A> {
+    addrs: Vec<A>,
+    next: AtomicUsize,
+}
+
+impl<A: ToSocketAddrs> RoundRobin<A> {
+    pub fn new(addrs: Vec<A>) -> Self {
+        Self {
+            addrs,
+            next: AtomicUsize::new(0),
+        }
+    }
+    pub fn addrs(&self) -> &[A] {
+        &self.addrs
+    }
+    pub fn connect(&self) -> Result<Connection<TcpStream>, io::Error> {
+        let start = self.next.fetch_add(1, Ordering::Relaxed);
+        Connection::connect_any_from(&self.addrs, start)
+    }
+}
+
+/// spreads keys across several independent servers, each key belongs to a single server,
+/// chosen by consistent hashing of the key on a ring of the addresses of the servers,
+/// so adding or removing a server only moves the keys that belong to it, for data that outgrows a single server
+///
+/// each server has a pool of connections, reused by the operations on its keys,
+/// counts, lists and scans go to every server at once and merge their results,
+/// and a transaction only sees the keys of one server, see [`ShardedClient::with_shard`]
+///
+/// the points of a server on the ring come from its address as given, so every client must be given
+/// the same addresses, in any order, for them to agree on where each key is
+pub struct ShardedClient {
+    /// the points of the servers on the ring, sorted, with the index of their server
+    ring: Vec<(u64, usize)>,
+    shards: Vec<Shard>,
+    token: Option<Vec<u8>>,
+    min_idle: usize,
+}
+
+struct Shard {
+    addr: String,
+    idle: Mutex<Vec<Connection<TcpStream>>>,
+    health: Mutex<Health>,
+}
+
+#[derive(Default)]
+struct Health {
+    checked: Option<Instant>,
+    round_trip: Option<Duration>,
+    error: Option<String>,
+}
+
+/// a server of a [`ShardedClient`] as of its last health check, see [`ShardedClient::check_health`]
+#[derive(Debug, Clone)]
+pub struct ShardStatus {
+    pub addr: String,
+    /// none if it was never checked
+    pub checked: Option<Instant>,
+    /// how long the ping of the last check took, none if it failed
+    pub round_trip: Option<Duration>,
+    /// why the last check failed
+    pub error: Option<String>,
+    /// the connections in its pool
+    pub idle: usize,
+}
+
+impl ShardStatus {
+    /// whether the last check succeeded
+    pub fn is_healthy(&self) -> bool {
+        self.round_trip.is_some()
+    }
+}
+
+/// health checks started by [`ShardedClient::spawn_health_checks`], they stop when it is dropped
+pub struct HealthChecker {
+    stop: Option<Sender<()>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl HealthChecker {
+    /// same as dropping it, waits for a check in progress to finish
+    pub fn stop(self) {}
+}
+
+impl Drop for HealthChecker {
+    fn drop(&mut self) {
+        self.stop.take();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl ShardedClient {
+    /// panics if no address is given
+    pub fn new(addrs: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        let shards = addrs
+            .into_iter()
+            .map(|addr| Shard {
+                addr: addr.into(),
+                idle: Mutex::new(Vec::new()),
+                health: Mutex::new(Health::default()),
+            })
+            .collect::<Vec<_>>();
+        assert!(
+            !shards.is_empty(),
+            "pathkvs client: no server address given"
+        );
+        let mut ring = shards
+            .iter()
+            .enumerate()
+            .flat_map(|(index, shard)| {
+                (0..SHARD_POINTS).map(move |point| {
+                    (
+                        ring_hash(format!("{}#{point}", shard.addr).as_bytes()),
+                        index,
+                    )
+                })
+            })
+            .collect::<Vec<_>>();
+        ring.sort_unstable();
+        Self {
+            ring,
+            shards,
+            token: None,
+            min_idle: 0,
+        }
+    }
+    /// every new connection is authenticated with the token, see [`Connection::authenticate`]
+    pub fn with_token(mut self, token: impl AsRef<[u8]>) -> Self {
+        self.token = Some(token.as_ref().to_vec());
+        self
+    }
+    /// every health check opens connections until the pool of each server has this many,
+    /// up to the most it keeps, so they are ready before they are needed
+    pub fn with_min_idle(mut self, min_idle: usize) -> Self {
+        self.min_idle = min_idle.min(SHARD_IDLE_CONNECTIONS);
+        self
+    }
+    /// the health of every server as of its last check, in the order the addresses were given
+    pub fn status(&self) -> Vec<ShardStatus> {
+        self.shards
+            .iter()
+            .map(|shard| {
+                let health = shard.health.lock().unwrap();
+                ShardStatus {
+                    addr: shard.addr.clone(),
+                    checked: health.checked,
+                    round_trip: health.round_trip,
+                    error: health.error.clone(),
+                    idle: shard.idle.lock().unwrap().len(),
+                }
+            })
+            .collect()
+    }
+    /// pings every server at once, through every idle connection of its pool, closing those that fail,
+    /// and opens connections until the pool has [`ShardedClient::with_min_idle`] of them, at least one,
+    /// then returns [`ShardedClient::status`]
+    pub fn check_health(&self) -> Vec<ShardStatus> {
+        std::thread::scope(|scope| {
+            for shard in &self.shards {
+                scope.spawn(move || {
+                    let result = self.check(shard);
+                    let mut health = shard.health.lock().unwrap();
+                    health.checked = Some(Instant::now());
+                    (health.round_trip, health.error) = match result {
+                        Ok(round_trip) => (Some(round_trip), None),
+                        Err(error) => (None, Some(error.to_string())),
+                    };
+                });
+            }
+        });
+        self.status()
+    }
+    /// runs [`ShardedClient::check_health`] every `interval`, in a thread of its own,
+    /// which only holds a weak reference, so it stops once the client is dropped
+    pub fn spawn_health_checks(self: &Arc<Self>, interval: Duration) -> HealthChecker {
+        let client = Arc::downgrade(self);
+        let (stop, stopped) = mpsc::channel::<()>();
+        let thread = std::thread::spawn(move || {
+            while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(interval) {
+                let Some(client) = client.upgrade() else {
+                    return;
+                };
+                client.check_health();
+            }
+        });
+        HealthChecker {
+            stop: Some(stop),
+            thread: Some(thread),
+        }
+    }
+    pub fn addrs(&self) -> impl Iterator<Item = &str> {
+        self.shards.iter().map(|shard| shard.addr.as_str())
+    }
+    /// the index of the server the key belongs to, in the order the addresses were given
+    pub fn shard(&self, key: &[u8]) -> usize {
+        let hash = ring_hash(key);
+        let at = self.ring.partition_point(|&(point, _)| point < hash);
+        self.ring[at % self.ring.len()].1
+    }
+    /// runs `f` with a connection to the server the key belongs to, taken from its pool,
+    /// such as a transaction with [`Connection::transact`], whose keys must all belong to that server
+    ///
+    /// the connection goes back to the pool if `f` succeeds and leaves it outside of a transaction or snapshot,
+    /// otherwise it is closed
+    pub fn with_shard<R>(
+        &self,
+        key: impl AsRef<[u8]>,
+        f: impl FnOnce(&mut Connection<TcpStream>) -> Result<R, Error>,
+    ) -> Result<R, Error> {
+        self.with_index(self.shard(key.as_ref()), f)
+    }
+    pub fn read(&self, key: impl AsRef<[u8]>) -> Result<Vec<u8>, Error> {
+        let key = key.as_ref();
+        self.with_shard(key, |conn| conn.read(key))
+    }
+    pub fn write(&self, key: impl AsRef<[u8]>, value: impl AsRef<[u8]>) -> Result<(), Error> {
+        let key = key.as_ref();
+        self.with_shard(key, |conn| conn.write(key, value))
+    }
+    pub fn delete(&self, key: impl AsRef<[u8]>) -> Result<(), Error> {
+        let key = key.as_ref();
+        self.with_shard(key, |conn| conn.delete(key))
+    }
+    pub fn exists(&self, key: impl AsRef<[u8]>) -> Result<bool, Error> {
+        let key = key.as_ref();
+        self.with_shard(key, |conn| conn.exists(key))
+    }
+    /// see [`Connection::incr`]
+    pub fn incr(&self, key: impl AsRef<[u8]>, delta: i64) -> Result<i64, Error> {
+        let key = key.as_ref();
+        self.with_shard(key, |conn| conn.incr(key, delta))
+    }
+    /// the values of the keys, in the same order as the keys, with a round trip to each server that has any of them
+    pub fn read_many<K: AsRef<[u8]>>(&self, keys: &[K]) -> Result<Vec<Vec<u8>>, Error> {
+        let mut by_shard = vec![Vec::new(); self.shards.len()];
+        for (index, key) in keys.iter().enumerate() {
+            by_shard[self.shard(key.as_ref())].push(index);
+        }
+        let mut values = vec![Vec::new(); keys.len()];
+        for (shard, indexes) in by_shard.iter().enumerate() {
+            if indexes.is_empty() {
+                continue;
+            }
+            let read = self.with_index(shard, |conn| {
+                conn.read_many(indexes.iter().map(|&index| keys[index].as_ref()))
+            })?;
+            for (&index, value) in indexes.iter().zip(read) {
+                values[index] = value;
+            }
+        }
+        Ok(values)
+    }
+    /// the sum of the counts of every server
+    pub fn count(&self, start: impl AsRef<[u8]>, end: impl AsRef<[u8]>) -> Result<u64, Error> {
+        let (start, end) = (start.as_ref(), end.as_ref());
+        let counts = self.scatter(|conn| conn.count(start, end))?;
+        Ok(counts.into_iter().map(u64::from).sum())
+    }
+    /// the keys of every server, in order
+    pub fn list(
+        &self,
+        start: impl AsRef<[u8]>,
+        end: impl AsRef<[u8]>,
+    ) -> Result<Vec<Vec<u8>>, Error> {
+        let (start, end) = (start.as_ref(), end.as_ref());
+        let mut keys = self.scatter(|conn| conn.list(start, end))?.concat();
+        keys.sort_unstable();
+        Ok(keys)
+    }
+    /// the entries of every server, in the order of the keys
+    pub fn scan(&self, start: impl AsRef<[u8]>, end: impl AsRef<[u8]>) -> Result<Entries, Error> {
+        let (start, end) = (start.as_ref(), end.as_ref());
+        let mut entries = self.scatter(|conn| conn.scan(start, end))?.concat();
+        entries.sort_unstable_by(|a, b| a.0.cmp(&b.0));
+        Ok(entries)
+    }
+    fn with_index<R>(
+        &self,
+        index: usize,
+        f: impl FnOnce(&mut Connection<TcpStream>) -> Result<R, Error>,
+    ) -> Result<R, Error> {
+        let shard = &self.shards[index];
+        let idle = shard.idle.lock().unwrap().pop();
+        let mut conn = match idle {
+            Some(conn) => conn,
+            None => self.open(shard)?,
+        };
+        let result = f(&mut conn)?;
+        if conn.mode().is_normal() {
+            let mut idle = shard.idle.lock().unwrap();
+            if idle.len() < SHARD_IDLE_CONNECTIONS {
+                idle.push(conn);
+            }
+        }
+        Ok(result)
+    }
+    fn open(&self, shard: &Shard) -> Result<Connection<TcpStream>, Error> {
+        let mut conn = Connection::new(TcpStream::connect(&shard.addr)?);
+        if let Some(token) = &self.token {
+            conn.authenticate(token)?;
+        }
+        Ok(conn)
+    }
+    /// the round trip of the last ping that succeeded
+    fn check(&self, shard: &Shard) -> Result<Duration, Error> {
+        let idle = std::mem::take(&mut *shard.idle.lock().unwrap());
+        let mut alive = Vec::new();
+        let mut round_trip = None;
+        for mut conn in idle {
+            if let Ok(took) = conn.ping() {
+                round_trip = Some(took);
+                alive.push(conn);
+            }
+        }
+        let mut result = Ok(());
+        while result.is_ok() && (alive.len() < self.min_idle || round_trip.is_none()) {
+            result = self.open(shard).and_then(|mut conn| {
+                round_trip = Some(conn.ping()?);
+                alive.push(conn);
+                Ok(())
+            });
+        }
+        let mut idle = shard.idle.lock().unwrap();
+        idle.extend(alive);
+        idle.truncate(SHARD_IDLE_CONNECTIONS);
+        match round_trip {
+            Some(round_trip) => Ok(round_trip),
+            // the loop only stops without a ping that succeeded if opening a connection failed
+            None => Err(result.unwrap_err()),
+        }
+    }
+    /// runs `f` on every server at once, a thread each, the results are in the order of the servers
+    fn scatter<R: Send>(
+        &self,
+        f: impl Fn(&mut Connection<TcpStream>) -> Result<R, Error> + Sync,
+    ) -> Result<Vec<R>, Error> {
+        let f = &f;
+        std::thread::scope(|scope| {
+            let threads = (0..self.shards.len())
+                .map(|index| scope.spawn(move || self.with_index(index, f)))
+                .collect::<Vec<_>>();
+            threads
+                .into_iter()
+                .map(|thread| {
+                    thread
+                        .join()
+                        .unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+                })
+                .collect()
+        })
+    }
+}
+
+/// fnv-1a followed by the finalizer of splitmix64, unlike the hashers of std it is the same
+/// in every version and platform, so every client places the keys in the same servers
+fn ring_hash(bytes: &[u8]) -> u64 {
+    let mut hash = 0xcbf2_9ce4_8422_2325u64;
+    for &byte in bytes {
+        hash ^= u64::from(byte);
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
+    }
+    hash ^= hash >> 30;
+    hash = hash.wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    hash ^= hash >> 27;
+    hash = hash.wrapping_mul(0x94d0_49bb_1331_11eb);
+    hash ^ (hash >> 31)
 }
 
 impl<T> Connection<T>
@@ -42,28 +732,129 @@ where
         Self {
             conn: inner,
             mode: ConnectionMode::Normal,
+            trace_id: None,
+            batching: false,
+            batch: HashMap::new(),
+            batch_sent: false,
         }
     }
     pub fn get_inner(&mut self) -> &mut T {
         &mut self.conn
     }
+    pub fn into_inner(self) -> T {
+        self.conn
+    }
     pub fn mode(&self) -> ConnectionMode {
         self.mode
     }
+    /// sends the trace id with every request from now on, the server logs it with the request and echoes it back,
+    /// so requests can be correlated with server side slow operations, `None` stops sending it
+    ///
+    /// panics if the trace id is longer than 64 bytes
+    pub fn set_trace_id(&mut self, trace_id: Option<&[u8]>) {
+        if let Some(trace_id) = trace_id {
+            assert!(
+                trace_id.len() <= message::MAX_TRACE_ID_LEN as usize,
+                "pathkvs client: the trace id cannot be longer than {} bytes",
+                message::MAX_TRACE_ID_LEN
+            );
+        }
+        self.trace_id = trace_id.map(<[u8]>::to_vec);
+    }
+    /// when enabled, writes in a transaction are kept by the client and sent together with its next request,
+    /// so they don't wait for the server, and a transaction that only writes takes a single round trip, on commit
+    ///
+    /// reads still go to the server, after the writes before them, so they see the writes of the transaction,
+    /// a rollback discards the writes not sent yet, writes outside of transactions are never batched
+    pub fn set_write_batching(&mut self, batching: bool) {
+        self.batching = batching;
+    }
+    pub fn trace_id(&self) -> Option<&[u8]> {
+        self.trace_id.as_deref()
+    }
     pub fn len(&mut self, key: impl AsRef<[u8]>) -> Result<u32, Error> {
         let key = key.as_ref();
         if key.is_empty() {
             return Ok(0);
         }
         assert!(key.len() <= u32::MAX as usize);
-        self.conn.write_u8(message::LEN)?;
+        self.send(message::LEN)?;
+        self.conn.write_u32(key.len() as u32)?;
+        self.conn.write_all(key)?;
+        self.conn.flush()?;
+        if self.response()? != message::LEN {
+            return Err(Error::Protocol);
+        }
+        Ok(self.conn.read_u32()?)
+    }
+    /// unlike [`Connection::len`], this can tell a missing key from one with an empty value,
+    /// as long as the server keeps them apart
+    /// a round trip to the server, for health checks, returns how long it took
+    pub fn ping(&mut self) -> Result<Duration, Error> {
+        let start = Instant::now();
+        self.send(message::PING)?;
+        self.conn.flush()?;
+        match self.response()? {
+            message::PING => Ok(start.elapsed()),
+            _ => Err(Error::Protocol),
+        }
+    }
+    pub fn exists(&mut self, key: impl AsRef<[u8]>) -> Result<bool, Error> {
+        let key = key.as_ref();
+        if key.is_empty() {
+            return Ok(false);
+        }
+        assert!(key.len() <= u32::MAX as usize);
+        self.send(message::EXISTS)?;
         self.conn.write_u32(key.len() as u32)?;
         self.conn.write_all(key)?;
         self.conn.flush()?;
-        if self.conn.read_u8()? != message::LEN {
-            return Err(ProtocolError.into());
+        if self.response()? != message::EXISTS {
+            return Err(Error::Protocol);
+        }
+        match self.conn.read_u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(Error::Protocol),
+        }
+    }
+    /// the lengths of the values of all the keys in a single round trip, in the same order as the keys
+    pub fn len_many<K: AsRef<[u8]>>(
+        &mut self,
+        keys: impl IntoIterator<Item = K>,
+    ) -> Result<Vec<u32>, Error> {
+        let count = self.request_many(message::LEN_MANY, keys)?;
+        (0..count).map(|_| Ok(self.conn.read_u32()?)).collect()
+    }
+    /// whether each key is present, like [`Connection::exists`], in a single round trip, in the same order as the keys
+    pub fn exists_many<K: AsRef<[u8]>>(
+        &mut self,
+        keys: impl IntoIterator<Item = K>,
+    ) -> Result<Vec<bool>, Error> {
+        let count = self.request_many(message::EXISTS_MANY, keys)?;
+        let bitmap = self.conn.read_vec(count.div_ceil(8))?;
+        Ok((0..count)
+            .map(|index| bitmap[index / 8] & (1 << (index % 8)) != 0)
+            .collect())
+    }
+    /// sends the request with the keys and reads the start of its response, which has one value per key
+    fn request_many<K: AsRef<[u8]>>(
+        &mut self,
+        request: u8,
+        keys: impl IntoIterator<Item = K>,
+    ) -> Result<usize, Error> {
+        let keys = keys.into_iter().collect::<Vec<_>>();
+        assert!(keys.len() <= u32::MAX as usize);
+        self.send(request)?;
+        self.conn.write_u32(keys.len() as u32)?;
+        for key in &keys {
+            self.conn.write_vec_lengthed(key.as_ref())?;
+        }
+        self.conn.flush()?;
+        if self.response()? != request || self.conn.read_u32()? as usize != keys.len() {
+            return Err(Error::Protocol);
         }
-        self.conn.read_u32()
+        Ok(keys.len())
     }
     pub fn read(&mut self, key: impl AsRef<[u8]>) -> Result<Vec<u8>, Error> {
         let key = key.as_ref();
@@ -73,7 +864,7 @@ where
         let key = key.as_ref();
         match self.read_limited_opt(key, max_len).transpose() {
             Some(result) => result,
-            None => Err(LimitExceeded.into()),
+            None => Err(Error::LimitExceeded),
         }
     }
     pub fn read_limited_opt(
@@ -86,22 +877,102 @@ where
             return Ok(Some(Vec::new()));
         }
         assert!(key.len() <= u32::MAX as usize);
-        self.conn.write_u8(message::READ)?;
+        self.send(message::READ)?;
         self.conn.write_u32(key.len() as u32)?;
         self.conn.write_all(key)?;
         self.conn.write_u32(max_len)?;
         self.conn.flush()?;
-        match self.conn.read_u8()? {
+        match self.response()? {
             message::READ => {
                 let recv_len = self.conn.read_u32()?;
                 if recv_len <= max_len {
-                    self.conn.read_vec(recv_len as usize).map(Some)
+                    Ok(Some(self.conn.read_vec(recv_len as usize)?))
                 } else {
-                    Err(ProtocolError.into())
+                    Err(Error::Protocol)
                 }
             }
             message::LIMIT_EXCEEDED => Ok(None),
-            _ => Err(ProtocolError.into()),
+            _ => Err(Error::Protocol),
+        }
+    }
+    /// reads up to `len` bytes of the value starting at `offset`, fewer if the value ends before that,
+    /// so pieces of a large value can be read without transferring all of it, see [`pathkvs_core::Snapshot::read_range`]
+    pub fn read_range(
+        &mut self,
+        key: impl AsRef<[u8]>,
+        offset: u32,
+        len: u32,
+    ) -> Result<Vec<u8>, Error> {
+        let key = key.as_ref();
+        if key.is_empty() {
+            return Ok(Vec::new());
+        }
+        assert!(key.len() <= u32::MAX as usize);
+        self.send(message::READ_RANGE)?;
+        self.conn.write_vec_lengthed(key)?;
+        self.conn.write_u32(offset)?;
+        self.conn.write_u32(len)?;
+        self.conn.flush()?;
+        match self.response()? {
+            message::READ_RANGE => Ok(self.conn.read_vec_lengthed(len)?),
+            _ => Err(Error::Protocol),
+        }
+    }
+    /// reads all the keys in a single round trip, the values are in the same order as the keys
+    pub fn read_many<K: AsRef<[u8]>>(
+        &mut self,
+        keys: impl IntoIterator<Item = K>,
+    ) -> Result<Vec<Vec<u8>>, Error> {
+        self.read_many_limited(keys, u32::MAX)
+    }
+    /// `max_len` limits the sum of the lengths of all the values
+    pub fn read_many_limited<K: AsRef<[u8]>>(
+        &mut self,
+        keys: impl IntoIterator<Item = K>,
+        max_len: u32,
+    ) -> Result<Vec<Vec<u8>>, Error> {
+        match self.read_many_limited_opt(keys, max_len).transpose() {
+            Some(result) => result,
+            None => Err(Error::LimitExceeded),
+        }
+    }
+    pub fn read_many_limited_opt<K: AsRef<[u8]>>(
+        &mut self,
+        keys: impl IntoIterator<Item = K>,
+        max_len: u32,
+    ) -> Result<Option<Vec<Vec<u8>>>, Error> {
+        let keys = keys.into_iter().collect::<Vec<_>>();
+        assert!(keys.len() <= u32::MAX as usize);
+        self.send(message::READ_MANY)?;
+        self.conn.write_u32(keys.len() as u32)?;
+        for key in &keys {
+            let key = key.as_ref();
+            assert!(key.len() <= u32::MAX as usize);
+            self.conn.write_u32(key.len() as u32)?;
+            self.conn.write_all(key)?;
+        }
+        self.conn.write_u32(max_len)?;
+        self.conn.flush()?;
+        match self.response()? {
+            message::READ_MANY => {
+                if self.conn.read_u32()? as usize != keys.len() {
+                    return Err(Error::Protocol);
+                }
+                let mut total = Some(0u32);
+                let mut values = Vec::with_capacity(keys.len());
+                for _ in 0..keys.len() {
+                    let recv_len = self.conn.read_u32()?;
+                    total = total.and_then(|x| x.checked_add(recv_len));
+                    if total.is_some_and(|total| total <= max_len) {
+                        values.push(self.conn.read_vec(recv_len as usize)?);
+                    } else {
+                        return Err(Error::Protocol);
+                    }
+                }
+                Ok(Some(values))
+            }
+            message::LIMIT_EXCEEDED => Ok(None),
+            _ => Err(Error::Protocol),
         }
     }
     pub fn write(&mut self, key: impl AsRef<[u8]>, value: impl AsRef<[u8]>) -> Result<(), Error> {
@@ -115,49 +986,479 @@ where
         let value = value.as_ref();
         assert!(key.len() <= u32::MAX as usize);
         assert!(value.len() <= u32::MAX as usize);
-        self.conn.write_u8(message::WRITE)?;
+        if self.batching && self.mode == ConnectionMode::Transaction {
+            self.batch.insert(key.to_vec(), value.to_vec());
+            return Ok(());
+        }
+        self.send(message::WRITE)?;
         self.conn.write_u32(key.len() as u32)?;
         self.conn.write_all(key)?;
         self.conn.write_u32(value.len() as u32)?;
         self.conn.write_all(value)?;
         self.conn.flush()?;
-        if self.conn.read_u8()? != message::WRITE {
-            return Err(ProtocolError.into());
+        match self.response()? {
+            message::WRITE => Ok(()),
+            message::BUSY => Err(Error::Busy),
+            _ => Err(Error::Protocol),
+        }
+    }
+    /// removes the key, unlike writing an empty value, which the key keeps, see [`pathkvs_core::Transaction::delete`]
+    ///
+    /// deletions are not batched, a batched write of the key is dropped and the rest of the batch goes before it
+    pub fn delete(&mut self, key: impl AsRef<[u8]>) -> Result<(), Error> {
+        if self.mode == ConnectionMode::Snapshot {
+            panic!("pathks client: can't write to a snapshot");
+        }
+        let key = key.as_ref();
+        if key.is_empty() {
+            return Ok(());
+        }
+        assert!(key.len() <= u32::MAX as usize);
+        self.batch.remove(key);
+        self.send(message::DELETE)?;
+        self.conn.write_vec_lengthed(key)?;
+        self.conn.flush()?;
+        match self.response()? {
+            message::DELETE => Ok(()),
+            message::BUSY => Err(Error::Busy),
+            message::UNSUPPORTED => Err(Error::Unsupported),
+            _ => Err(Error::Protocol),
+        }
+    }
+    /// deletes the key because its time to live ran out, subscribers of the changes are told it expired
+    /// instead of being deleted, see [`pathkvs_core::Transaction::expire`]
+    pub fn expire(&mut self, key: impl AsRef<[u8]>) -> Result<(), Error> {
+        if self.mode == ConnectionMode::Snapshot {
+            panic!("pathks client: can't write to a snapshot");
+        }
+        let key = key.as_ref();
+        if key.is_empty() {
+            return Ok(());
+        }
+        assert!(key.len() <= u32::MAX as usize);
+        self.send(message::EXPIRE)?;
+        self.conn.write_vec_lengthed(key)?;
+        self.conn.flush()?;
+        match self.response()? {
+            message::EXPIRE => Ok(()),
+            message::BUSY => Err(Error::Busy),
+            message::UNSUPPORTED => Err(Error::Unsupported),
+            _ => Err(Error::Protocol),
         }
+    }
+    /// writes the value along with its content type, see [`pathkvs_core::content`],
+    /// outside of a transaction both are written in one commit, with [`Connection::check_write`]
+    ///
+    /// an empty value deletes the key, along with its content type
+    pub fn write_typed(
+        &mut self,
+        key: impl AsRef<[u8]>,
+        value: impl AsRef<[u8]>,
+        content_type: ContentType,
+    ) -> Result<(), Error> {
+        let (key, value) = (key.as_ref(), value.as_ref());
+        if key.is_empty() {
+            return Ok(());
+        }
+        if value.is_empty() {
+            return self.write(key, value);
+        }
+        let types_key = system::key(system::TYPES, key);
+        let content_type = [content_type.to_u8()];
+        if self.mode.is_transaction() {
+            self.write(key, value)?;
+            return self.write(types_key, content_type);
+        }
+        self.check_write(&[], &[(key, Some(value)), (&types_key, Some(&content_type))])?;
         Ok(())
     }
+    /// replaces the content type of the key, as a write of [`system::TYPES`], none removes it
+    pub fn set_content_type(
+        &mut self,
+        key: impl AsRef<[u8]>,
+        content_type: Option<ContentType>,
+    ) -> Result<(), Error> {
+        let types_key = system::key(system::TYPES, key.as_ref());
+        match content_type {
+            Some(content_type) => self.write(types_key, [content_type.to_u8()]),
+            None => self.delete(types_key),
+        }
+    }
+    /// the content type of the key, none if it has none
+    pub fn content_type(&mut self, key: impl AsRef<[u8]>) -> Result<Option<ContentType>, Error> {
+        let value = self.read(system::key(system::TYPES, key.as_ref()))?;
+        Ok(ContentType::decode(&value))
+    }
+    /// the value of the key and its content type, in one round trip
+    pub fn read_typed(
+        &mut self,
+        key: impl AsRef<[u8]>,
+    ) -> Result<(Vec<u8>, Option<ContentType>), Error> {
+        let key = key.as_ref();
+        let mut values = self.read_many([key, &system::key(system::TYPES, key)])?;
+        let content_type = ContentType::decode(&values.pop().ok_or(Error::Protocol)?);
+        Ok((values.pop().ok_or(Error::Protocol)?, content_type))
+    }
+    /// attaches the metadata to the commit of the transaction, as a write of [`system::META`],
+    /// see [`pathkvs_core::Transaction::set_meta`], outside of one it is a commit with only the metadata
+    pub fn set_meta(&mut self, meta: impl AsRef<[u8]>) -> Result<(), Error> {
+        self.write(system::META, meta)
+    }
+    /// appends the bytes to the value of the key, in a transaction the bytes go after the value the key has
+    /// when it commits, so concurrent appends to the same key don't conflict, see [`pathkvs_core::Transaction::append`]
+    pub fn append(&mut self, key: impl AsRef<[u8]>, bytes: impl AsRef<[u8]>) -> Result<(), Error> {
+        if self.mode == ConnectionMode::Snapshot {
+            panic!("pathks client: can't append to a snapshot");
+        }
+        let key = key.as_ref();
+        let bytes = bytes.as_ref();
+        if key.is_empty() || bytes.is_empty() {
+            return Ok(());
+        }
+        assert!(key.len() <= u32::MAX as usize);
+        assert!(bytes.len() <= u32::MAX as usize);
+        self.send(message::APPEND)?;
+        self.conn.write_u32(key.len() as u32)?;
+        self.conn.write_all(key)?;
+        self.conn.write_u32(bytes.len() as u32)?;
+        self.conn.write_all(bytes)?;
+        self.conn.flush()?;
+        match self.response()? {
+            message::APPEND => Ok(()),
+            message::BUSY => Err(Error::Busy),
+            message::UNSUPPORTED => Err(Error::Unsupported),
+            _ => Err(Error::Protocol),
+        }
+    }
+    /// writes `n` if it is larger than the number the key has, in a transaction it is compared with the number
+    /// the key has when it commits, so concurrent merges of the same key don't conflict,
+    /// see [`pathkvs_core::Transaction::write_max`]
+    pub fn write_max(&mut self, key: impl AsRef<[u8]>, n: i64) -> Result<(), Error> {
+        self.write_merged(message::WRITE_MAX, key.as_ref(), n)
+    }
+    /// like [`Connection::write_max`], but writes `n` if it is smaller
+    pub fn write_min(&mut self, key: impl AsRef<[u8]>, n: i64) -> Result<(), Error> {
+        self.write_merged(message::WRITE_MIN, key.as_ref(), n)
+    }
+    fn write_merged(&mut self, request: u8, key: &[u8], n: i64) -> Result<(), Error> {
+        if self.mode == ConnectionMode::Snapshot {
+            panic!("pathks client: can't write to a snapshot");
+        }
+        if key.is_empty() {
+            return Ok(());
+        }
+        assert!(key.len() <= u32::MAX as usize);
+        self.send(request)?;
+        self.conn.write_u32(key.len() as u32)?;
+        self.conn.write_all(key)?;
+        self.conn.write_u64(n as u64)?;
+        self.conn.flush()?;
+        match self.response()? {
+            response if response == request => Ok(()),
+            message::BUSY => Err(Error::Busy),
+            message::UNSUPPORTED => Err(Error::Unsupported),
+            _ => Err(Error::Protocol),
+        }
+    }
+    /// adds `delta` to the number the key has and returns the new value, see [`pathkvs_core::Transaction::incr`]
+    ///
+    /// outside of a transaction the server retries conflicts by itself, so this never fails with a conflict,
+    /// fails with [`Error::InvalidValue`] if the value is not a number or the sum overflows, and nothing is written
+    pub fn incr(&mut self, key: impl AsRef<[u8]>, delta: i64) -> Result<i64, Error> {
+        if self.mode == ConnectionMode::Snapshot {
+            panic!("pathks client: can't write to a snapshot");
+        }
+        let key = key.as_ref();
+        if key.is_empty() {
+            return Ok(0);
+        }
+        assert!(key.len() <= u32::MAX as usize);
+        self.send(message::INCR)?;
+        self.conn.write_vec_lengthed(key)?;
+        self.conn.write_u64(delta as u64)?;
+        self.conn.flush()?;
+        match self.response()? {
+            message::INCR => Ok(self.conn.read_u64()? as i64),
+            message::NOT_A_NUMBER => Err(Error::InvalidValue("not a number, or the sum overflows")),
+            message::BUSY => Err(Error::Busy),
+            message::UNSUPPORTED => Err(Error::Unsupported),
+            _ => Err(Error::Protocol),
+        }
+    }
+    /// adds one to the counter of the current time window of the key and returns the new count,
+    /// the counters of old windows are deleted by the server, see [`pathkvs_core::Transaction::incr_windowed`]
+    ///
+    /// outside of a transaction the server retries conflicts by itself, so this never fails with a conflict
+    pub fn incr_windowed(&mut self, key: impl AsRef<[u8]>, window: Duration) -> Result<u64, Error> {
+        if self.mode == ConnectionMode::Snapshot {
+            panic!("pathks client: can't write to a snapshot");
+        }
+        assert!(
+            !window.is_zero(),
+            "pathkvs client: the window cannot be zero"
+        );
+        let key = key.as_ref();
+        assert!(key.len() <= u32::MAX as usize);
+        self.send(message::INCR_WINDOWED)?;
+        self.conn.write_vec_lengthed(key)?;
+        self.conn.write_duration(window)?;
+        self.conn.flush()?;
+        match self.response()? {
+            message::INCR_WINDOWED => Ok(self.conn.read_u64()?),
+            message::BUSY => Err(Error::Busy),
+            message::UNSUPPORTED => Err(Error::Unsupported),
+            _ => Err(Error::Protocol),
+        }
+    }
+    /// writes the keys if every checked key has the expected value, none for a missing key, a write of none
+    /// deletes the key, in one round trip, as a transaction of its own on the server, see [`pathkvs_core::Database::check_write`],
+    /// and returns the time of the commit
+    ///
+    /// if a check fails nothing is written and this fails with [`Error::CheckFailed`] with the index of the check,
+    /// the server retries conflicts with other transactions by itself, so this never fails with a conflict
+    ///
+    /// panics if the connection is in a transaction or snapshot
+    pub fn check_write(
+        &mut self,
+        checks: &[(&[u8], Option<&[u8]>)],
+        writes: &[(&[u8], Option<&[u8]>)],
+    ) -> Result<SystemTime, Error> {
+        assert!(
+            self.mode.is_normal(),
+            "pathkvs client: check_write can't be called in a transaction or snapshot"
+        );
+        assert!(checks.len() <= u32::MAX as usize);
+        assert!(writes.len() <= u32::MAX as usize);
+        self.send(message::CHECK_WRITE)?;
+        self.conn.write_u32(checks.len() as u32)?;
+        for &(key, expected) in checks {
+            self.conn.write_vec_lengthed(key)?;
+            self.conn.write_opt_lengthed(expected)?;
+        }
+        self.conn.write_u32(writes.len() as u32)?;
+        for &(key, value) in writes {
+            self.conn.write_vec_lengthed(key)?;
+            self.conn.write_opt_lengthed(value)?;
+        }
+        self.conn.flush()?;
+        match self.response()? {
+            message::CHECK_WRITE => {
+                let duration = self.conn.read_duration()?;
+                Ok(SystemTime::UNIX_EPOCH.checked_add(duration).unwrap())
+            }
+            message::CONFLICT => Err(Error::CheckFailed(self.conn.read_u32()? as usize)),
+            message::BUSY => Err(Error::Busy),
+            message::UNSUPPORTED => Err(Error::Unsupported),
+            _ => Err(Error::Protocol),
+        }
+    }
+    /// changes what the reads of this connection see when it is not in a transaction or snapshot
+    pub fn set_read_consistency(&mut self, consistency: ReadConsistency) -> Result<(), Error> {
+        self.send(message::SET_READ_CONSISTENCY)?;
+        self.conn.write_u8(consistency.to_u8())?;
+        self.conn.flush()?;
+        match self.response()? {
+            message::SET_READ_CONSISTENCY => Ok(()),
+            message::UNSUPPORTED => Err(Error::Unsupported),
+            _ => Err(Error::Protocol),
+        }
+    }
+    /// fails with [`Error::LimitExceeded`] if the connection already started as many transactions as the server allows
     pub fn start_transaction(&mut self) -> Result<(), Error> {
-        self.conn.write_u8(message::START_TRANSACTION)?;
+        self.send(message::START_TRANSACTION)?;
         self.conn.flush()?;
-        if self.conn.read_u8()? != message::START_TRANSACTION {
-            return Err(ProtocolError.into());
+        match self.response()? {
+            message::START_TRANSACTION => {}
+            message::LIMIT_EXCEEDED => return Err(Error::LimitExceeded),
+            _ => return Err(Error::Protocol),
         }
         self.mode = ConnectionMode::Transaction;
         Ok(())
     }
+    /// if the server is busy this fails with [`Error::Busy`] as an io error, and the transaction stays open,
+    /// so the commit can be retried, or the transaction rolled back
+    ///
+    /// if the server waits for replicas and they don't acknowledge the commit in time this fails with
+    /// [`Error::NotReplicated`], see [`Connection::commit_replicated`]
     pub fn commit(&mut self) -> Result<Option<SystemTime>, TransactionError> {
-        self.conn.write_u8(message::COMMIT)?;
+        self.send(message::COMMIT)?;
         self.conn.flush()?;
-        match self.conn.read_u8()? {
+        match self.response()? {
             message::COMMIT => {
                 let duration = self.conn.read_duration()?;
                 self.mode = ConnectionMode::Normal;
                 Ok((!duration.is_zero())
                     .then(|| SystemTime::UNIX_EPOCH.checked_add(duration).unwrap()))
             }
-            message::CONFLICT => Err(TransactionError::Conflict),
+            message::CONFLICT => {
+                self.mode = ConnectionMode::Normal;
+                Err(TransactionError::Conflict)
+            }
+            message::BUSY => Err(TransactionError::Io(Error::Busy.into())),
+            message::NOT_REPLICATED => {
+                self.mode = ConnectionMode::Normal;
+                Err(TransactionError::Io(Error::NotReplicated.into()))
+            }
+            _ => Err(TransactionError::Io(ProtocolError.into())),
+        }
+    }
+    /// like [`Connection::commit`], but also returns the sequence number of the commit and the length of the history
+    /// once it was persisted, see [`pathkvs_core::Transaction::commit_with_receipt`]
+    ///
+    /// if the server doesn't support it this fails with [`Error::Unsupported`] as an io error, and the transaction stays open
+    pub fn commit_with_receipt(&mut self) -> Result<Option<CommitReceipt>, TransactionError> {
+        self.send(message::COMMIT_RECEIPT)?;
+        self.conn.flush()?;
+        self.read_receipt(message::COMMIT_RECEIPT)
+    }
+    /// like [`Connection::commit_with_receipt`], but only returns once `replicas` replicas acknowledged the commit,
+    /// instead of as many as the server requires by default, zero doesn't wait for any,
+    /// see [`pathkvs_core::Database::wait_for_replicas`]
+    ///
+    /// if they don't acknowledge it before the timeout of the server this fails with [`Error::NotReplicated`]
+    /// as an io error, the commit was still done and the transaction is over
+    pub fn commit_replicated(
+        &mut self,
+        replicas: u32,
+    ) -> Result<Option<CommitReceipt>, TransactionError> {
+        self.send(message::COMMIT_REPLICATED)?;
+        self.conn.write_u32(replicas)?;
+        self.conn.flush()?;
+        self.read_receipt(message::COMMIT_REPLICATED)
+    }
+    fn read_receipt(&mut self, request: u8) -> Result<Option<CommitReceipt>, TransactionError> {
+        match self.response()? {
+            response if response == request => {
+                let time = self.conn.read_duration()?;
+                let seq = self.conn.read_u64()?;
+                let offset = self.conn.read_u64()?;
+                self.mode = ConnectionMode::Normal;
+                Ok((!time.is_zero()).then_some(CommitReceipt {
+                    time,
+                    seq,
+                    offset: (offset != 0).then_some(offset),
+                }))
+            }
+            message::CONFLICT => {
+                self.mode = ConnectionMode::Normal;
+                Err(TransactionError::Conflict)
+            }
+            message::BUSY => Err(TransactionError::Io(Error::Busy.into())),
+            message::NOT_REPLICATED => {
+                self.mode = ConnectionMode::Normal;
+                Err(TransactionError::Io(Error::NotReplicated.into()))
+            }
+            message::UNSUPPORTED => Err(TransactionError::Io(Error::Unsupported.into())),
             _ => Err(TransactionError::Io(ProtocolError.into())),
         }
     }
     pub fn rollback(&mut self) -> Result<(), Error> {
-        self.conn.write_u8(message::ROLLBACK)?;
+        self.batch.clear();
+        self.send(message::ROLLBACK)?;
         self.conn.flush()?;
-        if self.conn.read_u8()? != message::ROLLBACK {
-            return Err(ProtocolError.into());
+        if self.response()? != message::ROLLBACK {
+            return Err(Error::Protocol);
         }
         self.mode = ConnectionMode::Normal;
         Ok(())
     }
+    /// the keys that make the current transaction conflict, without committing it, empty if it can still commit
+    /// or if there is no transaction, see [`pathkvs_core::Transaction::validate`]
+    pub fn validate(&mut self) -> Result<Vec<Vec<u8>>, Error> {
+        self.send(message::VALIDATE)?;
+        self.conn.flush()?;
+        match self.response()? {
+            message::VALIDATE => {
+                let count = self.conn.read_u32()?;
+                let mut keys = Vec::new();
+                for _ in 0..count {
+                    keys.push(self.conn.read_vec_lengthed(u32::MAX)?);
+                }
+                Ok(keys)
+            }
+            message::UNSUPPORTED => Err(Error::Unsupported),
+            _ => Err(Error::Protocol),
+        }
+    }
+    /// the last `limit` commits of the history of the server, newest first,
+    /// the sequence number of one can be given to [`Connection::start_seq_snapshot`] to look at the database right after it
+    pub fn recent_commits(&mut self, limit: u32) -> Result<Vec<CommitSummary>, Error> {
+        self.send(message::RECENT_COMMITS)?;
+        self.conn.write_u32(limit)?;
+        self.conn.flush()?;
+        match self.response()? {
+            message::RECENT_COMMITS => {
+                let count = self.conn.read_u32()?;
+                let mut commits = Vec::new();
+                for _ in 0..count {
+                    commits.push(CommitSummary {
+                        time: self.conn.read_duration()?,
+                        seq: self.conn.read_u64()?,
+                        keys: self.conn.read_u32()? as usize,
+                        meta: match self.conn.read_u8()? {
+                            0 => None,
+                            _ => Some(self.conn.read_vec_lengthed(u32::MAX)?),
+                        },
+                    });
+                }
+                Ok(commits)
+            }
+            message::UNSUPPORTED => Err(Error::Unsupported),
+            _ => Err(Error::Protocol),
+        }
+    }
+    /// runs `f` in a transaction and commits it, running it again from the start on conflict,
+    /// or if the server is busy, up to [`TRANSACT_ATTEMPTS`] times, after which it returns the last error
+    ///
+    /// the wait between attempts grows and is randomized, so clients that conflict with each other
+    /// don't keep retrying in lockstep, if `f` fails the transaction is rolled back and its error returned
+    ///
+    /// panics if the connection is already in a transaction or snapshot
+    pub fn transact<R>(
+        &mut self,
+        f: impl FnMut(&mut Self) -> Result<R, Error>,
+    ) -> Result<R, Error> {
+        self.transact_with_attempts(TRANSACT_ATTEMPTS, f)
+    }
+    /// [`Connection::transact`] with a different number of attempts, which must not be zero
+    pub fn transact_with_attempts<R>(
+        &mut self,
+        attempts: u32,
+        mut f: impl FnMut(&mut Self) -> Result<R, Error>,
+    ) -> Result<R, Error> {
+        assert!(attempts != 0, "pathkvs client: attempts cannot be zero");
+        assert!(
+            self.mode.is_normal(),
+            "pathkvs client: transact can't be called in a transaction or snapshot"
+        );
+        let mut backoff = TRANSACT_BACKOFF;
+        for attempt in 1..=attempts {
+            self.start_transaction()?;
+            let result = match f(self) {
+                Ok(result) => result,
+                Err(error) => {
+                    // the original error is more useful than a failure to roll back
+                    let _ = self.rollback();
+                    return Err(error);
+                }
+            };
+            match self.commit().map_err(Error::from) {
+                Ok(_) => return Ok(result),
+                Err(Error::Conflict) if attempt < attempts => {}
+                // a busy server keeps the transaction open
+                Err(Error::Busy) if attempt < attempts => self.rollback()?,
+                Err(error) => {
+                    if !self.mode.is_normal() {
+                        let _ = self.rollback();
+                    }
+                    return Err(error);
+                }
+            }
+            std::thread::sleep(jitter(backoff));
+            backoff = (backoff * 2).min(TRANSACT_MAX_BACKOFF);
+        }
+        unreachable!("the last attempt always returns")
+    }
     pub fn count(&mut self, start: impl AsRef<[u8]>, end: impl AsRef<[u8]>) -> Result<u32, Error> {
         let start = start.as_ref();
         let end = end.as_ref();
@@ -165,16 +1466,16 @@ where
             .len()
             .checked_add(end.len())
             .is_some_and(|x| x <= u32::MAX as usize));
-        self.conn.write_u8(message::COUNT)?;
+        self.send(message::COUNT)?;
         self.conn.write_u32(start.len() as u32)?;
         self.conn.write_all(start)?;
         self.conn.write_u32(end.len() as u32)?;
         self.conn.write_all(end)?;
         self.conn.flush()?;
-        if self.conn.read_u8()? != message::COUNT {
-            return Err(ProtocolError.into());
+        if self.response()? != message::COUNT {
+            return Err(Error::Protocol);
         }
-        self.conn.read_u32()
+        Ok(self.conn.read_u32()?)
     }
     pub fn list(
         &mut self,
@@ -195,7 +1496,7 @@ where
         let end = end.as_ref();
         match self.list_limited_opt(start, end, max_len).transpose() {
             Some(result) => result,
-            None => Err(LimitExceeded.into()),
+            None => Err(Error::LimitExceeded),
         }
     }
     pub fn list_limited_opt(
@@ -210,14 +1511,14 @@ where
             .len()
             .checked_add(end.len())
             .is_some_and(|x| x <= u32::MAX as usize));
-        self.conn.write_u8(message::LIST)?;
+        self.send(message::LIST)?;
         self.conn.write_u32(start.len() as u32)?;
         self.conn.write_all(start)?;
         self.conn.write_u32(end.len() as u32)?;
         self.conn.write_all(end)?;
         self.conn.write_u32(max_len)?;
         self.conn.flush()?;
-        match self.conn.read_u8()? {
+        match self.response()? {
             message::LIST => {
                 let mut total = Some(0u32);
                 let mut rows = Vec::new();
@@ -229,101 +1530,746 @@ where
                     if total.is_some_and(|total| total <= max_len) {
                         rows.push(self.conn.read_vec(recv_len as usize)?);
                     } else {
-                        return Err(ProtocolError.into());
+                        return Err(Error::Protocol);
+                    }
+                }
+                Ok(Some(rows))
+            }
+            message::LIMIT_EXCEEDED => Ok(None),
+            _ => Err(Error::Protocol),
+        }
+    }
+    pub fn scan(
+        &mut self,
+        start: impl AsRef<[u8]>,
+        end: impl AsRef<[u8]>,
+    ) -> Result<Vec<(Vec<u8>, Vec<u8>)>, Error> {
+        let start = start.as_ref();
+        let end = end.as_ref();
+        self.scan_limited(start, end, u32::MAX)
+    }
+    pub fn scan_limited(
+        &mut self,
+        start: impl AsRef<[u8]>,
+        end: impl AsRef<[u8]>,
+        max_len: u32,
+    ) -> Result<Vec<(Vec<u8>, Vec<u8>)>, Error> {
+        let start = start.as_ref();
+        let end = end.as_ref();
+        match self.scan_limited_opt(start, end, max_len).transpose() {
+            Some(result) => result,
+            None => Err(Error::LimitExceeded),
+        }
+    }
+    pub fn scan_limited_opt(
+        &mut self,
+        start: impl AsRef<[u8]>,
+        end: impl AsRef<[u8]>,
+        max_len: u32,
+    ) -> Result<Option<Vec<(Vec<u8>, Vec<u8>)>>, Error> {
+        self.scan_request(start.as_ref(), end.as_ref(), None, max_len)
+    }
+    /// like [`Connection::scan`], but only the keys changed after `since`, deleted keys included with an empty value,
+    /// so a client that keeps the time of its last sync only transfers what changed,
+    /// see [`pathkvs_core::Snapshot::scan_modified_since`]
+    ///
+    /// the times of the commits come from the clock of the server, so `since` should also come from it, like the time of a commit
+    pub fn scan_modified_since(
+        &mut self,
+        start: impl AsRef<[u8]>,
+        end: impl AsRef<[u8]>,
+        since: SystemTime,
+    ) -> Result<Entries, Error> {
+        let since = since
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .expect("since must be after the unix epoch");
+        match self.scan_request(start.as_ref(), end.as_ref(), Some(since), u32::MAX)? {
+            Some(rows) => Ok(rows),
+            None => Err(Error::LimitExceeded),
+        }
+    }
+    fn scan_request(
+        &mut self,
+        start: &[u8],
+        end: &[u8],
+        since: Option<Duration>,
+        max_len: u32,
+    ) -> Result<Option<Entries>, Error> {
+        assert!(start
+            .len()
+            .checked_add(end.len())
+            .is_some_and(|x| x <= u32::MAX as usize));
+        let request = match since {
+            Some(_) => message::SCAN_MODIFIED_SINCE,
+            None => message::SCAN,
+        };
+        self.send(request)?;
+        self.conn.write_u32(start.len() as u32)?;
+        self.conn.write_all(start)?;
+        self.conn.write_u32(end.len() as u32)?;
+        self.conn.write_all(end)?;
+        if let Some(since) = since {
+            self.conn.write_duration(since)?;
+        }
+        self.conn.write_u32(max_len)?;
+        self.conn.flush()?;
+        match self.response()? {
+            response if response == request => {
+                let mut total = Some(0u32);
+                let mut rows = Vec::new();
+                let rowc = self.conn.read_u32()?;
+                rows.reserve_exact(rowc as usize);
+                for _ in 0..rowc {
+                    let recv_len = self.conn.read_u32()?;
+                    total = total.and_then(|x| x.checked_add(recv_len));
+                    if !total.is_some_and(|total| total <= max_len) {
+                        return Err(Error::Protocol);
+                    }
+                    let key = self.conn.read_vec(recv_len as usize)?;
+
+                    let recv_len = self.conn.read_u32()?;
+                    total = total.and_then(|x| x.checked_add(recv_len));
+                    if !total.is_some_and(|total| total <= max_len) {
+                        return Err(Error::Protocol);
                     }
+                    let value = self.conn.read_vec(recv_len as usize)?;
+
+                    rows.push((key, value));
                 }
                 Ok(Some(rows))
             }
             message::LIMIT_EXCEEDED => Ok(None),
-            _ => Err(ProtocolError.into()),
+            message::UNSUPPORTED => Err(Error::Unsupported),
+            _ => Err(Error::Protocol),
+        }
+    }
+    pub fn start_snapshot(&mut self, prev_time: Option<SystemTime>) -> Result<(), Error> {
+        self.send(message::START_SNAPSHOT)?;
+        self.conn.write_duration(
+            prev_time
+                .map(|x| {
+                    x.duration_since(SystemTime::UNIX_EPOCH)
+                        .expect("prev_time must be after the unix epoch")
+                })
+                .unwrap_or_default(),
+        )?;
+        self.conn.flush()?;
+        if self.response()? != message::START_SNAPSHOT {
+            return Err(Error::Protocol);
+        }
+        self.mode = ConnectionMode::Snapshot;
+        Ok(())
+    }
+    /// starts a snapshot after the first `seq` commits of the history, the sequence number of a commit receipt
+    /// gives the snapshot right after that commit, see [`Connection::commit_with_receipt`]
+    pub fn start_seq_snapshot(&mut self, seq: u64) -> Result<(), Error> {
+        self.send(message::START_SEQ_SNAPSHOT)?;
+        self.conn.write_u64(seq)?;
+        self.conn.flush()?;
+        match self.response()? {
+            message::START_SEQ_SNAPSHOT => {
+                self.mode = ConnectionMode::Snapshot;
+                Ok(())
+            }
+            message::UNSUPPORTED => Err(Error::Unsupported),
+            _ => Err(Error::Protocol),
+        }
+    }
+
+    /// streams every key and value of a consistent snapshot to `entry`
+    ///
+    /// returns the time of the snapshot, none if the database is empty
+    pub fn backup(
+        &mut self,
+        mut entry: impl FnMut(Vec<u8>, Vec<u8>) -> Result<(), Error>,
+    ) -> Result<Option<SystemTime>, Error> {
+        self.send(message::BACKUP)?;
+        self.conn.flush()?;
+        if self.response()? != message::BACKUP {
+            return Err(Error::Protocol);
+        }
+        let duration = self.conn.read_duration()?;
+        loop {
+            match self.conn.read_u8()? {
+                0 => break,
+                1 => {
+                    let key = self.conn.read_vec_lengthed(u32::MAX)?;
+                    let value = self.conn.read_vec_lengthed(u32::MAX)?;
+                    entry(key, value)?;
+                }
+                _ => return Err(Error::Protocol),
+            }
+        }
+        Ok((!duration.is_zero()).then(|| SystemTime::UNIX_EPOCH.checked_add(duration).unwrap()))
+    }
+
+    /// runs a procedure registered on the server with [`Connection::define_procedure`] and returns its result
+    ///
+    /// in a transaction the procedure becomes part of it, otherwise it commits on its own
+    pub fn call_procedure<A: AsRef<[u8]>>(
+        &mut self,
+        name: &str,
+        args: impl IntoIterator<Item = A>,
+    ) -> Result<Vec<u8>, Error> {
+        self.call_procedure_limited(name, args, u32::MAX)
+    }
+    pub fn call_procedure_limited<A: AsRef<[u8]>>(
+        &mut self,
+        name: &str,
+        args: impl IntoIterator<Item = A>,
+        max_len: u32,
+    ) -> Result<Vec<u8>, Error> {
+        match self
+            .call_procedure_limited_opt(name, args, max_len)
+            .transpose()
+        {
+            Some(result) => result,
+            None => Err(Error::LimitExceeded),
+        }
+    }
+    /// returns `None` if the result is longer than `max_len`, the procedure still ran
+    pub fn call_procedure_limited_opt<A: AsRef<[u8]>>(
+        &mut self,
+        name: &str,
+        args: impl IntoIterator<Item = A>,
+        max_len: u32,
+    ) -> Result<Option<Vec<u8>>, Error> {
+        let args = args.into_iter().collect::<Vec<_>>();
+        assert!(args.len() <= u32::MAX as usize);
+        self.send(message::CALL_PROCEDURE)?;
+        self.conn.write_vec_lengthed(name.as_bytes())?;
+        self.conn.write_u32(args.len() as u32)?;
+        for arg in &args {
+            self.conn.write_vec_lengthed(arg.as_ref())?;
+        }
+        self.conn.write_u32(max_len)?;
+        self.conn.flush()?;
+        match self.response()? {
+            message::CALL_PROCEDURE => {
+                let recv_len = self.conn.read_u32()?;
+                if recv_len > max_len {
+                    return Err(Error::Protocol);
+                }
+                Ok(Some(self.conn.read_vec(recv_len as usize)?))
+            }
+            message::LIMIT_EXCEEDED => Ok(None),
+            message::PROCEDURE_FAILED => Err(self.read_procedure_failure()?),
+            message::BUSY => Err(Error::Busy),
+            message::UNAUTHORIZED => Err(Error::Unauthorized),
+            message::UNSUPPORTED => Err(Error::Unsupported),
+            _ => Err(Error::Protocol),
+        }
+    }
+
+    /// sends a message to every connection subscribed to the channel, returns how many received it
+    ///
+    /// channels have nothing to do with keys, publishing does not write to the database
+    pub fn publish(
+        &mut self,
+        channel: impl AsRef<[u8]>,
+        message: impl AsRef<[u8]>,
+    ) -> Result<u32, Error> {
+        let channel = channel.as_ref();
+        let message = message.as_ref();
+        assert!(channel.len() <= u32::MAX as usize);
+        assert!(message.len() <= u32::MAX as usize);
+        self.send(message::PUBLISH)?;
+        self.conn.write_vec_lengthed(channel)?;
+        self.conn.write_vec_lengthed(message)?;
+        self.conn.flush()?;
+        match self.response()? {
+            message::PUBLISH => Ok(self.conn.read_u32()?),
+            message::UNSUPPORTED => Err(Error::Unsupported),
+            _ => Err(Error::Protocol),
+        }
+    }
+    /// turns this connection into a subscription to the channels
+    ///
+    /// a subscribed connection can't send commands anymore, use another connection for that
+    pub fn subscribe<C: AsRef<[u8]>>(
+        self,
+        channels: impl IntoIterator<Item = C>,
+    ) -> Result<Subscription<T>, Error> {
+        self.subscribe_with(message::SUBSCRIBE, channels)
+    }
+    /// like [`Connection::subscribe`], but to every channel whose name matches one of the glob patterns,
+    /// such as `user:*:email`, the patterns are matched by the server, see [`crate::glob::matches`]
+    ///
+    /// a message is received once, even if its channel matches many patterns
+    pub fn subscribe_patterns<P: AsRef<[u8]>>(
+        self,
+        patterns: impl IntoIterator<Item = P>,
+    ) -> Result<Subscription<T>, Error> {
+        self.subscribe_with(message::SUBSCRIBE_PATTERNS, patterns)
+    }
+    fn subscribe_with<C: AsRef<[u8]>>(
+        mut self,
+        request: u8,
+        channels: impl IntoIterator<Item = C>,
+    ) -> Result<Subscription<T>, Error> {
+        let channels = channels.into_iter().collect::<Vec<_>>();
+        assert!(channels.len() <= u32::MAX as usize);
+        self.send(request)?;
+        self.conn.write_u32(channels.len() as u32)?;
+        for channel in &channels {
+            self.conn.write_vec_lengthed(channel.as_ref())?;
+        }
+        self.conn.flush()?;
+        match self.response()? {
+            response if response == request => Ok(Subscription { conn: self.conn }),
+            message::UNSUPPORTED => Err(Error::Unsupported),
+            _ => Err(Error::Protocol),
+        }
+    }
+
+    /// names the connection, so it can be told apart in [`Connection::connections`] and the logs of the server,
+    /// such as with the name of the application and of its host, names don't have to be unique
+    pub fn set_name(&mut self, name: &str) -> Result<(), Error> {
+        assert!(name.len() <= u8::MAX as usize);
+        self.send(message::SET_NAME)?;
+        self.conn.write_vec_lengthed(name.as_bytes())?;
+        self.conn.flush()?;
+        match self.response()? {
+            message::SET_NAME => Ok(()),
+            message::UNSUPPORTED => Err(Error::Unsupported),
+            _ => Err(Error::Protocol),
+        }
+    }
+    /// grants admin to the connection if `token` is the admin token of the server,
+    /// or the role of the user the token belongs to, see [`Connection::add_user`]
+    pub fn authenticate(&mut self, token: impl AsRef<[u8]>) -> Result<(), Error> {
+        let token = token.as_ref();
+        assert!(token.len() <= u32::MAX as usize);
+        self.send(message::AUTHENTICATE)?;
+        self.conn.write_vec_lengthed(token)?;
+        self.conn.flush()?;
+        self.read_admin_response(message::AUTHENTICATE)
+    }
+    /// the state of the server in sections, the structured form of [`Connection::stats`]
+    pub fn info(&mut self) -> Result<Info, Error> {
+        self.send(message::INFO)?;
+        self.conn.flush()?;
+        self.read_admin_response(message::INFO)?;
+        let version = self.conn.read_u32()?;
+        let sectionc = self.conn.read_u32()?;
+        let mut sections = Vec::new();
+        for _ in 0..sectionc {
+            let name = self.conn.read_vec_lengthed(u8::MAX as u32)?;
+            let name = String::from_utf8(name).map_err(|_| Error::Protocol)?;
+            let fieldc = self.conn.read_u32()?;
+            let mut fields = Vec::new();
+            for _ in 0..fieldc {
+                let field = self.conn.read_vec_lengthed(u8::MAX as u32)?;
+                let field = String::from_utf8(field).map_err(|_| Error::Protocol)?;
+                fields.push((field, self.conn.read_u64()?));
+            }
+            sections.push(InfoSection { name, fields });
+        }
+        Ok(Info::from_sections(version, sections))
+    }
+    pub fn stats(&mut self) -> Result<Vec<(String, u64)>, Error> {
+        self.send(message::STATS)?;
+        self.conn.flush()?;
+        self.read_admin_response(message::STATS)?;
+        let rowc = self.conn.read_u32()?;
+        let mut rows = Vec::new();
+        for _ in 0..rowc {
+            let name = self.conn.read_vec_lengthed(u8::MAX as u32)?;
+            let name = String::from_utf8(name).map_err(|_| Error::Protocol)?;
+            let value = self.conn.read_u64()?;
+            rows.push((name, value));
+        }
+        Ok(rows)
+    }
+    /// rewrites the history of the database with only the latest value of each key, see [`Connection::compact_keeping`]
+    pub fn compact(&mut self) -> Result<(), Error> {
+        self.compact_keeping(Duration::ZERO)
+    }
+    /// like [`Connection::compact`], but the commits of the last `age` are kept as they are
+    pub fn compact_keeping(&mut self, age: Duration) -> Result<(), Error> {
+        self.send(message::COMPACT)?;
+        self.conn.write_duration(age)?;
+        self.conn.flush()?;
+        self.read_admin_response(message::COMPACT)
+    }
+    pub fn gc(&mut self) -> Result<(), Error> {
+        self.send(message::GC)?;
+        self.conn.flush()?;
+        self.read_admin_response(message::GC)
+    }
+    pub fn connections(&mut self) -> Result<Vec<ConnectionInfo>, Error> {
+        self.send(message::CONNECTIONS)?;
+        self.conn.flush()?;
+        self.read_admin_response(message::CONNECTIONS)?;
+        let rowc = self.conn.read_u32()?;
+        let mut rows = Vec::new();
+        for _ in 0..rowc {
+            let id = self.conn.read_u64()?;
+            let peer = self.conn.read_vec_lengthed(u8::MAX as u32)?;
+            let peer = String::from_utf8(peer).map_err(|_| Error::Protocol)?;
+            let name = self.conn.read_vec_lengthed(u8::MAX as u32)?;
+            let name = String::from_utf8(name).map_err(|_| Error::Protocol)?;
+            let mode = ConnectionMode::from_u8(self.conn.read_u8()?).ok_or(Error::Protocol)?;
+            let commands = self.conn.read_u64()?;
+            let bytes = self.conn.read_u64()?;
+            let idle = self.conn.read_duration()?;
+            let transaction = match self.conn.read_u8()? {
+                0 => None,
+                _ => Some(self.conn.read_duration()?),
+            };
+            rows.push(ConnectionInfo {
+                id,
+                peer,
+                name,
+                mode,
+                commands,
+                bytes,
+                idle,
+                transaction,
+            });
+        }
+        Ok(rows)
+    }
+    /// the most recent requests that took longer than the threshold of the server, the newest first,
+    /// like the `SLOWLOG` of redis, fails with [`Error::Unsupported`] if the server does not keep them
+    pub fn slow_log(&mut self) -> Result<Vec<SlowCommand>, Error> {
+        self.send(message::SLOW_LOG)?;
+        self.conn.flush()?;
+        self.read_admin_response(message::SLOW_LOG)?;
+        let count = self.conn.read_u32()?;
+        let mut commands = Vec::new();
+        for _ in 0..count {
+            let id = self.conn.read_u64()?;
+            let time = self.conn.read_duration()?;
+            let connection = self.conn.read_u64()?;
+            let mut strings = [String::new(), String::new(), String::new()];
+            for string in &mut strings {
+                let bytes = self.conn.read_vec_lengthed(u8::MAX as u32)?;
+                *string = String::from_utf8(bytes).map_err(|_| Error::Protocol)?;
+            }
+            let [peer, name, request] = strings;
+            let key_bytes = self.conn.read_u64()?;
+            let bytes = self.conn.read_u64()?;
+            let elapsed = self.conn.read_duration()?;
+            let outcome = self.conn.read_vec_lengthed(u8::MAX as u32)?;
+            let outcome = String::from_utf8(outcome).map_err(|_| Error::Protocol)?;
+            commands.push(SlowCommand {
+                id,
+                time,
+                connection,
+                peer,
+                name,
+                request,
+                key_bytes,
+                bytes,
+                elapsed,
+                outcome,
+            });
+        }
+        Ok(commands)
+    }
+    /// every replica that acknowledged a position to the server, by name, with how far behind its newest commit it is,
+    /// see [`pathkvs_core::Database::replication_status`]
+    pub fn replication_status(&mut self) -> Result<Vec<ReplicaStatus>, Error> {
+        self.send(message::REPLICATION)?;
+        self.conn.flush()?;
+        self.read_admin_response(message::REPLICATION)?;
+        let count = self.conn.read_u32()?;
+        let mut replicas = Vec::new();
+        for _ in 0..count {
+            let name = self.conn.read_vec_lengthed(u8::MAX as u32)?;
+            replicas.push(ReplicaStatus {
+                name: String::from_utf8(name).map_err(|_| Error::Protocol)?,
+                seq: self.conn.read_u64()?,
+                acked: self.conn.read_duration()?,
+                lag_commits: self.conn.read_u64()?,
+                lag_bytes: self.conn.read_u64()?,
+            });
+        }
+        Ok(replicas)
+    }
+    /// writes a database file with the latest value of every key of a consistent snapshot of the server,
+    /// and returns its sequence number, to pass to [`Connection::backup_since`] for the commits after it,
+    /// see [`pathkvs_core::Database::backup_snapshot`]
+    ///
+    /// this is how a new replica starts, instead of applying the whole history from 0, it requires admin
+    pub fn backup_snapshot(&mut self, writer: impl std::io::Write) -> Result<u64, Error> {
+        self.send(message::BACKUP_SNAPSHOT)?;
+        self.conn.flush()?;
+        self.read_history(message::BACKUP_SNAPSHOT, writer)
+    }
+    /// writes the commits after the first `seq` of the history of the server, and returns the sequence number
+    /// to pass to the next call, see [`pathkvs_core::Database::backup_since`], they can be applied with
+    /// [`pathkvs_core::Database::apply_backup`], it requires admin
+    ///
+    /// fails with [`Error::Conflict`] if `seq` is past the end of the history of the server,
+    /// then the replica has to start again from [`Connection::backup_snapshot`]
+    pub fn backup_since(&mut self, seq: u64, writer: impl std::io::Write) -> Result<u64, Error> {
+        self.send(message::BACKUP_SINCE)?;
+        self.conn.write_u64(seq)?;
+        self.conn.flush()?;
+        self.read_history(message::BACKUP_SINCE, writer)
+    }
+    fn read_history(&mut self, request: u8, mut writer: impl std::io::Write) -> Result<u64, Error> {
+        match self.response()? {
+            response if response == request => {}
+            message::CONFLICT => return Err(Error::Conflict),
+            message::UNAUTHORIZED => return Err(Error::Unauthorized),
+            message::UNSUPPORTED => return Err(Error::Unsupported),
+            _ => return Err(Error::Protocol),
+        }
+        let seq = self.conn.read_u64()?;
+        loop {
+            match self.conn.read_u8()? {
+                0 => break,
+                message::CHUNK => {
+                    writer.write_all(&self.conn.read_vec_lengthed(message::MAX_CHUNK_LEN)?)?
+                }
+                message::COMPRESSED_CHUNK => {
+                    let compression =
+                        Compression::from_u8(self.conn.read_u8()?).ok_or(Error::Protocol)?;
+                    let len = self.conn.read_u32()?;
+                    if len > message::MAX_CHUNK_LEN {
+                        return Err(Error::Protocol);
+                    }
+                    let compressed = self.conn.read_vec_lengthed(message::MAX_CHUNK_LEN)?;
+                    let chunk = compression
+                        .decompress(&compressed, len as usize)
+                        .ok_or(Error::Protocol)?;
+                    writer.write_all(&chunk)?;
+                }
+                _ => return Err(Error::Protocol),
+            }
+        }
+        writer.flush()?;
+        Ok(seq)
+    }
+    /// agrees with the server on how to compress the history sent by [`Connection::backup_snapshot`]
+    /// and [`Connection::backup_since`], for replicas on slow links, the server picks the first of `accepted`
+    /// it knows, and it is returned, [`Compression::None`] if it knows none of them
+    ///
+    /// each chunk of the history is compressed on its own, and sent as it is if that doesn't make it smaller,
+    /// servers from before compression close the connection
+    pub fn negotiate_compression(
+        &mut self,
+        accepted: &[Compression],
+    ) -> Result<Compression, Error> {
+        assert!(accepted.len() <= u8::MAX as usize);
+        self.send(message::NEGOTIATE_COMPRESSION)?;
+        self.conn.write_u8(accepted.len() as u8)?;
+        for compression in accepted {
+            self.conn.write_u8(compression.to_u8())?;
+        }
+        self.conn.flush()?;
+        match self.response()? {
+            message::NEGOTIATE_COMPRESSION => {
+                Compression::from_u8(self.conn.read_u8()?).ok_or(Error::Protocol)
+            }
+            _ => Err(Error::Protocol),
+        }
+    }
+    /// tells the server the replica `name` has applied the first `seq` commits of its history,
+    /// see [`pathkvs_core::Database::ack_replication`], the name is at most 255 bytes
+    pub fn ack_replication(&mut self, name: &str, seq: u64) -> Result<(), Error> {
+        assert!(name.len() <= u8::MAX as usize);
+        self.send(message::ACK_REPLICATION)?;
+        self.conn.write_vec_lengthed(name.as_bytes())?;
+        self.conn.write_u64(seq)?;
+        self.conn.flush()?;
+        self.read_admin_response(message::ACK_REPLICATION)
+    }
+    /// ends the connection with the id, as listed by [`Connection::connections`], rolling back its transaction,
+    /// returns false if there is no such connection or it can't be ended, like this one
+    pub fn kill(&mut self, id: u64) -> Result<bool, Error> {
+        self.send(message::KILL)?;
+        self.conn.write_u64(id)?;
+        self.conn.flush()?;
+        self.read_admin_response(message::KILL)?;
+        Ok(self.conn.read_u8()? != 0)
+    }
+    pub fn drain(&mut self) -> Result<(), Error> {
+        self.send(message::DRAIN)?;
+        self.conn.flush()?;
+        self.read_admin_response(message::DRAIN)
+    }
+    /// makes the server start a new process of itself, which takes over its listeners, and then drain
+    pub fn upgrade(&mut self) -> Result<(), Error> {
+        self.send(message::UPGRADE)?;
+        self.conn.flush()?;
+        self.read_admin_response(message::UPGRADE)
+    }
+    /// makes the server read its configuration again, a rejected configuration
+    /// is an [`std::io::ErrorKind::InvalidData`] error with the reason given by the server
+    pub fn reload(&mut self) -> Result<(), Error> {
+        self.send(message::RELOAD)?;
+        self.conn.flush()?;
+        self.read_admin_response(message::RELOAD)?;
+        let rejected = self.conn.read_vec_lengthed(u32::MAX)?;
+        if rejected.is_empty() {
+            return Ok(());
+        }
+        let reason = String::from_utf8_lossy(&rejected).into_owned();
+        Err(Error::Io(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            reason,
+        )))
+    }
+    /// registers a procedure on the server, replacing any procedure with the same name,
+    /// see [`pathkvs_core::procedure`] for the language
+    pub fn define_procedure(&mut self, name: &str, source: &str) -> Result<(), Error> {
+        self.send(message::DEFINE_PROCEDURE)?;
+        self.conn.write_vec_lengthed(name.as_bytes())?;
+        self.conn.write_vec_lengthed(source.as_bytes())?;
+        self.conn.flush()?;
+        match self.response()? {
+            message::PROCEDURE_FAILED => Err(self.read_procedure_failure()?),
+            message::UNAUTHORIZED => Err(Error::Unauthorized),
+            message::UNSUPPORTED => Err(Error::Unsupported),
+            message::DEFINE_PROCEDURE => Ok(()),
+            _ => Err(Error::Protocol),
         }
     }
-    pub fn scan(
+    /// adds a user, or replaces the one with the same name, connections that authenticate with `token`
+    /// get `role`, the server only keeps a hash of the token
+    pub fn add_user(
         &mut self,
-        start: impl AsRef<[u8]>,
-        end: impl AsRef<[u8]>,
-    ) -> Result<Vec<(Vec<u8>, Vec<u8>)>, Error> {
-        let start = start.as_ref();
-        let end = end.as_ref();
-        self.scan_limited(start, end, u32::MAX)
+        name: &str,
+        role: &str,
+        token: impl AsRef<[u8]>,
+    ) -> Result<(), Error> {
+        let token = token.as_ref();
+        assert!(!name.is_empty() && !role.is_empty() && !token.is_empty());
+        self.send(message::ADD_USER)?;
+        self.conn.write_vec_lengthed(name.as_bytes())?;
+        self.conn.write_vec_lengthed(role.as_bytes())?;
+        self.conn.write_vec_lengthed(token)?;
+        self.conn.flush()?;
+        self.read_admin_response(message::ADD_USER)
     }
-    pub fn scan_limited(
-        &mut self,
-        start: impl AsRef<[u8]>,
-        end: impl AsRef<[u8]>,
-        max_len: u32,
-    ) -> Result<Vec<(Vec<u8>, Vec<u8>)>, Error> {
-        let start = start.as_ref();
-        let end = end.as_ref();
-        match self.scan_limited_opt(start, end, max_len).transpose() {
-            Some(result) => result,
-            None => Err(LimitExceeded.into()),
+    /// returns false if there was no such user, connections that already authenticated as it keep its role
+    pub fn remove_user(&mut self, name: &str) -> Result<bool, Error> {
+        self.send(message::REMOVE_USER)?;
+        self.conn.write_vec_lengthed(name.as_bytes())?;
+        self.conn.flush()?;
+        self.read_admin_response(message::REMOVE_USER)?;
+        Ok(self.conn.read_u8()? != 0)
+    }
+    /// the name and role of every user, sorted by name
+    pub fn users(&mut self) -> Result<Vec<(String, String)>, Error> {
+        self.send(message::USERS)?;
+        self.conn.flush()?;
+        self.read_admin_response(message::USERS)?;
+        let count = self.conn.read_u32()?;
+        let mut users = Vec::new();
+        for _ in 0..count {
+            let name = self.conn.read_vec_lengthed(u32::MAX)?;
+            let role = self.conn.read_vec_lengthed(u32::MAX)?;
+            users.push((
+                String::from_utf8(name).map_err(|_| Error::Protocol)?,
+                String::from_utf8(role).map_err(|_| Error::Protocol)?,
+            ));
         }
+        Ok(users)
     }
-    pub fn scan_limited_opt(
+    /// sets which role may access the keys starting with `prefix` this way, or removes the rule if `role` is none,
+    /// see [`AclRule`]
+    ///
+    /// the users and the rules themselves are kept in the [`pathkvs_core::system`] keyspace, which only admins may access
+    pub fn set_acl(
         &mut self,
-        start: impl AsRef<[u8]>,
-        end: impl AsRef<[u8]>,
-        max_len: u32,
-    ) -> Result<Option<Vec<(Vec<u8>, Vec<u8>)>>, Error> {
-        let start = start.as_ref();
-        let end = end.as_ref();
-        assert!(start
-            .len()
-            .checked_add(end.len())
-            .is_some_and(|x| x <= u32::MAX as usize));
-        self.conn.write_u8(message::SCAN)?;
-        self.conn.write_u32(start.len() as u32)?;
-        self.conn.write_all(start)?;
-        self.conn.write_u32(end.len() as u32)?;
-        self.conn.write_all(end)?;
-        self.conn.write_u32(max_len)?;
+        prefix: impl AsRef<[u8]>,
+        access: Access,
+        role: Option<&str>,
+    ) -> Result<(), Error> {
+        let prefix = prefix.as_ref();
+        assert!(role != Some(""));
+        self.send(message::SET_ACL)?;
+        self.conn.write_vec_lengthed(prefix)?;
+        self.conn.write_u8(access.to_u8())?;
+        self.conn
+            .write_vec_lengthed(role.unwrap_or_default().as_bytes())?;
         self.conn.flush()?;
-        match self.conn.read_u8()? {
-            message::SCAN => {
-                let mut total = Some(0u32);
-                let mut rows = Vec::new();
-                let rowc = self.conn.read_u32()?;
-                rows.reserve_exact(rowc as usize);
-                for _ in 0..rowc {
-                    let recv_len = self.conn.read_u32()?;
-                    total = total.and_then(|x| x.checked_add(recv_len));
-                    if !total.is_some_and(|total| total <= max_len) {
-                        return Err(ProtocolError.into());
-                    }
-                    let key = self.conn.read_vec(recv_len as usize)?;
-
-                    let recv_len = self.conn.read_u32()?;
-                    total = total.and_then(|x| x.checked_add(recv_len));
-                    if !total.is_some_and(|total| total <= max_len) {
-                        return Err(ProtocolError.into());
-                    }
-                    let value = self.conn.read_vec(recv_len as usize)?;
-
-                    rows.push((key, value));
-                }
-                Ok(Some(rows))
+        self.read_admin_response(message::SET_ACL)
+    }
+    /// every rule, sorted by prefix
+    pub fn acl(&mut self) -> Result<Vec<AclRule>, Error> {
+        self.send(message::ACL)?;
+        self.conn.flush()?;
+        self.read_admin_response(message::ACL)?;
+        let count = self.conn.read_u32()?;
+        let mut rules = Vec::new();
+        for _ in 0..count {
+            let prefix = self.conn.read_vec_lengthed(u32::MAX)?;
+            let access = Access::from_u8(self.conn.read_u8()?).ok_or(Error::Protocol)?;
+            let role = self.conn.read_vec_lengthed(u32::MAX)?;
+            let role = String::from_utf8(role).map_err(|_| Error::Protocol)?;
+            rules.push(AclRule {
+                prefix,
+                access,
+                role,
+            });
+        }
+        Ok(rules)
+    }
+    fn read_procedure_failure(&mut self) -> Result<Error, Error> {
+        let message = self.conn.read_vec_lengthed(u16::MAX as u32)?;
+        let message = String::from_utf8(message).map_err(|_| Error::Protocol)?;
+        Ok(Error::ProcedureFailed(message))
+    }
+    /// starts a request, preceded by the batched writes and the trace id if there are any
+    fn send(&mut self, request: u8) -> Result<(), std::io::Error> {
+        if !self.batch.is_empty() {
+            self.send_one(message::WRITE_MANY)?;
+            self.conn.write_u32(self.batch.len() as u32)?;
+            for (key, value) in self.batch.drain() {
+                self.conn.write_vec_lengthed(&key)?;
+                self.conn.write_vec_lengthed(&value)?;
             }
-            message::LIMIT_EXCEEDED => Ok(None),
-            _ => Err(ProtocolError.into()),
+            self.batch_sent = true;
         }
+        self.send_one(request)
     }
-    pub fn start_snapshot(&mut self, prev_time: Option<SystemTime>) -> Result<(), Error> {
-        self.conn.write_u8(message::START_SNAPSHOT)?;
-        self.conn.write_duration(
-            prev_time
-                .map(|x| {
-                    x.duration_since(SystemTime::UNIX_EPOCH)
-                        .expect("prev_time must be after the unix epoch")
-                })
-                .unwrap_or_default(),
-        )?;
-        self.conn.flush()?;
-        if self.conn.read_u8()? != message::START_SNAPSHOT {
-            return Err(ProtocolError.into());
+    fn send_one(&mut self, request: u8) -> Result<(), std::io::Error> {
+        if let Some(trace_id) = &self.trace_id {
+            self.conn.write_u8(message::TRACE)?;
+            self.conn.write_vec_lengthed(trace_id)?;
+        }
+        self.conn.write_u8(request)
+    }
+    /// reads the first byte of the response, after the response to the batched writes
+    /// and the echo of the trace id if there are any
+    fn response(&mut self) -> Result<u8, std::io::Error> {
+        if std::mem::take(&mut self.batch_sent) {
+            match self.response_one()? {
+                message::WRITE_MANY => {}
+                message::BUSY => return Err(Error::Busy.into()),
+                message::UNAUTHORIZED => return Err(Error::Unauthorized.into()),
+                _ => return Err(ProtocolError.into()),
+            }
+        }
+        // any request may be refused by the access control list
+        match self.response_one()? {
+            message::UNAUTHORIZED => Err(Error::Unauthorized.into()),
+            response => Ok(response),
+        }
+    }
+    fn response_one(&mut self) -> Result<u8, std::io::Error> {
+        if let Some(trace_id) = &self.trace_id {
+            if self.conn.read_u8()? != message::TRACE
+                || self.conn.read_vec_lengthed(message::MAX_TRACE_ID_LEN)? != *trace_id
+            {
+                return Err(ProtocolError.into());
+            }
+        }
+        self.conn.read_u8()
+    }
+    fn read_admin_response(&mut self, expected: u8) -> Result<(), Error> {
+        match self.response()? {
+            response if response == expected => Ok(()),
+            message::UNAUTHORIZED => Err(Error::Unauthorized),
+            message::UNSUPPORTED => Err(Error::Unsupported),
+            _ => Err(Error::Protocol),
         }
-        self.mode = ConnectionMode::Snapshot;
-        Ok(())
     }
 
     pub fn read_str(&mut self, key: impl AsRef<[u8]>) -> Result<String, Error> {
@@ -336,10 +2282,8 @@ where
         max_len: u32,
     ) -> Result<String, Error> {
         let key = key.as_ref();
-        match String::from_utf8(self.read_limited(key, max_len)?) {
-            Ok(text) => Ok(text),
-            Err(error) => Err(Error::other(error)),
-        }
+        String::from_utf8(self.read_limited(key, max_len)?)
+            .map_err(|_| invalid("expected utf-8 string"))
     }
 
     pub fn read_u8_bin(&mut self, key: impl AsRef<[u8]>) -> Result<u8, Error> {
@@ -413,6 +2357,46 @@ where
         }
     }
 
+    pub fn read_f32_bin(&mut self, key: impl AsRef<[u8]>) -> Result<f32, Error> {
+        let key = key.as_ref();
+        match self.read_limited(key, 4)?.try_into() {
+            Ok(array) => Ok(f32::from_le_bytes(array)),
+            Err(_) => Err(invalid("expected binary f32")),
+        }
+    }
+    pub fn read_f64_bin(&mut self, key: impl AsRef<[u8]>) -> Result<f64, Error> {
+        let key = key.as_ref();
+        match self.read_limited(key, 8)?.try_into() {
+            Ok(array) => Ok(f64::from_le_bytes(array)),
+            Err(_) => Err(invalid("expected binary f64")),
+        }
+    }
+    pub fn read_bool_bin(&mut self, key: impl AsRef<[u8]>) -> Result<bool, Error> {
+        let key = key.as_ref();
+        match self.read_limited(key, 1)?.as_slice() {
+            [0] => Ok(false),
+            [1] => Ok(true),
+            _ => Err(invalid("expected binary bool")),
+        }
+    }
+    #[cfg(feature = "uuid")]
+    pub fn read_uuid_bin(&mut self, key: impl AsRef<[u8]>) -> Result<uuid::Uuid, Error> {
+        let key = key.as_ref();
+        match self.read_limited(key, 16)?.try_into() {
+            Ok(array) => Ok(uuid::Uuid::from_bytes(array)),
+            Err(_) => Err(invalid("expected binary uuid")),
+        }
+    }
+    pub fn read_timestamp_bin(&mut self, key: impl AsRef<[u8]>) -> Result<SystemTime, Error> {
+        let key = key.as_ref();
+        match self.read_limited(key, 12)?.try_into() {
+            Ok(array) => {
+                timestamp_from_bytes(array).ok_or_else(|| invalid("expected binary timestamp"))
+            }
+            Err(_) => Err(invalid("expected binary timestamp")),
+        }
+    }
+
     pub fn read_u8(&mut self, key: impl AsRef<[u8]>) -> Result<u8, Error> {
         let key = key.as_ref();
         let bytes = self.read_limited_opt(key, 3)?;
@@ -504,6 +2488,53 @@ where
             .ok_or_else(|| invalid("expected i128"))
     }
 
+    pub fn read_f32(&mut self, key: impl AsRef<[u8]>) -> Result<f32, Error> {
+        let key = key.as_ref();
+        let bytes = self.read_limited_opt(key, 64)?;
+        bytes
+            .as_deref()
+            .and_then(|x| std::str::from_utf8(x).ok())
+            .and_then(|x| x.parse().ok())
+            .ok_or_else(|| invalid("expected f32"))
+    }
+    pub fn read_f64(&mut self, key: impl AsRef<[u8]>) -> Result<f64, Error> {
+        let key = key.as_ref();
+        let bytes = self.read_limited_opt(key, 400)?;
+        bytes
+            .as_deref()
+            .and_then(|x| std::str::from_utf8(x).ok())
+            .and_then(|x| x.parse().ok())
+            .ok_or_else(|| invalid("expected f64"))
+    }
+    pub fn read_bool(&mut self, key: impl AsRef<[u8]>) -> Result<bool, Error> {
+        let key = key.as_ref();
+        let bytes = self.read_limited_opt(key, 5)?;
+        bytes
+            .as_deref()
+            .and_then(|x| std::str::from_utf8(x).ok())
+            .and_then(|x| x.parse().ok())
+            .ok_or_else(|| invalid("expected bool"))
+    }
+    #[cfg(feature = "uuid")]
+    pub fn read_uuid(&mut self, key: impl AsRef<[u8]>) -> Result<uuid::Uuid, Error> {
+        let key = key.as_ref();
+        let bytes = self.read_limited_opt(key, 45)?;
+        bytes
+            .as_deref()
+            .and_then(|x| std::str::from_utf8(x).ok())
+            .and_then(|x| uuid::Uuid::parse_str(x).ok())
+            .ok_or_else(|| invalid("expected uuid"))
+    }
+    pub fn read_timestamp(&mut self, key: impl AsRef<[u8]>) -> Result<SystemTime, Error> {
+        let key = key.as_ref();
+        let bytes = self.read_limited_opt(key, 30)?;
+        bytes
+            .as_deref()
+            .and_then(|x| std::str::from_utf8(x).ok())
+            .and_then(parse_timestamp)
+            .ok_or_else(|| invalid("expected timestamp"))
+    }
+
     pub fn read_u8_bin_opt(&mut self, key: impl AsRef<[u8]>) -> Result<Option<u8>, Error> {
         let key = key.as_ref();
         match self.read_limited(key, 1)?.try_into() {
@@ -585,6 +2616,57 @@ where
         }
     }
 
+    pub fn read_f32_bin_opt(&mut self, key: impl AsRef<[u8]>) -> Result<Option<f32>, Error> {
+        let key = key.as_ref();
+        match self.read_limited(key, 4)?.try_into() {
+            Ok(array) => Ok(Some(f32::from_le_bytes(array))),
+            Err(error) if error.is_empty() => Ok(None),
+            Err(_) => Err(invalid("expected binary f32")),
+        }
+    }
+    pub fn read_f64_bin_opt(&mut self, key: impl AsRef<[u8]>) -> Result<Option<f64>, Error> {
+        let key = key.as_ref();
+        match self.read_limited(key, 8)?.try_into() {
+            Ok(array) => Ok(Some(f64::from_le_bytes(array))),
+            Err(error) if error.is_empty() => Ok(None),
+            Err(_) => Err(invalid("expected binary f64")),
+        }
+    }
+    pub fn read_bool_bin_opt(&mut self, key: impl AsRef<[u8]>) -> Result<Option<bool>, Error> {
+        let key = key.as_ref();
+        match self.read_limited(key, 1)?.as_slice() {
+            [] => Ok(None),
+            [0] => Ok(Some(false)),
+            [1] => Ok(Some(true)),
+            _ => Err(invalid("expected binary bool")),
+        }
+    }
+    #[cfg(feature = "uuid")]
+    pub fn read_uuid_bin_opt(
+        &mut self,
+        key: impl AsRef<[u8]>,
+    ) -> Result<Option<uuid::Uuid>, Error> {
+        let key = key.as_ref();
+        match self.read_limited(key, 16)?.try_into() {
+            Ok(array) => Ok(Some(uuid::Uuid::from_bytes(array))),
+            Err(error) if error.is_empty() => Ok(None),
+            Err(_) => Err(invalid("expected binary uuid")),
+        }
+    }
+    pub fn read_timestamp_bin_opt(
+        &mut self,
+        key: impl AsRef<[u8]>,
+    ) -> Result<Option<SystemTime>, Error> {
+        let key = key.as_ref();
+        match self.read_limited(key, 12)?.try_into() {
+            Ok(array) => timestamp_from_bytes(array)
+                .ok_or_else(|| invalid("expected binary timestamp"))
+                .map(Some),
+            Err(error) if error.is_empty() => Ok(None),
+            Err(_) => Err(invalid("expected binary timestamp")),
+        }
+    }
+
     pub fn read_u8_opt(&mut self, key: impl AsRef<[u8]>) -> Result<Option<u8>, Error> {
         let key = key.as_ref();
         let bytes = self.read_limited_opt(key, 3)?;
@@ -716,8 +2798,79 @@ where
             .map(Some)
     }
 
+    pub fn read_f32_opt(&mut self, key: impl AsRef<[u8]>) -> Result<Option<f32>, Error> {
+        let key = key.as_ref();
+        let bytes = self.read_limited_opt(key, 64)?;
+        if bytes.as_ref().is_some_and(|x| x.is_empty()) {
+            return Ok(None);
+        }
+        bytes
+            .as_deref()
+            .and_then(|x| std::str::from_utf8(x).ok())
+            .and_then(|x| x.parse().ok())
+            .ok_or_else(|| invalid("expected f32"))
+            .map(Some)
+    }
+    pub fn read_f64_opt(&mut self, key: impl AsRef<[u8]>) -> Result<Option<f64>, Error> {
+        let key = key.as_ref();
+        let bytes = self.read_limited_opt(key, 400)?;
+        if bytes.as_ref().is_some_and(|x| x.is_empty()) {
+            return Ok(None);
+        }
+        bytes
+            .as_deref()
+            .and_then(|x| std::str::from_utf8(x).ok())
+            .and_then(|x| x.parse().ok())
+            .ok_or_else(|| invalid("expected f64"))
+            .map(Some)
+    }
+    pub fn read_bool_opt(&mut self, key: impl AsRef<[u8]>) -> Result<Option<bool>, Error> {
+        let key = key.as_ref();
+        let bytes = self.read_limited_opt(key, 5)?;
+        if bytes.as_ref().is_some_and(|x| x.is_empty()) {
+            return Ok(None);
+        }
+        bytes
+            .as_deref()
+            .and_then(|x| std::str::from_utf8(x).ok())
+            .and_then(|x| x.parse().ok())
+            .ok_or_else(|| invalid("expected bool"))
+            .map(Some)
+    }
+    #[cfg(feature = "uuid")]
+    pub fn read_uuid_opt(&mut self, key: impl AsRef<[u8]>) -> Result<Option<uuid::Uuid>, Error> {
+        let key = key.as_ref();
+        let bytes = self.read_limited_opt(key, 45)?;
+        if bytes.as_ref().is_some_and(|x| x.is_empty()) {
+            return Ok(None);
+        }
+        bytes
+            .as_deref()
+            .and_then(|x| std::str::from_utf8(x).ok())
+            .and_then(|x| uuid::Uuid::parse_str(x).ok())
+            .ok_or_else(|| invalid("expected uuid"))
+            .map(Some)
+    }
+    pub fn read_timestamp_opt(
+        &mut self,
+        key: impl AsRef<[u8]>,
+    ) -> Result<Option<SystemTime>, Error> {
+        let key = key.as_ref();
+        let bytes = self.read_limited_opt(key, 30)?;
+        if bytes.as_ref().is_some_and(|x| x.is_empty()) {
+            return Ok(None);
+        }
+        bytes
+            .as_deref()
+            .and_then(|x| std::str::from_utf8(x).ok())
+            .and_then(parse_timestamp)
+            .ok_or_else(|| invalid("expected timestamp"))
+            .map(Some)
+    }
+
+    /// the same as [`Connection::delete`]
     pub fn clear(&mut self, key: impl AsRef<[u8]>) -> Result<(), Error> {
-        self.write(key, &[])
+        self.delete(key)
     }
     pub fn write_u8_bin(&mut self, key: impl AsRef<[u8]>, value: u8) -> Result<(), Error> {
         self.write(key, u8::to_le_bytes(value))
@@ -749,6 +2902,35 @@ where
     pub fn write_i128_bin(&mut self, key: impl AsRef<[u8]>, value: i128) -> Result<(), Error> {
         self.write(key, i128::to_le_bytes(value))
     }
+    pub fn write_f32_bin(&mut self, key: impl AsRef<[u8]>, value: f32) -> Result<(), Error> {
+        self.write(key, f32::to_le_bytes(value))
+    }
+    pub fn write_f64_bin(&mut self, key: impl AsRef<[u8]>, value: f64) -> Result<(), Error> {
+        self.write(key, f64::to_le_bytes(value))
+    }
+    pub fn write_bool_bin(&mut self, key: impl AsRef<[u8]>, value: bool) -> Result<(), Error> {
+        self.write(key, [value as u8])
+    }
+    #[cfg(feature = "uuid")]
+    pub fn write_uuid_bin(
+        &mut self,
+        key: impl AsRef<[u8]>,
+        value: uuid::Uuid,
+    ) -> Result<(), Error> {
+        self.write(key, value.as_bytes())
+    }
+    /// panics if the time is before the unix epoch
+    pub fn write_timestamp_bin(
+        &mut self,
+        key: impl AsRef<[u8]>,
+        value: SystemTime,
+    ) -> Result<(), Error> {
+        let time = since_epoch(value);
+        let mut bytes = [0; 12];
+        bytes[..8].copy_from_slice(&time.as_secs().to_le_bytes());
+        bytes[8..].copy_from_slice(&time.subsec_nanos().to_le_bytes());
+        self.write(key, bytes)
+    }
     pub fn write_fmt(
         &mut self,
         key: impl AsRef<[u8]>,
@@ -789,10 +2971,86 @@ where
     pub fn write_i128(&mut self, key: impl AsRef<[u8]>, value: i128) -> Result<(), Error> {
         self.write_fmt(key, format_args!("{value}"))
     }
+    /// written with the shortest representation that parses back to the same value
+    pub fn write_f32(&mut self, key: impl AsRef<[u8]>, value: f32) -> Result<(), Error> {
+        self.write_fmt(key, format_args!("{value:?}"))
+    }
+    /// written with the shortest representation that parses back to the same value
+    pub fn write_f64(&mut self, key: impl AsRef<[u8]>, value: f64) -> Result<(), Error> {
+        self.write_fmt(key, format_args!("{value:?}"))
+    }
+    pub fn write_bool(&mut self, key: impl AsRef<[u8]>, value: bool) -> Result<(), Error> {
+        self.write_fmt(key, format_args!("{value}"))
+    }
+    /// written in the hyphenated form
+    #[cfg(feature = "uuid")]
+    pub fn write_uuid(&mut self, key: impl AsRef<[u8]>, value: uuid::Uuid) -> Result<(), Error> {
+        self.write_fmt(key, format_args!("{}", value.hyphenated()))
+    }
+    /// written as unix seconds with nanoseconds as the fraction, like `1700000000.250000000`
+    ///
+    /// panics if the time is before the unix epoch
+    pub fn write_timestamp(
+        &mut self,
+        key: impl AsRef<[u8]>,
+        value: SystemTime,
+    ) -> Result<(), Error> {
+        let time = since_epoch(value);
+        self.write_fmt(
+            key,
+            format_args!("{}.{:09}", time.as_secs(), time.subsec_nanos()),
+        )
+    }
 }
 
 fn invalid(description: &'static str) -> Error {
-    Error::new(ErrorKind::InvalidData, description)
+    Error::InvalidValue(description)
+}
+
+impl<T> Subscription<T>
+where
+    T: Read + Write,
+{
+    pub fn get_inner(&mut self) -> &mut T {
+        &mut self.conn
+    }
+    /// blocks until the next message, returns the channel it was published to and the message
+    pub fn next_message(&mut self) -> Result<(Vec<u8>, Vec<u8>), Error> {
+        if self.conn.read_u8()? != message::PUBLISH {
+            return Err(Error::Protocol);
+        }
+        let channel = self.conn.read_vec_lengthed(u32::MAX)?;
+        let message = self.conn.read_vec_lengthed(u32::MAX)?;
+        Ok((channel, message))
+    }
+}
+
+fn since_epoch(time: SystemTime) -> Duration {
+    time.duration_since(SystemTime::UNIX_EPOCH)
+        .expect("pathkvs client: timestamps cannot be before the unix epoch")
+}
+
+fn timestamp_from_bytes(bytes: [u8; 12]) -> Option<SystemTime> {
+    let seconds = u64::from_le_bytes(bytes[..8].try_into().unwrap());
+    let nanoseconds = u32::from_le_bytes(bytes[8..].try_into().unwrap());
+    if nanoseconds >= 1_000_000_000 {
+        return None;
+    }
+    SystemTime::UNIX_EPOCH.checked_add(Duration::new(seconds, nanoseconds))
+}
+
+/// unix seconds with an optional fraction of up to 9 digits
+fn parse_timestamp(text: &str) -> Option<SystemTime> {
+    let (seconds, fraction) = text.split_once('.').unwrap_or((text, ""));
+    if seconds.is_empty() || !seconds.bytes().all(|x| x.is_ascii_digit()) {
+        return None;
+    }
+    if fraction.len() > 9 || !fraction.bytes().all(|x| x.is_ascii_digit()) {
+        return None;
+    }
+    let seconds = seconds.parse().ok()?;
+    let nanoseconds = format!("{fraction:0<9}").parse().ok()?;
+    SystemTime::UNIX_EPOCH.checked_add(Duration::new(seconds, nanoseconds))
 }
 
 const fn buf<const N: usize>() -> BufWriter<N> {
@@ -839,3 +3097,14 @@ impl<const N: usize> std::fmt::Write for BufWriter<N> {
         Ok(())
     }
 }
+
+/// a random duration between half of `backoff` and all of it
+fn jitter(backoff: Duration) -> Duration {
+    use std::hash::{BuildHasher, Hasher};
+
+    // each RandomState is seeded differently, which is random enough to spread retries apart
+    let random = std::collections::hash_map::RandomState::new()
+        .build_hasher()
+        .finish();
+    backoff / 2 + backoff.mul_f64((random % 1024) as f64 / 2048.0)
+}
@@ -0,0 +1,99 @@
+//! compression of the history sent to replicas and backups, see [`crate::client::Connection::negotiate_compression`]
+//!
+//! the history is sent in chunks, and each chunk is compressed on its own, as a zstd frame, so a chunk can be
+//! decompressed as soon as it arrives, commits compress well since the same keys show up in commit after commit
+//!
+//! [`Compression::Zstd`] needs the `zstd` feature, without it only [`Compression::None`] is known
+
+/// the algorithms a client and a server can agree on, in the order a client usually prefers them
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Compression {
+    /// the chunks are sent as they are
+    #[default]
+    None,
+    #[cfg(feature = "zstd")]
+    Zstd,
+}
+
+impl Compression {
+    /// every algorithm this version knows, from the most to the least preferred
+    pub const ALL: &'static [Compression] = &[
+        #[cfg(feature = "zstd")]
+        Compression::Zstd,
+        Compression::None,
+    ];
+
+    pub(crate) const fn to_u8(self) -> u8 {
+        match self {
+            Self::None => 0,
+            #[cfg(feature = "zstd")]
+            Self::Zstd => 1,
+        }
+    }
+    pub(crate) const fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0 => Some(Self::None),
+            #[cfg(feature = "zstd")]
+            1 => Some(Self::Zstd),
+            _ => None,
+        }
+    }
+    /// appends the compressed bytes to `out`, `None` appends them as they are
+    pub fn compress(self, bytes: &[u8], out: &mut Vec<u8>) {
+        match self {
+            Self::None => out.extend_from_slice(bytes),
+            // a chunk that fails to compress is sent as it is, as if it didn't get smaller
+            #[cfg(feature = "zstd")]
+            Self::Zstd => {
+                if let Ok(frame) = zstd::bulk::compress(bytes, zstd::DEFAULT_COMPRESSION_LEVEL) {
+                    out.extend_from_slice(&frame);
+                }
+            }
+        }
+    }
+    /// the bytes given to [`Compression::compress`], which must be `len` bytes long,
+    /// none if the compressed bytes are invalid or don't decompress to that many bytes
+    pub fn decompress(self, compressed: &[u8], len: usize) -> Option<Vec<u8>> {
+        match self {
+            Self::None => (compressed.len() == len).then(|| compressed.to_vec()),
+            #[cfg(feature = "zstd")]
+            Self::Zstd => zstd::bulk::decompress(compressed, len)
+                .ok()
+                .filter(|bytes| bytes.len() == len),
+        }
+    }
+}
+
+#[cfg(all(test, feature = "zstd"))]
+mod tests {
+    use super::*;
+    use crate::message::MAX_CHUNK_LEN;
+
+    fn round_trip(bytes: &[u8]) {
+        let mut compressed = Vec::new();
+        Compression::Zstd.compress(bytes, &mut compressed);
+        assert_eq!(
+            Compression::Zstd.decompress(&compressed, bytes.len()).as_deref(),
+            Some(bytes)
+        );
+    }
+
+    #[test]
+    fn zstd_round_trips() {
+        round_trip(b"");
+        round_trip(b"short");
+        round_trip(&[7; 100_000]);
+        let chunk = (0..MAX_CHUNK_LEN as usize).map(|i| (i * 31 % 251) as u8).collect::<Vec<_>>();
+        round_trip(&chunk);
+    }
+
+    #[test]
+    fn zstd_rejects_corrupt_input() {
+        let bytes = b"commit after commit after commit".repeat(100);
+        let mut compressed = Vec::new();
+        Compression::Zstd.compress(&bytes, &mut compressed);
+        assert_eq!(Compression::Zstd.decompress(&compressed, bytes.len() - 1), None);
+        assert_eq!(Compression::Zstd.decompress(&compressed[..compressed.len() / 2], bytes.len()), None);
+        assert_eq!(Compression::Zstd.decompress(b"not a zstd frame", bytes.len()), None);
+    }
+}
@@ -0,0 +1,117 @@
+use std::io::{self, ErrorKind};
+
+use pathkvs_core::error::{
+    LimitExceeded, NotReplicated, ProtocolError, TransactionConflict, TransactionError,
+    Unauthorized,
+};
+
+/// the cause of a failed client operation
+///
+/// converts to and from [`std::io::Error`] the same way [`pathkvs_core::Error`] does
+#[derive(Debug)]
+pub enum Error {
+    /// the peer sent a message that does not follow the protocol
+    Protocol,
+    Conflict,
+    /// the response would be larger than the maximum length requested, or than the server allows,
+    /// or the connection started as many transactions as the server allows
+    LimitExceeded,
+    /// the command requires an admin connection, or the role of the connection may not access the key,
+    /// see [`crate::client::Connection::authenticate`] and [`crate::client::Connection::set_acl`]
+    Unauthorized,
+    /// the server does not implement the command
+    Unsupported,
+    /// the server is behind persisting commits, the write or commit was not done and can be retried later,
+    /// the transaction stays open so its commit can be retried
+    Busy,
+    /// the commit was done, but not enough replicas acknowledged it before the timeout of the server,
+    /// see [`crate::client::Connection::commit_replicated`], the transaction is over
+    NotReplicated,
+    /// the check with this index of [`crate::client::Connection::check_write`] did not have the expected value,
+    /// nothing was written
+    CheckFailed(usize),
+    /// the stored value is not in the format it was read as
+    InvalidValue(&'static str),
+    /// the procedure could not be parsed or it failed, the message comes from the server
+    ProcedureFailed(String),
+    Io(io::Error),
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Protocol => std::fmt::Display::fmt(&ProtocolError, f),
+            Error::Conflict => std::fmt::Display::fmt(&TransactionConflict, f),
+            Error::LimitExceeded => std::fmt::Display::fmt(&LimitExceeded, f),
+            Error::Unauthorized => std::fmt::Display::fmt(&Unauthorized, f),
+            Error::Unsupported => f.write_str("pathkvs command not supported by the server"),
+            Error::Busy => f.write_str("pathkvs server busy, try again later"),
+            Error::NotReplicated => std::fmt::Display::fmt(&NotReplicated, f),
+            Error::CheckFailed(index) => write!(f, "pathkvs check {index} failed"),
+            Error::InvalidValue(description) => write!(f, "pathkvs invalid value: {description}"),
+            Error::ProcedureFailed(message) => write!(f, "pathkvs procedure failed: {message}"),
+            Error::Io(error) => std::fmt::Display::fmt(error, f),
+        }
+    }
+}
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+impl From<Error> for io::Error {
+    fn from(value: Error) -> Self {
+        match value {
+            Error::Io(error) => error,
+            Error::Unsupported => io::Error::new(ErrorKind::Unsupported, value),
+            Error::InvalidValue(_) => io::Error::new(ErrorKind::InvalidData, value),
+            Error::Busy => io::Error::new(ErrorKind::WouldBlock, value),
+            Error::NotReplicated => io::Error::new(ErrorKind::TimedOut, value),
+            value => io::Error::other(value),
+        }
+    }
+}
+impl From<io::Error> for Error {
+    fn from(value: io::Error) -> Self {
+        if value.get_ref().is_some_and(|inner| inner.is::<Error>()) {
+            let inner = value.into_inner().expect("checked by get_ref");
+            return *inner.downcast::<Error>().expect("checked by is");
+        }
+        pathkvs_core::Error::from(value).into()
+    }
+}
+impl From<pathkvs_core::Error> for Error {
+    fn from(value: pathkvs_core::Error) -> Self {
+        match value {
+            pathkvs_core::Error::Conflict => Error::Conflict,
+            pathkvs_core::Error::LimitExceeded => Error::LimitExceeded,
+            pathkvs_core::Error::Unauthorized => Error::Unauthorized,
+            pathkvs_core::Error::Protocol => Error::Protocol,
+            pathkvs_core::Error::NotReplicated => Error::NotReplicated,
+            pathkvs_core::Error::NotANumber => Error::InvalidValue("not a number, or the sum overflows"),
+            pathkvs_core::Error::Io(error) if error.kind() == ErrorKind::Unsupported => {
+                Error::Unsupported
+            }
+            pathkvs_core::Error::Io(error) => Error::Io(error),
+            value @ (pathkvs_core::Error::Corruption(_) | pathkvs_core::Error::SnapshotExpired) => {
+                Error::Io(value.into())
+            }
+        }
+    }
+}
+impl From<ProtocolError> for Error {
+    fn from(_: ProtocolError) -> Self {
+        Self::Protocol
+    }
+}
+impl From<TransactionError> for Error {
+    fn from(value: TransactionError) -> Self {
+        match value {
+            TransactionError::Conflict => Self::Conflict,
+            TransactionError::Io(error) => error.into(),
+        }
+    }
+}
@@ -0,0 +1,34 @@
+//! glob patterns of channel names, see [`Connection::subscribe_patterns`](crate::client::Connection::subscribe_patterns)
+
+/// whether `name` matches `pattern`, where `*` matches any sequence of bytes, even an empty one,
+/// `?` matches any single byte, and every other byte matches itself
+///
+/// `user:*:email` matches `user:1:email` and `user:a:b:email`, but not `user:1:name`
+pub fn matches(pattern: &[u8], name: &[u8]) -> bool {
+    let mut p = 0;
+    let mut n = 0;
+    // the position of the last `*` in the pattern, and of the byte of the name after what it matched so far
+    let mut star = None;
+    while n < name.len() {
+        match pattern.get(p) {
+            Some(b'*') => {
+                star = Some((p, n));
+                p += 1;
+            }
+            Some(&byte) if byte == b'?' || byte == name[n] => {
+                p += 1;
+                n += 1;
+            }
+            // the last `*` takes one more byte and the rest of the pattern is tried again after it
+            _ => match star {
+                Some((star_p, star_n)) => {
+                    star = Some((star_p, star_n + 1));
+                    p = star_p + 1;
+                    n = star_n + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&byte| byte == b'*')
+}
@@ -1,7 +1,16 @@
 pub mod client;
+pub mod compression;
+pub mod error;
+pub mod glob;
 pub mod server;
+#[cfg(feature = "session")]
+pub mod session;
+pub mod sets;
+pub mod stats;
 mod utils;
 
+pub use error::Error;
+
 mod message {
     pub const LEN: u8 = 1;
     pub const READ: u8 = 2;
@@ -13,6 +22,152 @@ mod message {
     pub const LIST: u8 = 8;
     pub const SCAN: u8 = 9;
     pub const START_SNAPSHOT: u8 = 10;
+    pub const AUTHENTICATE: u8 = 11;
+    pub const STATS: u8 = 12;
+    pub const COMPACT: u8 = 13;
+    pub const GC: u8 = 14;
+    pub const CONNECTIONS: u8 = 15;
+    pub const DRAIN: u8 = 16;
+    pub const BACKUP: u8 = 17;
+    pub const READ_MANY: u8 = 18;
+    pub const EXISTS: u8 = 19;
+    pub const DEFINE_PROCEDURE: u8 = 20;
+    pub const CALL_PROCEDURE: u8 = 21;
+    pub const PUBLISH: u8 = 22;
+    pub const SUBSCRIBE: u8 = 23;
+    pub const INCR_WINDOWED: u8 = 24;
+    pub const SET_READ_CONSISTENCY: u8 = 25;
+    /// precedes a request, with the trace id the server echoes before responding to it
+    pub const TRACE: u8 = 26;
+    pub const RELOAD: u8 = 27;
+    pub const WRITE_MANY: u8 = 28;
+    pub const APPEND: u8 = 29;
+    pub const WRITE_MAX: u8 = 30;
+    pub const WRITE_MIN: u8 = 31;
+    pub const COMMIT_RECEIPT: u8 = 32;
+    pub const START_SEQ_SNAPSHOT: u8 = 33;
+    pub const SUBSCRIBE_PATTERNS: u8 = 34;
+    pub const INFO: u8 = 35;
+    pub const READ_RANGE: u8 = 36;
+    pub const SCAN_MODIFIED_SINCE: u8 = 37;
+    pub const CHECK_WRITE: u8 = 38;
+    pub const VALIDATE: u8 = 39;
+    pub const LEN_MANY: u8 = 40;
+    pub const EXISTS_MANY: u8 = 41;
+    pub const UPGRADE: u8 = 42;
+    pub const RECENT_COMMITS: u8 = 43;
+    pub const ADD_USER: u8 = 44;
+    pub const REMOVE_USER: u8 = 45;
+    pub const USERS: u8 = 46;
+    pub const SET_ACL: u8 = 47;
+    pub const ACL: u8 = 48;
+    pub const SET_NAME: u8 = 49;
+    pub const KILL: u8 = 50;
+    pub const SLOW_LOG: u8 = 51;
+    pub const EXPIRE: u8 = 52;
+    pub const REPLICATION: u8 = 53;
+    pub const ACK_REPLICATION: u8 = 54;
+    pub const COMMIT_REPLICATED: u8 = 55;
+    pub const BACKUP_SNAPSHOT: u8 = 56;
+    pub const BACKUP_SINCE: u8 = 57;
+    pub const NEGOTIATE_COMPRESSION: u8 = 58;
+    pub const INCR: u8 = 59;
+    pub const DELETE: u8 = 60;
+    pub const PING: u8 = 61;
+    /// the value to increment is not a number, or the sum overflows, nothing was written
+    pub const NOT_A_NUMBER: u8 = 248;
+    /// the commit was done but not enough replicas acknowledged it in time
+    pub const NOT_REPLICATED: u8 = 249;
+    /// the server is behind persisting commits and refused a write or commit, it can be retried later
+    pub const BUSY: u8 = 250;
+    pub const PROCEDURE_FAILED: u8 = 251;
+    pub const UNSUPPORTED: u8 = 252;
+    pub const UNAUTHORIZED: u8 = 253;
     pub const LIMIT_EXCEEDED: u8 = 254;
     pub const CONFLICT: u8 = 255;
+
+    pub const MAX_TRACE_ID_LEN: u32 = 64;
+    /// the history sent by BACKUP_SNAPSHOT and BACKUP_SINCE is split in chunks of up to this many bytes
+    pub const MAX_CHUNK_LEN: u32 = 1 << 20;
+    /// precedes a chunk of the history as it is
+    pub const CHUNK: u8 = 1;
+    /// precedes a chunk of the history compressed as agreed with NEGOTIATE_COMPRESSION,
+    /// followed by the algorithm, the length of the chunk and the compressed bytes
+    pub const COMPRESSED_CHUNK: u8 = 2;
+
+    /// the name of a request or status, for logs
+    pub fn name(message: u8) -> &'static str {
+        match message {
+            LEN => "len",
+            READ => "read",
+            WRITE => "write",
+            START_TRANSACTION => "start_transaction",
+            COMMIT => "commit",
+            ROLLBACK => "rollback",
+            COUNT => "count",
+            LIST => "list",
+            SCAN => "scan",
+            START_SNAPSHOT => "start_snapshot",
+            AUTHENTICATE => "authenticate",
+            STATS => "stats",
+            COMPACT => "compact",
+            GC => "gc",
+            CONNECTIONS => "connections",
+            DRAIN => "drain",
+            BACKUP => "backup",
+            READ_MANY => "read_many",
+            EXISTS => "exists",
+            DEFINE_PROCEDURE => "define_procedure",
+            CALL_PROCEDURE => "call_procedure",
+            PUBLISH => "publish",
+            SUBSCRIBE => "subscribe",
+            INCR_WINDOWED => "incr_windowed",
+            SET_READ_CONSISTENCY => "set_read_consistency",
+            TRACE => "trace",
+            RELOAD => "reload",
+            WRITE_MANY => "write_many",
+            APPEND => "append",
+            WRITE_MAX => "write_max",
+            WRITE_MIN => "write_min",
+            COMMIT_RECEIPT => "commit_receipt",
+            START_SEQ_SNAPSHOT => "start_seq_snapshot",
+            SUBSCRIBE_PATTERNS => "subscribe_patterns",
+            INFO => "info",
+            READ_RANGE => "read_range",
+            SCAN_MODIFIED_SINCE => "scan_modified_since",
+            CHECK_WRITE => "check_write",
+            VALIDATE => "validate",
+            LEN_MANY => "len_many",
+            EXISTS_MANY => "exists_many",
+            UPGRADE => "upgrade",
+            RECENT_COMMITS => "recent_commits",
+            ADD_USER => "add_user",
+            REMOVE_USER => "remove_user",
+            USERS => "users",
+            SET_ACL => "set_acl",
+            ACL => "acl",
+            SET_NAME => "set_name",
+            KILL => "kill",
+            SLOW_LOG => "slow_log",
+            EXPIRE => "expire",
+            REPLICATION => "replication",
+            ACK_REPLICATION => "ack_replication",
+            COMMIT_REPLICATED => "commit_replicated",
+            BACKUP_SNAPSHOT => "backup_snapshot",
+            BACKUP_SINCE => "backup_since",
+            NEGOTIATE_COMPRESSION => "negotiate_compression",
+            INCR => "incr",
+            DELETE => "delete",
+            PING => "ping",
+            NOT_A_NUMBER => "not_a_number",
+            NOT_REPLICATED => "not_replicated",
+            BUSY => "busy",
+            PROCEDURE_FAILED => "procedure_failed",
+            UNSUPPORTED => "unsupported",
+            UNAUTHORIZED => "unauthorized",
+            LIMIT_EXCEEDED => "limit_exceeded",
+            CONFLICT => "conflict",
+            _ => "unknown",
+        }
+    }
 }
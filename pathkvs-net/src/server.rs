@@ -1,23 +1,200 @@
 use std::{
     convert::Infallible,
     io::{Error, ErrorKind, Read, Write},
-    time::Duration,
+    time::{Duration, Instant},
 };
 
-use pathkvs_core::error::{ProtocolError, TransactionConflict};
+use pathkvs_core::{
+    error::{NotReplicated, ProtocolError, TransactionConflict},
+    procedure::ProcedureError,
+    replication::ReplicaStatus,
+    system, CommitReceipt, CommitSummary,
+};
 
 use crate::{
+    client::{Access, AclRule, ConnectionInfo, ReadConsistency, SlowCommand, INFO_VERSION},
+    compression::Compression,
     message,
+    stats::CommandEvent,
     utils::{ReadEx, WriteEx},
 };
 
 pub trait Server {
     fn len(&mut self, key: &[u8]) -> Result<u32, Error>;
+    /// whether the key is present, even if its value is empty
+    ///
+    /// the default implementation can't tell an empty value from a missing key, and treats both as missing
+    fn exists(&mut self, key: &[u8]) -> Result<bool, Error> {
+        Ok(self.len(key)? != 0)
+    }
+    /// calls write with the lengths of the values of the keys, in the same order
+    ///
+    /// the default implementation calls [`Server::len`] for each key, override it to read all keys from the same snapshot
+    fn len_many(&mut self, keys: &[Vec<u8>], write: impl FnOnce(&[u32])) -> Result<(), Error> {
+        let mut lens = Vec::with_capacity(keys.len());
+        for key in keys {
+            lens.push(self.len(key)?);
+        }
+        write(&lens);
+        Ok(())
+    }
+    /// calls write with whether each key is present, in the same order
+    ///
+    /// the default implementation calls [`Server::exists`] for each key, override it to read all keys from the same snapshot
+    fn exists_many(&mut self, keys: &[Vec<u8>], write: impl FnOnce(&[bool])) -> Result<(), Error> {
+        let mut exists = Vec::with_capacity(keys.len());
+        for key in keys {
+            exists.push(self.exists(key)?);
+        }
+        write(&exists);
+        Ok(())
+    }
     fn read(&mut self, key: &[u8], write: impl FnOnce(&[u8])) -> Result<(), Error>;
+    /// calls write with up to `len` bytes of the value of the key starting at `offset`,
+    /// see [`pathkvs_core::Snapshot::read_range`]
+    ///
+    /// the default implementation reads the whole value with [`Server::read`]
+    fn read_range(
+        &mut self,
+        key: &[u8],
+        offset: u32,
+        len: u32,
+        write: impl FnOnce(&[u8]),
+    ) -> Result<(), Error> {
+        self.read(key, |bytes| {
+            let start = bytes.len().min(offset as usize);
+            let end = bytes.len().min(start.saturating_add(len as usize));
+            write(&bytes[start..end]);
+        })
+    }
+    /// calls write with the values of the keys, in the same order
+    ///
+    /// the default implementation calls [`Server::read`] for each key, override it to read all keys from the same snapshot
+    fn read_many(&mut self, keys: &[Vec<u8>], write: impl FnOnce(&[&[u8]])) -> Result<(), Error> {
+        let mut values = Vec::with_capacity(keys.len());
+        for key in keys {
+            let mut value = Vec::new();
+            self.read(key, |bytes| value = bytes.to_vec())?;
+            values.push(value);
+        }
+        write(&values.iter().map(Vec::as_slice).collect::<Vec<_>>());
+        Ok(())
+    }
     fn write(&mut self, key: &[u8], value: &[u8]) -> Result<(), Error>;
+    /// removes the key, unlike writing an empty value, see [`pathkvs_core::Transaction::delete`]
+    ///
+    /// return an error of kind [`ErrorKind::Unsupported`] to signal it is not available
+    fn delete(&mut self, _key: &[u8]) -> Result<(), Error> {
+        Err(ErrorKind::Unsupported.into())
+    }
+    /// deletes the key because its time to live ran out, see [`pathkvs_core::Transaction::expire`]
+    ///
+    /// return an error of kind [`ErrorKind::Unsupported`] to signal it is not available
+    fn expire(&mut self, _key: &[u8]) -> Result<(), Error> {
+        Err(ErrorKind::Unsupported.into())
+    }
+    /// writes the keys in order, the client only sends them together in transactions
+    ///
+    /// the default implementation calls [`Server::write`] for each key
+    fn write_many(&mut self, writes: &[(Vec<u8>, Vec<u8>)]) -> Result<(), Error> {
+        for (key, value) in writes {
+            self.write(key, value)?;
+        }
+        Ok(())
+    }
+    /// appends the bytes to the value of the key, without conflicting with other appends to it,
+    /// see [`pathkvs_core::Transaction::append`]
+    ///
+    /// return an error of kind [`ErrorKind::Unsupported`] to signal it is not available
+    fn append(&mut self, _key: &[u8], _bytes: &[u8]) -> Result<(), Error> {
+        Err(ErrorKind::Unsupported.into())
+    }
+    /// writes `n` if it is larger than the number the key has, without conflicting with other merges of it,
+    /// see [`pathkvs_core::Transaction::write_max`]
+    ///
+    /// return an error of kind [`ErrorKind::Unsupported`] to signal it is not available
+    fn write_max(&mut self, _key: &[u8], _n: i64) -> Result<(), Error> {
+        Err(ErrorKind::Unsupported.into())
+    }
+    /// like [`Server::write_max`], but writes `n` if it is smaller, see [`pathkvs_core::Transaction::write_min`]
+    fn write_min(&mut self, _key: &[u8], _n: i64) -> Result<(), Error> {
+        Err(ErrorKind::Unsupported.into())
+    }
+    /// adds `delta` to the number the key has and returns the new value, none if the value is not a number
+    /// or the sum overflows, see [`pathkvs_core::Transaction::incr`]
+    ///
+    /// return an error of kind [`ErrorKind::Unsupported`] to signal it is not available
+    fn incr(&mut self, _key: &[u8], _delta: i64) -> Result<Option<i64>, Error> {
+        Err(ErrorKind::Unsupported.into())
+    }
+    /// adds one to the counter of the current time window of the key and returns the new count,
+    /// see [`pathkvs_core::Transaction::incr_windowed`]
+    ///
+    /// return an error of kind [`ErrorKind::Unsupported`] to signal it is not available
+    fn incr_windowed(&mut self, _key: &[u8], _window: Duration) -> Result<u64, Error> {
+        Err(ErrorKind::Unsupported.into())
+    }
+    /// writes the keys if every checked key has the expected value, none for a missing key, a write of none
+    /// deletes the key, and returns the unix time of the commit, or writes nothing and returns the index of the first check that failed,
+    /// see [`pathkvs_core::Database::check_write`]
+    ///
+    /// it is a transaction of its own, never sent in a transaction or snapshot,
+    /// so conflicts must be retried by the server
+    ///
+    /// return an error of kind [`ErrorKind::Unsupported`] to signal it is not available
+    fn check_write(
+        &mut self,
+        _checks: &[(Vec<u8>, Option<Vec<u8>>)],
+        _writes: &[(Vec<u8>, Option<Vec<u8>>)],
+    ) -> Result<Result<Duration, u32>, Error> {
+        Err(ErrorKind::Unsupported.into())
+    }
     fn start_transaction(&mut self) -> Result<(), Error>;
+    /// checked before each commit and each write outside of a transaction, while it returns true they are
+    /// refused with a status the client can retry, so a server that is behind persisting commits stops taking new ones
+    fn busy(&self) -> bool {
+        false
+    }
     fn commit(&mut self) -> Result<Result<Option<Duration>, TransactionConflict>, Error>;
+    /// like [`Server::commit`], but with the sequence number of the commit and the length of the history
+    /// once it was persisted, see [`pathkvs_core::Transaction::commit_with_receipt`]
+    ///
+    /// return an error of kind [`ErrorKind::Unsupported`] to signal it is not available
+    fn commit_with_receipt(
+        &mut self,
+    ) -> Result<Result<Option<CommitReceipt>, TransactionConflict>, Error> {
+        Err(ErrorKind::Unsupported.into())
+    }
+    /// like [`Server::commit_with_receipt`], but waits for `replicas` replicas to acknowledge the commit
+    /// instead of as many as the server requires by default, see [`pathkvs_core::Database::wait_for_replicas`]
+    ///
+    /// [`Server::commit`] and [`Server::commit_with_receipt`] may wait too, a commit that was done but not acknowledged
+    /// in time is an error carrying [`NotReplicated`], return an error of kind [`ErrorKind::Unsupported`]
+    /// to signal it is not available
+    fn commit_replicated(
+        &mut self,
+        _replicas: u32,
+    ) -> Result<Result<Option<CommitReceipt>, TransactionConflict>, Error> {
+        Err(ErrorKind::Unsupported.into())
+    }
     fn rollback(&mut self) -> Result<(), Error>;
+    /// calls write with the keys that make the current transaction conflict, none if it can still commit
+    /// or if there is no transaction, without committing it, see [`pathkvs_core::Transaction::validate`]
+    ///
+    /// return an error of kind [`ErrorKind::Unsupported`] to signal it is not available
+    fn validate(&mut self, _write: impl FnOnce(&[&[u8]])) -> Result<(), Error> {
+        Err(ErrorKind::Unsupported.into())
+    }
+    /// calls write with the last `limit` commits of the history, newest first, see [`pathkvs_core::Database::recent_commits`]
+    ///
+    /// return an error of kind [`ErrorKind::Unsupported`] to signal it is not available
+    fn recent_commits(
+        &mut self,
+        _limit: u32,
+        _write: impl FnOnce(&[CommitSummary]),
+    ) -> Result<(), Error> {
+        Err(ErrorKind::Unsupported.into())
+    }
     fn count(&mut self, start: &[u8], end: &[u8]) -> Result<u32, Error>;
     fn list(&mut self, start: &[u8], end: &[u8], write: impl FnOnce(&[&[u8]]))
         -> Result<(), Error>;
@@ -27,61 +204,485 @@ pub trait Server {
         end: &[u8],
         write: impl FnOnce(&[(&[u8], &[u8])]),
     ) -> Result<(), Error>;
+    /// like [`Server::scan`], but only the keys changed after the unix time `since`, deleted keys included
+    /// with an empty value, see [`pathkvs_core::Snapshot::scan_modified_since`]
+    ///
+    /// return an error of kind [`ErrorKind::Unsupported`] to signal it is not available
+    fn scan_modified_since(
+        &mut self,
+        _start: &[u8],
+        _end: &[u8],
+        _since: Duration,
+        _write: impl FnOnce(&[(&[u8], &[u8])]),
+    ) -> Result<(), Error> {
+        Err(ErrorKind::Unsupported.into())
+    }
     fn start_snapshot(&mut self, past_unix_time: Option<Duration>) -> Result<(), Error>;
+    /// starts a snapshot after the first `seq` commits of the history, see [`pathkvs_core::Database::past_seq_snapshot`]
+    ///
+    /// return an error of kind [`ErrorKind::Unsupported`] to signal it is not available
+    fn start_seq_snapshot(&mut self, _seq: u64) -> Result<(), Error> {
+        Err(ErrorKind::Unsupported.into())
+    }
+    /// changes what reads see outside of transactions and snapshots, see [`ReadConsistency`]
+    ///
+    /// return an error of kind [`ErrorKind::Unsupported`] to signal it is not available
+    fn set_read_consistency(&mut self, _consistency: ReadConsistency) -> Result<(), Error> {
+        Err(ErrorKind::Unsupported.into())
+    }
+    /// calls write with the unix time of a consistent snapshot and all of its keys and values
+    fn backup(&mut self, write: impl FnOnce(Duration, &[(&[u8], &[u8])])) -> Result<(), Error> {
+        write(Duration::default(), &[]);
+        Ok(())
+    }
+
+    /// runs the procedure registered with the name, in the current transaction, or in a transaction of its own
+    /// if there is none, and calls write with its result
+    ///
+    /// return an error of kind [`ErrorKind::PermissionDenied`] if it touched a key the connection may not access
+    ///
+    /// return an error of kind [`ErrorKind::Unsupported`] to signal it is not available
+    fn call_procedure(
+        &mut self,
+        _name: &str,
+        _args: &[Vec<u8>],
+        _write: impl FnOnce(&[u8]),
+    ) -> Result<Result<(), ProcedureError>, Error> {
+        Err(ErrorKind::Unsupported.into())
+    }
+
+    /// sends the message to every connection subscribed to the channel, returns how many received it
+    ///
+    /// channels are independent from the keys, publishing does not touch the database
+    ///
+    /// return an error of kind [`ErrorKind::Unsupported`] to signal it is not available
+    fn publish(&mut self, _channel: &[u8], _message: &[u8]) -> Result<u32, Error> {
+        Err(ErrorKind::Unsupported.into())
+    }
+    /// subscribes to the channels and returns the messages published to them, as channel and message pairs
+    ///
+    /// the subscription must be in place when this returns, the iterator blocks until the next message
+    /// and is dropped when the subscriber goes away
+    ///
+    /// return an error of kind [`ErrorKind::Unsupported`] to signal it is not available
+    fn subscribe(
+        &mut self,
+        _channels: &[Vec<u8>],
+    ) -> Result<impl Iterator<Item = (Vec<u8>, Vec<u8>)>, Error> {
+        Err::<std::iter::Empty<_>, _>(ErrorKind::Unsupported.into())
+    }
+    /// like [`Server::subscribe`], but to every channel whose name matches one of the patterns,
+    /// see [`crate::glob::matches`], each message is returned once, even if it matches many patterns
+    ///
+    /// return an error of kind [`ErrorKind::Unsupported`] to signal it is not available
+    fn subscribe_patterns(
+        &mut self,
+        _patterns: &[Vec<u8>],
+    ) -> Result<impl Iterator<Item = (Vec<u8>, Vec<u8>)>, Error> {
+        Err::<std::iter::Empty<_>, _>(ErrorKind::Unsupported.into())
+    }
 
     fn max_len(&self) -> u32 {
         u32::MAX
     }
+    /// keys, and the start and end of ranges, longer than this close the connection, like lengths above [`Server::max_len`]
+    fn max_key_len(&self) -> u32 {
+        self.max_len()
+    }
+    /// values written longer than this close the connection, like lengths above [`Server::max_len`]
+    fn max_value_len(&self) -> u32 {
+        self.max_len()
+    }
+    /// list and scan respond with [`message::LIMIT_EXCEEDED`] past this many bytes, as if the client had asked for less
+    fn max_scan_bytes(&self) -> u32 {
+        u32::MAX
+    }
+    /// how many transactions a connection can start, past it they are refused with [`message::LIMIT_EXCEEDED`]
+    fn max_transactions(&self) -> u64 {
+        u64::MAX
+    }
+
+    /// return an error of kind [`ErrorKind::Unsupported`] to signal it is not available
+    fn set_name(&mut self, _name: &str) -> Result<(), Error> {
+        Err(ErrorKind::Unsupported.into())
+    }
+    /// returns true if the token grants the admin role, or the role of a user, to this connection
+    fn authenticate(&mut self, _token: &[u8]) -> Result<bool, Error> {
+        Ok(false)
+    }
+    /// admin commands are only dispatched if this returns true
+    fn is_admin(&self) -> bool {
+        false
+    }
+    /// requests that access `key` this way are answered with unauthorized if this returns false,
+    /// for a range only its start is given to this, see [`Server::permitted_range`]
+    fn permitted(&self, _key: &[u8], _access: Access) -> bool {
+        true
+    }
+    /// requests that read the keys starting with `start` are answered with unauthorized if this returns false
+    fn permitted_range(&self, _start: &[u8]) -> bool {
+        true
+    }
+    fn stats(&mut self, write: impl FnOnce(&[(&str, u64)])) -> Result<(), Error> {
+        write(&[]);
+        Ok(())
+    }
+    /// calls write with named sections of named counters, see [`crate::client::Info`] for the sections
+    /// and counters of [`crate::client::INFO_VERSION`]
+    ///
+    /// the default implementation sends [`Server::stats`] as a single `stats` section
+    fn info(&mut self, write: impl FnOnce(&[(&str, &[(&str, u64)])])) -> Result<(), Error> {
+        self.stats(|stats| write(&[("stats", stats)]))
+    }
+    /// called when [`serve`] starts serving the connection
+    fn on_connect(&mut self) {}
+    /// called after each request, see [`crate::stats::CommandStats`] to aggregate them
+    fn on_command(&mut self, _command: &CommandEvent) {}
+    /// called when [`serve`] stops serving the connection, because the client went away or because of an error
+    fn on_disconnect(&mut self) {}
+    /// called after each request the client sent with a trace id, with the name of the request
+    /// and the time since its trace id arrived, so slow requests can be correlated with the client's traces
+    fn traced(&mut self, _trace_id: &[u8], _request: &'static str, _elapsed: Duration) {}
+    /// rewrite the history of the database with only the latest value of each key, keeping
+    /// the commits of the last `age` as they are, return an error of kind [`ErrorKind::Unsupported`]
+    /// to signal it is not available
+    fn compact(&mut self, _age: Duration) -> Result<(), Error> {
+        Err(ErrorKind::Unsupported.into())
+    }
+    /// return an error of kind [`ErrorKind::Unsupported`] to signal it is not available
+    fn gc(&mut self) -> Result<(), Error> {
+        Err(ErrorKind::Unsupported.into())
+    }
+    fn connections(&mut self, write: impl FnOnce(&[ConnectionInfo])) -> Result<(), Error> {
+        write(&[]);
+        Ok(())
+    }
+    /// return an error of kind [`ErrorKind::Unsupported`] to signal it is not available
+    fn drain(&mut self) -> Result<(), Error> {
+        Err(ErrorKind::Unsupported.into())
+    }
+    /// hands the listeners over to a new process of the server and drains this one,
+    /// so it can be replaced without refusing connections
+    ///
+    /// return an error of kind [`ErrorKind::Unsupported`] to signal it is not available
+    fn upgrade(&mut self) -> Result<(), Error> {
+        Err(ErrorKind::Unsupported.into())
+    }
+    /// reads the configuration again and applies it without dropping connections, or returns why it was rejected
+    ///
+    /// return an error of kind [`ErrorKind::Unsupported`] to signal it is not available
+    fn reload(&mut self) -> Result<Result<(), String>, Error> {
+        Err(ErrorKind::Unsupported.into())
+    }
+    /// parses the procedure and registers it under the name, replacing the previous one
+    ///
+    /// return an error of kind [`ErrorKind::Unsupported`] to signal it is not available
+    fn define_procedure(
+        &mut self,
+        _name: &str,
+        _source: &str,
+    ) -> Result<Result<(), ProcedureError>, Error> {
+        Err(ErrorKind::Unsupported.into())
+    }
+    /// return an error of kind [`ErrorKind::Unsupported`] to signal it is not available
+    fn add_user(&mut self, _name: &str, _role: &str, _token: &[u8]) -> Result<(), Error> {
+        Err(ErrorKind::Unsupported.into())
+    }
+    /// returns false if there was no such user
+    ///
+    /// return an error of kind [`ErrorKind::Unsupported`] to signal it is not available
+    fn remove_user(&mut self, _name: &str) -> Result<bool, Error> {
+        Err(ErrorKind::Unsupported.into())
+    }
+    /// the most recent requests that took longer than the threshold of the server, the newest first
+    ///
+    /// return an error of kind [`ErrorKind::Unsupported`] to signal it is not available
+    fn slow_log(&mut self, _write: impl FnOnce(&[SlowCommand])) -> Result<(), Error> {
+        Err(ErrorKind::Unsupported.into())
+    }
+    /// every replica that acknowledged a position, by name, see [`pathkvs_core::Database::replication_status`]
+    ///
+    /// return an error of kind [`ErrorKind::Unsupported`] to signal it is not available
+    fn replication_status(&mut self, _write: impl FnOnce(&[ReplicaStatus])) -> Result<(), Error> {
+        Err(ErrorKind::Unsupported.into())
+    }
+    /// calls write with the sequence number and the bytes of [`pathkvs_core::Database::backup_snapshot`],
+    /// the state a new replica starts from
+    ///
+    /// return an error of kind [`ErrorKind::Unsupported`] to signal it is not available
+    fn backup_snapshot(&mut self, _write: impl FnOnce(u64, &[u8])) -> Result<(), Error> {
+        Err(ErrorKind::Unsupported.into())
+    }
+    /// calls write with the sequence number and the bytes of [`pathkvs_core::Database::backup_since`],
+    /// the commits a replica at `seq` has yet to apply
+    ///
+    /// return an error of kind [`ErrorKind::InvalidInput`] if `seq` is past the end of the history,
+    /// and of kind [`ErrorKind::Unsupported`] to signal it is not available
+    fn backup_since(&mut self, _seq: u64, _write: impl FnOnce(u64, &[u8])) -> Result<(), Error> {
+        Err(ErrorKind::Unsupported.into())
+    }
+    /// see [`pathkvs_core::Database::ack_replication`]
+    ///
+    /// return an error of kind [`ErrorKind::Unsupported`] to signal it is not available
+    fn ack_replication(&mut self, _name: &str, _seq: u64) -> Result<(), Error> {
+        Err(ErrorKind::Unsupported.into())
+    }
+    /// ends the connection with the id, rolling back its transaction, returns false if there is no such connection
+    /// or it can't be ended
+    ///
+    /// return an error of kind [`ErrorKind::Unsupported`] to signal it is not available
+    fn kill(&mut self, _id: u64) -> Result<bool, Error> {
+        Err(ErrorKind::Unsupported.into())
+    }
+    /// the name and role of every user
+    ///
+    /// return an error of kind [`ErrorKind::Unsupported`] to signal it is not available
+    fn users(&mut self, _write: impl FnOnce(&[(&str, &str)])) -> Result<(), Error> {
+        Err(ErrorKind::Unsupported.into())
+    }
+    /// removes the rule if `role` is none
+    ///
+    /// return an error of kind [`ErrorKind::Unsupported`] to signal it is not available
+    fn set_acl(
+        &mut self,
+        _prefix: &[u8],
+        _access: Access,
+        _role: Option<&str>,
+    ) -> Result<(), Error> {
+        Err(ErrorKind::Unsupported.into())
+    }
+    /// return an error of kind [`ErrorKind::Unsupported`] to signal it is not available
+    fn acl(&mut self, _write: impl FnOnce(&[AclRule])) -> Result<(), Error> {
+        Err(ErrorKind::Unsupported.into())
+    }
 }
 
 pub fn serve<T>(stream: &mut T, server: &mut impl Server) -> Result<(), Error>
 where
     T: Read + Write,
 {
-    match serve_indefinite(stream, server) {
+    let mut stream = Counted {
+        inner: stream,
+        bytes: 0,
+        key_bytes: 0,
+        status: None,
+    };
+    server.on_connect();
+    let result = serve_indefinite(&mut stream, server);
+    server.on_disconnect();
+    match result {
         Ok(infallible) => match infallible {},
         Err(error) if error.kind() == ErrorKind::ConnectionReset => Ok(()),
         Err(error) if error.kind() == ErrorKind::UnexpectedEof => Ok(()),
         Err(error) => Err(error),
     }
 }
-fn serve_indefinite<T>(stream: &mut T, server: &mut impl Server) -> Result<Infallible, Error>
+fn serve_indefinite<T>(
+    stream: &mut Counted<'_, T>,
+    server: &mut impl Server,
+) -> Result<Infallible, Error>
 where
     T: Read + Write,
 {
     let mut readonly = false;
+    // writes in a transaction are only throttled by its commit, they don't reach the database before it
+    let mut transaction = false;
+    let mut transactions = 0u64;
+    let mut trace = None;
+    // how the history sent to replicas and backups is compressed
+    let mut compression = Compression::None;
     loop {
+        let bytes = stream.bytes;
+        stream.key_bytes = 0;
+        stream.status = None;
         let mut recv_command = [0];
         stream.read_exact(&mut recv_command)?;
+        let started = Instant::now();
+        if recv_command[0] == message::TRACE {
+            let trace_id = stream.read_vec_lengthed(message::MAX_TRACE_ID_LEN)?;
+            stream.write_u8(message::TRACE)?;
+            stream.write_vec_lengthed(&trace_id)?;
+            trace = Some((trace_id, Instant::now()));
+            continue;
+        }
+        let traced = trace.take();
         match recv_command[0] {
+            message::PING => stream.write_u8(message::PING)?,
             message::LEN => {
-                let max_len = server.max_len();
-                let key = stream.read_vec_lengthed(max_len)?;
-                let len = server.len(&key)?;
-                stream.write_u8(message::LEN)?;
-                stream.write_u32(len)?;
+                let max_key_len = server.max_key_len();
+                let key = stream.read_key(max_key_len)?;
+                if !server.permitted(&key, Access::Read) {
+                    stream.write_u8(message::UNAUTHORIZED)?;
+                } else {
+                    let len = server.len(&key)?;
+                    stream.write_u8(message::LEN)?;
+                    stream.write_u32(len)?;
+                }
+            }
+            message::EXISTS => {
+                let max_key_len = server.max_key_len();
+                let key = stream.read_key(max_key_len)?;
+                if !server.permitted(&key, Access::Read) {
+                    stream.write_u8(message::UNAUTHORIZED)?;
+                } else {
+                    let exists = server.exists(&key)?;
+                    stream.write_u8(message::EXISTS)?;
+                    stream.write_u8(exists as u8)?;
+                }
+            }
+            request @ (message::LEN_MANY | message::EXISTS_MANY) => {
+                let max_key_len = server.max_key_len();
+                let count = stream.read_u32()?;
+                let mut keys = Vec::new();
+                for _ in 0..count {
+                    keys.push(stream.read_key(max_key_len)?);
+                }
+                if !keys.iter().all(|key| server.permitted(key, Access::Read)) {
+                    stream.write_u8(message::UNAUTHORIZED)?;
+                } else {
+                    let mut result = None;
+                    let mut respond = |len: usize, bytes: Vec<u8>| {
+                        result = Some((|| {
+                            if len != keys.len() {
+                                return Err(Error::other(format!(
+                                    "{} returned the wrong number of values",
+                                    message::name(request)
+                                )));
+                            }
+                            stream.write_u8(request)?;
+                            stream.write_u32(count)?;
+                            stream.write_all(&bytes)
+                        })());
+                    };
+                    if request == message::LEN_MANY {
+                        server.len_many(&keys, |lens| {
+                            respond(
+                                lens.len(),
+                                lens.iter().flat_map(|len| len.to_le_bytes()).collect(),
+                            )
+                        })?;
+                    } else {
+                        // one bit per key, the first key is the lowest bit of the first byte
+                        server.exists_many(&keys, |exists| {
+                            let mut bitmap = vec![0u8; exists.len().div_ceil(8)];
+                            for (index, _) in
+                                exists.iter().enumerate().filter(|(_, exists)| **exists)
+                            {
+                                bitmap[index / 8] |= 1 << (index % 8);
+                            }
+                            respond(exists.len(), bitmap)
+                        })?;
+                    }
+                    match result {
+                        Some(result) => result?,
+                        None => {
+                            let len = match request {
+                                message::LEN_MANY => count as usize * 4,
+                                _ => (count as usize).div_ceil(8),
+                            };
+                            stream.write_u8(request)?;
+                            stream.write_u32(count)?;
+                            stream.write_all(&vec![0; len])?;
+                        }
+                    }
+                }
             }
             message::READ => {
-                let max_len = server.max_len();
-                let key = stream.read_vec_lengthed(max_len)?;
+                let max_key_len = server.max_key_len();
+                let key = stream.read_key(max_key_len)?;
                 let client_max_len = stream.read_u32()?;
-                let mut result = None;
-                server.read(&key, |bytes| {
-                    result = Some((|| {
-                        if bytes.len() <= client_max_len as usize {
+                if !server.permitted(&key, Access::Read) {
+                    stream.write_u8(message::UNAUTHORIZED)?;
+                } else {
+                    let mut result = None;
+                    server.read(&key, |bytes| {
+                        result = Some((|| {
+                            if bytes.len() <= client_max_len as usize {
+                                stream.write_u8(message::READ)?;
+                                stream.write_vec_lengthed(bytes)?;
+                            } else {
+                                stream.write_u8(message::LIMIT_EXCEEDED)?;
+                            }
+                            Ok::<_, Error>(())
+                        })());
+                    })?;
+                    match result {
+                        Some(result) => result?,
+                        None => {
                             stream.write_u8(message::READ)?;
+                            stream.write_u32(0)?;
+                        }
+                    }
+                }
+            }
+            message::READ_RANGE => {
+                let max_key_len = server.max_key_len();
+                let key = stream.read_key(max_key_len)?;
+                let offset = stream.read_u32()?;
+                let len = stream.read_u32()?;
+                if !server.permitted(&key, Access::Read) {
+                    stream.write_u8(message::UNAUTHORIZED)?;
+                } else {
+                    let mut result = None;
+                    server.read_range(&key, offset, len, |bytes| {
+                        result = Some((|| {
+                            stream.write_u8(message::READ_RANGE)?;
                             stream.write_vec_lengthed(bytes)?;
-                        } else {
-                            stream.write_u8(message::LIMIT_EXCEEDED)?;
+                            Ok::<_, Error>(())
+                        })());
+                    })?;
+                    match result {
+                        Some(result) => result?,
+                        None => {
+                            stream.write_u8(message::READ_RANGE)?;
+                            stream.write_u32(0)?;
+                        }
+                    }
+                }
+            }
+            message::READ_MANY => {
+                let max_key_len = server.max_key_len();
+                let count = stream.read_u32()?;
+                let mut keys = Vec::new();
+                for _ in 0..count {
+                    keys.push(stream.read_key(max_key_len)?);
+                }
+                let client_max_len = stream.read_u32()?;
+                if !keys.iter().all(|key| server.permitted(key, Access::Read)) {
+                    stream.write_u8(message::UNAUTHORIZED)?;
+                } else {
+                    let mut result = None;
+                    server.read_many(&keys, |values| {
+                        result = Some((|| {
+                            if values.len() != keys.len() {
+                                return Err(Error::other(
+                                    "read_many returned the wrong number of values",
+                                ));
+                            }
+                            let total = values
+                                .iter()
+                                .try_fold(0usize, |acc, x| acc.checked_add(x.len()));
+                            if total.is_some_and(|x| x <= client_max_len as usize) {
+                                stream.write_u8(message::READ_MANY)?;
+                                stream.write_u32(values.len() as u32)?;
+                                for value in values {
+                                    stream.write_vec_lengthed(value)?;
+                                }
+                            } else {
+                                stream.write_u8(message::LIMIT_EXCEEDED)?;
+                            }
+                            Ok::<_, Error>(())
+                        })());
+                    })?;
+                    match result {
+                        Some(result) => result?,
+                        None => {
+                            stream.write_u8(message::READ_MANY)?;
+                            stream.write_u32(count)?;
+                            for _ in 0..count {
+                                stream.write_u32(0)?;
+                            }
                         }
-                        Ok::<_, Error>(())
-                    })());
-                })?;
-                match result {
-                    Some(result) => result?,
-                    None => {
-                        stream.write_u8(message::READ)?;
-                        stream.write_u32(0)?;
                     }
                 }
             }
@@ -89,62 +690,811 @@ where
                 if readonly {
                     return Err(ProtocolError.into());
                 }
-                let max_len = server.max_len();
-                let key = stream.read_vec_lengthed(max_len)?;
-                let value = stream.read_vec_lengthed(max_len)?;
-                server.write(&key, &value)?;
-                stream.write_u8(message::WRITE)?;
+                let max_key_len = server.max_key_len();
+                let max_value_len = server.max_value_len();
+                let key = stream.read_key(max_key_len)?;
+                let value = stream.read_vec_lengthed(max_value_len)?;
+                if !server.permitted(&key, Access::Write) {
+                    stream.write_u8(message::UNAUTHORIZED)?;
+                } else if !transaction && server.busy() {
+                    stream.write_u8(message::BUSY)?;
+                } else {
+                    server.write(&key, &value)?;
+                    stream.write_u8(message::WRITE)?;
+                }
+            }
+            request @ (message::DELETE | message::EXPIRE) => {
+                if readonly {
+                    return Err(ProtocolError.into());
+                }
+                let key = stream.read_key(server.max_key_len())?;
+                if !server.permitted(&key, Access::Write) {
+                    stream.write_u8(message::UNAUTHORIZED)?;
+                } else if !transaction && server.busy() {
+                    stream.write_u8(message::BUSY)?;
+                } else {
+                    let result = match request {
+                        message::DELETE => server.delete(&key),
+                        _ => server.expire(&key),
+                    };
+                    match result {
+                        Ok(()) => stream.write_u8(request)?,
+                        Err(error) if error.kind() == ErrorKind::Unsupported => {
+                            stream.write_u8(message::UNSUPPORTED)?;
+                        }
+                        Err(error) => return Err(error),
+                    }
+                }
+            }
+            message::WRITE_MANY => {
+                if readonly {
+                    return Err(ProtocolError.into());
+                }
+                let max_key_len = server.max_key_len();
+                let max_value_len = server.max_value_len();
+                let count = stream.read_u32()?;
+                let mut writes = Vec::new();
+                for _ in 0..count {
+                    let key = stream.read_key(max_key_len)?;
+                    let value = stream.read_vec_lengthed(max_value_len)?;
+                    writes.push((key, value));
+                }
+                if !writes
+                    .iter()
+                    .all(|(key, _)| server.permitted(key, Access::Write))
+                {
+                    stream.write_u8(message::UNAUTHORIZED)?;
+                } else if !transaction && server.busy() {
+                    stream.write_u8(message::BUSY)?;
+                } else {
+                    server.write_many(&writes)?;
+                    stream.write_u8(message::WRITE_MANY)?;
+                }
+            }
+            message::APPEND => {
+                if readonly {
+                    return Err(ProtocolError.into());
+                }
+                let max_key_len = server.max_key_len();
+                let max_value_len = server.max_value_len();
+                let key = stream.read_key(max_key_len)?;
+                let bytes = stream.read_vec_lengthed(max_value_len)?;
+                if !server.permitted(&key, Access::Write) {
+                    stream.write_u8(message::UNAUTHORIZED)?;
+                } else if !transaction && server.busy() {
+                    stream.write_u8(message::BUSY)?;
+                } else {
+                    match server.append(&key, &bytes) {
+                        Ok(()) => stream.write_u8(message::APPEND)?,
+                        Err(error) if error.kind() == ErrorKind::Unsupported => {
+                            stream.write_u8(message::UNSUPPORTED)?;
+                        }
+                        Err(error) => return Err(error),
+                    }
+                }
+            }
+            request @ (message::WRITE_MAX | message::WRITE_MIN) => {
+                if readonly {
+                    return Err(ProtocolError.into());
+                }
+                let max_key_len = server.max_key_len();
+                let key = stream.read_key(max_key_len)?;
+                let n = stream.read_u64()? as i64;
+                if !server.permitted(&key, Access::Write) {
+                    stream.write_u8(message::UNAUTHORIZED)?;
+                } else if !transaction && server.busy() {
+                    stream.write_u8(message::BUSY)?;
+                } else {
+                    let result = match request {
+                        message::WRITE_MAX => server.write_max(&key, n),
+                        _ => server.write_min(&key, n),
+                    };
+                    match result {
+                        Ok(()) => stream.write_u8(request)?,
+                        Err(error) if error.kind() == ErrorKind::Unsupported => {
+                            stream.write_u8(message::UNSUPPORTED)?;
+                        }
+                        Err(error) => return Err(error),
+                    }
+                }
+            }
+            message::INCR => {
+                if readonly {
+                    return Err(ProtocolError.into());
+                }
+                let max_key_len = server.max_key_len();
+                let key = stream.read_key(max_key_len)?;
+                let delta = stream.read_u64()? as i64;
+                if !server.permitted(&key, Access::Write) {
+                    stream.write_u8(message::UNAUTHORIZED)?;
+                } else if !transaction && server.busy() {
+                    stream.write_u8(message::BUSY)?;
+                } else {
+                    match server.incr(&key, delta) {
+                        Ok(Some(n)) => {
+                            stream.write_u8(message::INCR)?;
+                            stream.write_u64(n as u64)?;
+                        }
+                        Ok(None) => stream.write_u8(message::NOT_A_NUMBER)?,
+                        Err(error) if error.kind() == ErrorKind::Unsupported => {
+                            stream.write_u8(message::UNSUPPORTED)?;
+                        }
+                        Err(error) => return Err(error),
+                    }
+                }
+            }
+            message::INCR_WINDOWED => {
+                if readonly {
+                    return Err(ProtocolError.into());
+                }
+                let max_key_len = server.max_key_len();
+                let key = stream.read_key(max_key_len)?;
+                let window = stream.read_duration()?;
+                if window.is_zero() {
+                    return Err(ProtocolError.into());
+                }
+                if !server.permitted(&key, Access::Write) {
+                    stream.write_u8(message::UNAUTHORIZED)?;
+                } else if !transaction && server.busy() {
+                    stream.write_u8(message::BUSY)?;
+                } else {
+                    match server.incr_windowed(&key, window) {
+                        Ok(count) => {
+                            stream.write_u8(message::INCR_WINDOWED)?;
+                            stream.write_u64(count)?;
+                        }
+                        Err(error) if error.kind() == ErrorKind::Unsupported => {
+                            stream.write_u8(message::UNSUPPORTED)?;
+                        }
+                        Err(error) => return Err(error),
+                    }
+                }
+            }
+            message::CHECK_WRITE => {
+                if readonly || transaction {
+                    return Err(ProtocolError.into());
+                }
+                let max_key_len = server.max_key_len();
+                let max_value_len = server.max_value_len();
+                let mut pairs = || {
+                    let count = stream.read_u32()?;
+                    let mut pairs = Vec::new();
+                    for _ in 0..count {
+                        let key = stream.read_key(max_key_len)?;
+                        let value = stream.read_opt_lengthed(max_value_len)?;
+                        pairs.push((key, value));
+                    }
+                    Ok::<_, Error>(pairs)
+                };
+                let checks = pairs()?;
+                let writes = pairs()?;
+                if !(checks
+                    .iter()
+                    .all(|(key, _)| server.permitted(key, Access::Read))
+                    && writes
+                        .iter()
+                        .all(|(key, _)| server.permitted(key, Access::Write)))
+                {
+                    stream.write_u8(message::UNAUTHORIZED)?;
+                } else if server.busy() {
+                    stream.write_u8(message::BUSY)?;
+                } else {
+                    match server.check_write(&checks, &writes) {
+                        Ok(Ok(time)) => {
+                            stream.write_u8(message::CHECK_WRITE)?;
+                            stream.write_duration(time)?;
+                        }
+                        Ok(Err(index)) => {
+                            stream.write_u8(message::CONFLICT)?;
+                            stream.write_u32(index)?;
+                        }
+                        Err(error) if error.kind() == ErrorKind::Unsupported => {
+                            stream.write_u8(message::UNSUPPORTED)?;
+                        }
+                        Err(error) => return Err(error),
+                    }
+                }
+            }
+            message::START_TRANSACTION if transactions >= server.max_transactions() => {
+                stream.write_u8(message::LIMIT_EXCEEDED)?;
             }
             message::START_TRANSACTION => {
+                transactions += 1;
                 server.start_transaction()?;
                 stream.write_u8(message::START_TRANSACTION)?;
                 readonly = false;
+                transaction = true;
             }
-            message::COMMIT if readonly => {
+            request @ (message::COMMIT | message::COMMIT_RECEIPT) if readonly => {
                 server.rollback()?;
-                stream.write_u8(message::COMMIT)?;
+                stream.write_u8(request)?;
                 stream.write_duration(Duration::default())?;
+                if request == message::COMMIT_RECEIPT {
+                    stream.write_u64(0)?;
+                    stream.write_u64(0)?;
+                }
             }
-            message::COMMIT => match server.commit()? {
-                Ok(duration) => {
-                    stream.write_u8(message::COMMIT)?;
-                    stream.write_duration(duration.unwrap_or_default())?;
+            message::COMMIT | message::COMMIT_RECEIPT if transaction && server.busy() => {
+                stream.write_u8(message::BUSY)?;
+            }
+            message::COMMIT => {
+                match server.commit() {
+                    Ok(Ok(duration)) => {
+                        stream.write_u8(message::COMMIT)?;
+                        stream.write_duration(duration.unwrap_or_default())?;
+                    }
+                    Ok(Err(TransactionConflict)) => {
+                        stream.write_u8(message::CONFLICT)?;
+                    }
+                    Err(error) if not_replicated(&error) => {
+                        stream.write_u8(message::NOT_REPLICATED)?;
+                    }
+                    Err(error) => return Err(error),
                 }
-                Err(TransactionConflict) => {
-                    stream.write_u8(message::CONFLICT)?;
+                transaction = false;
+            }
+            request @ (message::COMMIT_RECEIPT | message::COMMIT_REPLICATED) => {
+                // the readonly and busy checks of COMMIT_RECEIPT are done above, with the other commits
+                let result = if request == message::COMMIT_RECEIPT {
+                    Some(server.commit_with_receipt())
+                } else {
+                    let replicas = stream.read_u32()?;
+                    if readonly {
+                        Some(server.rollback().map(|()| Ok(None)))
+                    } else if transaction && server.busy() {
+                        None
+                    } else {
+                        Some(server.commit_replicated(replicas))
+                    }
+                };
+                match result {
+                    None => {
+                        stream.write_u8(message::BUSY)?;
+                    }
+                    // a zero time is no commit, like with COMMIT, and a zero offset is a history that is not persisted
+                    Some(Ok(Ok(receipt))) => {
+                        let receipt = receipt.unwrap_or(CommitReceipt {
+                            time: Duration::default(),
+                            seq: 0,
+                            offset: None,
+                        });
+                        stream.write_u8(request)?;
+                        stream.write_duration(receipt.time)?;
+                        stream.write_u64(receipt.seq)?;
+                        stream.write_u64(receipt.offset.unwrap_or(0))?;
+                        transaction = false;
+                    }
+                    Some(Ok(Err(TransactionConflict))) => {
+                        stream.write_u8(message::CONFLICT)?;
+                        transaction = false;
+                    }
+                    Some(Err(error)) if error.kind() == ErrorKind::Unsupported => {
+                        stream.write_u8(message::UNSUPPORTED)?;
+                    }
+                    Some(Err(error)) if not_replicated(&error) => {
+                        stream.write_u8(message::NOT_REPLICATED)?;
+                        transaction = false;
+                    }
+                    Some(Err(error)) => return Err(error),
                 }
-            },
+            }
             message::ROLLBACK => {
                 server.rollback()?;
                 stream.write_u8(message::ROLLBACK)?;
+                transaction = false;
+            }
+            message::VALIDATE => {
+                let mut result = None;
+                let validated = server.validate(|keys| {
+                    result = Some((|| {
+                        stream.write_u8(message::VALIDATE)?;
+                        stream.write_u32(keys.len() as u32)?;
+                        for key in keys {
+                            stream.write_vec_lengthed(key)?;
+                        }
+                        Ok::<_, Error>(())
+                    })());
+                });
+                match validated {
+                    Ok(()) => match result {
+                        Some(result) => result?,
+                        None => {
+                            stream.write_u8(message::VALIDATE)?;
+                            stream.write_u32(0)?;
+                        }
+                    },
+                    Err(error) if error.kind() == ErrorKind::Unsupported => {
+                        stream.write_u8(message::UNSUPPORTED)?;
+                    }
+                    Err(error) => return Err(error),
+                }
+            }
+            message::RECENT_COMMITS => {
+                let limit = stream.read_u32()?;
+                let mut result = None;
+                let listed = server.recent_commits(limit, |commits| {
+                    result = Some((|| {
+                        stream.write_u8(message::RECENT_COMMITS)?;
+                        stream.write_u32(commits.len() as u32)?;
+                        for commit in commits {
+                            stream.write_duration(commit.time)?;
+                            stream.write_u64(commit.seq)?;
+                            stream.write_u32(commit.keys as u32)?;
+                            match &commit.meta {
+                                Some(meta) => {
+                                    stream.write_u8(1)?;
+                                    stream.write_vec_lengthed(meta)?;
+                                }
+                                None => stream.write_u8(0)?,
+                            }
+                        }
+                        Ok::<_, Error>(())
+                    })());
+                });
+                match listed {
+                    Ok(()) => match result {
+                        Some(result) => result?,
+                        None => {
+                            stream.write_u8(message::RECENT_COMMITS)?;
+                            stream.write_u32(0)?;
+                        }
+                    },
+                    Err(error) if error.kind() == ErrorKind::Unsupported => {
+                        stream.write_u8(message::UNSUPPORTED)?;
+                    }
+                    Err(error) => return Err(error),
+                }
             }
             message::COUNT => {
-                let max_len = server.max_len();
-                let start = stream.read_vec_lengthed(max_len)?;
-                let end = stream.read_vec_lengthed(max_len)?;
-                let count = server.count(&start, &end)?;
-                stream.write_u8(message::COUNT)?;
-                stream.write_u32(count)?;
+                let max_key_len = server.max_key_len();
+                let start = stream.read_key(max_key_len)?;
+                let end = stream.read_key(max_key_len)?;
+                if !server.permitted_range(&start) {
+                    stream.write_u8(message::UNAUTHORIZED)?;
+                } else {
+                    let count = server.count(&start, &end)?;
+                    stream.write_u8(message::COUNT)?;
+                    stream.write_u32(count)?;
+                }
             }
             message::LIST => {
+                let max_key_len = server.max_key_len();
+                let start = stream.read_key(max_key_len)?;
+                let end = stream.read_key(max_key_len)?;
+                let client_max_len = stream.read_u32()?.min(server.max_scan_bytes());
+                if !server.permitted_range(&start) {
+                    stream.write_u8(message::UNAUTHORIZED)?;
+                } else {
+                    let mut result = None;
+                    server.list(&start, &end, |list| {
+                        result = Some((|| {
+                            let total =
+                                list.iter().map(|x| x.len()).fold(Some(0usize), |acc, x| {
+                                    acc.and_then(|acc| acc.checked_add(x))
+                                });
+                            if total.is_some_and(|x| x < client_max_len as usize) {
+                                stream.write_u8(message::LIST)?;
+                                stream.write_u32(list.len() as u32)?;
+                                for i in list {
+                                    stream.write_vec_lengthed(i)?;
+                                }
+                            } else {
+                                stream.write_u8(message::LIMIT_EXCEEDED)?;
+                            }
+                            Ok::<_, Error>(())
+                        })());
+                    })?;
+                    match result {
+                        Some(result) => result?,
+                        None => {
+                            stream.write_u8(message::LIST)?;
+                            stream.write_u32(0)?;
+                        }
+                    }
+                }
+            }
+            request @ (message::SCAN | message::SCAN_MODIFIED_SINCE) => {
+                let max_key_len = server.max_key_len();
+                let start = stream.read_key(max_key_len)?;
+                let end = stream.read_key(max_key_len)?;
+                let since = if request == message::SCAN_MODIFIED_SINCE {
+                    Some(stream.read_duration()?)
+                } else {
+                    None
+                };
+                let client_max_len = stream.read_u32()?.min(server.max_scan_bytes());
+                if !server.permitted_range(&start) {
+                    stream.write_u8(message::UNAUTHORIZED)?;
+                } else {
+                    let mut result = None;
+                    let write = |scan: &[(&[u8], &[u8])]| {
+                        result = Some((|| {
+                            let total = scan
+                                .iter()
+                                .flat_map(|(k, v)| [k.len(), v.len()])
+                                .fold(Some(0usize), |acc, x| {
+                                    acc.and_then(|acc| acc.checked_add(x))
+                                });
+                            if total.is_some_and(|x| x < client_max_len as usize) {
+                                stream.write_u8(request)?;
+                                stream.write_u32(scan.len() as u32)?;
+                                for (k, v) in scan {
+                                    stream.write_vec_lengthed(k)?;
+                                    stream.write_vec_lengthed(v)?;
+                                }
+                            } else {
+                                stream.write_u8(message::LIMIT_EXCEEDED)?;
+                            }
+                            Ok::<_, Error>(())
+                        })());
+                    };
+                    let scanned = match since {
+                        None => server.scan(&start, &end, write),
+                        Some(since) => server.scan_modified_since(&start, &end, since, write),
+                    };
+                    match scanned {
+                        Ok(()) => match result {
+                            Some(result) => result?,
+                            None => {
+                                stream.write_u8(request)?;
+                                stream.write_u32(0)?;
+                            }
+                        },
+                        Err(error) if error.kind() == ErrorKind::Unsupported => {
+                            stream.write_u8(message::UNSUPPORTED)?;
+                        }
+                        Err(error) => return Err(error),
+                    }
+                }
+            }
+            message::START_SNAPSHOT => {
+                let duration = stream.read_duration()?;
+                let duration = (!duration.is_zero()).then_some(duration);
+                server.start_snapshot(duration)?;
+                stream.write_u8(message::START_SNAPSHOT)?;
+                readonly = true;
+                transaction = false;
+            }
+            message::START_SEQ_SNAPSHOT => {
+                let seq = stream.read_u64()?;
+                match server.start_seq_snapshot(seq) {
+                    Ok(()) => {
+                        stream.write_u8(message::START_SEQ_SNAPSHOT)?;
+                        readonly = true;
+                        transaction = false;
+                    }
+                    Err(error) if error.kind() == ErrorKind::Unsupported => {
+                        stream.write_u8(message::UNSUPPORTED)?;
+                    }
+                    Err(error) => return Err(error),
+                }
+            }
+            message::SET_READ_CONSISTENCY => {
+                let consistency =
+                    ReadConsistency::from_u8(stream.read_u8()?).ok_or(ProtocolError)?;
+                match server.set_read_consistency(consistency) {
+                    Ok(()) => stream.write_u8(message::SET_READ_CONSISTENCY)?,
+                    Err(error) if error.kind() == ErrorKind::Unsupported => {
+                        stream.write_u8(message::UNSUPPORTED)?;
+                    }
+                    Err(error) => return Err(error),
+                }
+            }
+            message::BACKUP => {
+                if !(server.permitted_range(b"") && server.permitted_range(system::PREFIX)) {
+                    stream.write_u8(message::UNAUTHORIZED)?;
+                } else {
+                    let mut result = None;
+                    server.backup(|time, entries| {
+                        result = Some((|| {
+                            stream.write_u8(message::BACKUP)?;
+                            stream.write_duration(time)?;
+                            for (k, v) in entries {
+                                stream.write_u8(1)?;
+                                stream.write_vec_lengthed(k)?;
+                                stream.write_vec_lengthed(v)?;
+                            }
+                            stream.write_u8(0)?;
+                            Ok::<_, Error>(())
+                        })());
+                    })?;
+                    match result {
+                        Some(result) => result?,
+                        None => {
+                            stream.write_u8(message::BACKUP)?;
+                            stream.write_duration(Duration::default())?;
+                            stream.write_u8(0)?;
+                        }
+                    }
+                }
+            }
+            message::CALL_PROCEDURE => {
                 let max_len = server.max_len();
-                let start = stream.read_vec_lengthed(max_len)?;
-                let end = stream.read_vec_lengthed(max_len)?;
+                let name = read_string_lengthed(stream, max_len)?;
+                let count = stream.read_u32()?;
+                let mut args = Vec::new();
+                for _ in 0..count {
+                    args.push(stream.read_vec_lengthed(max_len)?);
+                }
                 let client_max_len = stream.read_u32()?;
+                if !transaction && server.busy() {
+                    stream.write_u8(message::BUSY)?;
+                } else {
+                    let mut result = None;
+                    let outcome = server.call_procedure(&name, &args, |value| {
+                        result = Some((|| {
+                            if value.len() <= client_max_len as usize {
+                                stream.write_u8(message::CALL_PROCEDURE)?;
+                                stream.write_vec_lengthed(value)?;
+                            } else {
+                                stream.write_u8(message::LIMIT_EXCEEDED)?;
+                            }
+                            Ok::<_, Error>(())
+                        })());
+                    });
+                    match outcome {
+                        Ok(Ok(())) => match result {
+                            Some(result) => result?,
+                            None => {
+                                stream.write_u8(message::CALL_PROCEDURE)?;
+                                stream.write_u32(0)?;
+                            }
+                        },
+                        Ok(Err(ProcedureError(error))) => {
+                            stream.write_u8(message::PROCEDURE_FAILED)?;
+                            stream.write_vec_lengthed(error.as_bytes())?;
+                        }
+                        Err(error) if error.kind() == ErrorKind::PermissionDenied => {
+                            stream.write_u8(message::UNAUTHORIZED)?;
+                        }
+                        Err(error) if error.kind() == ErrorKind::Unsupported => {
+                            stream.write_u8(message::UNSUPPORTED)?;
+                        }
+                        Err(error) => return Err(error),
+                    }
+                }
+            }
+            message::PUBLISH => {
+                let max_len = server.max_len();
+                let channel = stream.read_vec_lengthed(max_len)?;
+                let payload = stream.read_vec_lengthed(max_len)?;
+                match server.publish(&channel, &payload) {
+                    Ok(receivers) => {
+                        stream.write_u8(message::PUBLISH)?;
+                        stream.write_u32(receivers)?;
+                    }
+                    Err(error) if error.kind() == ErrorKind::Unsupported => {
+                        stream.write_u8(message::UNSUPPORTED)?;
+                    }
+                    Err(error) => return Err(error),
+                }
+            }
+            request @ (message::SUBSCRIBE | message::SUBSCRIBE_PATTERNS) => {
+                let max_len = server.max_len();
+                let count = stream.read_u32()?;
+                let mut channels = Vec::new();
+                for _ in 0..count {
+                    channels.push(stream.read_vec_lengthed(max_len)?);
+                }
+                let subscribed: Result<Messages, Error> = if request == message::SUBSCRIBE {
+                    server
+                        .subscribe(&channels)
+                        .map(|messages| Box::new(messages) as _)
+                } else {
+                    server
+                        .subscribe_patterns(&channels)
+                        .map(|messages| Box::new(messages) as _)
+                };
+                let messages = match subscribed {
+                    Ok(messages) => messages,
+                    Err(error) if error.kind() == ErrorKind::Unsupported => {
+                        stream.write_u8(message::UNSUPPORTED)?;
+                        continue;
+                    }
+                    Err(error) => return Err(error),
+                };
+                // from here on the connection only carries published messages
+                stream.write_u8(request)?;
+                stream.flush()?;
+                for (channel, payload) in messages {
+                    stream.write_u8(message::PUBLISH)?;
+                    stream.write_vec_lengthed(&channel)?;
+                    stream.write_vec_lengthed(&payload)?;
+                    stream.flush()?;
+                }
+                return Err(ErrorKind::ConnectionReset.into());
+            }
+            message::SET_NAME => {
+                let name = read_string_lengthed(stream, u8::MAX as u32)?;
+                match server.set_name(&name) {
+                    Ok(()) => stream.write_u8(message::SET_NAME)?,
+                    Err(error) if error.kind() == ErrorKind::Unsupported => {
+                        stream.write_u8(message::UNSUPPORTED)?;
+                    }
+                    Err(error) => return Err(error),
+                }
+            }
+            message::AUTHENTICATE => {
+                let token = stream.read_vec_lengthed(u8::MAX as u32)?;
+                if server.authenticate(&token)? {
+                    stream.write_u8(message::AUTHENTICATE)?;
+                } else {
+                    stream.write_u8(message::UNAUTHORIZED)?;
+                }
+            }
+            message::STATS
+            | message::INFO
+            | message::GC
+            | message::CONNECTIONS
+            | message::SLOW_LOG
+            | message::REPLICATION
+            | message::DRAIN
+            | message::UPGRADE
+            | message::RELOAD
+            | message::USERS
+            | message::ACL
+                if !server.is_admin() =>
+            {
+                stream.write_u8(message::UNAUTHORIZED)?;
+            }
+            message::DEFINE_PROCEDURE if !server.is_admin() => {
+                // the request still has to be consumed, so the connection stays in sync
+                let max_len = server.max_len();
+                stream.read_vec_lengthed(max_len)?;
+                stream.read_vec_lengthed(max_len)?;
+                stream.write_u8(message::UNAUTHORIZED)?;
+            }
+            message::DEFINE_PROCEDURE => {
+                let max_len = server.max_len();
+                let name = read_string_lengthed(stream, max_len)?;
+                let source = read_string_lengthed(stream, max_len)?;
+                match server.define_procedure(&name, &source) {
+                    Ok(Ok(())) => stream.write_u8(message::DEFINE_PROCEDURE)?,
+                    Ok(Err(ProcedureError(error))) => {
+                        stream.write_u8(message::PROCEDURE_FAILED)?;
+                        stream.write_vec_lengthed(error.as_bytes())?;
+                    }
+                    Err(error) if error.kind() == ErrorKind::Unsupported => {
+                        stream.write_u8(message::UNSUPPORTED)?;
+                    }
+                    Err(error) => return Err(error),
+                }
+            }
+            request @ (message::ADD_USER
+            | message::REMOVE_USER
+            | message::SET_ACL
+            | message::KILL
+            | message::ACK_REPLICATION
+            | message::COMPACT) => {
+                let max_len = server.max_len();
+                let result = match request {
+                    message::ADD_USER => {
+                        let name = read_string_lengthed(stream, max_len)?;
+                        let role = read_string_lengthed(stream, max_len)?;
+                        let token = stream.read_vec_lengthed(u8::MAX as u32)?;
+                        if name.is_empty() || role.is_empty() || token.is_empty() {
+                            return Err(ProtocolError.into());
+                        }
+                        server
+                            .is_admin()
+                            .then(|| server.add_user(&name, &role, &token).map(|()| None))
+                    }
+                    message::REMOVE_USER => {
+                        let name = read_string_lengthed(stream, max_len)?;
+                        server
+                            .is_admin()
+                            .then(|| server.remove_user(&name).map(Some))
+                    }
+                    message::KILL => {
+                        let id = stream.read_u64()?;
+                        server.is_admin().then(|| server.kill(id).map(Some))
+                    }
+                    message::ACK_REPLICATION => {
+                        let name = read_string_lengthed(stream, u8::MAX as u32)?;
+                        let seq = stream.read_u64()?;
+                        server
+                            .is_admin()
+                            .then(|| server.ack_replication(&name, seq).map(|()| None))
+                    }
+                    message::COMPACT => {
+                        let age = stream.read_duration()?;
+                        server
+                            .is_admin()
+                            .then(|| server.compact(age).map(|()| None))
+                    }
+                    _ => {
+                        let prefix = stream.read_key(server.max_key_len())?;
+                        let access = Access::from_u8(stream.read_u8()?).ok_or(ProtocolError)?;
+                        let role = read_string_lengthed(stream, max_len)?;
+                        let role = (!role.is_empty()).then_some(role.as_str());
+                        server
+                            .is_admin()
+                            .then(|| server.set_acl(&prefix, access, role).map(|()| None))
+                    }
+                };
+                match result {
+                    None => stream.write_u8(message::UNAUTHORIZED)?,
+                    Some(Ok(existed)) => {
+                        stream.write_u8(request)?;
+                        if let Some(existed) = existed {
+                            stream.write_u8(existed as u8)?;
+                        }
+                    }
+                    Some(Err(error)) if error.kind() == ErrorKind::Unsupported => {
+                        stream.write_u8(message::UNSUPPORTED)?;
+                    }
+                    Some(Err(error)) => return Err(error),
+                }
+            }
+            message::USERS => {
                 let mut result = None;
-                server.list(&start, &end, |list| {
+                let listed = server.users(|users| {
                     result = Some((|| {
-                        let total = list.iter().map(|x| x.len()).fold(Some(0usize), |acc, x| {
-                            acc.and_then(|acc| acc.checked_add(x))
-                        });
-                        if total.is_some_and(|x| x < client_max_len as usize) {
-                            stream.write_u8(message::LIST)?;
-                            stream.write_u32(list.len() as u32)?;
-                            for i in list {
-                                stream.write_vec_lengthed(i)?;
+                        stream.write_u8(message::USERS)?;
+                        stream.write_u32(users.len() as u32)?;
+                        for (name, role) in users {
+                            stream.write_vec_lengthed(name.as_bytes())?;
+                            stream.write_vec_lengthed(role.as_bytes())?;
+                        }
+                        Ok::<_, Error>(())
+                    })());
+                });
+                match listed {
+                    Ok(()) => match result {
+                        Some(result) => result?,
+                        None => {
+                            stream.write_u8(message::USERS)?;
+                            stream.write_u32(0)?;
+                        }
+                    },
+                    Err(error) if error.kind() == ErrorKind::Unsupported => {
+                        stream.write_u8(message::UNSUPPORTED)?;
+                    }
+                    Err(error) => return Err(error),
+                }
+            }
+            message::ACL => {
+                let mut result = None;
+                let listed = server.acl(|rules| {
+                    result = Some((|| {
+                        stream.write_u8(message::ACL)?;
+                        stream.write_u32(rules.len() as u32)?;
+                        for rule in rules {
+                            stream.write_vec_lengthed(&rule.prefix)?;
+                            stream.write_u8(rule.access.to_u8())?;
+                            stream.write_vec_lengthed(rule.role.as_bytes())?;
+                        }
+                        Ok::<_, Error>(())
+                    })());
+                });
+                match listed {
+                    Ok(()) => match result {
+                        Some(result) => result?,
+                        None => {
+                            stream.write_u8(message::ACL)?;
+                            stream.write_u32(0)?;
+                        }
+                    },
+                    Err(error) if error.kind() == ErrorKind::Unsupported => {
+                        stream.write_u8(message::UNSUPPORTED)?;
+                    }
+                    Err(error) => return Err(error),
+                }
+            }
+            message::INFO => {
+                let mut result = None;
+                server.info(|sections| {
+                    result = Some((|| {
+                        stream.write_u8(message::INFO)?;
+                        stream.write_u32(INFO_VERSION)?;
+                        stream.write_u32(sections.len() as u32)?;
+                        for (name, fields) in sections {
+                            stream.write_vec_lengthed(name.as_bytes())?;
+                            stream.write_u32(fields.len() as u32)?;
+                            for (field, value) in *fields {
+                                stream.write_vec_lengthed(field.as_bytes())?;
+                                stream.write_u64(*value)?;
                             }
-                        } else {
-                            stream.write_u8(message::LIMIT_EXCEEDED)?;
                         }
                         Ok::<_, Error>(())
                     })());
@@ -152,34 +1502,54 @@ where
                 match result {
                     Some(result) => result?,
                     None => {
-                        stream.write_u8(message::LIST)?;
+                        stream.write_u8(message::INFO)?;
+                        stream.write_u32(INFO_VERSION)?;
                         stream.write_u32(0)?;
                     }
                 }
             }
-            message::SCAN => {
-                let max_len = server.max_len();
-                let start = stream.read_vec_lengthed(max_len)?;
-                let end = stream.read_vec_lengthed(max_len)?;
-                let client_max_len = stream.read_u32()?;
+            message::STATS => {
                 let mut result = None;
-                server.scan(&start, &end, |scan| {
+                server.stats(|stats| {
                     result = Some((|| {
-                        let total = scan
-                            .iter()
-                            .flat_map(|(k, v)| [k.len(), v.len()])
-                            .fold(Some(0usize), |acc, x| {
-                                acc.and_then(|acc| acc.checked_add(x))
-                            });
-                        if total.is_some_and(|x| x < client_max_len as usize) {
-                            stream.write_u8(message::SCAN)?;
-                            stream.write_u32(scan.len() as u32)?;
-                            for (k, v) in scan {
-                                stream.write_vec_lengthed(k)?;
-                                stream.write_vec_lengthed(v)?;
+                        stream.write_u8(message::STATS)?;
+                        stream.write_u32(stats.len() as u32)?;
+                        for (name, value) in stats {
+                            stream.write_vec_lengthed(name.as_bytes())?;
+                            stream.write_u64(*value)?;
+                        }
+                        Ok::<_, Error>(())
+                    })());
+                })?;
+                match result {
+                    Some(result) => result?,
+                    None => {
+                        stream.write_u8(message::STATS)?;
+                        stream.write_u32(0)?;
+                    }
+                }
+            }
+            message::CONNECTIONS => {
+                let mut result = None;
+                server.connections(|connections| {
+                    result = Some((|| {
+                        stream.write_u8(message::CONNECTIONS)?;
+                        stream.write_u32(connections.len() as u32)?;
+                        for connection in connections {
+                            stream.write_u64(connection.id)?;
+                            stream.write_vec_lengthed(connection.peer.as_bytes())?;
+                            stream.write_vec_lengthed(connection.name.as_bytes())?;
+                            stream.write_u8(connection.mode.to_u8())?;
+                            stream.write_u64(connection.commands)?;
+                            stream.write_u64(connection.bytes)?;
+                            stream.write_duration(connection.idle)?;
+                            match connection.transaction {
+                                Some(age) => {
+                                    stream.write_u8(1)?;
+                                    stream.write_duration(age)?;
+                                }
+                                None => stream.write_u8(0)?,
                             }
-                        } else {
-                            stream.write_u8(message::LIMIT_EXCEEDED)?;
                         }
                         Ok::<_, Error>(())
                     })());
@@ -187,21 +1557,237 @@ where
                 match result {
                     Some(result) => result?,
                     None => {
-                        stream.write_u8(message::SCAN)?;
+                        stream.write_u8(message::CONNECTIONS)?;
                         stream.write_u32(0)?;
                     }
                 }
             }
-            message::START_SNAPSHOT => {
-                let duration = stream.read_duration()?;
-                let duration = (!duration.is_zero()).then_some(duration);
-                server.start_snapshot(duration)?;
-                stream.write_u8(message::START_SNAPSHOT)?;
-                readonly = true;
+            message::SLOW_LOG => {
+                let mut result = None;
+                let listed = server.slow_log(|commands| {
+                    result = Some((|| {
+                        stream.write_u8(message::SLOW_LOG)?;
+                        stream.write_u32(commands.len() as u32)?;
+                        for command in commands {
+                            stream.write_u64(command.id)?;
+                            stream.write_duration(command.time)?;
+                            stream.write_u64(command.connection)?;
+                            stream.write_vec_lengthed(command.peer.as_bytes())?;
+                            stream.write_vec_lengthed(command.name.as_bytes())?;
+                            stream.write_vec_lengthed(command.request.as_bytes())?;
+                            stream.write_u64(command.key_bytes)?;
+                            stream.write_u64(command.bytes)?;
+                            stream.write_duration(command.elapsed)?;
+                            stream.write_vec_lengthed(command.outcome.as_bytes())?;
+                        }
+                        Ok::<_, Error>(())
+                    })());
+                });
+                match listed {
+                    Ok(()) => match result {
+                        Some(result) => result?,
+                        None => {
+                            stream.write_u8(message::SLOW_LOG)?;
+                            stream.write_u32(0)?;
+                        }
+                    },
+                    Err(error) if error.kind() == ErrorKind::Unsupported => {
+                        stream.write_u8(message::UNSUPPORTED)?;
+                    }
+                    Err(error) => return Err(error),
+                }
+            }
+            message::REPLICATION => {
+                let mut result = None;
+                let listed = server.replication_status(|replicas| {
+                    result = Some((|| {
+                        stream.write_u8(message::REPLICATION)?;
+                        stream.write_u32(replicas.len() as u32)?;
+                        for replica in replicas {
+                            stream.write_vec_lengthed(replica.name.as_bytes())?;
+                            stream.write_u64(replica.seq)?;
+                            stream.write_duration(replica.acked)?;
+                            stream.write_u64(replica.lag_commits)?;
+                            stream.write_u64(replica.lag_bytes)?;
+                        }
+                        Ok::<_, Error>(())
+                    })());
+                });
+                match listed {
+                    Ok(()) => match result {
+                        Some(result) => result?,
+                        None => {
+                            stream.write_u8(message::REPLICATION)?;
+                            stream.write_u32(0)?;
+                        }
+                    },
+                    Err(error) if error.kind() == ErrorKind::Unsupported => {
+                        stream.write_u8(message::UNSUPPORTED)?;
+                    }
+                    Err(error) => return Err(error),
+                }
+            }
+            request @ (message::BACKUP_SNAPSHOT | message::BACKUP_SINCE) => {
+                let seq = match request {
+                    message::BACKUP_SINCE => Some(stream.read_u64()?),
+                    _ => None,
+                };
+                if !server.is_admin() {
+                    stream.write_u8(message::UNAUTHORIZED)?;
+                } else {
+                    let mut result = None;
+                    let write = |seq: u64, history: &[u8]| {
+                        result = Some((|| {
+                            stream.write_u8(request)?;
+                            stream.write_u64(seq)?;
+                            let mut compressed = Vec::new();
+                            for chunk in history.chunks(message::MAX_CHUNK_LEN as usize) {
+                                compressed.clear();
+                                if compression != Compression::None {
+                                    compression.compress(chunk, &mut compressed);
+                                }
+                                // chunks that don't get smaller are sent as they are
+                                if !compressed.is_empty() && compressed.len() < chunk.len() {
+                                    stream.write_u8(message::COMPRESSED_CHUNK)?;
+                                    stream.write_u8(compression.to_u8())?;
+                                    stream.write_u32(chunk.len() as u32)?;
+                                    stream.write_vec_lengthed(&compressed)?;
+                                } else {
+                                    stream.write_u8(message::CHUNK)?;
+                                    stream.write_vec_lengthed(chunk)?;
+                                }
+                            }
+                            stream.write_u8(0)?;
+                            Ok::<_, Error>(())
+                        })());
+                    };
+                    let backup = match seq {
+                        Some(seq) => server.backup_since(seq, write),
+                        None => server.backup_snapshot(write),
+                    };
+                    match backup {
+                        Ok(()) => result.ok_or(ProtocolError)??,
+                        // the replica has a history the server doesn't, it has to start again from a snapshot
+                        Err(error) if error.kind() == ErrorKind::InvalidInput => {
+                            stream.write_u8(message::CONFLICT)?;
+                        }
+                        Err(error) if error.kind() == ErrorKind::Unsupported => {
+                            stream.write_u8(message::UNSUPPORTED)?;
+                        }
+                        Err(error) => return Err(error),
+                    }
+                }
+            }
+            message::NEGOTIATE_COMPRESSION => {
+                let count = stream.read_u8()?;
+                let mut accepted = Vec::with_capacity(count as usize);
+                for _ in 0..count {
+                    accepted.push(stream.read_u8()?);
+                }
+                // the first one the client accepts that this version knows
+                compression = accepted
+                    .into_iter()
+                    .find_map(Compression::from_u8)
+                    .unwrap_or_default();
+                stream.write_u8(message::NEGOTIATE_COMPRESSION)?;
+                stream.write_u8(compression.to_u8())?;
+            }
+            message::RELOAD => match server.reload() {
+                Ok(result) => {
+                    stream.write_u8(message::RELOAD)?;
+                    stream.write_vec_lengthed(result.err().unwrap_or_default().as_bytes())?;
+                }
+                Err(error) if error.kind() == ErrorKind::Unsupported => {
+                    stream.write_u8(message::UNSUPPORTED)?;
+                }
+                Err(error) => return Err(error),
+            },
+            command @ (message::GC | message::DRAIN | message::UPGRADE) => {
+                let result = match command {
+                    message::GC => server.gc(),
+                    message::DRAIN => server.drain(),
+                    _ => server.upgrade(),
+                };
+                match result {
+                    Ok(()) => stream.write_u8(command)?,
+                    Err(error) if error.kind() == ErrorKind::Unsupported => {
+                        stream.write_u8(message::UNSUPPORTED)?
+                    }
+                    Err(error) => return Err(error),
+                }
             }
             _ => {
                 return Err(ProtocolError.into());
             }
         }
+        let request = message::name(recv_command[0]);
+        server.on_command(&CommandEvent {
+            request,
+            key_bytes: stream.key_bytes,
+            bytes: stream.bytes - bytes,
+            elapsed: started.elapsed(),
+            outcome: match stream.status {
+                Some(status) if status == recv_command[0] => "ok",
+                Some(status) => message::name(status),
+                None => "none",
+            },
+        });
+        if let Some((trace_id, started)) = traced {
+            server.traced(&trace_id, request, started.elapsed());
+        }
+    }
+}
+
+/// counts the bytes read and written through it, for [`Server::on_command`]
+struct Counted<'a, T> {
+    inner: &'a mut T,
+    bytes: u64,
+    /// the length of the keys read with [`Counted::read_key`] since the request started
+    key_bytes: u64,
+    /// the first byte written since the request started, which is the status of its response
+    status: Option<u8>,
+}
+
+impl<T: Read> Counted<'_, T> {
+    fn read_key(&mut self, max_len: u32) -> Result<Vec<u8>, Error> {
+        let key = self.read_vec_lengthed(max_len)?;
+        self.key_bytes += key.len() as u64;
+        Ok(key)
     }
 }
+
+impl<T: Read> Read for Counted<'_, T> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+        let len = self.inner.read(buf)?;
+        self.bytes += len as u64;
+        Ok(len)
+    }
+}
+
+impl<T: Write> Write for Counted<'_, T> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize, Error> {
+        let len = self.inner.write(buf)?;
+        if len != 0 && self.status.is_none() {
+            self.status = Some(buf[0]);
+        }
+        self.bytes += len as u64;
+        Ok(len)
+    }
+    fn flush(&mut self) -> Result<(), Error> {
+        self.inner.flush()
+    }
+}
+
+/// the channel and message of each message published to a subscription
+type Messages<'a> = Box<dyn Iterator<Item = (Vec<u8>, Vec<u8>)> + 'a>;
+
+/// whether the commit was done but not acknowledged by enough replicas in time, see [`Server::commit_replicated`]
+fn not_replicated(error: &Error) -> bool {
+    error
+        .get_ref()
+        .is_some_and(|inner| inner.is::<NotReplicated>())
+}
+
+fn read_string_lengthed(stream: &mut impl Read, max_len: u32) -> Result<String, Error> {
+    String::from_utf8(stream.read_vec_lengthed(max_len)?).map_err(|_| ProtocolError.into())
+}
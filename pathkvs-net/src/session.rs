@@ -0,0 +1,213 @@
+//! session backend for web applications over a pathkvs server
+//!
+//! a session with token `TOKEN` is stored under the key `<prefix>TOKEN`, its value is the
+//! expiry time (12 bytes, seconds and nanoseconds since the unix epoch, little endian) followed by the data
+//!
+//! the server does not expire keys on its own, expired sessions are ignored by every operation
+//! and only removed by [`SessionStore::purge_expired`] or when they are destroyed
+//!
+//! expiry times come from the clock of the client, so every client sharing a store should have its clock in sync
+//!
+//! every operation runs in its own transaction with [`Connection::transact`], which retries it on conflict,
+//! so the connection must not be in a transaction or snapshot when they are called
+
+use std::{
+    io::{Read, Write},
+    time::{Duration, SystemTime},
+};
+
+use crate::{client::Connection, Error};
+
+/// length of the random part of a token, the token itself is twice as long, since it is hexadecimal
+const TOKEN_BYTES: usize = 16;
+const EXPIRY_LEN: usize = 12;
+
+#[derive(Debug, Clone)]
+pub struct SessionStore {
+    prefix: Vec<u8>,
+    ttl: Duration,
+}
+
+impl SessionStore {
+    /// sessions expire `ttl` after they are created, touched or rotated
+    pub fn new(prefix: impl Into<Vec<u8>>, ttl: Duration) -> Self {
+        Self {
+            prefix: prefix.into(),
+            ttl,
+        }
+    }
+    pub fn prefix(&self) -> &[u8] {
+        &self.prefix
+    }
+    pub fn ttl(&self) -> Duration {
+        self.ttl
+    }
+
+    /// stores a new session and returns its token
+    pub fn create_session<T: Read + Write>(
+        &self,
+        conn: &mut Connection<T>,
+        data: impl AsRef<[u8]>,
+    ) -> Result<String, Error> {
+        let data = data.as_ref();
+        conn.transact(|conn| loop {
+            let token = new_token()?;
+            // a collision is practically impossible, but it would hand someone else's session out
+            if conn.exists(self.key(&token))? {
+                continue;
+            }
+            conn.write(self.key(&token), self.value(data))?;
+            return Ok(token);
+        })
+    }
+    /// the data of the session, or `None` if it does not exist or expired
+    pub fn get<T: Read + Write>(
+        &self,
+        conn: &mut Connection<T>,
+        token: &str,
+    ) -> Result<Option<Vec<u8>>, Error> {
+        assert_normal(conn);
+        let value = conn.read(self.key(token))?;
+        Ok(live_data(&value, SystemTime::now())?.map(<[u8]>::to_vec))
+    }
+    /// replaces the data of the session and extends it, returns false if it does not exist or expired
+    pub fn update<T: Read + Write>(
+        &self,
+        conn: &mut Connection<T>,
+        token: &str,
+        data: impl AsRef<[u8]>,
+    ) -> Result<bool, Error> {
+        let data = data.as_ref();
+        conn.transact(|conn| {
+            let value = conn.read(self.key(token))?;
+            if live_data(&value, SystemTime::now())?.is_none() {
+                return Ok(false);
+            }
+            conn.write(self.key(token), self.value(data))?;
+            Ok(true)
+        })
+    }
+    /// extends the session by the ttl of the store, returns false if it does not exist or expired
+    pub fn touch<T: Read + Write>(
+        &self,
+        conn: &mut Connection<T>,
+        token: &str,
+    ) -> Result<bool, Error> {
+        conn.transact(|conn| {
+            let value = conn.read(self.key(token))?;
+            let Some(data) = live_data(&value, SystemTime::now())? else {
+                return Ok(false);
+            };
+            conn.write(self.key(token), self.value(data))?;
+            Ok(true)
+        })
+    }
+    /// moves the session to a new token and extends it, the old token stops working
+    ///
+    /// the old token is checked and removed in the same transaction, so if two requests rotate
+    /// the same token concurrently only one of them gets a new token, the other gets `None`
+    pub fn rotate<T: Read + Write>(
+        &self,
+        conn: &mut Connection<T>,
+        token: &str,
+    ) -> Result<Option<String>, Error> {
+        conn.transact(|conn| {
+            let value = conn.read(self.key(token))?;
+            let Some(data) = live_data(&value, SystemTime::now())? else {
+                return Ok(None);
+            };
+            let new_token = loop {
+                let new_token = new_token()?;
+                if !conn.exists(self.key(&new_token))? {
+                    break new_token;
+                }
+            };
+            conn.write(self.key(&new_token), self.value(data))?;
+            conn.delete(self.key(token))?;
+            Ok(Some(new_token))
+        })
+    }
+    /// removes the session, returns false if it did not exist or already expired
+    pub fn destroy<T: Read + Write>(
+        &self,
+        conn: &mut Connection<T>,
+        token: &str,
+    ) -> Result<bool, Error> {
+        conn.transact(|conn| {
+            let value = conn.read(self.key(token))?;
+            if value.is_empty() {
+                return Ok(false);
+            }
+            conn.delete(self.key(token))?;
+            Ok(live_data(&value, SystemTime::now())?.is_some())
+        })
+    }
+    /// removes every expired session of the store, returns how many were removed
+    ///
+    /// they are removed with [`Connection::expire`], so subscribers of the changes are told they expired,
+    /// or deleted if the server does not support it
+    pub fn purge_expired<T: Read + Write>(&self, conn: &mut Connection<T>) -> Result<usize, Error> {
+        conn.transact(|conn| {
+            let now = SystemTime::now();
+            let mut purged = 0;
+            for (key, value) in conn.scan(&self.prefix, b"")? {
+                if live_data(&value, now)?.is_none() {
+                    match conn.expire(&key) {
+                        Err(Error::Unsupported) => conn.delete(key)?,
+                        result => result?,
+                    }
+                    purged += 1;
+                }
+            }
+            Ok(purged)
+        })
+    }
+
+    fn key(&self, token: &str) -> Vec<u8> {
+        let mut key = self.prefix.clone();
+        key.extend_from_slice(token.as_bytes());
+        key
+    }
+    fn value(&self, data: &[u8]) -> Vec<u8> {
+        let expiry = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .expect("pathkvs session: the clock is before the unix epoch")
+            .saturating_add(self.ttl);
+        let mut value = Vec::with_capacity(EXPIRY_LEN + data.len());
+        value.extend_from_slice(&expiry.as_secs().to_le_bytes());
+        value.extend_from_slice(&expiry.subsec_nanos().to_le_bytes());
+        value.extend_from_slice(data);
+        value
+    }
+}
+
+fn assert_normal<T: Read + Write>(conn: &Connection<T>) {
+    assert!(
+        conn.mode().is_normal(),
+        "pathkvs session: the connection must not be in a transaction or snapshot"
+    );
+}
+
+/// the data of a stored session, `None` if there is no session or it expired at `now`
+fn live_data(value: &[u8], now: SystemTime) -> Result<Option<&[u8]>, Error> {
+    if value.is_empty() {
+        return Ok(None);
+    }
+    if value.len() < EXPIRY_LEN {
+        return Err(Error::InvalidValue("session without expiry"));
+    }
+    let (expiry, data) = value.split_at(EXPIRY_LEN);
+    let seconds = u64::from_le_bytes(expiry[..8].try_into().unwrap());
+    let nanoseconds = u32::from_le_bytes(expiry[8..].try_into().unwrap());
+    if nanoseconds >= 1_000_000_000 {
+        return Err(Error::InvalidValue("session with invalid expiry"));
+    }
+    let expiry = SystemTime::UNIX_EPOCH.checked_add(Duration::new(seconds, nanoseconds));
+    Ok(expiry.is_none_or(|expiry| now < expiry).then_some(data))
+}
+
+fn new_token() -> Result<String, Error> {
+    let mut bytes = [0; TOKEN_BYTES];
+    getrandom::fill(&mut bytes).map_err(|error| Error::Io(error.into()))?;
+    Ok(bytes.iter().map(|byte| format!("{byte:02x}")).collect())
+}
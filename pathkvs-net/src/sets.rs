@@ -0,0 +1,123 @@
+//! sets stored as one key per member, over a transaction or a connection
+//!
+//! the member `M` of the set with prefix `tags/` is stored under the key `tags/M` with the value `1`,
+//! so the members can also be counted and listed as plain keys
+//!
+//! adding and removing members are plain writes that never read the set, so transactions that only change
+//! membership never conflict with each other, and the last write of each member wins, [`Set::contains`]
+//! reads the member, and [`Set::len`] and [`Set::iter`] read the whole set, so they conflict with any change to it
+//!
+//! every key under the prefix is taken as a member, so the prefix of a set must not start with the prefix of another
+
+use std::io::{Read, Write};
+
+use pathkvs_core::Transaction;
+
+use crate::{client::Connection, Error};
+
+/// the value of the key of each member
+const MEMBER: &[u8] = b"1";
+
+/// what a [`Set`] is stored in, implemented for [`Transaction`] and [`Connection`]
+pub trait SetStore {
+    fn contains_key(&mut self, key: &[u8]) -> Result<bool, Error>;
+    fn write_key(&mut self, key: &[u8], value: &[u8]) -> Result<(), Error>;
+    fn delete_key(&mut self, key: &[u8]) -> Result<(), Error>;
+    fn count_prefix(&mut self, prefix: &[u8]) -> Result<u32, Error>;
+    fn list_prefix(&mut self, prefix: &[u8]) -> Result<Vec<Vec<u8>>, Error>;
+}
+
+impl SetStore for Transaction<'_> {
+    fn contains_key(&mut self, key: &[u8]) -> Result<bool, Error> {
+        Ok(!self.read(key).is_empty())
+    }
+    /// a write refused by the validator of the database fails the commit instead
+    fn write_key(&mut self, key: &[u8], value: &[u8]) -> Result<(), Error> {
+        self.write(key, value);
+        Ok(())
+    }
+    fn delete_key(&mut self, key: &[u8]) -> Result<(), Error> {
+        self.delete(key);
+        Ok(())
+    }
+    fn count_prefix(&mut self, prefix: &[u8]) -> Result<u32, Error> {
+        Ok(self.count(prefix, b""))
+    }
+    fn list_prefix(&mut self, prefix: &[u8]) -> Result<Vec<Vec<u8>>, Error> {
+        Ok(self
+            .list(prefix, b"")
+            .into_iter()
+            .map(<[u8]>::to_vec)
+            .collect())
+    }
+}
+
+impl<T: Read + Write> SetStore for Connection<T> {
+    fn contains_key(&mut self, key: &[u8]) -> Result<bool, Error> {
+        Ok(self.len(key)? != 0)
+    }
+    fn write_key(&mut self, key: &[u8], value: &[u8]) -> Result<(), Error> {
+        self.write(key, value)
+    }
+    fn delete_key(&mut self, key: &[u8]) -> Result<(), Error> {
+        self.delete(key)
+    }
+    fn count_prefix(&mut self, prefix: &[u8]) -> Result<u32, Error> {
+        self.count(prefix, b"")
+    }
+    fn list_prefix(&mut self, prefix: &[u8]) -> Result<Vec<Vec<u8>>, Error> {
+        self.list(prefix, b"")
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Set {
+    prefix: Vec<u8>,
+}
+
+impl Set {
+    /// panics if the prefix is empty
+    pub fn new(prefix: impl Into<Vec<u8>>) -> Self {
+        let prefix = prefix.into();
+        assert!(
+            !prefix.is_empty(),
+            "pathkvs: the prefix of a set cannot be empty"
+        );
+        Self { prefix }
+    }
+    pub fn prefix(&self) -> &[u8] {
+        &self.prefix
+    }
+
+    /// adding a member that is already in the set does nothing
+    pub fn add(&self, store: &mut impl SetStore, member: impl AsRef<[u8]>) -> Result<(), Error> {
+        store.write_key(&self.key(member.as_ref()), MEMBER)
+    }
+    /// removing a member that is not in the set does nothing
+    pub fn remove(&self, store: &mut impl SetStore, member: impl AsRef<[u8]>) -> Result<(), Error> {
+        store.delete_key(&self.key(member.as_ref()))
+    }
+    pub fn contains(
+        &self,
+        store: &mut impl SetStore,
+        member: impl AsRef<[u8]>,
+    ) -> Result<bool, Error> {
+        store.contains_key(&self.key(member.as_ref()))
+    }
+    pub fn len(&self, store: &mut impl SetStore) -> Result<u32, Error> {
+        store.count_prefix(&self.prefix)
+    }
+    pub fn is_empty(&self, store: &mut impl SetStore) -> Result<bool, Error> {
+        Ok(self.len(store)? == 0)
+    }
+    /// the members, in order
+    pub fn iter(&self, store: &mut impl SetStore) -> Result<impl Iterator<Item = Vec<u8>>, Error> {
+        let prefix_len = self.prefix.len();
+        let keys = store.list_prefix(&self.prefix)?;
+        Ok(keys.into_iter().map(move |key| key[prefix_len..].to_vec()))
+    }
+
+    fn key(&self, member: &[u8]) -> Vec<u8> {
+        [&self.prefix, member].concat()
+    }
+}
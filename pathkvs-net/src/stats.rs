@@ -0,0 +1,77 @@
+//! counters of the requests served, for implementations of [`Server::on_command`](crate::server::Server::on_command)
+
+use std::{collections::BTreeMap, time::Duration};
+
+/// a request served by [`serve`](crate::server::serve), given to [`Server::on_command`](crate::server::Server::on_command)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CommandEvent {
+    /// the name of the request
+    pub request: &'static str,
+    /// the total length of the keys it named, the start and end of counts, lists and scans included
+    pub key_bytes: u64,
+    /// the bytes it read and wrote on the connection
+    pub bytes: u64,
+    /// the time from its first byte to its response
+    pub elapsed: Duration,
+    /// `ok`, or the name of the status the server responded with instead, such as `conflict`, `busy` or `unauthorized`
+    pub outcome: &'static str,
+}
+
+/// how many times a request was served, the bytes it read and wrote on the connection, and the time it took
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CommandCount {
+    pub count: u64,
+    pub bytes: u64,
+    pub time: Duration,
+}
+
+impl CommandCount {
+    fn add(&mut self, other: CommandCount) {
+        self.count += other.count;
+        self.bytes += other.bytes;
+        self.time += other.time;
+    }
+}
+
+/// the counters of each request by name, kept per connection or for the whole server,
+/// the counters of a connection can be merged into those of the server when it ends
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CommandStats {
+    commands: BTreeMap<&'static str, CommandCount>,
+}
+
+impl CommandStats {
+    pub fn record(&mut self, command: &CommandEvent) {
+        self.commands
+            .entry(command.request)
+            .or_default()
+            .add(CommandCount {
+                count: 1,
+                bytes: command.bytes,
+                time: command.elapsed,
+            });
+    }
+    pub fn merge(&mut self, other: &CommandStats) {
+        for (&request, &count) in &other.commands {
+            self.commands.entry(request).or_default().add(count);
+        }
+    }
+    /// the counters of the request, zero if it was never served
+    pub fn get(&self, request: &str) -> CommandCount {
+        self.commands.get(request).copied().unwrap_or_default()
+    }
+    /// the counters of every request added together
+    pub fn total(&self) -> CommandCount {
+        let mut total = CommandCount::default();
+        for &count in self.commands.values() {
+            total.add(count);
+        }
+        total
+    }
+    /// the requests served at least once, ordered by name
+    pub fn iter(&self) -> impl Iterator<Item = (&'static str, CommandCount)> + '_ {
+        self.commands
+            .iter()
+            .map(|(&request, &count)| (request, count))
+    }
+}
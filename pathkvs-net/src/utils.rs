@@ -1,9 +1,12 @@
 use pathkvs_core::error::ProtocolError;
 use std::{
-    io::{Error, Read, Write},
+    io::{Error, ErrorKind, Read, Write},
     time::Duration,
 };
 
+/// how much [`ReadEx::read_vec`] allocates before any of the bytes are read
+const MAX_RESERVE: usize = 64 * 1024;
+
 pub trait ReadEx: Read {
     fn read_u8(&mut self) -> Result<u8, Error> {
         let mut buf = [0];
@@ -15,11 +18,18 @@ pub trait ReadEx: Read {
         self.read_exact(&mut buf)?;
         Ok(u32::from_le_bytes(buf))
     }
-    fn read_vec(&mut self, len: usize) -> Result<Vec<u8>, Error> {
-        let mut buf = Vec::new();
-        buf.reserve_exact(len);
-        unsafe { buf.set_len(len) };
+    fn read_u64(&mut self) -> Result<u64, Error> {
+        let mut buf = [0; 8];
         self.read_exact(&mut buf)?;
+        Ok(u64::from_le_bytes(buf))
+    }
+    /// the length comes from the peer, so the vector only grows past [`MAX_RESERVE`] as the bytes arrive
+    fn read_vec(&mut self, len: usize) -> Result<Vec<u8>, Error> {
+        let mut buf = Vec::with_capacity(len.min(MAX_RESERVE));
+        Read::take(&mut *self, len as u64).read_to_end(&mut buf)?;
+        if buf.len() != len {
+            return Err(ErrorKind::UnexpectedEof.into());
+        }
         Ok(buf)
     }
     fn read_vec_lengthed(&mut self, max_len: u32) -> Result<Vec<u8>, Error> {
@@ -29,6 +39,14 @@ pub trait ReadEx: Read {
         }
         self.read_vec(len as usize)
     }
+    /// a byte that says whether the value is there, followed by it, see [`WriteEx::write_opt_lengthed`]
+    fn read_opt_lengthed(&mut self, max_len: u32) -> Result<Option<Vec<u8>>, Error> {
+        match self.read_u8()? {
+            0 => Ok(None),
+            1 => Ok(Some(self.read_vec_lengthed(max_len)?)),
+            _ => Err(ProtocolError.into()),
+        }
+    }
     fn read_duration(&mut self) -> Result<Duration, Error> {
         let mut seconds = [0; 8];
         self.read_exact(&mut seconds)?;
@@ -51,12 +69,24 @@ pub trait WriteEx: Write {
     fn write_u32(&mut self, value: u32) -> Result<(), Error> {
         self.write_all(&u32::to_le_bytes(value))
     }
+    fn write_u64(&mut self, value: u64) -> Result<(), Error> {
+        self.write_all(&u64::to_le_bytes(value))
+    }
     fn write_vec_lengthed(&mut self, bytes: &[u8]) -> Result<(), Error> {
         assert!(bytes.len() <= u32::MAX as usize);
         let len = bytes.len() as u32;
         self.write_u32(len)?;
         self.write_all(bytes)
     }
+    fn write_opt_lengthed(&mut self, bytes: Option<&[u8]>) -> Result<(), Error> {
+        match bytes {
+            Some(bytes) => {
+                self.write_u8(1)?;
+                self.write_vec_lengthed(bytes)
+            }
+            None => self.write_u8(0),
+        }
+    }
     fn write_duration(&mut self, duration: Duration) -> Result<(), Error> {
         self.write_all(&u64::to_le_bytes(duration.as_secs()))?;
         self.write_all(&u32::to_le_bytes(duration.subsec_nanos()))
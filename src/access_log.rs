@@ -0,0 +1,93 @@
+use std::{
+    fs::{File, OpenOptions},
+    io::{Error, LineWriter, Write},
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use chrono::Local;
+use pathkvs_net::stats::CommandEvent;
+
+/// the file of `serve --access-log`, with one line per request, kept apart from the messages the server prints
+///
+/// once it is larger than `max_bytes`, or older than `max_age`, it is renamed with the time it was rotated
+/// appended to its name and a new one is started, rotated files are never deleted by the server
+pub struct AccessLog {
+    path: PathBuf,
+    max_bytes: u64,
+    max_age: Option<Duration>,
+    current: Mutex<Current>,
+}
+
+struct Current {
+    file: LineWriter<File>,
+    len: u64,
+    /// when the server started writing to it, the age of a file left by a previous run starts over
+    opened: Instant,
+}
+
+impl AccessLog {
+    /// appends to the file if it already exists
+    pub fn open(
+        path: impl Into<PathBuf>,
+        max_bytes: u64,
+        max_age: Option<Duration>,
+    ) -> Result<Self, Error> {
+        let path = path.into();
+        let current = Current::open(&path)?;
+        Ok(Self {
+            path,
+            max_bytes,
+            max_age,
+            current: Mutex::new(current),
+        })
+    }
+    /// errors are printed instead of returned, so a full disk doesn't stop the server
+    pub fn log(&self, id: u64, peer: &str, command: &CommandEvent) {
+        let line = format!(
+            "{} conn={id} peer={peer} op={} key_bytes={} bytes={} micros={} outcome={}\n",
+            Local::now().format("%Y-%m-%dT%H:%M:%S%.3f"),
+            command.request,
+            command.key_bytes,
+            command.bytes,
+            command.elapsed.as_micros(),
+            command.outcome,
+        );
+        let mut current = self.current.lock().unwrap();
+        let result = (|| {
+            if current.len >= self.max_bytes
+                || self
+                    .max_age
+                    .is_some_and(|max_age| current.opened.elapsed() >= max_age)
+            {
+                self.rotate(&mut current)?;
+            }
+            current.file.write_all(line.as_bytes())?;
+            current.len += line.len() as u64;
+            Ok::<_, Error>(())
+        })();
+        if let Err(error) = result {
+            eprintln!("não foi possível escrever no log de acesso: {error}");
+        }
+    }
+    fn rotate(&self, current: &mut Current) -> Result<(), Error> {
+        current.file.flush()?;
+        let mut rotated = self.path.clone().into_os_string();
+        rotated.push(Local::now().format(".%Y%m%d-%H%M%S%.3f").to_string());
+        std::fs::rename(&self.path, rotated)?;
+        *current = Current::open(&self.path)?;
+        Ok(())
+    }
+}
+
+impl Current {
+    fn open(path: &Path) -> Result<Self, Error> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Self {
+            len: file.metadata()?.len(),
+            file: LineWriter::new(file),
+            opened: Instant::now(),
+        })
+    }
+}
@@ -0,0 +1,168 @@
+//! the users of the server and the rules of which role may access each prefix, both kept in the system keyspace,
+//! so they are persisted, backed up and replicated along with the data
+//!
+//! a user is stored under [`system::USERS`] followed by its name, with its role, a line break and the sha-256
+//! of its token in hexadecimal, a rule under [`system::ACL`] followed by `read/` or `write/` and the prefix, with the role
+//!
+//! while there is no user and no rule every connection may access every key, as before they existed
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use pathkvs_core::system;
+use pathkvs_net::client::{Access, AclRule};
+use sha2::{Digest, Sha256};
+
+/// the parts of the system keyspace only admins may access once the list is in use
+const PROTECTED: [&[u8]; 2] = [system::USERS, system::ACL];
+
+/// the names of the users and the rules, kept in memory so requests don't read the database to be checked,
+/// and updated by a commit hook on the keys they are stored in
+#[derive(Default)]
+pub struct Acl {
+    users: BTreeSet<Vec<u8>>,
+    read: BTreeMap<Vec<u8>, String>,
+    write: BTreeMap<Vec<u8>, String>,
+}
+
+impl Acl {
+    pub fn load(db: &pathkvs_core::Database) -> Self {
+        let mut acl = Self::default();
+        for section in PROTECTED {
+            for (key, value) in db.scan(section, b"") {
+                acl.apply(&key, &value);
+            }
+        }
+        acl
+    }
+    /// applies a change to one of the keys of [`system::USERS`] or [`system::ACL`]
+    pub fn apply(&mut self, key: &[u8], value: &[u8]) {
+        if let Some(name) = key.strip_prefix(system::USERS) {
+            if value.is_empty() {
+                self.users.remove(name);
+            } else {
+                self.users.insert(name.to_vec());
+            }
+            return;
+        }
+        let Some(rule) = key.strip_prefix(system::ACL) else {
+            return;
+        };
+        let (rules, prefix) = if let Some(prefix) = rule.strip_prefix(b"read/") {
+            (&mut self.read, prefix)
+        } else if let Some(prefix) = rule.strip_prefix(b"write/") {
+            (&mut self.write, prefix)
+        } else {
+            return;
+        };
+        if value.is_empty() {
+            rules.remove(prefix);
+        } else {
+            rules.insert(prefix.to_vec(), String::from_utf8_lossy(value).into_owned());
+        }
+    }
+    fn in_use(&self) -> bool {
+        !self.users.is_empty() || !self.read.is_empty() || !self.write.is_empty()
+    }
+    fn rules(&self, access: Access) -> &BTreeMap<Vec<u8>, String> {
+        match access {
+            Access::Read => &self.read,
+            Access::Write => &self.write,
+        }
+    }
+    /// whether a connection that is not admin, authenticated with `role` if any, may access `key` this way
+    pub fn permits(&self, role: Option<&str>, key: &[u8], access: Access) -> bool {
+        if !self.in_use() {
+            return true;
+        }
+        if PROTECTED.iter().any(|section| key.starts_with(section)) {
+            return false;
+        }
+        let rule = self
+            .rules(access)
+            .iter()
+            .filter(|(prefix, _)| key.starts_with(prefix))
+            .max_by_key(|(prefix, _)| prefix.len());
+        match rule {
+            Some((_, required)) => role == Some(required.as_str()),
+            None => true,
+        }
+    }
+    /// like [`Acl::permits`] for reading every key starting with `start`, so the rules of the prefixes
+    /// inside of the range have to permit it too
+    pub fn permits_range(&self, role: Option<&str>, start: &[u8]) -> bool {
+        if !self.in_use() {
+            return true;
+        }
+        // ranges outside of the system keyspace don't see the keys in it
+        if system::is_system_key(start)
+            && PROTECTED.iter().any(|section| section.starts_with(start))
+        {
+            return false;
+        }
+        self.permits(role, start, Access::Read)
+            && self
+                .read
+                .iter()
+                .filter(|(prefix, _)| prefix.starts_with(start))
+                .all(|(_, required)| role == Some(required.as_str()))
+    }
+    pub fn list(&self) -> Vec<AclRule> {
+        let mut rules = [Access::Read, Access::Write]
+            .into_iter()
+            .flat_map(|access| {
+                self.rules(access)
+                    .iter()
+                    .map(move |(prefix, role)| AclRule {
+                        prefix: prefix.clone(),
+                        access,
+                        role: role.clone(),
+                    })
+            })
+            .collect::<Vec<_>>();
+        rules.sort_by(|a, b| a.prefix.cmp(&b.prefix));
+        rules
+    }
+}
+
+pub fn user_key(name: &str) -> Vec<u8> {
+    system::key(system::USERS, name.as_bytes())
+}
+
+pub fn rule_key(prefix: &[u8], access: Access) -> Vec<u8> {
+    let access: &[u8] = match access {
+        Access::Read => b"read/",
+        Access::Write => b"write/",
+    };
+    system::key(system::ACL, &[access, prefix].concat())
+}
+
+pub fn user_value(role: &str, token: &[u8]) -> Vec<u8> {
+    format!("{role}\n{}", hash(token)).into_bytes()
+}
+
+/// the role of the user if `token` is its token
+pub fn check_token<'a>(value: &'a [u8], token: &[u8]) -> Option<&'a str> {
+    let value = std::str::from_utf8(value).ok()?;
+    let (role, hash) = value.rsplit_once('\n')?;
+    (hash == self::hash(token)).then_some(role)
+}
+
+/// whether `token` is `expected`, comparing their hashes, so the time it takes says nothing about `expected`
+pub fn same_token(expected: &[u8], token: &[u8]) -> bool {
+    hash(expected) == hash(token)
+}
+
+/// the role of a stored user
+pub fn role(value: &[u8]) -> &str {
+    std::str::from_utf8(value)
+        .ok()
+        .and_then(|value| value.rsplit_once('\n'))
+        .map_or("", |(role, _)| role)
+}
+
+fn hash(token: &[u8]) -> String {
+    Sha256::digest(token)
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect()
+}
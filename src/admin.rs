@@ -0,0 +1,215 @@
+use std::{
+    io::{Read, Write},
+    time::{Duration, SystemTime},
+};
+
+use chrono::{DateTime, Local};
+
+use pathkvs_net::{
+    client::{Access, Connection, ConnectionMode},
+    Error,
+};
+
+use crate::{progress, utils::DisplayBytesEx, AclAccess, AclCommand, AdminCommand, UserCommand};
+
+pub fn admin(
+    mut conn: Connection<impl Read + Write>,
+    token: Option<&str>,
+    command: AdminCommand,
+) -> Result<(), std::io::Error> {
+    let result = (|| {
+        if let Some(token) = token {
+            conn.authenticate(token)?;
+        }
+        match command {
+            AdminCommand::Stats => {
+                for (name, value) in conn.stats()? {
+                    println!("{name}: {value}");
+                }
+            }
+            AdminCommand::Info => {
+                let info = conn.info()?;
+                println!("versão: {}", info.version);
+                for section in &info.sections {
+                    println!();
+                    println!("[{}]", section.name);
+                    for (name, value) in &section.fields {
+                        println!("{name}: {value}");
+                    }
+                }
+            }
+            AdminCommand::Compact { keep_days } => {
+                let progress = progress::waiting("compactando");
+                conn.compact_keeping(Duration::from_secs(keep_days * 24 * 60 * 60))?;
+                progress.finish_and_clear();
+                println!("compactação concluída");
+            }
+            AdminCommand::Gc => {
+                let progress = progress::waiting("coletando o lixo");
+                conn.gc()?;
+                progress.finish_and_clear();
+                println!("coleta de lixo concluída");
+            }
+            AdminCommand::Connections => {
+                for connection in conn.connections()? {
+                    let mode = match connection.mode {
+                        ConnectionMode::Normal => "normal",
+                        ConnectionMode::Transaction => "transação",
+                        ConnectionMode::Snapshot => "snapshot",
+                    };
+                    let name = match connection.name.as_str() {
+                        "" => String::new(),
+                        name => format!(" {name}"),
+                    };
+                    let transaction = match connection.transaction {
+                        Some(age) => format!(", transação aberta há {:.1}s", age.as_secs_f64()),
+                        None => String::new(),
+                    };
+                    println!(
+                        "{} {}{name} {mode} {} comando(s) {} byte(s), ociosa há {:.1}s{transaction}",
+                        connection.id,
+                        connection.peer,
+                        connection.commands,
+                        connection.bytes,
+                        connection.idle.as_secs_f64()
+                    );
+                }
+            }
+            AdminCommand::Slowlog => {
+                for command in conn.slow_log()? {
+                    let time = DateTime::<Local>::from(SystemTime::UNIX_EPOCH + command.time);
+                    let name = match command.name.as_str() {
+                        "" => String::new(),
+                        name => format!(" {name}"),
+                    };
+                    println!(
+                        "{} {} conexão {} {}{name} {} {:.1}ms, {} byte(s) de chaves, {} byte(s), {}",
+                        command.id,
+                        time.format("%Y-%m-%dT%H:%M:%S%.3f"),
+                        command.connection,
+                        command.peer,
+                        command.request,
+                        command.elapsed.as_secs_f64() * 1000.0,
+                        command.key_bytes,
+                        command.bytes,
+                        command.outcome
+                    );
+                }
+            }
+            AdminCommand::Replication => {
+                for replica in conn.replication_status()? {
+                    let acked = DateTime::<Local>::from(SystemTime::UNIX_EPOCH + replica.acked);
+                    println!(
+                        "{} commit nº {}, confirmado em {}, atrasada {} commit(s), {} byte(s)",
+                        replica.name,
+                        replica.seq,
+                        acked.format("%Y-%m-%d %H:%M:%S%.3f"),
+                        replica.lag_commits,
+                        replica.lag_bytes
+                    );
+                }
+            }
+            AdminCommand::Kill { id } => {
+                if conn.kill(id)? {
+                    println!("conexão {id} encerrada");
+                } else {
+                    eprintln!("a conexão {id} não existe ou não pode ser encerrada");
+                    std::process::exit(1);
+                }
+            }
+            AdminCommand::Drain => {
+                conn.drain()?;
+                println!("o servidor está drenando, novas conexões serão recusadas");
+            }
+            AdminCommand::Upgrade => {
+                conn.upgrade()?;
+                println!("o novo processo do servidor assume as conexões novas quando as atuais terminarem");
+            }
+            AdminCommand::Reload => match conn.reload() {
+                Err(Error::Io(error)) if error.kind() == std::io::ErrorKind::InvalidData => {
+                    eprintln!("configuração rejeitada: {error}");
+                    std::process::exit(1);
+                }
+                result => {
+                    result?;
+                    println!("configuração recarregada");
+                }
+            },
+            AdminCommand::Procedure { name, file } => {
+                let source = std::fs::read_to_string(file)?;
+                conn.define_procedure(&name, &source)?;
+                println!("procedimento {name} registrado");
+            }
+            AdminCommand::User { command } => match command {
+                UserCommand::Add { name, role, token } => {
+                    let token = match token {
+                        Some(token) => token,
+                        None => {
+                            let mut token = String::new();
+                            std::io::stdin().read_line(&mut token)?;
+                            token.trim_end_matches(['\r', '\n']).to_string()
+                        }
+                    };
+                    if name.is_empty() || role.is_empty() || token.is_empty() {
+                        eprintln!("o nome, o papel e o token não podem ser vazios");
+                        std::process::exit(1);
+                    }
+                    conn.add_user(&name, &role, token)?;
+                    println!("usuário {name} adicionado com o papel {role}");
+                }
+                UserCommand::Remove { name } => {
+                    if conn.remove_user(&name)? {
+                        println!("usuário {name} removido");
+                    } else {
+                        println!("o usuário {name} não existe");
+                    }
+                }
+                UserCommand::List => {
+                    for (name, role) in conn.users()? {
+                        println!("{name} {role}");
+                    }
+                }
+            },
+            AdminCommand::Acl { command } => match command {
+                AclCommand::Set {
+                    prefix,
+                    access,
+                    role,
+                } => {
+                    let access = match access {
+                        AclAccess::Read => Access::Read,
+                        AclAccess::Write => Access::Write,
+                    };
+                    let role = role.filter(|role| !role.is_empty());
+                    conn.set_acl(&prefix, access, role.as_deref())?;
+                    println!("regra definida");
+                }
+                AclCommand::List => {
+                    for rule in conn.acl()? {
+                        let access = match rule.access {
+                            Access::Read => "read",
+                            Access::Write => "write",
+                        };
+                        println!("{}* {access} {}", rule.prefix.display(), rule.role);
+                    }
+                }
+            },
+        }
+        Ok::<(), Error>(())
+    })();
+    match result {
+        Err(Error::Unauthorized) => {
+            eprintln!("não autorizado, informe um token de administrador válido com --token");
+            std::process::exit(1);
+        }
+        Err(Error::Unsupported) => {
+            eprintln!("o servidor não suporta esse comando");
+            std::process::exit(1);
+        }
+        Err(Error::ProcedureFailed(message)) => {
+            eprintln!("procedimento inválido: {message}");
+            std::process::exit(1);
+        }
+        result => Ok(result?),
+    }
+}
@@ -0,0 +1,168 @@
+use std::{
+    io::{Error, ErrorKind, Read, Write},
+    path::Path,
+    time::SystemTime,
+};
+
+use chrono::{DateTime, Local};
+use pathkvs_core::{
+    codec::{self, Header, RawCodec, Record, RecordCodec, RecordReader},
+    error::TransactionError,
+    system, Database,
+};
+use pathkvs_net::client::Connection;
+
+use crate::progress;
+
+/// a backup is a single transaction of the whole server, past this its values wait in a temporary file until the commit
+const SPILL_THRESHOLD: usize = 64 << 20;
+
+/// saves a consistent snapshot of the server into a new database file at `output`
+pub fn backup(
+    mut conn: Connection<impl Read + Write>,
+    output: impl AsRef<Path>,
+) -> Result<(), Error> {
+    let database = Database::create(output)?;
+    let mut ts = database.start_writes().spill_threshold(SPILL_THRESHOLD);
+    let mut count = 0u64;
+    let progress = progress::keys(None, "copiando");
+    let time = conn.backup(|key, value| {
+        ts.write(&key, &value);
+        count += 1;
+        progress.inc(1);
+        Ok(())
+    })?;
+    progress.set_message("salvando");
+    ts.commit()?;
+    progress.finish_and_clear();
+    match time {
+        Some(time) => {
+            let time = DateTime::<Local>::from(time).format("%Y-%m-%d %H:%M:%S");
+            println!("salvo {count} chave(s) do snapshot de {time}");
+        }
+        None => println!("o banco está vazio, nada foi salvo"),
+    }
+    Ok(())
+}
+
+/// replays the database file at `input` into a database in memory, with the codec its header names,
+/// without changing the file, and fails if any record of it is not whole, unlike opening it,
+/// which discards an incomplete end, records have no checksum, so only malformed ones are found
+pub fn verify(input: impl AsRef<Path>) -> Result<(), Error> {
+    let input = input.as_ref();
+    if !input.is_file() {
+        return Err(Error::new(
+            ErrorKind::NotFound,
+            format!("{} não existe", input.display()),
+        ));
+    }
+    let bytes = std::fs::read(input)?;
+    let database = Database::memory();
+    let mut rest = bytes.as_slice();
+    let header = codec::read_header(&mut rest)
+        .map_err(|error| Error::new(ErrorKind::InvalidData, format!("cópia inválida: {error}")))?;
+    match header {
+        Header::Missing if !bytes.is_empty() => {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "cópia inválida, o cabeçalho está incompleto",
+            ));
+        }
+        // from before the header, the records start right away
+        Header::Legacy(_) => rest = bytes.as_slice(),
+        _ => {}
+    }
+    let codec = match header.codec() {
+        Some(id) => codec::by_id(id).ok_or_else(|| {
+            Error::new(
+                ErrorKind::InvalidData,
+                format!("cópia inválida, o codec {id} é desconhecido"),
+            )
+        })?,
+        None => Box::new(RawCodec),
+    };
+    let mut commits = 0u64;
+    let mut last_time = None;
+    let progress = progress::bytes(Some(bytes.len() as u64), "conferindo");
+    let mut rest = RecordReader::new(rest);
+    while !rest.get_ref().is_empty() {
+        let offset = bytes.len() - rest.get_ref().len();
+        progress.set_position(offset as u64);
+        let record = codec.decode(&mut rest).map_err(|error| {
+            Error::new(
+                ErrorKind::InvalidData,
+                format!("cópia inválida, o registro no byte {offset} está corrompido ou incompleto, depois de {commits} commit(s) válido(s): {error}"),
+            )
+        })?;
+        let (time, changes, appends) = match record {
+            Record::Commit { time, changes } => (time, changes, Default::default()),
+            Record::Append {
+                time,
+                changes,
+                appends,
+            } => (time, changes, appends),
+            _ => unreachable!("the codecs of pathkvs only decode commits and appends"),
+        };
+        let mut ts = database.start_writes();
+        for (key, value) in changes.iter() {
+            match value {
+                Some(value) => ts.write(key, value),
+                None => ts.delete(key),
+            }
+        }
+        for (key, bytes) in appends.iter() {
+            ts.append(key, bytes);
+        }
+        ts.commit()?;
+        commits += 1;
+        last_time = Some(time);
+    }
+    progress.finish_and_clear();
+    let keys = database.count(b"", b"") + database.count(system::PREFIX, b"");
+    match last_time {
+        Some(time) => {
+            let time = DateTime::<Local>::from(SystemTime::UNIX_EPOCH + time);
+            println!(
+                "cópia válida: formato {}, {commits} commit(s), {keys} chave(s), {} byte(s), último commit em {}",
+                header.version(),
+                bytes.len(),
+                time.format("%Y-%m-%d %H:%M:%S")
+            );
+        }
+        None => println!("cópia válida, mas vazia"),
+    }
+    Ok(())
+}
+
+/// writes every key of the database file at `input` into the server in a single transaction
+pub fn restore(
+    mut conn: Connection<impl Read + Write>,
+    input: impl AsRef<Path>,
+) -> Result<(), Error> {
+    let input = input.as_ref();
+    if !input.is_file() {
+        return Err(Error::new(
+            ErrorKind::NotFound,
+            format!("{} não existe", input.display()),
+        ));
+    }
+    let database = Database::open(input)?;
+    let entries = database.scan(b"", b"");
+    let progress = progress::keys(Some(entries.len() as u64), "restaurando");
+    conn.start_transaction()?;
+    for (key, value) in &entries {
+        conn.write(key, value)?;
+        progress.inc(1);
+    }
+    progress.set_message("comitando");
+    let committed = conn.commit();
+    progress.finish_and_clear();
+    match committed {
+        Ok(_) => {
+            println!("restaurado {} chave(s)", entries.len());
+            Ok(())
+        }
+        Err(TransactionError::Conflict) => unreachable!("a write only transaction cannot conflict"),
+        Err(TransactionError::Io(error)) => Err(error),
+    }
+}
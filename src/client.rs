@@ -1,29 +1,47 @@
 use chrono::{DateTime, Local};
-use pathkvs_core::error::{TransactionConflict, TransactionError, TransposeConflict};
-use pathkvs_net::client::ConnectionMode;
-use std::{io::BufRead, time::Duration};
+use pathkvs_core::{
+    content::ContentType,
+    error::{TransactionConflict, TransactionError, TransposeConflict},
+    system,
+};
+use pathkvs_net::client::{Connection, ConnectionMode};
+use std::{
+    collections::{BTreeMap, HashMap},
+    io::{BufRead, Read, Write},
+    time::{Instant, SystemTime},
+};
 
 const CLEAR: &str = "\x1B[H\x1B[2J\x1B[3J";
-const RETURN: &str = "\x1B[1A\x1B[2K\x1B[G";
 
-use crate::utils::{parse_general_timestamp, DisplayBytesEx};
+use crate::utils::{display_typed, parse_general_timestamp, DisplayBytesEx};
 
-pub fn client() -> Result<(), std::io::Error> {
-    let addr = "127.0.0.1:6314";
-    let conn = std::net::TcpStream::connect(addr)?;
-    conn.set_read_timeout(Some(Duration::from_secs(1)))?;
-    conn.set_write_timeout(Some(Duration::from_secs(1)))?;
-    let mut conn = pathkvs_net::client::Connection::new(conn);
+pub fn client(mut conn: Connection<impl Read + Write>, target: &str) -> Result<(), std::io::Error> {
     let stdin = std::io::stdin();
     let handle = stdin.lock();
     let mut lines = handle.lines();
     let mut read_count = 0;
     let mut write_count = 0;
-    println!("{CLEAR}PATHKVS: cliente interativo, conectado a {addr}");
+    let mut snapshot_display = String::new();
+    let mut log = TransactionLog::default();
+    let mut timing = false;
+    // what the current snapshot looks at, and the ones named with =bookmark
+    let mut snapshot_target = None;
+    let mut bookmarks = BTreeMap::new();
+    println!("{CLEAR}PATHKVS: cliente interativo, conectado a {target}");
     println!("use o comando \"=h\" para ver a ajuda");
     println!("aperte Ctrl+C para sair");
     println!();
-    while let Some(line) = lines.next() {
+    loop {
+        match conn.mode() {
+            ConnectionMode::Normal => print!("> "),
+            ConnectionMode::Transaction => print!("txn({read_count}r/{write_count}w)> "),
+            ConnectionMode::Snapshot => print!("snap@{snapshot_display}> "),
+        }
+        std::io::stdout().flush()?;
+        let Some(line) = lines.next() else {
+            println!();
+            break;
+        };
         let line = line?;
         if line.is_empty() {
             continue;
@@ -33,42 +51,118 @@ pub fn client() -> Result<(), std::io::Error> {
                 "s" | "start" => {
                     let mode = conn.mode();
                     conn.start_transaction()?;
+                    read_count = 0;
+                    write_count = 0;
+                    log = TransactionLog::default();
                     match mode {
-                        ConnectionMode::Normal => println!("{RETURN}começado a transação"),
+                        ConnectionMode::Normal => println!("começado a transação"),
                         ConnectionMode::Transaction => {
-                            println!(
-                                "{RETURN}começado a transação, descartado a transação anterior"
-                            )
+                            println!("começado a transação, descartado a transação anterior")
                         }
                         ConnectionMode::Snapshot => {
-                            println!("{RETURN}começado a transação, finalizado a snapshot anterior")
+                            println!("começado a transação, finalizado a snapshot anterior")
                         }
                     }
                 }
+                line if line.starts_with("snaps") => {
+                    let limit = line[5..].trim().parse().unwrap_or(10);
+                    let commits = conn.recent_commits(limit)?;
+                    if commits.is_empty() {
+                        println!("snaps: nenhum commit foi feito");
+                    } else {
+                        for commit in &commits {
+                            let time =
+                                DateTime::<Local>::from(SystemTime::UNIX_EPOCH + commit.time)
+                                    .format("%Y-%m-%d %H:%M:%S%.3f");
+                            match &commit.meta {
+                                Some(meta) => println!(
+                                    "  nº {} {time} {} chave(s) - {}",
+                                    commit.seq,
+                                    commit.keys,
+                                    meta.display()
+                                ),
+                                None => {
+                                    println!("  nº {} {time} {} chave(s)", commit.seq, commit.keys)
+                                }
+                            }
+                        }
+                        println!("use =at N para ver o banco logo após o commit nº N");
+                    }
+                }
+                line if line.starts_with("bookmark") => {
+                    let name = line[8..].trim();
+                    if name.is_empty() {
+                        if bookmarks.is_empty() {
+                            println!("bookmark: nenhum marcador, use =bookmark NOME para marcar o snapshot atual");
+                        }
+                        for (name, bookmark) in &bookmarks {
+                            println!("  @{name} - {bookmark}");
+                        }
+                        continue;
+                    }
+                    let bookmark = match (conn.mode(), snapshot_target) {
+                        (ConnectionMode::Snapshot, Some(bookmark)) => bookmark,
+                        _ => Bookmark::Seq(
+                            conn.recent_commits(1)?
+                                .first()
+                                .map_or(0, |commit| commit.seq),
+                        ),
+                    };
+                    println!("bookmark: @{name} - {bookmark}, volte a ele com =snap @{name}");
+                    bookmarks.insert(name.to_string(), bookmark);
+                }
                 line if line.starts_with("snap") => {
                     let timestamp = line[4..].trim();
                     if timestamp.is_empty() {
                         let mode = conn.mode();
                         conn.start_snapshot(None)?;
+                        snapshot_target = Some(Bookmark::Time(SystemTime::now()));
+                        snapshot_display = Local::now().format("%Y-%m-%d %H:%M:%S").to_string();
                         match mode {
-                            ConnectionMode::Normal => println!("{RETURN}obtido o snapshot atual"),
-                            ConnectionMode::Transaction => println!(
-                                "{RETURN}obtido o snapshot atual, descartado a transação anterior"
-                            ),
+                            ConnectionMode::Normal => println!("obtido o snapshot atual"),
+                            ConnectionMode::Transaction => {
+                                println!("obtido o snapshot atual, descartado a transação anterior")
+                            }
                             ConnectionMode::Snapshot => {
-                                println!(
-                                    "{RETURN}obtido o snapshot atual, finalizado a snapshot anterior"
-                                )
+                                println!("obtido o snapshot atual, finalizado a snapshot anterior")
                             }
                         }
+                    } else if let Some(name) = timestamp.strip_prefix('@') {
+                        let name = name.trim();
+                        let Some(&bookmark) = bookmarks.get(name) else {
+                            println!("não existe o marcador @{name}, use =bookmark para ver os marcadores");
+                            continue;
+                        };
+                        let mode = conn.mode();
+                        match bookmark {
+                            Bookmark::Time(time) => conn.start_snapshot(Some(time))?,
+                            Bookmark::Seq(seq) => conn.start_seq_snapshot(seq)?,
+                        }
+                        snapshot_target = Some(bookmark);
+                        snapshot_display = name.to_string();
+                        match mode {
+                            ConnectionMode::Normal => println!("obtido o snapshot @{name}"),
+                            ConnectionMode::Transaction => println!(
+                                "obtido o snapshot @{name}, descartado a transação anterior"
+                            ),
+                            ConnectionMode::Snapshot => println!(
+                                "obtido o snapshot @{name}, finalizado a snapshot anterior"
+                            ),
+                        }
                     } else if let Some(time) = parse_general_timestamp(timestamp) {
                         let display = DateTime::<Local>::from(time).format("%Y-%m-%d %H:%M:%S");
                         let mode = conn.mode();
                         conn.start_snapshot(Some(time))?;
+                        snapshot_target = Some(Bookmark::Time(time));
+                        snapshot_display = display.to_string();
                         match mode {
-                            ConnectionMode::Normal => println!("{RETURN}obtido o snapshot de {display}"),
-                            ConnectionMode::Transaction => println!("{RETURN}obtido o snapshot de {display}, descartado a transação anterior"),
-                            ConnectionMode::Snapshot => println!("{RETURN}obtido o snapshot de {display}, finalizado a snapshot anterior"),
+                            ConnectionMode::Normal => println!("obtido o snapshot de {display}"),
+                            ConnectionMode::Transaction => println!(
+                                "obtido o snapshot de {display}, descartado a transação anterior"
+                            ),
+                            ConnectionMode::Snapshot => println!(
+                                "obtido o snapshot de {display}, finalizado a snapshot anterior"
+                            ),
                         }
                     } else {
                         println!("tempo inválido, formatos suportados:");
@@ -84,29 +178,60 @@ pub fn client() -> Result<(), std::io::Error> {
                         println!();
                     }
                 }
+                line if line.starts_with("at ") => {
+                    if let Ok(seq) = line[3..].trim().parse::<u64>() {
+                        let mode = conn.mode();
+                        conn.start_seq_snapshot(seq)?;
+                        snapshot_target = Some(Bookmark::Seq(seq));
+                        snapshot_display = format!("nº {seq}");
+                        match mode {
+                            ConnectionMode::Normal => {
+                                println!("obtido o snapshot após o commit nº {seq}")
+                            }
+                            ConnectionMode::Transaction => println!(
+                                "obtido o snapshot após o commit nº {seq}, descartado a transação anterior"
+                            ),
+                            ConnectionMode::Snapshot => println!(
+                                "obtido o snapshot após o commit nº {seq}, finalizado a snapshot anterior"
+                            ),
+                        }
+                    } else {
+                        println!("número inválido, use o número mostrado após um commit");
+                        println!();
+                    }
+                }
                 "c" | "commit" => match conn.mode() {
                     ConnectionMode::Normal => {
-                        println!("{RETURN}commit: não estamos em uma transação");
+                        println!("commit: não estamos em uma transação");
                     }
-                    ConnectionMode::Transaction => match conn.commit() {
-                        Ok(Some(commit_time)) => {
+                    ConnectionMode::Transaction => match conn.commit_with_receipt() {
+                        Ok(Some(receipt)) => {
                             let commit_time =
-                                DateTime::<Local>::from(commit_time).format("%Y-%m-%d %H:%M:%S");
+                                DateTime::<Local>::from(SystemTime::UNIX_EPOCH + receipt.time)
+                                    .format("%Y-%m-%d %H:%M:%S%.3f");
                             println!(
-                            "{RETURN}commit: salvo {read_count} leitura(s) e {write_count} escritas(s) em {commit_time}"
+                            "commit: salvo {read_count} leitura(s) e {write_count} escritas(s) em {commit_time}"
                         );
+                            match receipt.offset {
+                                Some(offset) => println!(
+                                    "commit: nº {}, histórico salvo até o byte {offset}",
+                                    receipt.seq
+                                ),
+                                None => println!("commit: nº {}", receipt.seq),
+                            }
                             read_count = 0;
                             write_count = 0;
                         }
                         Ok(None) => {
                             println!(
-                                "{RETURN}commit: salvo {read_count} leitura(s) e {write_count} escritas(s)"
+                                "commit: salvo {read_count} leitura(s) e {write_count} escritas(s)"
                             );
                             read_count = 0;
                             write_count = 0;
                         }
                         Err(TransactionError::Conflict) => {
-                            println!("{RETURN}commit: houve um conflito, nada foi salvo");
+                            println!("commit: houve um conflito, nada foi salvo");
+                            resolve_conflict(&mut conn, &log, &mut lines)?;
                             read_count = 0;
                             write_count = 0;
                         }
@@ -115,28 +240,82 @@ pub fn client() -> Result<(), std::io::Error> {
                         }
                     },
                     ConnectionMode::Snapshot => {
-                        println!("{RETURN}commit: a snapshot foi finalizada, nada foi salvo");
+                        println!("commit: a snapshot foi finalizada, nada foi salvo");
                     }
                 },
-                "r" | "rollback" => {
+                line if line.starts_with("meta") => {
+                    let meta = line[4..].trim();
                     match conn.mode() {
-                        ConnectionMode::Normal => {
-                            println!("{RETURN}rollback: nada foi descartado, não estamos em uma transação");
+                        ConnectionMode::Transaction if !meta.is_empty() => {
+                            conn.set_meta(meta)?;
+                            println!("meta: o commit da transação vai ter a descrição \"{meta}\"");
                         }
                         ConnectionMode::Transaction => {
-                            conn.rollback()?;
-                            println!("{RETURN}rollback: descartado {read_count} leitura(s) and {write_count} escrita(s)");
-                            read_count = 0;
-                            write_count = 0;
+                            println!("use =meta TEXTO para descrever o commit da transação")
                         }
-                        ConnectionMode::Snapshot => {
-                            conn.rollback()?;
-                            println!(
-                                "{RETURN}rollback: a snapshot foi finalizada, nada foi descartado"
-                            );
+                        _ => println!("meta: não estamos em uma transação"),
+                    }
+                }
+                line if line.starts_with("type") => {
+                    let line = line[4..].trim();
+                    // the type is the last word only if it is one, so keys with spaces can still be shown
+                    let change = line.rsplit_once(' ').and_then(|(key, name)| match name {
+                        "-" => Some((key.trim(), None)),
+                        name => Some((key.trim(), Some(name.parse::<ContentType>().ok()?))),
+                    });
+                    match change {
+                        _ if line.is_empty() => println!(
+                            "use =type CHAVE para ver o tipo de uma chave, ou =type CHAVE TIPO para mudá-lo"
+                        ),
+                        Some(_) if conn.mode().is_snapshot() => {
+                            println!("erro: não é possivel escrever em uma snapshot");
+                        }
+                        Some((key, content_type)) => {
+                            write_count += 1;
+                            conn.set_content_type(key, content_type)?;
+                            match content_type {
+                                Some(content_type) => println!("type: {key} é {content_type}"),
+                                None => println!("type: {key} não tem mais um tipo"),
+                            }
+                        }
+                        None => {
+                            read_count += 1;
+                            match conn.content_type(line)? {
+                                Some(content_type) => println!("type: {line} é {content_type}"),
+                                None => println!("type: {line} não tem um tipo"),
+                            }
                         }
                     }
                 }
+                "v" | "validate" => match conn.mode() {
+                    ConnectionMode::Transaction => {
+                        let keys = conn.validate()?;
+                        if keys.is_empty() {
+                            println!("validate: nenhuma chave lida foi alterada, a transação ainda pode ser comitada");
+                        } else {
+                            println!("validate: o commit vai conflitar, chaves alteradas por outras transações:");
+                            for key in &keys {
+                                println!("  {}", key.display());
+                            }
+                        }
+                    }
+                    _ => println!("validate: não estamos em uma transação"),
+                },
+                "r" | "rollback" => match conn.mode() {
+                    ConnectionMode::Normal => {
+                        println!("rollback: nada foi descartado, não estamos em uma transação");
+                    }
+                    ConnectionMode::Transaction => {
+                        conn.rollback()?;
+                        println!("rollback: descartado {read_count} leitura(s) and {write_count} escrita(s)");
+                        read_count = 0;
+                        write_count = 0;
+                    }
+                    ConnectionMode::Snapshot => {
+                        conn.rollback()?;
+                        println!("rollback: a snapshot foi finalizada, nada foi descartado");
+                    }
+                },
                 line if line.starts_with("stress") => {
                     let count = line[6..].trim();
                     let count = count.parse().unwrap_or(500);
@@ -176,6 +355,21 @@ pub fn client() -> Result<(), std::io::Error> {
                         }
                     }
                 }
+                line if line.starts_with("tree") => {
+                    let depth = line[4..].trim().parse().unwrap_or(2);
+                    crate::tree::print_tree(&mut conn, "", depth)?;
+                }
+                line if line.starts_with("time") => match line[4..].trim() {
+                    "on" => {
+                        timing = true;
+                        println!("tempo: o tempo de ida e volta será mostrado após cada comando");
+                    }
+                    "off" => {
+                        timing = false;
+                        println!("tempo: desligado");
+                    }
+                    _ => println!("use =time on ou =time off"),
+                },
                 "q" | "quit" | "e" | "exit" | "bye" => {
                     break;
                 }
@@ -185,9 +379,24 @@ pub fn client() -> Result<(), std::io::Error> {
                     println!("  =s =start    - começar uma transação");
                     println!("  =snap        - tira um foto para leitura");
                     println!("  =snap YYYY-MM-DD HH:MM:DD - obter uma foto do passado");
+                    println!("  =at N        - obter a foto logo após o commit nº N");
+                    println!("  =snaps N     - listar os últimos N commits, 10 se omitido");
+                    println!("  =bookmark NOME - dar um nome à foto atual, ou ao último commit");
+                    println!("  =snap @NOME  - voltar à foto com esse nome");
                     println!("  =c =commit   - salvar a transação ou finalizar a snapshot");
+                    println!(
+                        "  =meta TEXTO  - descrever o commit da transação, mostrado em =snaps"
+                    );
+                    println!("  =type CHAVE TIPO - mudar o tipo do valor, text, json, binary, integer, ou - para nenhum");
+                    println!("  =v =validate - ver se o commit da transação vai conflitar");
                     println!("  =r =rollback - descartar a transação ou finalizar a snapshot");
                     println!("  =stress N    - incrementar INC N vezes");
+                    println!(
+                        "  =tree N      - mostrar as chaves agrupadas pelos caminhos, até N partes"
+                    );
+                    println!(
+                        "  =time on|off - mostrar o tempo de ida e volta de cada leitura e escrita"
+                    );
                     println!("  =q =e =quit =exit =bye - sair do programa");
                     println!("Comando de escrita:");
                     println!("  mudar o valor da variável INC: \"INC=0\"");
@@ -200,56 +409,89 @@ pub fn client() -> Result<(), std::io::Error> {
                 }
                 command => {
                     println!(
-                        "{RETURN}={}: não é um comando, digite \"=h\" para ver a ajuda",
+                        "={}: não é um comando, digite \"=h\" para ver a ajuda",
                         command
                     );
                 }
             },
             Some((key, value)) => match key.split_once('*') {
                 Some((start, end)) if value.is_empty() => {
+                    let started = Instant::now();
                     let scan = conn.scan(start.as_bytes(), end.as_bytes())?;
+                    let latency = latency(started, timing);
+                    let types = conn
+                        .scan(system::key(system::TYPES, start.as_bytes()), end.as_bytes())?
+                        .into_iter()
+                        .map(|(key, value)| {
+                            let key = key[system::TYPES.len()..].to_vec();
+                            (key, ContentType::decode(&value))
+                        })
+                        .collect::<HashMap<_, _>>();
+                    let display = |key: &[u8], value: &[u8]| {
+                        let content_type = types.get(key).copied().flatten();
+                        format!("{}={}", key.display(), display_typed(value, content_type))
+                    };
                     read_count += scan.len();
+                    if conn.mode().is_transaction() {
+                        log.scans
+                            .entry((start.into(), end.into()))
+                            .or_insert_with(|| scan.iter().cloned().collect());
+                    }
                     match scan.as_slice() {
                         [] => {
-                            println!("{RETURN}{}: nada foi encontrado", key);
+                            println!("{}: nada foi encontrado{latency}", key);
                         }
                         [(k, v)] => {
-                            println!("{RETURN}{}: um foi encontrado", key);
-                            println!("{}={}", k.display(), v.display());
+                            println!("{}: um foi encontrado{latency}", key);
+                            println!("{}", display(k, v));
                         }
                         scan => {
-                            println!("{RETURN}{}: {} itens encontrados", key, scan.len());
+                            println!("{}: {} itens encontrados{latency}", key, scan.len());
                             for (k, v) in scan {
-                                println!("{}={}", k.display(), v.display());
+                                println!("{}", display(k, v));
                             }
                         }
                     }
                 }
                 Some(_) => {
-                    println!("{RETURN}erro: não é possível mudar vários valores de uma vez");
+                    println!("erro: não é possível mudar vários valores de uma vez");
                 }
                 None if conn.mode().is_snapshot() => {
-                    println!("{RETURN}erro: não é possivel escrever em uma snapshot");
+                    println!("erro: não é possivel escrever em uma snapshot");
                 }
                 None => {
                     write_count += 1;
+                    let started = Instant::now();
                     conn.write(key.as_bytes(), value.as_bytes())?;
+                    if timing {
+                        println!("{key}: escrito{}", latency(started, timing));
+                    }
+                    if conn.mode().is_transaction() {
+                        log.writes.insert(key.into(), value.into());
+                    }
                 }
             },
             None => match line.split_once('*') {
                 Some((start, end)) => {
+                    let started = Instant::now();
                     let list = conn.list(start.as_bytes(), end.as_bytes())?;
+                    let latency = latency(started, timing);
                     read_count += list.len();
+                    if conn.mode().is_transaction() {
+                        log.lists
+                            .entry((start.into(), end.into()))
+                            .or_insert_with(|| list.clone());
+                    }
                     match list.as_slice() {
                         [] => {
-                            println!("{RETURN}{}: nada foi encontrado", line);
+                            println!("{}: nada foi encontrado{latency}", line);
                         }
                         [key] => {
-                            println!("{RETURN}{}: um foi encontrado", line);
+                            println!("{}: um foi encontrado{latency}", line);
                             println!("{}", key.display());
                         }
                         list => {
-                            println!("{RETURN}{}: {} itens encontrados", line, list.len());
+                            println!("{}: {} itens encontrados{latency}", line, list.len());
                             for key in list {
                                 println!("{}", key.display());
                             }
@@ -258,10 +500,142 @@ pub fn client() -> Result<(), std::io::Error> {
                 }
                 None => {
                     read_count += 1;
-                    println!("{RETURN}{}={}", line, conn.read(line.as_bytes())?.display());
+                    let started = Instant::now();
+                    let (value, content_type) = conn.read_typed(line.as_bytes())?;
+                    let latency = latency(started, timing);
+                    println!("{}={}{latency}", line, display_typed(&value, content_type));
+                    if conn.mode().is_transaction() && !log.writes.contains_key(line.as_bytes()) {
+                        log.reads.entry(line.into()).or_insert(value);
+                    }
                 }
             },
         }
     }
     Ok(())
 }
+
+/// the round trip of a request, shown after its result with `=time on`
+fn latency(started: Instant, timing: bool) -> String {
+    if timing {
+        format!(" ({:.3?})", started.elapsed())
+    } else {
+        String::new()
+    }
+}
+
+/// a snapshot named with `=bookmark`, by how it was taken
+#[derive(Clone, Copy)]
+enum Bookmark {
+    Time(SystemTime),
+    Seq(u64),
+}
+
+impl std::fmt::Display for Bookmark {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Bookmark::Time(time) => write!(
+                f,
+                "snapshot de {}",
+                DateTime::<Local>::from(*time).format("%Y-%m-%d %H:%M:%S%.3f")
+            ),
+            Bookmark::Seq(seq) => write!(f, "snapshot após o commit nº {seq}"),
+        }
+    }
+}
+
+/// what the current transaction has seen and written, used to explain conflicts
+#[derive(Default)]
+struct TransactionLog {
+    reads: BTreeMap<Vec<u8>, Vec<u8>>,
+    writes: BTreeMap<Vec<u8>, Vec<u8>>,
+    lists: BTreeMap<Pattern, Vec<Vec<u8>>>,
+    scans: BTreeMap<Pattern, BTreeMap<Vec<u8>, Vec<u8>>>,
+}
+
+/// the start and end of a list or scan
+type Pattern = (Vec<u8>, Vec<u8>);
+
+/// shows which of the values seen by the failed transaction have changed since,
+/// then offers to replay its writes in a new transaction
+fn resolve_conflict<T: Read + Write>(
+    conn: &mut Connection<T>,
+    log: &TransactionLog,
+    lines: &mut impl Iterator<Item = Result<String, std::io::Error>>,
+) -> Result<(), std::io::Error> {
+    let mut changes = BTreeMap::new();
+    for (key, seen) in &log.reads {
+        let current = conn.read(key)?;
+        if *seen != current {
+            changes.insert(
+                key.clone(),
+                (seen.display().to_string(), current.display().to_string()),
+            );
+        }
+    }
+    for ((start, end), seen) in &log.scans {
+        let current = conn
+            .scan(start, end)?
+            .into_iter()
+            .collect::<BTreeMap<_, _>>();
+        for key in seen.keys().chain(current.keys()) {
+            let before = seen.get(key).map(Vec::as_slice).unwrap_or_default();
+            let after = current.get(key).map(Vec::as_slice).unwrap_or_default();
+            if before != after && !log.writes.contains_key(key) {
+                changes.insert(
+                    key.clone(),
+                    (before.display().to_string(), after.display().to_string()),
+                );
+            }
+        }
+    }
+    for ((start, end), seen) in &log.lists {
+        let current = conn.list(start, end)?;
+        for key in seen.iter().filter(|key| !current.contains(key)) {
+            if !log.writes.contains_key(key) {
+                changes
+                    .entry(key.clone())
+                    .or_insert_with(|| ("<presente>".into(), "<ausente>".into()));
+            }
+        }
+        for key in current.iter().filter(|key| !seen.contains(key)) {
+            if !log.writes.contains_key(key) {
+                changes
+                    .entry(key.clone())
+                    .or_insert_with(|| ("<ausente>".into(), "<presente>".into()));
+            }
+        }
+    }
+    if changes.is_empty() {
+        println!("não foi possível identificar quais chaves mudaram, elas podem ter voltado ao valor anterior");
+    } else {
+        println!("chaves alteradas por outras transações (lido -> atual):");
+        for (key, (before, after)) in &changes {
+            println!("  {}: {before} -> {after}", key.display());
+        }
+    }
+    if log.writes.is_empty() {
+        return Ok(());
+    }
+    print!(
+        "reaplicar as {} escrita(s) em uma nova transação? (s/N) ",
+        log.writes.len()
+    );
+    std::io::stdout().flush()?;
+    let answer = lines.next().transpose()?.unwrap_or_default();
+    if !matches!(answer.trim(), "s" | "S" | "sim") {
+        println!("as escritas foram descartadas");
+        return Ok(());
+    }
+    conn.start_transaction()?;
+    for (key, value) in &log.writes {
+        conn.write(key, value)?;
+    }
+    match conn.commit() {
+        Ok(_) => println!("commit: reaplicado {} escrita(s)", log.writes.len()),
+        Err(TransactionError::Conflict) => {
+            println!("commit: houve um novo conflito, nada foi salvo")
+        }
+        Err(TransactionError::Io(error)) => return Err(error),
+    }
+    Ok(())
+}
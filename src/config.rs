@@ -0,0 +1,121 @@
+//! the configuration file of `serve --config`, read when the server starts and again by `admin reload`
+//!
+//! each line is `chave = valor`, empty lines and lines starting with `#` are ignored
+//!
+//! * `admin-token` - the token that grants admin, empty disables the admin commands
+//! * `sync` - `sync`, `flush` or `cached`, see the flags of `serve`
+//! * `max-persist-backlog` - see the flag of `serve`, empty disables the limit
+//! * `slow-ms` - see the flag of `serve`, empty disables the slow log
+//! * `min-replicas` - see the flag of `serve`, 0 doesn't wait for replicas
+//! * `replica-timeout-ms` - see the flag of `serve`
+//! * `maintenance-window` - see the flag of `serve`, empty lets the maintenance run at any time
+//! * `maintenance-max-commits` - see the flag of `serve`, empty lets the maintenance run however busy the database is
+//!
+//! settings missing from the file keep their current value
+
+use std::path::Path;
+
+use pathkvs_core::DatabaseWriteSyncMode;
+
+use crate::maintenance::Window;
+
+#[derive(Debug, Default)]
+pub struct Config {
+    pub admin_token: Option<Option<String>>,
+    pub sync: Option<DatabaseWriteSyncMode>,
+    pub max_persist_backlog: Option<Option<u64>>,
+    pub slow_ms: Option<Option<u64>>,
+    pub min_replicas: Option<u32>,
+    pub replica_timeout_ms: Option<u64>,
+    pub maintenance_window: Option<Option<Window>>,
+    pub maintenance_max_commits: Option<Option<u64>>,
+}
+
+impl Config {
+    /// the error is a message for the admin, in portuguese like the rest of the interface
+    pub fn read(path: &Path) -> Result<Self, String> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|error| format!("não foi possível ler {}: {error}", path.display()))?;
+        let mut config = Config::default();
+        for (index, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let Some((key, value)) = line.split_once('=') else {
+                return Err(format!("linha {}: esperado `chave = valor`", index + 1));
+            };
+            let value = value.trim();
+            match key.trim() {
+                "admin-token" => {
+                    config.admin_token = Some((!value.is_empty()).then(|| value.to_string()));
+                }
+                "sync" => {
+                    config.sync = Some(match value {
+                        "sync" => DatabaseWriteSyncMode::Sync,
+                        "flush" => DatabaseWriteSyncMode::Flush,
+                        "cached" => DatabaseWriteSyncMode::Cached,
+                        _ => {
+                            return Err(format!(
+                                "linha {}: modo de sincronização desconhecido {value:?}",
+                                index + 1
+                            ))
+                        }
+                    });
+                }
+                "max-persist-backlog" if value.is_empty() => {
+                    config.max_persist_backlog = Some(None);
+                }
+                "max-persist-backlog" => {
+                    let Ok(max) = value.parse() else {
+                        return Err(format!("linha {}: número inválido {value:?}", index + 1));
+                    };
+                    config.max_persist_backlog = Some(Some(max));
+                }
+                "slow-ms" if value.is_empty() => {
+                    config.slow_ms = Some(None);
+                }
+                "slow-ms" => {
+                    let Ok(ms) = value.parse() else {
+                        return Err(format!("linha {}: número inválido {value:?}", index + 1));
+                    };
+                    config.slow_ms = Some(Some(ms));
+                }
+                "min-replicas" => {
+                    let Ok(replicas) = value.parse() else {
+                        return Err(format!("linha {}: número inválido {value:?}", index + 1));
+                    };
+                    config.min_replicas = Some(replicas);
+                }
+                "replica-timeout-ms" => {
+                    let Ok(ms) = value.parse() else {
+                        return Err(format!("linha {}: número inválido {value:?}", index + 1));
+                    };
+                    config.replica_timeout_ms = Some(ms);
+                }
+                "maintenance-window" if value.is_empty() => {
+                    config.maintenance_window = Some(None);
+                }
+                "maintenance-window" => {
+                    let window = value
+                        .parse()
+                        .map_err(|error| format!("linha {}: {error}", index + 1))?;
+                    config.maintenance_window = Some(Some(window));
+                }
+                "maintenance-max-commits" if value.is_empty() => {
+                    config.maintenance_max_commits = Some(None);
+                }
+                "maintenance-max-commits" => {
+                    let Ok(max) = value.parse() else {
+                        return Err(format!("linha {}: número inválido {value:?}", index + 1));
+                    };
+                    config.maintenance_max_commits = Some(Some(max));
+                }
+                key => {
+                    return Err(format!("linha {}: chave desconhecida {key:?}", index + 1));
+                }
+            }
+        }
+        Ok(config)
+    }
+}
@@ -0,0 +1,168 @@
+use std::{
+    io::{Error, ErrorKind, Read, Write},
+    net::TcpStream,
+    sync::mpsc::{channel, Receiver, Sender},
+    sync::OnceLock,
+    time::Duration,
+};
+
+use pathkvs_net::client::Connection;
+
+/// a connection to either a remote server or a database opened in this process
+pub enum Stream {
+    Tcp(TcpStream),
+    Local(Pipe),
+}
+
+impl Read for Stream {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+        match self {
+            Stream::Tcp(stream) => stream.read(buf),
+            Stream::Local(pipe) => pipe.read(buf),
+        }
+    }
+}
+
+impl Write for Stream {
+    fn write(&mut self, buf: &[u8]) -> Result<usize, Error> {
+        match self {
+            Stream::Tcp(stream) => stream.write(buf),
+            Stream::Local(pipe) => pipe.write(buf),
+        }
+    }
+    fn flush(&mut self) -> Result<(), Error> {
+        match self {
+            Stream::Tcp(stream) => stream.flush(),
+            Stream::Local(pipe) => pipe.flush(),
+        }
+    }
+}
+
+/// the token the connections to a server authenticate with, as a user or as admin, if set
+const TOKEN_VAR: &str = "PATHKVS_TOKEN";
+
+/// the name of `--name`, given to every connection
+static NAME: OnceLock<String> = OnceLock::new();
+
+pub fn set_name(name: String) {
+    let _ = NAME.set(name);
+}
+
+/// connects to `addr`, or, if `db` is set, opens the database file directly and serves it in this process
+///
+/// `addr` may have several addresses separated by commas, of identical servers, which are tried in order
+pub fn connect(
+    db: Option<&str>,
+    addr: &str,
+    timeout: Duration,
+) -> Result<Connection<Stream>, Error> {
+    connect_from(db, addr, timeout, 0)
+}
+
+/// like [`connect`], but for commands that only read, which can go to any of the servers,
+/// so each run tries them from a different one, spreading the reads across them
+pub fn connect_reads(
+    db: Option<&str>,
+    addr: &str,
+    timeout: Duration,
+) -> Result<Connection<Stream>, Error> {
+    connect_from(db, addr, timeout, std::process::id() as usize)
+}
+
+fn connect_from(
+    db: Option<&str>,
+    addr: &str,
+    timeout: Duration,
+    start: usize,
+) -> Result<Connection<Stream>, Error> {
+    match db {
+        Some(path) => Ok(Connection::new(Stream::Local(crate::server::serve_local(
+            path,
+        )?))),
+        None => {
+            let addrs = addr.split(',').map(str::trim).collect::<Vec<_>>();
+            let conn = Connection::connect_any_from(&addrs, start)?.into_inner();
+            conn.set_read_timeout(Some(timeout))?;
+            conn.set_write_timeout(Some(Duration::from_secs(1)))?;
+            let mut conn = Connection::new(Stream::Tcp(conn));
+            if let Ok(token) = std::env::var(TOKEN_VAR) {
+                conn.authenticate(token).map_err(|error| {
+                    Error::new(
+                        ErrorKind::PermissionDenied,
+                        format!("o token de {TOKEN_VAR} foi recusado: {error}"),
+                    )
+                })?;
+            }
+            if let Some(name) = NAME.get() {
+                conn.set_name(name)?;
+            }
+            Ok(conn)
+        }
+    }
+}
+
+/// one end of an in memory duplex stream, see [`pipe`]
+pub struct Pipe {
+    sender: Sender<Vec<u8>>,
+    receiver: Receiver<Vec<u8>>,
+    buffer: Vec<u8>,
+    cursor: usize,
+}
+
+/// creates a pair of connected in memory streams, what is written to one can be read from the other
+///
+/// dropping one end makes reads on the other end return end of file
+pub fn pipe() -> (Pipe, Pipe) {
+    let (sender_a, receiver_a) = channel();
+    let (sender_b, receiver_b) = channel();
+    (
+        Pipe {
+            sender: sender_a,
+            receiver: receiver_b,
+            buffer: Vec::new(),
+            cursor: 0,
+        },
+        Pipe {
+            sender: sender_b,
+            receiver: receiver_a,
+            buffer: Vec::new(),
+            cursor: 0,
+        },
+    )
+}
+
+impl Read for Pipe {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        while self.cursor == self.buffer.len() {
+            match self.receiver.recv() {
+                Ok(buffer) => {
+                    self.buffer = buffer;
+                    self.cursor = 0;
+                }
+                Err(_) => return Ok(0),
+            }
+        }
+        let len = buf.len().min(self.buffer.len() - self.cursor);
+        buf[..len].copy_from_slice(&self.buffer[self.cursor..self.cursor + len]);
+        self.cursor += len;
+        Ok(len)
+    }
+}
+
+impl Write for Pipe {
+    fn write(&mut self, buf: &[u8]) -> Result<usize, Error> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        self.sender
+            .send(buf.to_vec())
+            .map_err(|_| Error::from(ErrorKind::BrokenPipe))?;
+        Ok(buf.len())
+    }
+    fn flush(&mut self) -> Result<(), Error> {
+        Ok(())
+    }
+}
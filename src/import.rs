@@ -0,0 +1,235 @@
+use std::io::{BufRead, Error, ErrorKind, Read, Write};
+
+use pathkvs_core::error::TransactionError;
+use pathkvs_net::client::Connection;
+
+use crate::progress;
+
+pub struct ImportOptions<'a> {
+    pub key_template: &'a str,
+    pub value_column: usize,
+    pub batch_size: usize,
+    pub skip_header: bool,
+    /// only counts the keys and bytes that would be written, and how many of the keys already exist
+    pub dry_run: bool,
+}
+
+/// reads csv rows from `input`, writing one key per row, committing every `batch_size` rows
+///
+/// `input_len` is the length of the input in bytes, if known, for the progress bar
+pub fn import_csv(
+    mut conn: Connection<impl Read + Write>,
+    input: impl BufRead,
+    input_len: Option<u64>,
+    options: ImportOptions,
+) -> Result<(), Error> {
+    let template = KeyTemplate::parse(options.key_template)?;
+    let progress = progress::bytes(input_len, "importando");
+    let input = progress.wrap_read(input);
+    let mut rows = CsvReader::new(input).enumerate();
+    if options.skip_header {
+        if let Some((_, header)) = rows.next() {
+            header?;
+        }
+    }
+    let mut total = 0u64;
+    let mut bytes = 0u64;
+    let mut existing = 0u64;
+    // the keys of the batch, only kept in a dry run, to ask which of them exist
+    let mut keys = Vec::new();
+    let mut pending = 0usize;
+    for (line, row) in rows {
+        let row = row?;
+        if row.len() == 1 && row[0].is_empty() {
+            continue;
+        }
+        let (Some(key), Some(value)) = (template.render(&row), row.get(options.value_column))
+        else {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("linha {}: coluna inexistente", line + 1),
+            ));
+        };
+        bytes += key.len() as u64 + value.len() as u64;
+        if options.dry_run {
+            keys.push(key);
+        } else {
+            if pending == 0 {
+                conn.start_transaction()?;
+            }
+            conn.write(key.as_bytes(), value.as_bytes())?;
+        }
+        pending += 1;
+        if pending >= options.batch_size {
+            if options.dry_run {
+                existing += count_existing(&mut conn, &mut keys)?;
+            } else {
+                commit(&mut conn)?;
+            }
+            total += pending as u64;
+            pending = 0;
+        }
+    }
+    if pending != 0 {
+        if options.dry_run {
+            existing += count_existing(&mut conn, &mut keys)?;
+        } else {
+            commit(&mut conn)?;
+        }
+        total += pending as u64;
+    }
+    progress.finish_and_clear();
+    if options.dry_run {
+        println!(
+            "seriam importadas {total} linha(s), {bytes} byte(s), {existing} chave(s) já existem e seriam substituídas, nada foi escrito"
+        );
+    } else {
+        println!("importado {total} linha(s), {bytes} byte(s)");
+    }
+    Ok(())
+}
+
+/// how many of `keys` exist, leaving it empty
+fn count_existing(
+    conn: &mut Connection<impl Read + Write>,
+    keys: &mut Vec<String>,
+) -> Result<u64, Error> {
+    let exists = conn.exists_many(keys.drain(..))?;
+    Ok(exists.into_iter().filter(|exists| *exists).count() as u64)
+}
+
+fn commit(conn: &mut Connection<impl Read + Write>) -> Result<(), Error> {
+    match conn.commit() {
+        Ok(_) => Ok(()),
+        Err(TransactionError::Conflict) => unreachable!("a write only transaction cannot conflict"),
+        Err(TransactionError::Io(error)) => Err(error),
+    }
+}
+
+/// a key with `{N}` placeholders that are replaced by the column N of the row
+///
+/// `{{` and `}}` are literal braces
+struct KeyTemplate {
+    parts: Vec<TemplatePart>,
+}
+
+enum TemplatePart {
+    Literal(String),
+    Column(usize),
+}
+
+impl KeyTemplate {
+    fn parse(template: &str) -> Result<Self, Error> {
+        let invalid = || {
+            Error::new(
+                ErrorKind::InvalidInput,
+                format!("modelo de chave inválido: {template}"),
+            )
+        };
+        let mut parts = Vec::new();
+        let mut literal = String::new();
+        let mut chars = template.chars().peekable();
+        while let Some(char) = chars.next() {
+            match char {
+                '{' if chars.peek() == Some(&'{') => {
+                    chars.next();
+                    literal.push('{');
+                }
+                '}' if chars.peek() == Some(&'}') => {
+                    chars.next();
+                    literal.push('}');
+                }
+                '{' => {
+                    let mut index = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some(digit) if digit.is_ascii_digit() => index.push(digit),
+                            _ => return Err(invalid()),
+                        }
+                    }
+                    let index = index.parse().map_err(|_| invalid())?;
+                    if !literal.is_empty() {
+                        parts.push(TemplatePart::Literal(std::mem::take(&mut literal)));
+                    }
+                    parts.push(TemplatePart::Column(index));
+                }
+                '}' => return Err(invalid()),
+                char => literal.push(char),
+            }
+        }
+        if !literal.is_empty() {
+            parts.push(TemplatePart::Literal(literal));
+        }
+        Ok(Self { parts })
+    }
+    fn render(&self, row: &[String]) -> Option<String> {
+        let mut key = String::new();
+        for part in &self.parts {
+            match part {
+                TemplatePart::Literal(literal) => key.push_str(literal),
+                TemplatePart::Column(index) => key.push_str(row.get(*index)?),
+            }
+        }
+        Some(key)
+    }
+}
+
+/// minimal RFC 4180 reader, supports quoted fields with commas, doubled quotes and line breaks
+struct CsvReader<R> {
+    input: R,
+    line: String,
+}
+
+impl<R: BufRead> CsvReader<R> {
+    fn new(input: R) -> Self {
+        Self {
+            input,
+            line: String::new(),
+        }
+    }
+    fn read_row(&mut self) -> Result<Option<Vec<String>>, Error> {
+        let mut row = Vec::new();
+        let mut field = String::new();
+        let mut quoted = false;
+        let mut started = false;
+        loop {
+            self.line.clear();
+            if self.input.read_line(&mut self.line)? == 0 {
+                if quoted {
+                    return Err(Error::new(ErrorKind::UnexpectedEof, "aspas não fechadas"));
+                }
+                if !started {
+                    return Ok(None);
+                }
+                row.push(field);
+                return Ok(Some(row));
+            }
+            started = true;
+            let mut chars = self.line.chars().peekable();
+            while let Some(char) = chars.next() {
+                match char {
+                    '"' if quoted && chars.peek() == Some(&'"') => {
+                        chars.next();
+                        field.push('"');
+                    }
+                    '"' if quoted => quoted = false,
+                    '"' if field.is_empty() => quoted = true,
+                    ',' if !quoted => row.push(std::mem::take(&mut field)),
+                    '\r' | '\n' if !quoted => {
+                        row.push(field);
+                        return Ok(Some(row));
+                    }
+                    char => field.push(char),
+                }
+            }
+        }
+    }
+}
+
+impl<R: BufRead> Iterator for CsvReader<R> {
+    type Item = Result<Vec<String>, Error>;
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read_row().transpose()
+    }
+}
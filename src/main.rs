@@ -1,13 +1,40 @@
+mod access_log;
+mod acl;
+mod admin;
+mod backup;
 mod client;
+mod config;
+mod connect;
+mod import;
+mod maintenance;
+mod oneshot;
+mod progress;
+mod replica;
+mod rewrite;
 mod server;
+mod slow_log;
+mod systemd;
+mod tail;
+mod tree;
+mod upgrade;
 mod utils;
 
-use clap::{Parser, Subcommand};
+use std::{net::SocketAddr, time::Duration};
+
+use clap::{Parser, Subcommand, ValueEnum};
 use pathkvs_core::DatabaseWriteSyncMode;
 
+const DEFAULT_ADDR: &str = "127.0.0.1:6314";
+
 #[derive(Parser)]
 #[command(name = "pathkvs", about = "Um banco chave valor")]
 struct Cli {
+    /// Abre diretamente o arquivo do banco, sem passar por um servidor
+    #[arg(long)]
+    db: Option<String>,
+    /// Nome das conexões, mostrado pelo servidor em `admin connections`
+    #[arg(long)]
+    name: Option<String>,
     #[command(subcommand)]
     command: Option<Commands>,
 }
@@ -18,6 +45,12 @@ enum Commands {
     Serve {
         /// Caminho do banco de dados (opcional)
         path: Option<String>,
+        /// Endereço onde escutar por conexões, pode ser repetido para escutar em vários, como `[::]:6314` e `0.0.0.0:6314`
+        #[arg(long)]
+        bind: Vec<SocketAddr>,
+        /// Usa os sockets passados pelo systemd, de uma unidade .socket, no lugar de `--bind`
+        #[arg(long, conflicts_with = "bind")]
+        systemd_socket: bool,
         /// Commits retornam quando os dados estiverem no disco
         #[arg(short, long)]
         sync: bool,
@@ -27,17 +60,420 @@ enum Commands {
         /// Commits retornam quando os conflitos forem resolvido
         #[arg(short, long)]
         cache: bool,
+        /// Token que concede acesso aos comandos administrativos
+        #[arg(long)]
+        admin_token: Option<String>,
+        /// Recusa escritas e commits, que o cliente pode tentar de novo, enquanto mais commits que isso esperam para serem salvos no disco
+        #[arg(long)]
+        max_persist_backlog: Option<u64>,
+        /// Registra os comandos que levam mais que esses milissegundos, mostrados também por `admin slowlog`
+        #[arg(long)]
+        slow_ms: Option<u64>,
+        /// Commits de transações só retornam depois que essa quantidade de réplicas confirmar, com `Connection::ack_replication`, que os aplicou
+        #[arg(long, default_value_t = 0)]
+        min_replicas: u32,
+        /// Milissegundos que um commit espera pelas réplicas antes de falhar, o commit continua feito
+        #[arg(long, default_value_t = 5000)]
+        replica_timeout_ms: u64,
+        /// Arquivo de configuração, seus valores substituem os das opções e podem ser recarregados com `admin reload`
+        #[arg(long)]
+        config: Option<String>,
+        /// Arquivo onde cada comando recebido é registrado em uma linha, com a conexão, o comando, o tamanho das chaves, o tempo e o resultado
+        #[arg(long)]
+        access_log: Option<String>,
+        /// Tamanho em bytes a partir do qual o log de acesso é renomeado e um novo é começado
+        #[arg(long, default_value_t = 64 << 20)]
+        access_log_max_bytes: u64,
+        /// Idade em segundos a partir da qual o log de acesso é renomeado e um novo é começado
+        #[arg(long)]
+        access_log_max_age: Option<u64>,
+        /// Tamanho máximo em bytes de um valor escrito, um maior encerra a conexão
+        #[arg(long)]
+        max_value_size: Option<u32>,
+        /// Tamanho máximo em bytes de uma chave, ou do início e do fim de um intervalo, uma maior encerra a conexão
+        #[arg(long)]
+        max_key_size: Option<u32>,
+        /// Bytes máximos de chaves e valores na resposta de um `list` ou `scan`, acima disso ele falha com limite excedido
+        #[arg(long)]
+        max_scan_bytes: Option<u32>,
+        /// Quantas transações uma conexão pode iniciar, as seguintes falham com limite excedido
+        #[arg(long)]
+        max_transactions_per_conn: Option<u64>,
+        /// Compacta o arquivo do banco a cada esses minutos, dentro da janela de manutenção
+        #[arg(long)]
+        compact_every_min: Option<u64>,
+        /// Dias de histórico que a compactação automática mantém como estão
+        #[arg(long, default_value_t = 0)]
+        compact_keep_days: u64,
+        /// Remove as chaves expiradas, com `expire_at`, a cada esses segundos, dentro da janela de manutenção
+        #[arg(long)]
+        sweep_every_secs: Option<u64>,
+        /// Horário local em que a manutenção pode rodar, como `02:00-05:00`, pode passar da meia-noite
+        #[arg(long)]
+        maintenance_window: Option<maintenance::Window>,
+        /// A manutenção só roda enquanto o banco faz menos commits por segundo que isso
+        #[arg(long)]
+        maintenance_max_commits: Option<u64>,
+    },
+    /// Executa um comando administrativo em um servidor
+    Admin {
+        /// Endereço do servidor, ou vários separados por vírgula, tentados em ordem
+        #[arg(long, default_value = DEFAULT_ADDR)]
+        connect: String,
+        /// Token de administrador do servidor
+        #[arg(long)]
+        token: Option<String>,
+        #[command(subcommand)]
+        command: AdminCommand,
+    },
+    /// Salva uma cópia consistente do banco de um servidor em um arquivo
+    Backup {
+        /// Endereço do servidor, ou vários separados por vírgula, tentados em ordem
+        #[arg(long, default_value = DEFAULT_ADDR)]
+        connect: String,
+        /// Arquivo onde a cópia será salva
+        output: String,
+    },
+    /// Carrega uma cópia feita com o comando backup em um servidor
+    Restore {
+        /// Endereço do servidor, ou vários separados por vírgula, tentados em ordem
+        #[arg(long, default_value = DEFAULT_ADDR)]
+        connect: String,
+        /// Arquivo da cópia
+        input: String,
+    },
+    /// Confere uma cópia feita com o comando backup, lendo ela em memória sem alterar o arquivo
+    ///
+    /// O formato não tem checksums, então só registros cortados ou mal formados são detectados
+    VerifyBackup {
+        /// Arquivo da cópia
+        input: String,
+    },
+    /// Importa linhas de um arquivo em transações em lotes
+    Import {
+        /// Endereço do servidor, ou vários separados por vírgula, tentados em ordem
+        #[arg(long, default_value = DEFAULT_ADDR)]
+        connect: String,
+        /// Lê o arquivo como CSV (o único formato suportado no momento)
+        #[arg(long)]
+        csv: bool,
+        /// Modelo da chave, {N} é substituído pela coluna N (começando em 0)
+        #[arg(long)]
+        key_template: String,
+        /// Coluna usada como valor (começando em 0)
+        #[arg(long)]
+        value_column: usize,
+        /// Quantidade de linhas por transação
+        #[arg(long, default_value_t = 1000)]
+        batch_size: usize,
+        /// Ignora a primeira linha
+        #[arg(long)]
+        header: bool,
+        /// Só lê o arquivo e mostra quantas chaves e bytes seriam escritos, sem escrever nada
+        #[arg(long)]
+        dry_run: bool,
+        /// Arquivo de entrada, ou - para ler da entrada padrão
+        input: String,
+    },
+    /// Reescreve o valor de cada chave com um prefixo usando um script, em transações em lotes, para migrações
+    Rewrite {
+        /// Endereço do servidor, ou vários separados por vírgula, tentados em ordem
+        #[arg(long, default_value = DEFAULT_ADDR)]
+        connect: String,
+        /// Prefixo das chaves reescritas
+        #[arg(long)]
+        prefix: String,
+        /// Arquivo do script, um procedimento que recebe a chave em (arg 0) e o valor em (arg 1) e devolve o novo valor
+        #[arg(long)]
+        map_script: String,
+        /// Nome da reescrita, com o qual ela continua de onde parou se for interrompida, o prefixo se omitido
+        #[arg(long)]
+        name: Option<String>,
+        /// Quantidade de chaves por transação
+        #[arg(long, default_value_t = 1000)]
+        batch_size: usize,
+        /// Só mostra quantas chaves seriam reescritas, sem escrever nada
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Envia uma mensagem para os inscritos em um canal
+    Publish {
+        /// Endereço do servidor, ou vários separados por vírgula, tentados em ordem
+        #[arg(long, default_value = DEFAULT_ADDR)]
+        connect: String,
+        /// Nome do canal
+        channel: String,
+        /// Mensagem
+        message: String,
+    },
+    /// Mostra as mensagens enviadas aos canais, até o programa ser encerrado
+    Subscribe {
+        /// Endereço do servidor, ou vários separados por vírgula, tentados em ordem
+        #[arg(long, default_value = DEFAULT_ADDR)]
+        connect: String,
+        /// Nomes dos canais
+        #[arg(required = true)]
+        channels: Vec<String>,
+        /// Os nomes são padrões, `*` aceita qualquer sequência e `?` qualquer caractere, como `user:*:email`
+        #[arg(long)]
+        pattern: bool,
+    },
+    /// Mantém um arquivo de banco como réplica de um servidor, até o programa ser encerrado
+    ///
+    /// Se o arquivo não existe ele começa de um snapshot do servidor, com o valor mais recente de cada chave, e depois aplica os commits seguintes e confirma cada posição para o servidor, exige um token de administrador em PATHKVS_TOKEN
+    Replicate {
+        /// Endereço do servidor, ou vários separados por vírgula, tentados em ordem
+        #[arg(long, default_value = DEFAULT_ADDR)]
+        connect: String,
+        /// Nome da réplica, mostrado por `admin replication` no servidor
+        #[arg(long)]
+        name: String,
+        /// Milissegundos entre as consultas ao servidor quando não há commits novos
+        #[arg(long, default_value_t = 200)]
+        interval_ms: u64,
+        /// Recebe o histórico comprimido, para réplicas em redes lentas
+        #[arg(long)]
+        compress: bool,
+        /// Arquivo da réplica
+        path: String,
+    },
+    /// Mostra as alterações de cada commit assim que acontecem, uma linha JSON por chave, até o programa ser encerrado
+    ///
+    /// Chaves apagadas têm o valor null
+    Tail {
+        /// Endereço do servidor, ou vários separados por vírgula, tentados em ordem
+        #[arg(long, default_value = DEFAULT_ADDR)]
+        connect: String,
+        /// Padrão das chaves, `*` aceita qualquer sequência e `?` qualquer caractere, como `user/*`
+        #[arg(default_value = "*")]
+        pattern: String,
+    },
+    /// Escreve o valor de uma chave na saída padrão, sem quebra de linha
+    ///
+    /// Sai com 2 se a chave não existe, e com 4 se a conexão falhou
+    Get {
+        /// Endereço do servidor, ou vários separados por vírgula, tentados em ordem
+        #[arg(long, default_value = DEFAULT_ADDR)]
+        connect: String,
+        /// Chave
+        key: String,
+    },
+    /// Escreve o valor de uma chave
+    ///
+    /// Sai com 3 se o valor atual não é o de --expect, e com 4 se a conexão falhou
+    Set {
+        /// Endereço do servidor, ou vários separados por vírgula, tentados em ordem
+        #[arg(long, default_value = DEFAULT_ADDR)]
+        connect: String,
+        /// Só escreve se o valor atual for este
+        #[arg(long)]
+        expect: Option<String>,
+        /// Chave
+        key: String,
+        /// Valor, se omitido é lido da entrada padrão
+        value: Option<String>,
+    },
+    /// Soma um número ao valor de uma chave, sem conflitos com outras somas, e escreve o novo valor
+    ///
+    /// Sai com 1 se o valor atual não é um número, e com 4 se a conexão falhou
+    Incr {
+        /// Endereço do servidor, ou vários separados por vírgula, tentados em ordem
+        #[arg(long, default_value = DEFAULT_ADDR)]
+        connect: String,
+        /// Chave
+        key: String,
+        /// Quanto somar, negativo para subtrair
+        #[arg(default_value_t = 1, allow_negative_numbers = true)]
+        delta: i64,
+    },
+    /// Apaga uma chave
+    ///
+    /// Sai com 2 se a chave não existe, com 3 se o valor atual não é o de --expect, e com 4 se a conexão falhou
+    Del {
+        /// Endereço do servidor, ou vários separados por vírgula, tentados em ordem
+        #[arg(long, default_value = DEFAULT_ADDR)]
+        connect: String,
+        /// Só apaga se o valor atual for este
+        #[arg(long, conflicts_with = "prefix")]
+        expect: Option<String>,
+        /// Apaga todas as chaves que começam com a chave informada, em uma única transação
+        #[arg(long)]
+        prefix: bool,
+        /// Só mostra quantas chaves e bytes seriam apagados, sem apagar nada
+        #[arg(long)]
+        dry_run: bool,
+        /// Chave, ou prefixo com --prefix
+        key: String,
+    },
+    /// Escreve as chaves que começam com o prefixo, uma por linha
+    ///
+    /// Sai com 2 se nenhuma chave foi encontrada, e com 4 se a conexão falhou
+    Scan {
+        /// Endereço do servidor, ou vários separados por vírgula, tentados em ordem
+        #[arg(long, default_value = DEFAULT_ADDR)]
+        connect: String,
+        /// Prefixo das chaves
+        #[arg(default_value = "")]
+        prefix: String,
+        /// Sufixo das chaves
+        #[arg(long, default_value = "")]
+        suffix: String,
+        /// Escreve também o valor de cada chave, depois de um tab
+        #[arg(long)]
+        values: bool,
+    },
+    /// Mostra as chaves agrupadas pelas partes dos seus caminhos, separadas por `/`, com quantas chaves e bytes cada uma tem
+    Tree {
+        /// Endereço do servidor, ou vários separados por vírgula, tentados em ordem
+        #[arg(long, default_value = DEFAULT_ADDR)]
+        connect: String,
+        /// Mostra só as chaves que começam com o prefixo
+        #[arg(default_value = "")]
+        prefix: String,
+        /// Quantas partes dos caminhos mostrar
+        #[arg(long, default_value_t = 2)]
+        depth: usize,
+    },
+    /// Executa um procedimento registrado no servidor e mostra o resultado
+    Call {
+        /// Endereço do servidor, ou vários separados por vírgula, tentados em ordem
+        #[arg(long, default_value = DEFAULT_ADDR)]
+        connect: String,
+        /// Nome do procedimento
+        name: String,
+        /// Argumentos do procedimento
+        args: Vec<String>,
     },
 }
 
+#[derive(Subcommand)]
+enum AdminCommand {
+    /// Mostra estatísticas do servidor
+    Stats,
+    /// Mostra o estado do servidor em seções (servidor, clientes, chaves e persistência)
+    Info,
+    /// Compacta o arquivo do banco, deixando só o valor mais recente de cada chave
+    Compact {
+        /// Mantém como estão os commits dos últimos dias
+        #[arg(long, default_value_t = 0)]
+        keep_days: u64,
+    },
+    /// Remove as chaves expiradas e compacta o arquivo do banco, mantendo os commits de `serve --compact-keep-days`
+    Gc,
+    /// Lista as conexões ativas, com há quanto tempo cada uma está ociosa e está em uma transação
+    Connections,
+    /// Mostra os comandos mais recentes que levaram mais que `serve --slow-ms`, do mais novo ao mais antigo
+    Slowlog,
+    /// Mostra até onde cada réplica aplicou o histórico e quantos commits e bytes faltam para ela
+    Replication,
+    /// Encerra uma conexão, desfazendo a sua transação, para liberar um cliente travado
+    Kill {
+        /// Número da conexão, como mostrado por `admin connections`
+        id: u64,
+    },
+    /// Recusa novas conexões e encerra o servidor quando as atuais terminarem
+    Drain,
+    /// Inicia o servidor de novo, com o mesmo comando, e drena este, o novo assume as conexões quando as atuais terminarem
+    Upgrade,
+    /// Lê de novo o arquivo de configuração do servidor, sem derrubar as conexões
+    Reload,
+    /// Registra um procedimento lido de um arquivo, substituindo o anterior com o mesmo nome
+    Procedure {
+        /// Nome do procedimento
+        name: String,
+        /// Arquivo com o código do procedimento
+        file: String,
+    },
+    /// Gerencia os usuários, que se autenticam com o próprio token e recebem o seu papel
+    User {
+        #[command(subcommand)]
+        command: UserCommand,
+    },
+    /// Gerencia as regras de qual papel pode ler ou escrever as chaves de cada prefixo
+    Acl {
+        #[command(subcommand)]
+        command: AclCommand,
+    },
+}
+
+#[derive(Subcommand)]
+enum UserCommand {
+    /// Adiciona um usuário, ou substitui o que tem o mesmo nome
+    Add {
+        /// Nome do usuário
+        name: String,
+        /// Papel do usuário
+        role: String,
+        /// Token do usuário, se omitido é lido da primeira linha da entrada padrão
+        #[arg(long)]
+        token: Option<String>,
+    },
+    /// Remove um usuário, conexões já autenticadas como ele mantêm o seu papel
+    Remove {
+        /// Nome do usuário
+        name: String,
+    },
+    /// Lista os usuários e os seus papéis
+    List,
+}
+
+#[derive(Subcommand)]
+enum AclCommand {
+    /// Define o único papel que pode acessar as chaves do prefixo desse jeito, admins sempre podem
+    Set {
+        /// Prefixo das chaves
+        prefix: String,
+        /// Tipo de acesso
+        access: AclAccess,
+        /// Papel, se omitido remove a regra
+        role: Option<String>,
+    },
+    /// Lista as regras
+    List,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum AclAccess {
+    Read,
+    Write,
+}
+
 fn main() -> std::io::Result<()> {
     let _ = ctrlc::set_handler(|| std::process::exit(0));
-    match Cli::parse().command {
+    let cli = Cli::parse();
+    if let Some(name) = cli.name {
+        if name.len() > u8::MAX as usize {
+            eprintln!("o nome da conexão pode ter no máximo 255 bytes");
+            std::process::exit(1);
+        }
+        connect::set_name(name);
+    }
+    match cli.command {
         Some(Commands::Serve {
             path,
+            bind,
+            systemd_socket,
             sync,
             flush,
             cache: cached,
+            admin_token,
+            max_persist_backlog,
+            slow_ms,
+            min_replicas,
+            replica_timeout_ms,
+            config,
+            access_log,
+            access_log_max_bytes,
+            access_log_max_age,
+            max_value_size,
+            max_key_size,
+            max_scan_bytes,
+            max_transactions_per_conn,
+            compact_every_min,
+            compact_keep_days,
+            sweep_every_secs,
+            maintenance_window,
+            maintenance_max_commits,
         }) => {
             let mode = if sync {
                 DatabaseWriteSyncMode::Sync
@@ -48,10 +484,266 @@ fn main() -> std::io::Result<()> {
             } else {
                 DatabaseWriteSyncMode::Sync
             };
-            server::serve(path, mode)?;
+            let listeners = if let Some(listeners) = upgrade::inherited()? {
+                listeners
+            } else if systemd_socket {
+                systemd::listen_fds()?
+            } else if bind.is_empty() {
+                vec![server::listen(DEFAULT_ADDR.parse().unwrap())?]
+            } else {
+                bind.into_iter()
+                    .map(server::listen)
+                    .collect::<Result<_, _>>()?
+            };
+            let access_log = access_log
+                .map(|path| {
+                    access_log::AccessLog::open(
+                        path,
+                        access_log_max_bytes,
+                        access_log_max_age.map(Duration::from_secs),
+                    )
+                })
+                .transpose()?;
+            server::serve(
+                path,
+                listeners,
+                mode,
+                admin_token,
+                max_persist_backlog,
+                slow_ms.map(Duration::from_millis),
+                min_replicas,
+                Duration::from_millis(replica_timeout_ms),
+                config,
+                access_log,
+                server::Limits {
+                    max_key_size: max_key_size.unwrap_or(u32::MAX),
+                    max_value_size: max_value_size.unwrap_or(u32::MAX),
+                    max_scan_bytes: max_scan_bytes.unwrap_or(u32::MAX),
+                    max_transactions_per_conn: max_transactions_per_conn.unwrap_or(u64::MAX),
+                },
+                maintenance::Maintenance {
+                    compact_every: compact_every_min.map(|min| Duration::from_secs(min * 60)),
+                    compact_keep: Duration::from_secs(compact_keep_days * 24 * 60 * 60),
+                    sweep_every: sweep_every_secs.map(Duration::from_secs),
+                },
+                maintenance::Schedule {
+                    window: maintenance_window,
+                    max_commits_per_sec: maintenance_max_commits,
+                },
+            )?;
+        }
+        Some(Commands::Admin {
+            connect,
+            token,
+            command,
+        }) => {
+            let conn = connect::connect(cli.db.as_deref(), &connect, Duration::from_secs(30))?;
+            admin::admin(conn, token.as_deref(), command)?;
+        }
+        Some(Commands::Backup { connect, output }) => {
+            let conn = connect::connect(cli.db.as_deref(), &connect, Duration::from_secs(30))?;
+            backup::backup(conn, output)?;
+        }
+        Some(Commands::Restore { connect, input }) => {
+            let conn = connect::connect(cli.db.as_deref(), &connect, Duration::from_secs(30))?;
+            backup::restore(conn, input)?;
+        }
+        Some(Commands::VerifyBackup { input }) => {
+            backup::verify(input)?;
+        }
+        Some(Commands::Import {
+            connect,
+            csv,
+            key_template,
+            value_column,
+            batch_size,
+            header,
+            dry_run,
+            input,
+        }) => {
+            if !csv {
+                eprintln!("informe o formato do arquivo, apenas --csv é suportado");
+                std::process::exit(1);
+            }
+            let conn = connect::connect(cli.db.as_deref(), &connect, Duration::from_secs(30))?;
+            let options = import::ImportOptions {
+                key_template: &key_template,
+                value_column,
+                batch_size: batch_size.max(1),
+                skip_header: header,
+                dry_run,
+            };
+            if input == "-" {
+                import::import_csv(conn, std::io::stdin().lock(), None, options)?;
+            } else {
+                let file = std::fs::File::open(input)?;
+                let len = file.metadata()?.len();
+                import::import_csv(conn, std::io::BufReader::new(file), Some(len), options)?;
+            }
+        }
+        Some(Commands::Rewrite {
+            connect,
+            prefix,
+            map_script,
+            name,
+            batch_size,
+            dry_run,
+        }) => {
+            let script = std::fs::read_to_string(map_script)?;
+            let conn = connect::connect(cli.db.as_deref(), &connect, Duration::from_secs(30))?;
+            let options = rewrite::RewriteOptions {
+                prefix: &prefix,
+                name: name.as_deref(),
+                batch_size: batch_size.max(1),
+                dry_run,
+            };
+            rewrite::rewrite(conn, &script, options)?;
+        }
+        Some(Commands::Publish {
+            connect,
+            channel,
+            message,
+        }) => {
+            let mut conn = connect::connect(cli.db.as_deref(), &connect, Duration::from_secs(30))?;
+            let receivers = conn.publish(channel, message)?;
+            println!("mensagem recebida por {receivers} inscrito(s)");
+        }
+        Some(Commands::Subscribe {
+            connect,
+            channels,
+            pattern,
+        }) => {
+            let conn = connect::connect(cli.db.as_deref(), &connect, Duration::from_secs(30))?;
+            let mut subscription = if pattern {
+                conn.subscribe_patterns(&channels)?
+            } else {
+                conn.subscribe(&channels)?
+            };
+            loop {
+                let (channel, message) = subscription.next_message()?;
+                println!(
+                    "{}: {}",
+                    String::from_utf8_lossy(&channel),
+                    String::from_utf8_lossy(&message)
+                );
+            }
+        }
+        Some(Commands::Replicate {
+            connect,
+            name,
+            interval_ms,
+            compress,
+            path,
+        }) => {
+            if name.is_empty() || name.len() > u8::MAX as usize {
+                eprintln!("o nome da réplica deve ter de 1 a 255 bytes");
+                std::process::exit(1);
+            }
+            let conn = connect::connect(cli.db.as_deref(), &connect, Duration::from_secs(30))?;
+            replica::replicate(
+                conn,
+                path,
+                &name,
+                Duration::from_millis(interval_ms),
+                compress,
+            )?;
+        }
+        Some(Commands::Tail { connect, pattern }) => {
+            let conn = connect::connect(cli.db.as_deref(), &connect, Duration::from_secs(30))?;
+            tail::tail(conn, &pattern)?;
+        }
+        Some(Commands::Get { connect, key }) => {
+            oneshot::exit(oneshot::get(cli.db.as_deref(), &connect, &key));
+        }
+        Some(Commands::Set {
+            connect,
+            expect,
+            key,
+            value,
+        }) => {
+            oneshot::exit(oneshot::set(
+                cli.db.as_deref(),
+                &connect,
+                &key,
+                value.as_deref(),
+                expect.as_deref(),
+            ));
+        }
+        Some(Commands::Incr {
+            connect,
+            key,
+            delta,
+        }) => {
+            oneshot::exit(oneshot::incr(cli.db.as_deref(), &connect, &key, delta));
+        }
+        Some(Commands::Del {
+            connect,
+            expect,
+            prefix,
+            dry_run,
+            key,
+        }) => {
+            if prefix {
+                oneshot::exit(oneshot::del_prefix(
+                    cli.db.as_deref(),
+                    &connect,
+                    &key,
+                    dry_run,
+                ));
+            }
+            oneshot::exit(oneshot::del(
+                cli.db.as_deref(),
+                &connect,
+                &key,
+                expect.as_deref(),
+                dry_run,
+            ));
+        }
+        Some(Commands::Scan {
+            connect,
+            prefix,
+            suffix,
+            values,
+        }) => {
+            oneshot::exit(oneshot::scan(
+                cli.db.as_deref(),
+                &connect,
+                &prefix,
+                &suffix,
+                values,
+            ));
+        }
+        Some(Commands::Tree {
+            connect,
+            prefix,
+            depth,
+        }) => {
+            let mut conn =
+                connect::connect_reads(cli.db.as_deref(), &connect, Duration::from_secs(30))?;
+            tree::print_tree(&mut conn, &prefix, depth)?;
+        }
+        Some(Commands::Call {
+            connect,
+            name,
+            args,
+        }) => {
+            let mut conn = connect::connect(cli.db.as_deref(), &connect, Duration::from_secs(30))?;
+            match conn.call_procedure(&name, &args) {
+                Ok(result) => println!("{}", String::from_utf8_lossy(&result)),
+                Err(pathkvs_net::Error::ProcedureFailed(message)) => {
+                    eprintln!("o procedimento falhou: {message}");
+                    std::process::exit(1);
+                }
+                Err(pathkvs_net::Error::Unsupported) => {
+                    eprintln!("o servidor não suporta procedimentos");
+                    std::process::exit(1);
+                }
+                Err(error) => return Err(error.into()),
+            }
         }
         None => {
-            client::client()?;
+            let conn = connect::connect(cli.db.as_deref(), DEFAULT_ADDR, Duration::from_secs(1))?;
+            client::client(conn, cli.db.as_deref().unwrap_or(DEFAULT_ADDR))?;
         }
     }
     Ok(())
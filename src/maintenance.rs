@@ -0,0 +1,125 @@
+//! the background maintenance of `serve`, compacting the file and sweeping the expired keys every so often
+//!
+//! both only run inside the maintenance window, a range of the local time of the day, such as `02:00-05:00`,
+//! which may wrap around midnight, and while the database commits less than a given number of times per second,
+//! measured from [`Database::metrics`], so they don't collide with peak traffic,
+//! a task that is due outside of them waits until they allow it
+
+use std::{
+    str::FromStr,
+    sync::RwLock,
+    time::{Duration, Instant},
+};
+
+use chrono::{Local, NaiveTime};
+use pathkvs_core::Database;
+
+/// how often the thread checks whether a task is due, and the period the commits per second are measured over
+const CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// the tasks of `serve --compact-every-min` and `--sweep-every-secs`
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Maintenance {
+    pub compact_every: Option<Duration>,
+    /// the commits of this last period are kept as they are by the compaction
+    pub compact_keep: Duration,
+    pub sweep_every: Option<Duration>,
+}
+
+/// when the tasks may run, replaced by `admin reload`
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Schedule {
+    /// none is any time of the day
+    pub window: Option<Window>,
+    /// none is however busy the database is
+    pub max_commits_per_sec: Option<u64>,
+}
+
+/// a range of the local time of the day, from `start` up to but not including `end`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Window {
+    start: NaiveTime,
+    end: NaiveTime,
+}
+
+impl Window {
+    pub fn contains(&self, time: NaiveTime) -> bool {
+        if self.start <= self.end {
+            self.start <= time && time < self.end
+        } else {
+            self.start <= time || time < self.end
+        }
+    }
+}
+
+/// `HH:MM-HH:MM`, the error is a message for the admin, in portuguese like the rest of the interface
+impl FromStr for Window {
+    type Err = String;
+    fn from_str(text: &str) -> Result<Self, String> {
+        let error =
+            || format!("janela de manutenção inválida {text:?}, esperado como `02:00-05:00`");
+        let (start, end) = text.split_once('-').ok_or_else(error)?;
+        let time =
+            |text: &str| NaiveTime::parse_from_str(text.trim(), "%H:%M").map_err(|_| error());
+        Ok(Self {
+            start: time(start)?,
+            end: time(end)?,
+        })
+    }
+}
+
+/// runs the tasks in a thread of its own, for as long as the process lives
+pub fn spawn(db: &'static Database, maintenance: Maintenance, schedule: &'static RwLock<Schedule>) {
+    if maintenance.compact_every.is_none() && maintenance.sweep_every.is_none() {
+        return;
+    }
+    std::thread::spawn(move || {
+        let start = Instant::now();
+        let mut next_compact = maintenance.compact_every.map(|every| start + every);
+        let mut next_sweep = maintenance.sweep_every.map(|every| start + every);
+        let mut commits = db.metrics().commits;
+        let mut measured = start;
+        loop {
+            std::thread::sleep(CHECK_INTERVAL);
+            let now = Instant::now();
+            let last = std::mem::replace(&mut commits, db.metrics().commits);
+            // a task that ran makes the period longer than the interval
+            let elapsed = now.duration_since(std::mem::replace(&mut measured, now));
+            let schedule = *schedule.read().unwrap();
+            if schedule
+                .window
+                .is_some_and(|window| !window.contains(Local::now().time()))
+            {
+                continue;
+            }
+            let per_sec = (commits - last) as f64 / elapsed.as_secs_f64();
+            if schedule
+                .max_commits_per_sec
+                .is_some_and(|max| per_sec > max as f64)
+            {
+                continue;
+            }
+            if next_sweep.is_some_and(|next| now >= next) {
+                match db.sweep_expired() {
+                    Ok(0) => {}
+                    Ok(swept) => println!("{swept} chave(s) expirada(s) pela manutenção"),
+                    Err(error) => println!("a manutenção não conseguiu expirar as chaves: {error}"),
+                }
+                next_sweep = maintenance.sweep_every.map(|every| now + every);
+            }
+            if next_compact.is_some_and(|next| now >= next) {
+                match db.compact_keeping(maintenance.compact_keep) {
+                    Ok(Some(compaction)) => println!(
+                        "histórico compactado pela manutenção: de {} para {} byte(s), {} commit(s)",
+                        compaction.before, compaction.after, compaction.commits
+                    ),
+                    Ok(None) => {}
+                    Err(error) => {
+                        println!("a manutenção não conseguiu compactar o histórico: {error}")
+                    }
+                }
+                next_compact = maintenance.compact_every.map(|every| now + every);
+            }
+        }
+    });
+}
@@ -0,0 +1,230 @@
+//! `get`, `set`, `incr`, `del` and `scan`, a single request for scripts, values go to stdout as they are,
+//! messages go to stderr, and the exit code tells why it failed
+
+use std::io::{Read, Write};
+
+use pathkvs_net::{client::Connection, Error};
+
+use crate::connect::{self, Stream};
+
+const EXIT_FAILED: i32 = 1;
+const EXIT_NOT_FOUND: i32 = 2;
+const EXIT_CONFLICT: i32 = 3;
+const EXIT_CONNECTION: i32 = 4;
+
+pub enum Failure {
+    /// the key does not exist, or no key is in the range
+    NotFound,
+    /// the value was not the one given with `--expect`
+    Conflict,
+    /// the server could not be reached, or the connection failed in the middle of the request
+    Connection(std::io::Error),
+    Other(String),
+}
+
+impl From<Error> for Failure {
+    fn from(error: Error) -> Self {
+        match error {
+            Error::Io(error) => Failure::Connection(error),
+            Error::Conflict | Error::CheckFailed(_) => Failure::Conflict,
+            error => Failure::Other(error.to_string()),
+        }
+    }
+}
+
+/// ends the process with the exit code of the result
+pub fn exit(result: Result<(), Failure>) -> ! {
+    let code = match result {
+        Ok(()) => 0,
+        Err(Failure::NotFound) => EXIT_NOT_FOUND,
+        Err(Failure::Conflict) => {
+            eprintln!("o valor atual não é o esperado");
+            EXIT_CONFLICT
+        }
+        Err(Failure::Connection(error)) => {
+            eprintln!("falha na conexão: {error}");
+            EXIT_CONNECTION
+        }
+        Err(Failure::Other(error)) => {
+            eprintln!("{error}");
+            EXIT_FAILED
+        }
+    };
+    std::process::exit(code)
+}
+
+fn connect(db: Option<&str>, addr: &str) -> Result<Connection<Stream>, Failure> {
+    connect::connect(db, addr, std::time::Duration::from_secs(30)).map_err(Failure::Connection)
+}
+
+/// for the commands that only read, see [`connect::connect_reads`]
+fn connect_reads(db: Option<&str>, addr: &str) -> Result<Connection<Stream>, Failure> {
+    connect::connect_reads(db, addr, std::time::Duration::from_secs(30))
+        .map_err(Failure::Connection)
+}
+
+fn output(bytes: &[u8]) -> Result<(), Failure> {
+    let mut stdout = std::io::stdout().lock();
+    stdout
+        .write_all(bytes)
+        .and_then(|()| stdout.flush())
+        .map_err(|error| Failure::Other(error.to_string()))
+}
+
+/// writes the value, without a line break, so it can be piped as it is
+pub fn get(db: Option<&str>, addr: &str, key: &str) -> Result<(), Failure> {
+    let mut conn = connect_reads(db, addr)?;
+    let value = conn.read(key)?;
+    if value.is_empty() && !conn.exists(key)? {
+        return Err(Failure::NotFound);
+    }
+    output(&value)
+}
+
+/// the value is read from stdin if not given, `expect` makes the write conditional on the current value
+pub fn set(
+    db: Option<&str>,
+    addr: &str,
+    key: &str,
+    value: Option<&str>,
+    expect: Option<&str>,
+) -> Result<(), Failure> {
+    let value = match value {
+        Some(value) => value.as_bytes().to_vec(),
+        None => {
+            let mut value = Vec::new();
+            std::io::stdin()
+                .read_to_end(&mut value)
+                .map_err(|error| Failure::Other(error.to_string()))?;
+            value
+        }
+    };
+    let mut conn = connect(db, addr)?;
+    match expect {
+        Some(expect) => {
+            let key = key.as_bytes();
+            conn.check_write(&[(key, Some(expect.as_bytes()))], &[(key, Some(&value))])?;
+        }
+        None => conn.write(key, value)?,
+    }
+    Ok(())
+}
+
+/// writes the new value, followed by a line break
+pub fn incr(db: Option<&str>, addr: &str, key: &str, delta: i64) -> Result<(), Failure> {
+    let mut conn = connect(db, addr)?;
+    let n = conn.incr(key, delta)?;
+    output(format!("{n}\n").as_bytes())
+}
+
+/// fails with not found if the key does not exist, `expect` makes it conditional on the current value,
+/// `dry_run` only writes how many bytes would be deleted
+pub fn del(
+    db: Option<&str>,
+    addr: &str,
+    key: &str,
+    expect: Option<&str>,
+    dry_run: bool,
+) -> Result<(), Failure> {
+    let mut conn = connect(db, addr)?;
+    if !conn.exists(key)? {
+        return Err(Failure::NotFound);
+    }
+    if dry_run {
+        let bytes = key.len() as u64 + conn.len(key)? as u64;
+        println!("seria apagada 1 chave, {bytes} byte(s), nada foi apagado");
+        return Ok(());
+    }
+    match expect {
+        Some(expect) => {
+            let key = key.as_bytes();
+            conn.check_write(&[(key, Some(expect.as_bytes()))], &[(key, None)])?;
+        }
+        None => conn.clear(key)?,
+    }
+    Ok(())
+}
+
+/// deletes every key starting with `prefix` in a single transaction, or, if `dry_run`, only writes how many keys
+/// and bytes would be deleted, fails with not found if there is no key
+pub fn del_prefix(
+    db: Option<&str>,
+    addr: &str,
+    prefix: &str,
+    dry_run: bool,
+) -> Result<(), Failure> {
+    if prefix.is_empty() {
+        return Err(Failure::Other(
+            "o prefixo vazio apagaria todas as chaves".to_string(),
+        ));
+    }
+    let mut conn = connect(db, addr)?;
+    let (keys, bytes) = if dry_run {
+        measure(&mut conn, prefix)?
+    } else {
+        conn.set_write_batching(true);
+        conn.transact(|conn| {
+            let (keys, bytes) = measure(conn, prefix)?;
+            for key in &keys {
+                conn.clear(key)?;
+            }
+            Ok((keys, bytes))
+        })?
+    };
+    if keys.is_empty() {
+        return Err(Failure::NotFound);
+    }
+    if dry_run {
+        println!(
+            "seriam apagadas {} chave(s), {bytes} byte(s), nada foi apagado",
+            keys.len()
+        );
+    } else {
+        println!("apagadas {} chave(s), {bytes} byte(s)", keys.len());
+    }
+    Ok(())
+}
+
+/// the keys starting with `prefix`, and how many bytes they take along with their values
+fn measure(
+    conn: &mut Connection<impl Read + Write>,
+    prefix: &str,
+) -> Result<(Vec<Vec<u8>>, u64), Error> {
+    let keys = conn.list(prefix, "")?;
+    let mut bytes = 0;
+    for chunk in keys.chunks(1000) {
+        for (key, len) in chunk.iter().zip(conn.len_many(chunk)?) {
+            bytes += key.len() as u64 + len as u64;
+        }
+    }
+    Ok((keys, bytes))
+}
+
+/// writes a line per key, with a tab and the value after it if `values`, fails with not found if there is no key
+pub fn scan(
+    db: Option<&str>,
+    addr: &str,
+    prefix: &str,
+    suffix: &str,
+    values: bool,
+) -> Result<(), Failure> {
+    let mut conn = connect_reads(db, addr)?;
+    let mut lines = Vec::new();
+    if values {
+        for (key, value) in conn.scan(prefix, suffix)? {
+            lines.extend_from_slice(&key);
+            lines.push(b'\t');
+            lines.extend_from_slice(&value);
+            lines.push(b'\n');
+        }
+    } else {
+        for key in conn.list(prefix, suffix)? {
+            lines.extend_from_slice(&key);
+            lines.push(b'\n');
+        }
+    }
+    if lines.is_empty() {
+        return Err(Failure::NotFound);
+    }
+    output(&lines)
+}
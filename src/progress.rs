@@ -0,0 +1,61 @@
+//! the progress bars of the long running subcommands, drawn on stderr, and only if it is a terminal
+//!
+//! the rate is redrawn even while nothing advances, so a stuck operation shows zero per second instead of freezing
+
+use std::time::Duration;
+
+use indicatif::{ProgressBar, ProgressStyle};
+
+const TICK: Duration = Duration::from_millis(200);
+
+/// a bar of the bytes of a file, or a spinner if its length is unknown, such as the one of stdin
+pub fn bytes(len: Option<u64>, message: &'static str) -> ProgressBar {
+    match len {
+        Some(len) => styled(
+            ProgressBar::new(len),
+            "{msg} [{bar:40}] {bytes}/{total_bytes} {bytes_per_sec} faltam {eta}",
+            message,
+        ),
+        None => styled(
+            ProgressBar::new_spinner(),
+            "{spinner} {msg} {bytes} {bytes_per_sec}",
+            message,
+        ),
+    }
+}
+
+/// a bar of keys, or a spinner if how many there are is unknown
+pub fn keys(len: Option<u64>, message: &'static str) -> ProgressBar {
+    match len {
+        Some(len) => styled(
+            ProgressBar::new(len),
+            "{msg} [{bar:40}] {pos}/{len} chave(s) {per_sec} faltam {eta}",
+            message,
+        ),
+        None => styled(
+            ProgressBar::new_spinner(),
+            "{spinner} {msg} {pos} chave(s) {per_sec}",
+            message,
+        ),
+    }
+}
+
+/// a spinner with the time spent, for a request the server reports no progress of
+pub fn waiting(message: &'static str) -> ProgressBar {
+    styled(
+        ProgressBar::new_spinner(),
+        "{spinner} {msg} {elapsed}",
+        message,
+    )
+}
+
+fn styled(bar: ProgressBar, template: &str, message: &'static str) -> ProgressBar {
+    bar.set_style(
+        ProgressStyle::with_template(template)
+            .unwrap()
+            .progress_chars("=> "),
+    );
+    bar.set_message(message);
+    bar.enable_steady_tick(TICK);
+    bar
+}
@@ -0,0 +1,114 @@
+//! `replicate`, keeps a database file up to date with a server, as one of its replicas
+//!
+//! a new replica starts from a snapshot of the server, with only the latest value of each key,
+//! instead of replaying its whole history, and from then on applies the commits after it as they happen,
+//! acknowledging each position, so `admin replication` on the server shows how far behind it is
+//!
+//! the position is kept in the file itself, under [`system::REPLICATION`] followed by the name of the replica,
+//! so a replica that is started again goes on from where it stopped, the commits are applied before the position
+//! is written, so if it stops in between they are applied again, which gives the keys the same values
+//!
+//! with `--compress` the history comes compressed, see [`pathkvs_net::compression`]
+
+use std::{
+    convert::Infallible,
+    io::{BufWriter, Error, ErrorKind, Read, Write},
+    path::Path,
+    time::Duration,
+};
+
+use pathkvs_core::{system, Database};
+use pathkvs_net::{client::Connection, compression::Compression};
+
+use crate::progress;
+
+/// replicates the server into the database file at `path`, copying a snapshot of it first if the file doesn't exist,
+/// only returns once the connection fails
+pub fn replicate(
+    mut conn: Connection<impl Read + Write>,
+    path: impl AsRef<Path>,
+    name: &str,
+    interval: Duration,
+    compress: bool,
+) -> Result<Infallible, Error> {
+    let path = path.as_ref();
+    if compress {
+        match conn.negotiate_compression(Compression::ALL)? {
+            Compression::None => println!("o servidor não comprime o histórico"),
+            compression => println!("histórico comprimido com {compression:?}"),
+        }
+    }
+    let key = system::key(system::REPLICATION, name.as_bytes());
+    if !path.exists() {
+        bootstrap(&mut conn, path, &key)?;
+    }
+    let database = Database::open(path)?;
+    let Some(mut seq) = position(&database, &key) else {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            format!("{} não é uma réplica com o nome {name}", path.display()),
+        ));
+    };
+    println!("réplica {name} no commit nº {seq}");
+    let mut commits = Vec::new();
+    loop {
+        commits.clear();
+        let next = match conn.backup_since(seq, &mut commits) {
+            Ok(next) => next,
+            Err(pathkvs_net::Error::Conflict) => {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    format!(
+                        "o servidor tem menos que {seq} commit(s), apague {} para copiar o banco de novo",
+                        path.display()
+                    ),
+                ))
+            }
+            Err(error) => return Err(error.into()),
+        };
+        let caught_up = next == seq;
+        if !caught_up {
+            let applied = database.apply_backup(commits.as_slice())?;
+            database.write(&key, next.to_string().as_bytes())?;
+            println!("aplicado(s) {applied} commit(s), réplica no commit nº {next}");
+            seq = next;
+        }
+        conn.ack_replication(name, seq)?;
+        if caught_up {
+            std::thread::sleep(interval);
+        }
+    }
+}
+
+/// copies a snapshot of the server into a new file, which is only moved to `path` once it is whole,
+/// with the position of the snapshot
+fn bootstrap(
+    conn: &mut Connection<impl Read + Write>,
+    path: &Path,
+    key: &[u8],
+) -> Result<(), Error> {
+    let mut partial = path.as_os_str().to_owned();
+    partial.push(".parcial");
+    let partial = Path::new(&partial);
+    let progress = progress::waiting("copiando o snapshot do servidor");
+    let seq = match conn.backup_snapshot(BufWriter::new(std::fs::File::create(partial)?)) {
+        Ok(seq) => seq,
+        Err(error) => {
+            let _ = std::fs::remove_file(partial);
+            return Err(error.into());
+        }
+    };
+    let database = Database::open(partial)?;
+    database.write(key, seq.to_string().as_bytes())?;
+    let keys = database.count(b"", b"");
+    database.close()?;
+    std::fs::rename(partial, path)?;
+    progress.finish_and_clear();
+    println!("copiada(s) {keys} chave(s) do servidor, até o commit nº {seq}");
+    Ok(())
+}
+
+/// how many commits of the server the replica has applied, none if it isn't a replica with this name
+fn position(database: &Database, key: &[u8]) -> Option<u64> {
+    std::str::from_utf8(&database.read(key)).ok()?.parse().ok()
+}
@@ -0,0 +1,118 @@
+//! `rewrite`, rewrites the value of every key under a prefix with a script, for migrations of the format of the values
+//!
+//! the script is a procedure, see [`pathkvs_core::procedure`], evaluated here by [`Procedure::eval`] for each key,
+//! with the key as `(arg 0)` and the value as `(arg 1)`, and its result is the new value,
+//! a result equal to the value leaves the key alone, and an empty one deletes the key
+//!
+//! like [`Database::rewrite`](pathkvs_core::Database::rewrite), each batch is a transaction that also writes
+//! the last key of the batch under [`system::REWRITE`] followed by the name of the rewrite,
+//! so an interrupted rewrite goes on after the last batch that committed when it is run again with the same name,
+//! the keys are listed once, when it starts
+
+use std::io::{Error, ErrorKind, Read, Write};
+
+use pathkvs_core::{procedure::Procedure, system};
+use pathkvs_net::client::Connection;
+
+use crate::{progress, utils::DisplayBytesEx};
+
+pub struct RewriteOptions<'a> {
+    pub prefix: &'a str,
+    /// where the position is kept, the prefix if none
+    pub name: Option<&'a str>,
+    pub batch_size: usize,
+    /// only counts the keys whose values the script would change, nothing is written
+    pub dry_run: bool,
+}
+
+pub fn rewrite(
+    mut conn: Connection<impl Read + Write>,
+    script: &str,
+    options: RewriteOptions,
+) -> Result<(), Error> {
+    let procedure =
+        Procedure::parse(script).map_err(|error| Error::new(ErrorKind::InvalidInput, error))?;
+    // the writes of each batch go with its commit, instead of a round trip each
+    conn.set_write_batching(true);
+    let name = options.name.unwrap_or(options.prefix);
+    let position = system::key(system::REWRITE, name.as_bytes());
+    let last = conn.read(&position)?;
+    if !last.is_empty() {
+        println!(
+            "continuando a reescrita {name} depois da chave {}",
+            last.display()
+        );
+    }
+    let keys = conn
+        .list(options.prefix, "")?
+        .into_iter()
+        .filter(|key| last.is_empty() || *key > last)
+        .collect::<Vec<_>>();
+    let progress = progress::keys(Some(keys.len() as u64), "reescrevendo");
+    let batches = keys.len().div_ceil(options.batch_size);
+    let mut rewritten = 0u64;
+    for (index, batch) in keys.chunks(options.batch_size).enumerate() {
+        rewritten += if options.dry_run {
+            let values = conn.read_many(batch)?;
+            let mut changed = 0;
+            for (key, value) in batch.iter().zip(&values) {
+                if map_value(&procedure, key, value)?.is_some() {
+                    changed += 1;
+                }
+            }
+            changed
+        } else {
+            conn.transact(|conn| {
+                // two runs of the same rewrite at once conflict, instead of both doing the batch
+                conn.read(&position)?;
+                let values = conn.read_many(batch)?;
+                let mut changed = 0;
+                for (key, value) in batch.iter().zip(&values) {
+                    match map_value(&procedure, key, value)? {
+                        Some(new) if new.is_empty() => conn.delete(key)?,
+                        Some(new) => conn.write(key, new)?,
+                        None => continue,
+                    }
+                    changed += 1;
+                }
+                // the last batch removes the position, so the next run with the name starts over
+                match batch.last() {
+                    Some(key) if index + 1 != batches => conn.write(&position, key)?,
+                    _ => conn.delete(&position)?,
+                }
+                Ok(changed)
+            })?
+        };
+        progress.inc(batch.len() as u64);
+    }
+    // a previous run was interrupted after its last batch and before removing the position
+    if keys.is_empty() && !last.is_empty() && !options.dry_run {
+        conn.delete(&position)?;
+    }
+    progress.finish_and_clear();
+    if options.dry_run {
+        println!(
+            "seriam reescrita(s) {rewritten} de {} chave(s), nada foi escrito",
+            keys.len()
+        );
+    } else {
+        println!("reescrita(s) {rewritten} de {} chave(s)", keys.len());
+    }
+    Ok(())
+}
+
+/// the new value of the key, none if the script leaves it as it is, or if it was deleted since it was listed
+fn map_value(procedure: &Procedure, key: &[u8], value: &[u8]) -> Result<Option<Vec<u8>>, Error> {
+    if value.is_empty() {
+        return Ok(None);
+    }
+    let new = procedure
+        .eval(&[key.to_vec(), value.to_vec()])
+        .map_err(|error| {
+            Error::new(
+                ErrorKind::InvalidData,
+                format!("o script falhou na chave {}: {error}", key.display()),
+            )
+        })?;
+    Ok((new != value).then_some(new))
+}
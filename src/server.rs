@@ -1,22 +1,126 @@
-use std::{io::Error, path::Path, time::Duration};
+use std::{
+    collections::{BTreeMap, HashMap},
+    io::{Error, ErrorKind},
+    net::{Shutdown, SocketAddr, TcpListener, TcpStream},
+    path::{Path, PathBuf},
+    process::Child,
+    sync::{
+        atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering},
+        mpsc::{channel, Sender},
+        Arc, Mutex, RwLock,
+    },
+    time::{Duration, Instant},
+};
 
 use pathkvs_core::{
-    error::{ProtocolError, TransactionConflict, TransposeConflict},
-    DatabaseWriteSyncMode,
+    error::{ProtocolError, TransactionConflict, TransactionError, TransposeConflict},
+    hook::{Change, CommitStage},
+    procedure::{KeyAccess, Procedure, ProcedureError},
+    replication::ReplicaStatus,
+    system, CommitReceipt, CommitSummary, DatabaseWriteSyncMode,
+};
+use pathkvs_net::{
+    client::{Access, AclRule, ConnectionInfo, ConnectionMode, ReadConsistency, SlowCommand},
+    glob,
+    stats::{CommandEvent, CommandStats},
 };
+use socket2::{Domain, Protocol, Socket, Type};
 
+use crate::{
+    access_log::AccessLog,
+    acl::{self, Acl},
+    config::Config,
+    connect::{pipe, Pipe},
+    maintenance::{self, Maintenance, Schedule},
+    slow_log::SlowLog,
+    tail,
+};
+
+#[allow(clippy::too_many_arguments)]
 pub fn serve(
     path: Option<impl AsRef<Path>>,
+    listeners: Vec<TcpListener>,
     sync: DatabaseWriteSyncMode,
+    mut admin_token: Option<String>,
+    mut max_persist_backlog: Option<u64>,
+    mut slow: Option<Duration>,
+    mut min_replicas: u32,
+    mut replica_timeout: Duration,
+    config: Option<impl Into<PathBuf>>,
+    access_log: Option<AccessLog>,
+    limits: Limits,
+    maintenance: Maintenance,
+    mut schedule: Schedule,
 ) -> Result<std::convert::Infallible, Error> {
-    let addr = "127.0.0.1:6314";
-    let listener = std::net::TcpListener::bind(addr)?;
+    let config = config.map(Into::into);
+    let mut sync = sync;
+    if let Some(path) = &config {
+        let config =
+            Config::read(path).map_err(|error| Error::new(ErrorKind::InvalidData, error))?;
+        if let Some(token) = config.admin_token {
+            admin_token = token;
+        }
+        sync = config.sync.unwrap_or(sync);
+        if let Some(max) = config.max_persist_backlog {
+            max_persist_backlog = max;
+        }
+        if let Some(ms) = config.slow_ms {
+            slow = ms.map(Duration::from_millis);
+        }
+        min_replicas = config.min_replicas.unwrap_or(min_replicas);
+        if let Some(ms) = config.replica_timeout_ms {
+            replica_timeout = Duration::from_millis(ms);
+        }
+        if let Some(window) = config.maintenance_window {
+            schedule.window = window;
+        }
+        if let Some(max) = config.maintenance_max_commits {
+            schedule.max_commits_per_sec = max;
+        }
+    }
+    let addr = listeners
+        .iter()
+        .map(|listener| Ok(listener.local_addr()?.to_string()))
+        .collect::<Result<Vec<_>, Error>>()?
+        .join(", ");
     let mem = path.is_none();
     let database = match path {
         Some(path) => pathkvs_core::Database::open(path)?.write_sync_mode(sync),
         None => pathkvs_core::Database::memory(),
     };
-    let database = &*Box::leak(Box::new(database));
+    let acl = Acl::load(&database);
+    let shared = &*Box::leak(Box::new(Shared {
+        db: database,
+        admin_token: RwLock::new(admin_token),
+        max_persist_backlog: AtomicU64::new(max_persist_backlog.unwrap_or(u64::MAX)),
+        busy_replies: AtomicU64::new(0),
+        min_replicas: AtomicU32::new(min_replicas),
+        replica_timeout_ms: AtomicU64::new(replica_timeout.as_millis() as u64),
+        config,
+        started: Instant::now(),
+        next_id: AtomicU64::new(1),
+        sessions: Mutex::new(BTreeMap::new()),
+        draining: AtomicBool::new(false),
+        procedures: Mutex::new(HashMap::new()),
+        channels: Mutex::new(HashMap::new()),
+        pattern_subscribers: Mutex::new(Vec::new()),
+        connections_accepted: AtomicU64::new(0),
+        commands: Mutex::new(CommandStats::default()),
+        access_log,
+        slow_log: SlowLog::new(slow),
+        listeners: listeners
+            .iter()
+            .map(TcpListener::try_clone)
+            .collect::<Result<_, _>>()?,
+        upgrade: Mutex::new(None),
+        limits,
+        acl: RwLock::new(acl),
+        schedule: RwLock::new(schedule),
+        compact_keep: maintenance.compact_keep,
+    }));
+    shared.publish_changes();
+    shared.track_acl();
+    maintenance::spawn(&shared.db, maintenance, &shared.schedule);
     match sync {
         _ if mem => {
             println!("servindo banco sem persistência em {addr}");
@@ -31,10 +135,52 @@ pub fn serve(
             println!("servindo banco não ACID em {addr} (modo cached)");
         }
     }
+    let (errors, error) = channel();
+    for listener in listeners {
+        let errors = errors.clone();
+        std::thread::spawn(move || {
+            if let Err(error) = accept_connections(listener, shared) {
+                let _ = errors.send(error);
+            }
+        });
+    }
+    Err(error.recv().unwrap())
+}
+
+/// binds a listener to `addr`, one on an ipv6 address only accepts ipv6,
+/// so that `[::]` and `0.0.0.0` can be bound together on the same port
+pub fn listen(addr: SocketAddr) -> Result<TcpListener, Error> {
+    let result = (|| {
+        let socket = Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))?;
+        if addr.is_ipv6() {
+            socket.set_only_v6(true)?;
+        }
+        #[cfg(not(windows))]
+        socket.set_reuse_address(true)?;
+        socket.bind(&addr.into())?;
+        socket.listen(128)?;
+        Ok::<_, Error>(socket.into())
+    })();
+    result.map_err(|error| {
+        Error::new(
+            error.kind(),
+            format!("não foi possível escutar em {addr}: {error}"),
+        )
+    })
+}
+
+/// the accept loop of one listener, every listener feeds the same database
+///
+/// it only ends after `admin upgrade`, once it accepted one more connection
+fn accept_connections(listener: TcpListener, shared: &'static Shared) -> Result<(), Error> {
     loop {
-        let (mut stream, _) = listener.accept()?;
+        let (mut stream, peer) = listener.accept()?;
+        let upgrading = shared.upgrade.lock().unwrap().is_some();
+        if shared.draining.load(Ordering::SeqCst) && !upgrading {
+            continue;
+        }
         std::thread::spawn(move || {
-            let mut server = Server::new(database);
+            let mut server = Server::new(shared, peer.to_string(), false, stream.try_clone().ok());
             let result = pathkvs_net::server::serve(&mut stream, &mut server);
             match result {
                 Ok(()) => {}
@@ -42,7 +188,226 @@ pub fn serve(
                     println!("{error:#?}")
                 }
             }
+            drop(server);
+            if shared.draining.load(Ordering::SeqCst) && shared.sessions.lock().unwrap().is_empty()
+            {
+                println!("todas as conexões foram encerradas, finalizando");
+                std::process::exit(0);
+            }
+        });
+        if upgrading {
+            // the new process only accepts once this one exits, so the connection is still served here,
+            // but it is the last one, the next wait in the queue for the new process
+            return Ok(());
+        }
+    }
+}
+
+/// opens the database file and serves it in a background thread of this process
+///
+/// the connection through the returned pipe is always granted admin
+pub fn serve_local(path: impl AsRef<Path>) -> Result<Pipe, Error> {
+    let database = pathkvs_core::Database::open(path)?;
+    let acl = Acl::load(&database);
+    let shared = &*Box::leak(Box::new(Shared {
+        db: database,
+        admin_token: RwLock::new(None),
+        max_persist_backlog: AtomicU64::new(u64::MAX),
+        busy_replies: AtomicU64::new(0),
+        min_replicas: AtomicU32::new(0),
+        replica_timeout_ms: AtomicU64::new(0),
+        config: None,
+        started: Instant::now(),
+        next_id: AtomicU64::new(1),
+        sessions: Mutex::new(BTreeMap::new()),
+        draining: AtomicBool::new(false),
+        procedures: Mutex::new(HashMap::new()),
+        channels: Mutex::new(HashMap::new()),
+        pattern_subscribers: Mutex::new(Vec::new()),
+        connections_accepted: AtomicU64::new(0),
+        commands: Mutex::new(CommandStats::default()),
+        access_log: None,
+        slow_log: SlowLog::new(None),
+        listeners: Vec::new(),
+        upgrade: Mutex::new(None),
+        limits: Limits::default(),
+        acl: RwLock::new(acl),
+        schedule: RwLock::new(Schedule::default()),
+        compact_keep: Duration::ZERO,
+    }));
+    shared.publish_changes();
+    shared.track_acl();
+    let (client, mut stream) = pipe();
+    std::thread::spawn(move || {
+        let mut server = Server::new(shared, "local".to_string(), true, None);
+        if let Err(error) = pathkvs_net::server::serve(&mut stream, &mut server) {
+            eprintln!("{error:#?}");
+        }
+    });
+    Ok(client)
+}
+
+struct Shared {
+    db: pathkvs_core::Database,
+    /// replaced by `admin reload`, connections that already authenticated keep their admin access
+    admin_token: RwLock<Option<String>>,
+    /// writes and commits are refused while more commits than this wait to be persisted, `u64::MAX` disables it
+    max_persist_backlog: AtomicU64,
+    busy_replies: AtomicU64,
+    /// commits of transactions wait for this many replicas to acknowledge them, replaced by `admin reload`
+    min_replicas: AtomicU32,
+    replica_timeout_ms: AtomicU64,
+    /// the file given to `serve --config`
+    config: Option<PathBuf>,
+    started: Instant,
+    next_id: AtomicU64,
+    sessions: Mutex<BTreeMap<u64, Session>>,
+    draining: AtomicBool,
+    /// procedures live only in memory, they have to be defined again when the server restarts
+    procedures: Mutex<HashMap<String, Arc<Procedure>>>,
+    /// subscribers of each pub/sub channel, a subscriber that went away is only noticed when a message fails to
+    /// reach it, and removed on the publish after that
+    channels: Mutex<HashMap<Vec<u8>, Vec<Subscriber>>>,
+    /// subscribers of the channels matching any of their glob patterns, removed like those of `channels`
+    pattern_subscribers: Mutex<Vec<(Vec<Vec<u8>>, Subscriber)>>,
+    connections_accepted: AtomicU64,
+    /// the requests served by every connection
+    commands: Mutex<CommandStats>,
+    access_log: Option<AccessLog>,
+    slow_log: SlowLog,
+    /// copies of the listeners, handed over by `admin upgrade`
+    listeners: Vec<TcpListener>,
+    /// the process started by `admin upgrade`, its stdin is closed when this process exits
+    upgrade: Mutex<Option<Child>>,
+    limits: Limits,
+    acl: RwLock<Acl>,
+    /// when the background maintenance may run, replaced by `admin reload`
+    schedule: RwLock<Schedule>,
+    /// the commits of this last period are kept as they are by `admin gc`, from `serve --compact-keep-days`
+    compact_keep: Duration,
+}
+
+impl Shared {
+    /// returns how many subscribers received the message
+    ///
+    /// a change published for `tail` only reaches the subscribers that may read its key
+    fn publish(&self, channel: &[u8], message: &[u8]) -> u32 {
+        let key = channel.strip_prefix(tail::CHANNEL.as_bytes());
+        let acl = self.acl.read().unwrap();
+        let mut receivers = 0;
+        let mut send = |subscriber: &Subscriber| {
+            if !key.is_none_or(|key| subscriber.may_read(&acl, key)) {
+                return true;
+            }
+            let sent = subscriber
+                .sender
+                .send((channel.to_vec(), message.to_vec()))
+                .is_ok();
+            receivers += sent as u32;
+            sent
+        };
+        let mut channels = self.channels.lock().unwrap();
+        if let Some(subscribers) = channels.get_mut(channel) {
+            subscribers.retain(&mut send);
+            if subscribers.is_empty() {
+                channels.remove(channel);
+            }
+        }
+        drop(channels);
+        let mut pattern_subscribers = self.pattern_subscribers.lock().unwrap();
+        pattern_subscribers.retain(|(patterns, subscriber)| {
+            if !patterns
+                .iter()
+                .any(|pattern| glob::matches(pattern, channel))
+            {
+                return true;
+            }
+            send(subscriber)
         });
+        drop(pattern_subscribers);
+        receivers
+    }
+
+    /// publishes every change of every commit, as `tail` reads them,
+    /// with the content type the key has by the time it is published
+    fn publish_changes(&'static self) {
+        self.db
+            .on_change(b"", CommitStage::Persisted, |time, changes| {
+                // no message is built while nobody could receive it
+                if self.channels.lock().unwrap().is_empty()
+                    && self.pattern_subscribers.lock().unwrap().is_empty()
+                {
+                    return;
+                }
+                let mut channel = tail::CHANNEL.as_bytes().to_vec();
+                for (key, change) in changes {
+                    channel.truncate(tail::CHANNEL.len());
+                    channel.extend_from_slice(key);
+                    let content_type = match change {
+                        Change::Written(_) => self.db.content_type(key),
+                        Change::Deleted | Change::Expired => None,
+                    };
+                    self.publish(&channel, &tail::message(time, *change, content_type));
+                }
+            });
+    }
+    /// keeps [`Shared::acl`] in sync with the keys it is stored in, however they are changed
+    fn track_acl(&'static self) {
+        for section in [system::USERS, system::ACL] {
+            self.db
+                .on_commit(section, CommitStage::Persisted, |_, changes| {
+                    let mut acl = self.acl.write().unwrap();
+                    for (key, value) in changes {
+                        acl.apply(key, value);
+                    }
+                });
+        }
+    }
+}
+
+/// the quotas of `serve --max-*`, each connection is held to them on its own
+#[derive(Clone, Copy)]
+pub struct Limits {
+    pub max_key_size: u32,
+    pub max_value_size: u32,
+    pub max_scan_bytes: u32,
+    pub max_transactions_per_conn: u64,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            max_key_size: u32::MAX,
+            max_value_size: u32::MAX,
+            max_scan_bytes: u32::MAX,
+            max_transactions_per_conn: u64::MAX,
+        }
+    }
+}
+
+/// a connection being served, as listed by `admin connections`
+struct Session {
+    info: ConnectionInfo,
+    /// when the last request ended, or when it connected
+    active: Instant,
+    /// when the transaction it is in started
+    transaction: Option<Instant>,
+    /// a copy of the socket, shut down by `admin kill`, none for the connection of `serve_local`
+    stream: Option<TcpStream>,
+}
+
+/// a subscribed connection, with what it authenticated as, which decides which changes published for `tail` it gets
+struct Subscriber {
+    /// the id of the connection, whose subscriptions end with `admin kill`
+    session: u64,
+    sender: Sender<(Vec<u8>, Vec<u8>)>,
+    admin: bool,
+    role: Option<String>,
+}
+
+impl Subscriber {
+    fn may_read(&self, acl: &Acl, key: &[u8]) -> bool {
+        self.admin || acl.permits(self.role.as_deref(), key, Access::Read)
     }
 }
 
@@ -50,71 +415,434 @@ pub fn serve(
 enum ServerMode {
     #[default]
     Normal,
-    Transaction(pathkvs_core::Transaction<'static>),
-    Snapshot(pathkvs_core::Snapshot<'static>),
+    Transaction(Box<pathkvs_core::Transaction<'static>>),
+    Snapshot(pathkvs_core::Snapshot),
 }
 
 struct Server {
     db: &'static pathkvs_core::Database,
+    shared: &'static Shared,
+    id: u64,
+    peer: String,
+    admin: bool,
+    /// of the user the connection authenticated as
+    role: Option<String>,
     mode: ServerMode,
+    /// the snapshot normal mode reads from with [`ReadConsistency::Session`]
+    pinned: Option<pathkvs_core::Snapshot>,
 }
 
 impl Server {
-    const fn new(db: &'static pathkvs_core::Database) -> Self {
+    fn new(shared: &'static Shared, peer: String, admin: bool, stream: Option<TcpStream>) -> Self {
+        let id = shared.next_id.fetch_add(1, Ordering::SeqCst);
+        shared.sessions.lock().unwrap().insert(
+            id,
+            Session {
+                info: ConnectionInfo {
+                    id,
+                    peer: peer.clone(),
+                    name: String::new(),
+                    mode: ConnectionMode::Normal,
+                    commands: 0,
+                    bytes: 0,
+                    idle: Duration::ZERO,
+                    transaction: None,
+                },
+                active: Instant::now(),
+                transaction: None,
+                stream,
+            },
+        );
         Self {
-            db,
+            db: &shared.db,
+            shared,
+            id,
+            peer,
+            admin,
+            role: None,
             mode: ServerMode::Normal,
+            pinned: None,
+        }
+    }
+    fn set_mode(&mut self, mode: ServerMode) {
+        let connection_mode = match mode {
+            ServerMode::Normal => ConnectionMode::Normal,
+            ServerMode::Transaction(_) => ConnectionMode::Transaction,
+            ServerMode::Snapshot(_) => ConnectionMode::Snapshot,
+        };
+        if let Some(session) = self.shared.sessions.lock().unwrap().get_mut(&self.id) {
+            if connection_mode != ConnectionMode::Transaction {
+                session.transaction = None;
+            } else if session.info.mode != ConnectionMode::Transaction {
+                session.transaction = Some(Instant::now());
+            }
+            session.info.mode = connection_mode;
+        }
+        self.mode = mode;
+    }
+    /// the snapshot normal mode reads from
+    fn normal_snapshot(&self) -> pathkvs_core::Snapshot {
+        match &self.pinned {
+            Some(pinned) => pinned.clone(),
+            None => self.db.snapshot(),
+        }
+    }
+    /// called after each write or commit, so a pinned snapshot sees it
+    fn advance(&mut self) {
+        if self.pinned.is_some() {
+            self.pinned = Some(self.db.snapshot());
         }
     }
 }
 
+impl Drop for Server {
+    fn drop(&mut self) {
+        self.shared.sessions.lock().unwrap().remove(&self.id);
+    }
+}
+
 impl pathkvs_net::server::Server for Server {
     fn len(&mut self, key: &[u8]) -> Result<u32, Error> {
         match &mut self.mode {
-            ServerMode::Normal => Ok(self.db.len(key)),
+            ServerMode::Normal => Ok(self.normal_snapshot().len(key)?),
             ServerMode::Transaction(tr) => Ok(tr.len(key)),
-            ServerMode::Snapshot(sn) => Ok(sn.len(key)),
+            ServerMode::Snapshot(sn) => Ok(sn.len(key)?),
+        }
+    }
+
+    fn len_many(&mut self, keys: &[Vec<u8>], write: impl FnOnce(&[u32])) -> Result<(), Error> {
+        match &mut self.mode {
+            ServerMode::Normal => {
+                let snapshot = self.normal_snapshot();
+                write(
+                    &keys
+                        .iter()
+                        .map(|key| snapshot.len(key))
+                        .collect::<Result<Vec<_>, _>>()?,
+                );
+            }
+            ServerMode::Transaction(tr) => {
+                write(&keys.iter().map(|key| tr.len(key)).collect::<Vec<_>>());
+            }
+            ServerMode::Snapshot(sn) => {
+                write(
+                    &keys
+                        .iter()
+                        .map(|key| sn.len(key))
+                        .collect::<Result<Vec<_>, _>>()?,
+                );
+            }
+        }
+        Ok(())
+    }
+
+    fn exists(&mut self, key: &[u8]) -> Result<bool, Error> {
+        match &mut self.mode {
+            ServerMode::Normal => Ok(self.normal_snapshot().exists(key)?),
+            ServerMode::Transaction(tr) => Ok(tr.exists(key)),
+            ServerMode::Snapshot(sn) => Ok(sn.exists(key)?),
+        }
+    }
+
+    fn exists_many(&mut self, keys: &[Vec<u8>], write: impl FnOnce(&[bool])) -> Result<(), Error> {
+        match &mut self.mode {
+            ServerMode::Normal => {
+                let snapshot = self.normal_snapshot();
+                write(
+                    &keys
+                        .iter()
+                        .map(|key| snapshot.exists(key))
+                        .collect::<Result<Vec<_>, _>>()?,
+                );
+            }
+            ServerMode::Transaction(tr) => {
+                write(&keys.iter().map(|key| tr.exists(key)).collect::<Vec<_>>());
+            }
+            ServerMode::Snapshot(sn) => {
+                write(
+                    &keys
+                        .iter()
+                        .map(|key| sn.exists(key))
+                        .collect::<Result<Vec<_>, _>>()?,
+                );
+            }
         }
+        Ok(())
     }
 
     fn read(&mut self, key: &[u8], write: impl FnOnce(&[u8])) -> Result<(), Error> {
         match &mut self.mode {
-            ServerMode::Normal => write(self.db.read(key)),
+            ServerMode::Normal => write(self.normal_snapshot().read(key)?),
             ServerMode::Transaction(tr) => write(tr.read(key)),
-            ServerMode::Snapshot(sn) => write(sn.read(key)),
+            ServerMode::Snapshot(sn) => write(sn.read(key)?),
+        }
+        Ok(())
+    }
+
+    fn read_range(
+        &mut self,
+        key: &[u8],
+        offset: u32,
+        len: u32,
+        write: impl FnOnce(&[u8]),
+    ) -> Result<(), Error> {
+        match &mut self.mode {
+            ServerMode::Normal => write(self.normal_snapshot().read_range(key, offset, len)?),
+            ServerMode::Transaction(tr) => write(tr.read_range(key, offset, len)),
+            ServerMode::Snapshot(sn) => write(sn.read_range(key, offset, len)?),
+        }
+        Ok(())
+    }
+
+    fn read_many(&mut self, keys: &[Vec<u8>], write: impl FnOnce(&[&[u8]])) -> Result<(), Error> {
+        match &mut self.mode {
+            ServerMode::Normal => {
+                let snapshot = self.normal_snapshot();
+                write(
+                    &keys
+                        .iter()
+                        .map(|key| snapshot.read(key))
+                        .collect::<Result<Vec<_>, _>>()?,
+                );
+            }
+            ServerMode::Transaction(tr) => {
+                let values = keys
+                    .iter()
+                    .map(|key| tr.read(key).to_vec())
+                    .collect::<Vec<_>>();
+                write(&values.iter().map(Vec::as_slice).collect::<Vec<_>>());
+            }
+            ServerMode::Snapshot(sn) => {
+                write(
+                    &keys
+                        .iter()
+                        .map(|key| sn.read(key))
+                        .collect::<Result<Vec<_>, _>>()?,
+                );
+            }
         }
         Ok(())
     }
 
+    /// the protocol has no deletion of its own, writing an empty value deletes the key
     fn write(&mut self, key: &[u8], value: &[u8]) -> Result<(), Error> {
         match &mut self.mode {
             ServerMode::Normal => {
                 self.db.write(key, value)?;
+                self.advance();
+            }
+            ServerMode::Transaction(tr) => tr.write(key, value),
+            ServerMode::Snapshot(_) => return Err(ProtocolError.into()),
+        }
+        Ok(())
+    }
+
+    fn delete(&mut self, key: &[u8]) -> Result<(), Error> {
+        match &mut self.mode {
+            ServerMode::Normal => {
+                self.db.delete(key)?;
+                self.advance();
+            }
+            ServerMode::Transaction(tr) => tr.delete(key),
+            ServerMode::Snapshot(_) => return Err(ProtocolError.into()),
+        }
+        Ok(())
+    }
+
+    fn expire(&mut self, key: &[u8]) -> Result<(), Error> {
+        match &mut self.mode {
+            ServerMode::Normal => {
+                self.db.expire(key)?;
+                self.advance();
+            }
+            ServerMode::Transaction(tr) => tr.expire(key),
+            ServerMode::Snapshot(_) => return Err(ProtocolError.into()),
+        }
+        Ok(())
+    }
+
+    fn append(&mut self, key: &[u8], bytes: &[u8]) -> Result<(), Error> {
+        match &mut self.mode {
+            ServerMode::Normal => {
+                self.db.append(key, bytes)?;
+                self.advance();
+            }
+            ServerMode::Transaction(tr) => {
+                tr.append(key, bytes);
+            }
+            ServerMode::Snapshot(_) => return Err(ProtocolError.into()),
+        }
+        Ok(())
+    }
+
+    fn write_max(&mut self, key: &[u8], n: i64) -> Result<(), Error> {
+        match &mut self.mode {
+            ServerMode::Normal => {
+                self.db.write_max(key, n)?;
+                self.advance();
+            }
+            ServerMode::Transaction(tr) => {
+                tr.write_max(key, n);
+            }
+            ServerMode::Snapshot(_) => return Err(ProtocolError.into()),
+        }
+        Ok(())
+    }
+
+    fn write_min(&mut self, key: &[u8], n: i64) -> Result<(), Error> {
+        match &mut self.mode {
+            ServerMode::Normal => {
+                self.db.write_min(key, n)?;
+                self.advance();
             }
             ServerMode::Transaction(tr) => {
-                tr.write(key, value);
+                tr.write_min(key, n);
             }
             ServerMode::Snapshot(_) => return Err(ProtocolError.into()),
         }
         Ok(())
     }
 
+    fn incr(&mut self, key: &[u8], delta: i64) -> Result<Option<i64>, Error> {
+        match &mut self.mode {
+            // the same as `Database::incr`, but a value that is not a number is not an error
+            ServerMode::Normal => loop {
+                let mut ts = self.db.start_writes();
+                let Some(n) = ts.incr(key, delta) else {
+                    return Ok(None);
+                };
+                match ts.commit() {
+                    Ok(_) => {
+                        self.advance();
+                        return Ok(Some(n));
+                    }
+                    Err(TransactionError::Conflict) => continue,
+                    Err(TransactionError::Io(error)) => return Err(error),
+                }
+            },
+            ServerMode::Transaction(tr) => Ok(tr.incr(key, delta)),
+            ServerMode::Snapshot(_) => Err(ProtocolError.into()),
+        }
+    }
+    fn incr_windowed(&mut self, key: &[u8], window: Duration) -> Result<u64, Error> {
+        match &mut self.mode {
+            ServerMode::Normal => {
+                let count = self.db.incr_windowed(key, window)?;
+                self.advance();
+                Ok(count)
+            }
+            ServerMode::Transaction(tr) => Ok(tr.incr_windowed(key, window)),
+            ServerMode::Snapshot(_) => Err(ProtocolError.into()),
+        }
+    }
+
+    fn check_write(
+        &mut self,
+        checks: &[(Vec<u8>, Option<Vec<u8>>)],
+        writes: &[(Vec<u8>, Option<Vec<u8>>)],
+    ) -> Result<Result<Duration, u32>, Error> {
+        fn borrow(pairs: &[(Vec<u8>, Option<Vec<u8>>)]) -> Vec<(&[u8], Option<&[u8]>)> {
+            pairs
+                .iter()
+                .map(|(key, value)| (key.as_slice(), value.as_deref()))
+                .collect()
+        }
+        let result = self.db.check_write(&borrow(checks), &borrow(writes))?;
+        self.advance();
+        Ok(result.map_err(|index| index as u32))
+    }
+
     fn start_transaction(&mut self) -> Result<(), Error> {
         self.rollback()?;
-        self.mode = ServerMode::Transaction(self.db.start_writes());
+        self.set_mode(ServerMode::Transaction(Box::new(self.db.start_writes())));
         Ok(())
     }
 
+    fn max_key_len(&self) -> u32 {
+        self.shared.limits.max_key_size
+    }
+    fn max_value_len(&self) -> u32 {
+        self.shared.limits.max_value_size
+    }
+    fn max_scan_bytes(&self) -> u32 {
+        self.shared.limits.max_scan_bytes
+    }
+    fn max_transactions(&self) -> u64 {
+        self.shared.limits.max_transactions_per_conn
+    }
+
+    fn busy(&self) -> bool {
+        let busy =
+            self.db.persist_backlog() > self.shared.max_persist_backlog.load(Ordering::Relaxed);
+        if busy {
+            self.shared.busy_replies.fetch_add(1, Ordering::Relaxed);
+        }
+        busy
+    }
+
     fn commit(&mut self) -> Result<Result<Option<Duration>, TransactionConflict>, Error> {
-        match std::mem::take(&mut self.mode) {
+        let replicas = self.shared.min_replicas.load(Ordering::Relaxed);
+        let result = self.commit_replicated(replicas)?;
+        Ok(result.map(|receipt| receipt.map(|receipt| receipt.time)))
+    }
+
+    fn commit_with_receipt(
+        &mut self,
+    ) -> Result<Result<Option<CommitReceipt>, TransactionConflict>, Error> {
+        let replicas = self.shared.min_replicas.load(Ordering::Relaxed);
+        self.commit_replicated(replicas)
+    }
+
+    fn commit_replicated(
+        &mut self,
+        replicas: u32,
+    ) -> Result<Result<Option<CommitReceipt>, TransactionConflict>, Error> {
+        let mode = std::mem::take(&mut self.mode);
+        self.set_mode(ServerMode::Normal);
+        match mode {
             ServerMode::Normal => Ok(Ok(None)),
-            ServerMode::Transaction(tr) => tr.commit().transpose_conflict().map(|x| x.map(Some)),
+            ServerMode::Transaction(tr) => {
+                let result = tr.commit_with_receipt().transpose_conflict();
+                self.advance();
+                let receipt = match result? {
+                    Ok(receipt) => receipt,
+                    Err(TransactionConflict) => return Ok(Err(TransactionConflict)),
+                };
+                if replicas > 0 {
+                    let timeout = self.shared.replica_timeout_ms.load(Ordering::Relaxed);
+                    self.db.wait_for_replicas(
+                        receipt.seq,
+                        replicas as usize,
+                        Duration::from_millis(timeout),
+                    )?;
+                }
+                Ok(Ok(Some(receipt)))
+            }
             ServerMode::Snapshot(_) => Ok(Ok(None)),
         }
     }
 
+    fn validate(&mut self, write: impl FnOnce(&[&[u8]])) -> Result<(), Error> {
+        if let ServerMode::Transaction(tr) = &self.mode {
+            if let Err(report) = tr.validate() {
+                write(&report.keys().iter().map(Vec::as_slice).collect::<Vec<_>>());
+            }
+        }
+        Ok(())
+    }
+    fn recent_commits(
+        &mut self,
+        limit: u32,
+        write: impl FnOnce(&[CommitSummary]),
+    ) -> Result<(), Error> {
+        write(&self.db.recent_commits(limit as usize));
+        Ok(())
+    }
+
     fn rollback(&mut self) -> Result<(), Error> {
-        match std::mem::take(&mut self.mode) {
+        let mode = std::mem::take(&mut self.mode);
+        self.set_mode(ServerMode::Normal);
+        match mode {
             ServerMode::Normal => {}
             ServerMode::Transaction(tr) => {
                 tr.rollback();
@@ -126,9 +854,9 @@ impl pathkvs_net::server::Server for Server {
 
     fn count(&mut self, start: &[u8], end: &[u8]) -> Result<u32, Error> {
         match &mut self.mode {
-            ServerMode::Normal => Ok(self.db.count(start, end)),
+            ServerMode::Normal => Ok(self.normal_snapshot().count(start, end)?),
             ServerMode::Transaction(tr) => Ok(tr.count(start, end)),
-            ServerMode::Snapshot(sn) => Ok(sn.count(start, end)),
+            ServerMode::Snapshot(sn) => Ok(sn.count(start, end)?),
         }
     }
     fn list(
@@ -139,13 +867,13 @@ impl pathkvs_net::server::Server for Server {
     ) -> Result<(), Error> {
         match &mut self.mode {
             ServerMode::Normal => {
-                write(&self.db.list(start, end));
+                write(&self.normal_snapshot().list(start, end)?);
             }
             ServerMode::Transaction(tr) => {
                 write(&tr.list(start, end));
             }
             ServerMode::Snapshot(sn) => {
-                write(&sn.list(start, end));
+                write(&sn.list(start, end)?);
             }
         }
         Ok(())
@@ -158,13 +886,38 @@ impl pathkvs_net::server::Server for Server {
     ) -> Result<(), Error> {
         match &mut self.mode {
             ServerMode::Normal => {
-                write(&self.db.scan(start, end));
+                write(&self.normal_snapshot().scan(start, end)?);
             }
             ServerMode::Transaction(tr) => {
                 write(&tr.scan(start, end));
             }
             ServerMode::Snapshot(sn) => {
-                write(&sn.scan(start, end));
+                write(&sn.scan(start, end)?);
+            }
+        }
+        Ok(())
+    }
+
+    fn scan_modified_since(
+        &mut self,
+        start: &[u8],
+        end: &[u8],
+        since: Duration,
+        write: impl FnOnce(&[(&[u8], &[u8])]),
+    ) -> Result<(), Error> {
+        match &mut self.mode {
+            ServerMode::Normal => {
+                write(
+                    &self
+                        .normal_snapshot()
+                        .scan_modified_since(start, end, since)?,
+                );
+            }
+            ServerMode::Transaction(tr) => {
+                write(&tr.scan_modified_since(start, end, since));
+            }
+            ServerMode::Snapshot(sn) => {
+                write(&sn.scan_modified_since(start, end, since)?);
             }
         }
         Ok(())
@@ -176,7 +929,543 @@ impl pathkvs_net::server::Server for Server {
             Some(past_unix_time) => self.db.past_unix_time_snapshot_with(past_unix_time),
             None => self.db.snapshot(),
         };
-        self.mode = ServerMode::Snapshot(sn);
+        self.set_mode(ServerMode::Snapshot(sn));
+        Ok(())
+    }
+
+    fn start_seq_snapshot(&mut self, seq: u64) -> Result<(), Error> {
+        self.rollback()?;
+        let sn = self.db.past_seq_snapshot(seq);
+        self.set_mode(ServerMode::Snapshot(sn));
+        Ok(())
+    }
+
+    fn backup(&mut self, write: impl FnOnce(Duration, &[(&[u8], &[u8])])) -> Result<(), Error> {
+        let sn = match &self.mode {
+            ServerMode::Snapshot(sn) => sn.clone(),
+            _ => self.normal_snapshot(),
+        };
+        write(sn.time().unwrap_or_default(), &sn.scan(b"", b"")?);
+        Ok(())
+    }
+
+    fn call_procedure(
+        &mut self,
+        name: &str,
+        args: &[Vec<u8>],
+        write: impl FnOnce(&[u8]),
+    ) -> Result<Result<(), ProcedureError>, Error> {
+        let procedure = self.shared.procedures.lock().unwrap().get(name).cloned();
+        let Some(procedure) = procedure else {
+            return Ok(Err(ProcedureError(format!("unknown procedure {name:?}"))));
+        };
+        // connections that are not admin run it with their permissions, so it can't reach the keys they can't
+        let shared = self.shared;
+        let role = self.role.clone();
+        let admin = self.admin;
+        let mut denied = false;
+        let mut run = |tr: &mut pathkvs_core::Transaction| {
+            if admin {
+                return procedure.run(tr, args);
+            }
+            procedure.run_guarded(tr, args, &mut |key, access| {
+                let acl = shared.acl.read().unwrap();
+                let permitted = match access {
+                    KeyAccess::Read => acl.permits(role.as_deref(), key, Access::Read),
+                    KeyAccess::Write => acl.permits(role.as_deref(), key, Access::Write),
+                    KeyAccess::Prefix => acl.permits_range(role.as_deref(), key),
+                };
+                denied |= !permitted;
+                permitted
+            })
+        };
+        let result = match &mut self.mode {
+            ServerMode::Normal => loop {
+                let mut tr = self.db.start_writes();
+                let result = match run(&mut tr) {
+                    Ok(result) => result,
+                    Err(_) if denied => return Err(ErrorKind::PermissionDenied.into()),
+                    Err(error) => return Ok(Err(error)),
+                };
+                match tr.commit() {
+                    Ok(_) => {
+                        self.advance();
+                        break result;
+                    }
+                    Err(TransactionError::Conflict) => continue,
+                    Err(TransactionError::Io(error)) => return Err(error),
+                }
+            },
+            ServerMode::Transaction(tr) => match run(tr) {
+                Ok(result) => result,
+                Err(_) if denied => return Err(ErrorKind::PermissionDenied.into()),
+                Err(error) => return Ok(Err(error)),
+            },
+            ServerMode::Snapshot(_) => {
+                return Ok(Err(ProcedureError(
+                    "procedures can't run in a snapshot".to_string(),
+                )))
+            }
+        };
+        write(&result);
+        Ok(Ok(()))
+    }
+
+    fn set_read_consistency(&mut self, consistency: ReadConsistency) -> Result<(), Error> {
+        self.pinned = match consistency {
+            ReadConsistency::Latest => None,
+            ReadConsistency::Session => Some(self.db.snapshot()),
+        };
         Ok(())
     }
+
+    fn publish(&mut self, channel: &[u8], message: &[u8]) -> Result<u32, Error> {
+        Ok(self.shared.publish(channel, message))
+    }
+    fn subscribe(
+        &mut self,
+        channels: &[Vec<u8>],
+    ) -> Result<impl Iterator<Item = (Vec<u8>, Vec<u8>)>, Error> {
+        let (sender, receiver) = channel();
+        let mut subscribed = self.shared.channels.lock().unwrap();
+        for (index, name) in channels.iter().enumerate() {
+            if !channels[..index].contains(name) {
+                subscribed
+                    .entry(name.clone())
+                    .or_default()
+                    .push(Subscriber {
+                        session: self.id,
+                        sender: sender.clone(),
+                        admin: self.admin,
+                        role: self.role.clone(),
+                    });
+            }
+        }
+        Ok(receiver.into_iter())
+    }
+    fn subscribe_patterns(
+        &mut self,
+        patterns: &[Vec<u8>],
+    ) -> Result<impl Iterator<Item = (Vec<u8>, Vec<u8>)>, Error> {
+        let (sender, receiver) = channel();
+        self.shared.pattern_subscribers.lock().unwrap().push((
+            patterns.to_vec(),
+            Subscriber {
+                session: self.id,
+                sender,
+                admin: self.admin,
+                role: self.role.clone(),
+            },
+        ));
+        Ok(receiver.into_iter())
+    }
+
+    fn set_name(&mut self, name: &str) -> Result<(), Error> {
+        let mut sessions = self.shared.sessions.lock().unwrap();
+        let others = sessions
+            .values()
+            .map(|session| &session.info)
+            .filter(|session| session.id != self.id && !name.is_empty() && session.name == name)
+            .map(|session| format!("{} ({})", session.id, session.peer))
+            .collect::<Vec<_>>();
+        if !others.is_empty() {
+            println!(
+                "a conexão {} ({}) se chamou {name}, mesmo nome da(s) conexão(ões) {}",
+                self.id,
+                self.peer,
+                others.join(", ")
+            );
+        }
+        if let Some(session) = sessions.get_mut(&self.id) {
+            session.info.name = name.to_string();
+        }
+        Ok(())
+    }
+    fn authenticate(&mut self, token: &[u8]) -> Result<bool, Error> {
+        self.admin = self
+            .shared
+            .admin_token
+            .read()
+            .unwrap()
+            .as_ref()
+            .is_some_and(|admin_token| acl::same_token(admin_token.as_bytes(), token));
+        self.role = None;
+        if !self.admin {
+            self.role = self
+                .db
+                .scan(system::USERS, b"")
+                .iter()
+                .find_map(|(_, value)| acl::check_token(value, token))
+                .map(str::to_string);
+        }
+        Ok(self.admin || self.role.is_some())
+    }
+    fn is_admin(&self) -> bool {
+        self.admin
+    }
+    fn permitted(&self, key: &[u8], access: Access) -> bool {
+        self.admin
+            || self
+                .shared
+                .acl
+                .read()
+                .unwrap()
+                .permits(self.role.as_deref(), key, access)
+    }
+    fn permitted_range(&self, start: &[u8]) -> bool {
+        self.admin
+            || self
+                .shared
+                .acl
+                .read()
+                .unwrap()
+                .permits_range(self.role.as_deref(), start)
+    }
+    fn add_user(&mut self, name: &str, role: &str, token: &[u8]) -> Result<(), Error> {
+        self.db
+            .write(&acl::user_key(name), &acl::user_value(role, token))?;
+        Ok(())
+    }
+    fn remove_user(&mut self, name: &str) -> Result<bool, Error> {
+        let key = acl::user_key(name);
+        if !self.db.exists(&key) {
+            return Ok(false);
+        }
+        self.db.delete(&key)?;
+        Ok(true)
+    }
+    fn users(&mut self, write: impl FnOnce(&[(&str, &str)])) -> Result<(), Error> {
+        let users = self.db.scan(system::USERS, b"");
+        let users = users
+            .iter()
+            .map(|(key, value)| {
+                let name = std::str::from_utf8(&key[system::USERS.len()..]).unwrap_or_default();
+                (name, acl::role(value))
+            })
+            .collect::<Vec<_>>();
+        write(&users);
+        Ok(())
+    }
+    fn set_acl(&mut self, prefix: &[u8], access: Access, role: Option<&str>) -> Result<(), Error> {
+        let key = acl::rule_key(prefix, access);
+        match role {
+            Some(role) => self.db.write(&key, role.as_bytes())?,
+            None => self.db.delete(&key)?,
+        }
+        Ok(())
+    }
+    fn acl(&mut self, write: impl FnOnce(&[AclRule])) -> Result<(), Error> {
+        write(&self.shared.acl.read().unwrap().list());
+        Ok(())
+    }
+    fn stats(&mut self, write: impl FnOnce(&[(&str, u64)])) -> Result<(), Error> {
+        self.info(|sections| {
+            let stats = sections
+                .iter()
+                .flat_map(|(_, fields)| fields.iter().copied())
+                .collect::<Vec<_>>();
+            write(&stats);
+        })
+    }
+    fn info(&mut self, write: impl FnOnce(&[(&str, &[(&str, u64)])])) -> Result<(), Error> {
+        let sessions = self.shared.sessions.lock().unwrap();
+        let connections = sessions.len() as u64;
+        let mut names = HashMap::<&str, u64>::new();
+        for session in sessions
+            .values()
+            .filter(|session| !session.info.name.is_empty())
+        {
+            *names.entry(&session.info.name).or_default() += 1;
+        }
+        let duplicate_names = names.values().filter(|count| **count > 1).sum::<u64>();
+        drop(names);
+        drop(sessions);
+        let metrics = self.db.metrics();
+        let commands = self.shared.commands.lock().unwrap().clone();
+        let command_fields = commands
+            .iter()
+            .flat_map(|(request, count)| {
+                [
+                    (request.to_string(), count.count),
+                    (format!("{request}_bytes"), count.bytes),
+                    (format!("{request}_micros"), count.time.as_micros() as u64),
+                ]
+            })
+            .collect::<Vec<_>>();
+        let command_fields = command_fields
+            .iter()
+            .map(|(name, value)| (name.as_str(), *value))
+            .collect::<Vec<_>>();
+        write(&[
+            (
+                "server",
+                &[
+                    ("uptime_seconds", self.shared.started.elapsed().as_secs()),
+                    (
+                        "busy_replies",
+                        self.shared.busy_replies.load(Ordering::Relaxed),
+                    ),
+                ],
+            ),
+            (
+                "clients",
+                &[
+                    ("connections", connections),
+                    (
+                        "connections_accepted",
+                        self.shared.connections_accepted.load(Ordering::Relaxed),
+                    ),
+                    ("duplicate_names", duplicate_names),
+                ],
+            ),
+            (
+                "keyspace",
+                &[
+                    ("keys", self.db.count(b"", b"") as u64),
+                    ("commits", self.db.commit_count()),
+                    ("commits_attempted", metrics.commits_attempted),
+                    ("commits_succeeded", metrics.commits),
+                    ("conflicts", metrics.conflicts),
+                    ("commit_retries", metrics.commit_retries),
+                    ("commit_time_micros", metrics.commit_time.as_micros() as u64),
+                    (
+                        "commit_time_max_micros",
+                        metrics.commit_time_max.as_micros() as u64,
+                    ),
+                ],
+            ),
+            (
+                "persistence",
+                &[
+                    ("persists", metrics.persists),
+                    ("persisted_commits", metrics.persisted_commits),
+                    (
+                        "persist_time_micros",
+                        metrics.persist_time.as_micros() as u64,
+                    ),
+                    (
+                        "persist_time_max_micros",
+                        metrics.persist_time_max.as_micros() as u64,
+                    ),
+                    ("persist_backlog", self.db.persist_backlog()),
+                ],
+            ),
+            ("commands", &command_fields),
+        ]);
+        Ok(())
+    }
+    fn reload(&mut self) -> Result<Result<(), String>, Error> {
+        let Some(path) = &self.shared.config else {
+            return Ok(Err("o servidor não foi iniciado com --config".to_string()));
+        };
+        let config = match Config::read(path) {
+            Ok(config) => config,
+            Err(error) => return Ok(Err(error)),
+        };
+        if let Some(token) = config.admin_token {
+            *self.shared.admin_token.write().unwrap() = token;
+        }
+        if let Some(sync) = config.sync {
+            self.shared.db.set_write_sync_mode(sync);
+        }
+        if let Some(max) = config.max_persist_backlog {
+            self.shared
+                .max_persist_backlog
+                .store(max.unwrap_or(u64::MAX), Ordering::Relaxed);
+        }
+        if let Some(ms) = config.slow_ms {
+            self.shared
+                .slow_log
+                .set_threshold(ms.map(Duration::from_millis));
+        }
+        if let Some(replicas) = config.min_replicas {
+            self.shared.min_replicas.store(replicas, Ordering::Relaxed);
+        }
+        if let Some(ms) = config.replica_timeout_ms {
+            self.shared.replica_timeout_ms.store(ms, Ordering::Relaxed);
+        }
+        {
+            let mut schedule = self.shared.schedule.write().unwrap();
+            if let Some(window) = config.maintenance_window {
+                schedule.window = window;
+            }
+            if let Some(max) = config.maintenance_max_commits {
+                schedule.max_commits_per_sec = max;
+            }
+        }
+        println!(
+            "configuração recarregada de {} pela conexão {}",
+            path.display(),
+            self.id
+        );
+        Ok(Ok(()))
+    }
+    fn on_connect(&mut self) {
+        self.shared
+            .connections_accepted
+            .fetch_add(1, Ordering::Relaxed);
+    }
+    fn on_command(&mut self, command: &CommandEvent) {
+        let mut name = None;
+        if let Some(session) = self.shared.sessions.lock().unwrap().get_mut(&self.id) {
+            session.info.commands += 1;
+            session.info.bytes += command.bytes;
+            session.active = Instant::now();
+            if self.shared.slow_log.is_slow(command.elapsed) {
+                name = Some(session.info.name.clone());
+            }
+        }
+        self.shared.commands.lock().unwrap().record(command);
+        if let Some(access_log) = &self.shared.access_log {
+            access_log.log(self.id, &self.peer, command);
+        }
+        if let Some(name) = name {
+            self.shared
+                .slow_log
+                .record(self.id, &self.peer, &name, command);
+        }
+    }
+    fn traced(&mut self, trace_id: &[u8], request: &'static str, elapsed: Duration) {
+        println!(
+            "trace {}: {request} da conexão {} levou {elapsed:?}",
+            String::from_utf8_lossy(trace_id),
+            self.id
+        );
+    }
+    /// the history in memory is left as it is, only the file is rewritten, not available without one
+    fn compact(&mut self, age: Duration) -> Result<(), Error> {
+        let Some(compaction) = self.shared.db.compact_keeping(age)? else {
+            return Err(ErrorKind::Unsupported.into());
+        };
+        println!(
+            "histórico compactado pela conexão {}: de {} para {} byte(s), {} commit(s)",
+            self.id, compaction.before, compaction.after, compaction.commits
+        );
+        Ok(())
+    }
+    /// removes the expired keys, then compacts the file like `compact`, keeping the commits of `serve --compact-keep-days`
+    fn gc(&mut self) -> Result<(), Error> {
+        match self.shared.db.sweep_expired()? {
+            0 => {}
+            swept => println!("{swept} chave(s) expirada(s) pela conexão {}", self.id),
+        }
+        if let Some(compaction) = self.shared.db.compact_keeping(self.shared.compact_keep)? {
+            println!(
+                "histórico compactado pela conexão {}: de {} para {} byte(s), {} commit(s)",
+                self.id, compaction.before, compaction.after, compaction.commits
+            );
+        }
+        Ok(())
+    }
+    fn connections(&mut self, write: impl FnOnce(&[ConnectionInfo])) -> Result<(), Error> {
+        let sessions = self
+            .shared
+            .sessions
+            .lock()
+            .unwrap()
+            .values()
+            .map(|session| ConnectionInfo {
+                idle: session.active.elapsed(),
+                transaction: session.transaction.map(|started| started.elapsed()),
+                ..session.info.clone()
+            })
+            .collect::<Vec<_>>();
+        write(&sessions);
+        Ok(())
+    }
+    fn slow_log(&mut self, write: impl FnOnce(&[SlowCommand])) -> Result<(), Error> {
+        write(&self.shared.slow_log.recent());
+        Ok(())
+    }
+    fn replication_status(&mut self, write: impl FnOnce(&[ReplicaStatus])) -> Result<(), Error> {
+        write(&self.db.replication_status());
+        Ok(())
+    }
+    fn backup_snapshot(&mut self, write: impl FnOnce(u64, &[u8])) -> Result<(), Error> {
+        let mut history = Vec::new();
+        let seq = self.db.backup_snapshot(&mut history)?;
+        write(seq, &history);
+        Ok(())
+    }
+    fn backup_since(&mut self, seq: u64, write: impl FnOnce(u64, &[u8])) -> Result<(), Error> {
+        let mut history = Vec::new();
+        let seq = self.db.backup_since(seq, &mut history)?;
+        write(seq, &history);
+        Ok(())
+    }
+    fn ack_replication(&mut self, name: &str, seq: u64) -> Result<(), Error> {
+        self.db.ack_replication(name, seq);
+        Ok(())
+    }
+    /// shuts the socket down, so the thread of the connection stops waiting for its next request and drops it,
+    /// rolling back its transaction, and ends its subscriptions, whose thread waits for messages instead
+    fn kill(&mut self, id: u64) -> Result<bool, Error> {
+        if id == self.id {
+            return Ok(false);
+        }
+        let sessions = self.shared.sessions.lock().unwrap();
+        let Some(session) = sessions.get(&id) else {
+            return Ok(false);
+        };
+        let Some(stream) = &session.stream else {
+            return Ok(false);
+        };
+        // the connection may be closing already
+        let _ = stream.shutdown(Shutdown::Both);
+        println!(
+            "conexão {id} ({}) encerrada pela conexão {}",
+            session.info.peer, self.id
+        );
+        drop(sessions);
+        let mut channels = self.shared.channels.lock().unwrap();
+        for subscribers in channels.values_mut() {
+            subscribers.retain(|subscriber| subscriber.session != id);
+        }
+        channels.retain(|_, subscribers| !subscribers.is_empty());
+        drop(channels);
+        self.shared
+            .pattern_subscribers
+            .lock()
+            .unwrap()
+            .retain(|(_, subscriber)| subscriber.session != id);
+        Ok(true)
+    }
+    fn drain(&mut self) -> Result<(), Error> {
+        self.shared.draining.store(true, Ordering::SeqCst);
+        println!("drenando: novas conexões serão recusadas");
+        Ok(())
+    }
+    fn upgrade(&mut self) -> Result<(), Error> {
+        if self.shared.listeners.is_empty() {
+            return Err(ErrorKind::Unsupported.into());
+        }
+        let mut upgrade = self.shared.upgrade.lock().unwrap();
+        if upgrade.is_some() {
+            return Ok(());
+        }
+        let child = crate::upgrade::spawn(&self.shared.listeners)?;
+        println!(
+            "atualizando: o processo {} assume as conexões novas quando as atuais terminarem",
+            child.id()
+        );
+        *upgrade = Some(child);
+        self.shared.draining.store(true, Ordering::SeqCst);
+        Ok(())
+    }
+    fn define_procedure(
+        &mut self,
+        name: &str,
+        source: &str,
+    ) -> Result<Result<(), ProcedureError>, Error> {
+        let procedure = match Procedure::parse(source) {
+            Ok(procedure) => procedure,
+            Err(error) => return Ok(Err(error)),
+        };
+        self.shared
+            .procedures
+            .lock()
+            .unwrap()
+            .insert(name.to_string(), Arc::new(procedure));
+        Ok(Ok(()))
+    }
 }
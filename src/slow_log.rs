@@ -0,0 +1,88 @@
+//! `serve --slow-ms`, the requests that took longer than a threshold, printed as they happen,
+//! with the most recent kept in memory for `admin slowlog`, like the `SLOWLOG` of redis
+
+use std::{
+    collections::VecDeque,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::{Duration, SystemTime},
+};
+
+use pathkvs_net::{client::SlowCommand, stats::CommandEvent};
+
+/// how many of the most recent slow requests are kept, older ones are only in the output of the server
+const KEPT: usize = 128;
+
+pub struct SlowLog {
+    /// in microseconds, `u64::MAX` disables it, replaced by `admin reload`
+    threshold: AtomicU64,
+    recent: Mutex<Recent>,
+}
+
+#[derive(Default)]
+struct Recent {
+    next_id: u64,
+    /// the oldest first
+    commands: VecDeque<SlowCommand>,
+}
+
+impl SlowLog {
+    /// nothing is logged if `threshold` is none
+    pub fn new(threshold: Option<Duration>) -> Self {
+        let slow_log = Self {
+            threshold: AtomicU64::new(u64::MAX),
+            recent: Mutex::default(),
+        };
+        slow_log.set_threshold(threshold);
+        slow_log
+    }
+    pub fn set_threshold(&self, threshold: Option<Duration>) {
+        let micros = threshold.map_or(u64::MAX, |threshold| threshold.as_micros() as u64);
+        self.threshold.store(micros, Ordering::Relaxed);
+    }
+    pub fn is_slow(&self, elapsed: Duration) -> bool {
+        elapsed.as_micros() as u64 >= self.threshold.load(Ordering::Relaxed)
+    }
+    /// a request [`SlowLog::is_slow`] found slow, `name` is the name of the connection, empty if it did not set one
+    pub fn record(&self, connection: u64, peer: &str, name: &str, command: &CommandEvent) {
+        let named = match name {
+            "" => String::new(),
+            name => format!(" {name}"),
+        };
+        println!(
+            "comando lento: {} da conexão {connection} ({peer}{named}) levou {:.1}ms, {} byte(s) de chaves, {} byte(s), {}",
+            command.request,
+            command.elapsed.as_secs_f64() * 1000.0,
+            command.key_bytes,
+            command.bytes,
+            command.outcome
+        );
+        let mut recent = self.recent.lock().unwrap();
+        recent.next_id += 1;
+        let id = recent.next_id;
+        if recent.commands.len() == KEPT {
+            recent.commands.pop_front();
+        }
+        recent.commands.push_back(SlowCommand {
+            id,
+            time: SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap_or_default(),
+            connection,
+            peer: peer.to_string(),
+            name: name.to_string(),
+            request: command.request.to_string(),
+            key_bytes: command.key_bytes,
+            bytes: command.bytes,
+            elapsed: command.elapsed,
+            outcome: command.outcome.to_string(),
+        });
+    }
+    /// the newest first
+    pub fn recent(&self) -> Vec<SlowCommand> {
+        let recent = self.recent.lock().unwrap();
+        recent.commands.iter().rev().cloned().collect()
+    }
+}
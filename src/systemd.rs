@@ -0,0 +1,73 @@
+//! socket activation, where systemd binds the listeners of `serve --systemd-socket` and passes them already open,
+//! so the server can be started on the first connection, and restarted without refusing connections in between
+
+use std::{
+    io::{Error, ErrorKind},
+    net::TcpListener,
+};
+
+/// the first file descriptor passed by systemd, `SD_LISTEN_FDS_START`
+#[cfg(unix)]
+const LISTEN_FDS_START: i32 = 3;
+
+/// takes the listeners passed to this process, as `sd_listen_fds` does,
+/// and removes the variables that describe them from the environment, so they are not passed on to child processes
+///
+/// every one of them must be a tcp socket, such as one of a `ListenStream=` of the `.socket` unit
+#[cfg(unix)]
+pub fn listen_fds() -> Result<Vec<TcpListener>, Error> {
+    use socket2::{Socket, Type};
+    use std::os::fd::FromRawFd;
+
+    let pid = std::env::var("LISTEN_PID").ok();
+    let fds = std::env::var("LISTEN_FDS").ok();
+    std::env::remove_var("LISTEN_PID");
+    std::env::remove_var("LISTEN_FDS");
+    std::env::remove_var("LISTEN_FDNAMES");
+    let not_activated = || {
+        Error::new(
+            ErrorKind::NotFound,
+            "nenhum socket foi passado pelo systemd, o servidor deve ser iniciado por uma unidade .socket",
+        )
+    };
+    let (Some(pid), Some(fds)) = (pid, fds) else {
+        return Err(not_activated());
+    };
+    if pid.parse() != Ok(std::process::id()) {
+        return Err(not_activated());
+    }
+    let count = fds
+        .parse::<i32>()
+        .ok()
+        .filter(|&count| count > 0)
+        .ok_or_else(not_activated)?;
+    (LISTEN_FDS_START..LISTEN_FDS_START + count)
+        .map(|fd| {
+            // SAFETY: systemd passes the descriptors from LISTEN_FDS_START on, owned by this process and used by nothing else
+            let socket = unsafe { Socket::from_raw_fd(fd) };
+            let result = (|| {
+                socket.set_cloexec(true)?;
+                let addr = socket.local_addr()?;
+                if socket.r#type()? != Type::STREAM || !(addr.is_ipv4() || addr.is_ipv6()) {
+                    return Err(Error::new(ErrorKind::InvalidInput, "não é um socket TCP"));
+                }
+                Ok(())
+            })();
+            match result {
+                Ok(()) => Ok(socket.into()),
+                Err(error) => Err(Error::new(
+                    error.kind(),
+                    format!("o descritor {fd} passado pelo systemd não pode ser usado: {error}"),
+                )),
+            }
+        })
+        .collect()
+}
+
+#[cfg(not(unix))]
+pub fn listen_fds() -> Result<Vec<TcpListener>, Error> {
+    Err(Error::new(
+        ErrorKind::Unsupported,
+        "a ativação por socket do systemd só existe em sistemas unix",
+    ))
+}
@@ -0,0 +1,140 @@
+//! `tail`, the changes of every commit as they happen, one json line per changed key, like `tail -f` of the database
+//!
+//! the server publishes each change on the channel [`CHANNEL`] followed by the key,
+//! so the changes under a prefix are those of the pattern of the channel followed by the prefix and `*`
+//!
+//! keys deleted because their time to live ran out, with `expire`, are told apart from keys deleted on purpose,
+//! so caches can react to each differently
+//!
+//! written keys with a [`ContentType`] carry it, and their values are shown as what it says,
+//! json and integers as they are, binary as a hex string, and text, or values without one, as a string
+
+use std::{
+    io::{Error, ErrorKind, Read, Write},
+    time::{Duration, SystemTime},
+};
+
+use chrono::{DateTime, Local};
+use pathkvs_core::{content::ContentType, hook::Change};
+use pathkvs_net::client::Connection;
+
+use crate::utils::hex;
+
+/// the changes are published only once persisted, changes to the system keys are never published
+pub const CHANNEL: &str = "__pathkvs__/changes/";
+
+const WRITTEN: u8 = 0;
+const DELETED: u8 = 1;
+const EXPIRED: u8 = 2;
+const WRITTEN_TYPED: u8 = 3;
+
+/// the message of a change, the time of the commit, as seconds and nanoseconds since the epoch,
+/// a byte with the kind of the change, the content type of the key, if it was written with one, and the value, if it was written
+pub fn message(time: Duration, change: Change, content_type: Option<ContentType>) -> Vec<u8> {
+    let (kind, value): (&[u8], &[u8]) = match (change, content_type) {
+        (Change::Written(value), Some(content_type)) => {
+            (&[WRITTEN_TYPED, content_type.to_u8()], value)
+        }
+        (Change::Written(value), None) => (&[WRITTEN], value),
+        (Change::Deleted, _) => (&[DELETED], &[]),
+        (Change::Expired, _) => (&[EXPIRED], &[]),
+    };
+    let mut message = Vec::with_capacity(12 + kind.len() + value.len());
+    message.extend_from_slice(&time.as_secs().to_be_bytes());
+    message.extend_from_slice(&time.subsec_nanos().to_be_bytes());
+    message.extend_from_slice(kind);
+    message.extend_from_slice(value);
+    message
+}
+
+fn parse(message: &[u8]) -> Option<(Duration, Change<'_>, Option<ContentType>)> {
+    let (secs, rest) = message.split_first_chunk::<8>()?;
+    let (nanos, rest) = rest.split_first_chunk::<4>()?;
+    let (&kind, value) = rest.split_first()?;
+    let time = Duration::new(u64::from_be_bytes(*secs), u32::from_be_bytes(*nanos));
+    let (change, content_type) = match kind {
+        WRITTEN => (Change::Written(value), None),
+        WRITTEN_TYPED => {
+            let (&content_type, value) = value.split_first()?;
+            (
+                Change::Written(value),
+                Some(ContentType::from_u8(content_type)?),
+            )
+        }
+        DELETED => (Change::Deleted, None),
+        EXPIRED => (Change::Expired, None),
+        _ => return None,
+    };
+    Some((time, change, content_type))
+}
+
+/// writes a line per change to a key matching `pattern`, only returns once the connection fails,
+/// the event is `write`, `delete` or `expire`, deleted and expired keys have a null value,
+/// written keys with a content type have a `type` too
+pub fn tail(conn: Connection<impl Read + Write>, pattern: &str) -> Result<(), Error> {
+    let mut subscription = conn.subscribe_patterns([format!("{CHANNEL}{pattern}")])?;
+    let mut stdout = std::io::stdout().lock();
+    loop {
+        let (channel, message) = subscription.next_message()?;
+        let (Some(key), Some((time, change, content_type))) =
+            (channel.strip_prefix(CHANNEL.as_bytes()), parse(&message))
+        else {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "mensagem inválida no canal de alterações",
+            ));
+        };
+        let time = DateTime::<Local>::from(SystemTime::UNIX_EPOCH + time).to_rfc3339();
+        let (event, value) = match change {
+            Change::Written(value) => ("write", json_value(value, content_type)),
+            Change::Deleted => ("delete", "null".to_string()),
+            Change::Expired => ("expire", "null".to_string()),
+        };
+        let content_type = match content_type {
+            Some(content_type) => format!(",\"type\":\"{content_type}\""),
+            None => String::new(),
+        };
+        writeln!(
+            stdout,
+            "{{\"time\":\"{time}\",\"event\":\"{event}\",\"key\":{}{content_type},\"value\":{value}}}",
+            json_string(key)
+        )?;
+        stdout.flush()?;
+    }
+}
+
+/// a json value can't be checked without parsing it, so one that is not utf-8, or is empty, becomes a string,
+/// and an integer that doesn't parse too
+fn json_value(value: &[u8], content_type: Option<ContentType>) -> String {
+    match content_type {
+        Some(ContentType::Json) => match std::str::from_utf8(value) {
+            Ok(text) if !text.trim().is_empty() => text.trim().replace(['\n', '\r'], " "),
+            _ => json_string(value),
+        },
+        Some(ContentType::Integer) => match std::str::from_utf8(value).map(str::parse::<i64>) {
+            Ok(Ok(number)) => number.to_string(),
+            _ => json_string(value),
+        },
+        Some(ContentType::Binary) => json_string(hex(value).as_bytes()),
+        Some(ContentType::Text) | None => json_string(value),
+    }
+}
+
+/// invalid utf-8 is replaced, as the output has to be valid json
+fn json_string(bytes: &[u8]) -> String {
+    let mut string = String::with_capacity(bytes.len() + 2);
+    string.push('"');
+    for c in String::from_utf8_lossy(bytes).chars() {
+        match c {
+            '"' => string.push_str("\\\""),
+            '\\' => string.push_str("\\\\"),
+            '\n' => string.push_str("\\n"),
+            '\r' => string.push_str("\\r"),
+            '\t' => string.push_str("\\t"),
+            c if c.is_control() => string.push_str(&format!("\\u{:04x}", c as u32)),
+            c => string.push(c),
+        }
+    }
+    string.push('"');
+    string
+}
@@ -0,0 +1,79 @@
+//! `tree` and `=tree`, the keys grouped by the parts of their paths, with how many keys and bytes are under each part
+
+use std::{
+    collections::BTreeMap,
+    io::{Error, Read, Write},
+};
+
+use pathkvs_core::keys;
+use pathkvs_net::client::Connection;
+
+use crate::utils::DisplayBytesEx;
+
+/// how many keys have their lengths asked for in a single request
+const BATCH: usize = 1000;
+
+#[derive(Default)]
+struct Node {
+    keys: u64,
+    /// of the keys and of the values
+    bytes: u64,
+    children: BTreeMap<Vec<u8>, Node>,
+}
+
+impl Node {
+    fn insert(&mut self, path: &[u8], bytes: u64, depth: usize) {
+        self.keys += 1;
+        self.bytes += bytes;
+        if depth == 0 {
+            return;
+        }
+        if let Some(part) = keys::path_prefix(path, 1) {
+            self.children.entry(part.to_vec()).or_default().insert(
+                &path[part.len()..],
+                bytes,
+                depth - 1,
+            );
+        }
+    }
+    fn print(&self, name: &str, indent: usize) {
+        println!(
+            "{:indent$}{name} {} chave(s), {}",
+            "",
+            self.keys,
+            size(self.bytes)
+        );
+        for (part, node) in &self.children {
+            node.print(&part.display().to_string(), indent + 2);
+        }
+    }
+}
+
+/// prints the keys that start with `prefix` grouped by the parts of their paths after it, up to `depth` parts deep
+///
+/// the values are not read, only their lengths
+pub fn print_tree(
+    conn: &mut Connection<impl Read + Write>,
+    prefix: &str,
+    depth: usize,
+) -> Result<(), Error> {
+    let list = conn.list(prefix, "")?;
+    let mut root = Node::default();
+    for chunk in list.chunks(BATCH) {
+        for (key, len) in chunk.iter().zip(conn.len_many(chunk)?) {
+            let bytes = key.len() as u64 + len as u64;
+            root.insert(&key[prefix.len()..], bytes, depth);
+        }
+    }
+    root.print(&format!("{prefix}*"), 0);
+    Ok(())
+}
+
+fn size(bytes: u64) -> String {
+    match bytes {
+        0..=0x3ff => format!("{bytes} B"),
+        0x400..=0xfffff => format!("{:.1} KiB", bytes as f64 / 1024.0),
+        0x100000..=0x3fffffff => format!("{:.1} MiB", bytes as f64 / 0x100000 as f64),
+        _ => format!("{:.1} GiB", bytes as f64 / 0x40000000 as f64),
+    }
+}
@@ -0,0 +1,80 @@
+//! `admin upgrade`, where the server starts a new process of itself, usually of a newer version, that inherits
+//! its listeners, and drains, the new process only opens the database and accepts once the old one exited,
+//! connections made in between wait in the queue of the listeners instead of being refused
+
+use std::{
+    io::{Error, ErrorKind},
+    net::TcpListener,
+    process::Child,
+};
+
+/// the descriptors of the inherited listeners, separated by commas
+#[cfg(unix)]
+const LISTEN_FDS: &str = "PATHKVS_LISTEN_FDS";
+
+/// starts this program again with the same arguments, inheriting `listeners`
+///
+/// its stdin is a pipe that is only closed when this process exits, which is how it knows the database is free
+#[cfg(unix)]
+pub fn spawn(listeners: &[TcpListener]) -> Result<Child, Error> {
+    use socket2::SockRef;
+    use std::{
+        os::fd::AsRawFd,
+        process::{Command, Stdio},
+    };
+
+    let mut args = std::env::args_os();
+    let program = args
+        .next()
+        .ok_or_else(|| Error::new(ErrorKind::NotFound, "o caminho do programa é desconhecido"))?;
+    let mut fds = Vec::new();
+    for listener in listeners {
+        SockRef::from(listener).set_cloexec(false)?;
+        fds.push(listener.as_raw_fd().to_string());
+    }
+    Command::new(program)
+        .args(args)
+        .env(LISTEN_FDS, fds.join(","))
+        .stdin(Stdio::piped())
+        .spawn()
+}
+
+#[cfg(not(unix))]
+pub fn spawn(_listeners: &[TcpListener]) -> Result<Child, Error> {
+    Err(ErrorKind::Unsupported.into())
+}
+
+/// the listeners left by the previous process, after waiting for it to exit, none if this one was not started by [`spawn`]
+#[cfg(unix)]
+pub fn inherited() -> Result<Option<Vec<TcpListener>>, Error> {
+    use socket2::Socket;
+    use std::os::fd::FromRawFd;
+
+    let Ok(fds) = std::env::var(LISTEN_FDS) else {
+        return Ok(None);
+    };
+    std::env::remove_var(LISTEN_FDS);
+    let listeners = fds
+        .split(',')
+        .map(|fd| {
+            let fd = fd.parse().map_err(|_| {
+                Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("{LISTEN_FDS} inválido: {fds}"),
+                )
+            })?;
+            // SAFETY: the previous process passed the descriptors of its listeners, which nothing else here uses
+            let socket = unsafe { Socket::from_raw_fd(fd) };
+            socket.set_cloexec(true)?;
+            Ok(socket.into())
+        })
+        .collect::<Result<Vec<_>, Error>>()?;
+    println!("esperando o servidor anterior terminar as conexões atuais");
+    std::io::copy(&mut std::io::stdin(), &mut std::io::sink())?;
+    Ok(Some(listeners))
+}
+
+#[cfg(not(unix))]
+pub fn inherited() -> Result<Option<Vec<TcpListener>>, Error> {
+    Ok(None)
+}
@@ -4,9 +4,10 @@ use std::{
 };
 
 use chrono::Local;
+use pathkvs_core::content::ContentType;
 
 pub trait DisplayBytesEx: AsRef<[u8]> {
-    fn display<'a>(&'a self) -> DisplayBytes<&'a Self> {
+    fn display(&self) -> DisplayBytes<&Self> {
         DisplayBytes(self)
     }
 }
@@ -21,6 +22,20 @@ impl<T: AsRef<[u8]>> Display for DisplayBytes<T> {
     }
 }
 
+/// the value as its content type says, json as it is, binary in hexadecimal after `0x`,
+/// and anything else like [`DisplayBytesEx::display`]
+pub fn display_typed(value: &[u8], content_type: Option<ContentType>) -> String {
+    match (content_type, std::str::from_utf8(value)) {
+        (Some(ContentType::Json), Ok(text)) => text.to_string(),
+        (Some(ContentType::Binary), _) => format!("0x{}", hex(value)),
+        _ => value.display().to_string(),
+    }
+}
+
+pub fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
 fn fmt_quoted_bytes(
     mut bytes: &[u8],
     f: &mut std::fmt::Formatter,
@@ -88,8 +103,8 @@ fn fmt_quoted_str(
 
 pub fn parse_general_timestamp(input: &str) -> Option<SystemTime> {
     let input = input.trim();
-    if input.starts_with('-') {
-        return parse_duration(&input[1..]).and_then(|x| SystemTime::now().checked_sub(x));
+    if let Some(input) = input.strip_prefix('-') {
+        return parse_duration(input).and_then(|x| SystemTime::now().checked_sub(x));
     }
     let patterns = [
         "%Y-%m-%d %H:%M:%S%.f",